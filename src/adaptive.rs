@@ -0,0 +1,85 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Heartbeat intervals observed before the learned timeout replaces `--timeout`.
+pub const MIN_SAMPLES: usize = 20;
+
+/// How many of the most recent intervals the timeout is learned from.
+const WINDOW: usize = 200;
+
+/// A timeout learned from the child's heartbeat cadence (`--adaptive-timeout`): `multiplier`
+/// times the p99 of recently observed intervals, kept within `min` and `max`. Until
+/// [`MIN_SAMPLES`] intervals have been seen, the configured timeout applies.
+#[derive(Debug)]
+pub struct Adaptive {
+    multiplier: f64,
+    min: Duration,
+    max: Duration,
+    learned: Mutex<Learned>,
+}
+
+#[derive(Debug, Default)]
+struct Learned {
+    /// The last heartbeat, which the next interval is measured from.
+    last: Option<Instant>,
+    intervals: VecDeque<Duration>,
+    timeout: Option<Duration>,
+}
+
+impl Adaptive {
+    pub fn new(multiplier: f64, min: Duration, max: Duration) -> Self {
+        Adaptive {
+            multiplier,
+            min,
+            max,
+            learned: Mutex::new(Learned::default()),
+        }
+    }
+
+    /// Records a heartbeat at `at`. Returns the learned timeout and the p99 interval it is
+    /// based on when the timeout has just been learned or has moved by 10% or more.
+    pub fn beat(&self, at: Instant) -> Option<(Duration, Duration)> {
+        let mut learned = self.learned.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = learned.last.replace(at)?;
+        if learned.intervals.len() == WINDOW {
+            learned.intervals.pop_front();
+        }
+        learned
+            .intervals
+            .push_back(at.saturating_duration_since(previous));
+        if learned.intervals.len() < MIN_SAMPLES {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = learned.intervals.iter().copied().collect();
+        sorted.sort_unstable();
+        // Nearest rank, as `ping-guard analyze` reports it.
+        let rank = (0.99 * sorted.len() as f64).ceil() as usize;
+        let p99 = sorted[rank.clamp(1, sorted.len()) - 1];
+        let timeout = p99.mul_f64(self.multiplier).clamp(self.min, self.max);
+        let moved = learned.timeout.is_none_or(|current| {
+            timeout.abs_diff(current).as_secs_f64() >= current.as_secs_f64() * 0.1
+        });
+        if !moved {
+            return None;
+        }
+        learned.timeout = Some(timeout);
+        Some((timeout, p99))
+    }
+
+    /// Forgets the last heartbeat, so the time a child was restarted or paused for is not taken
+    /// for an interval. What was learned so far still applies.
+    pub fn restart(&self) {
+        self.learned.lock().unwrap_or_else(|e| e.into_inner()).last = None;
+    }
+
+    /// The learned timeout, or `fallback` until enough intervals have been observed.
+    pub fn timeout(&self, fallback: Duration) -> Duration {
+        self.learned
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .timeout
+            .unwrap_or(fallback)
+    }
+}
@@ -0,0 +1,212 @@
+use clap::Args;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Gaps above this fraction of the configured timeout are reported as near-timeouts.
+pub const NEAR_TIMEOUT_FRACTION: f64 = 0.8;
+
+/// Upper bounds (exclusive, in milliseconds) of the gap histogram buckets.
+const HISTOGRAM_BOUNDS_MS: [u64; 10] = [
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000, 60_000,
+];
+
+#[derive(Args, Debug)]
+pub struct AnalyzeArgs {
+    #[arg(value_name = "AUDIT_LOG")]
+    audit_log: PathBuf,
+
+    #[arg(short, long, alias = "timeout-secs", value_name = "DURATION", value_parser = crate::parse_timeout)]
+    timeout: Option<Duration>,
+
+    #[arg(long)]
+    json: bool,
+}
+
+/// A heartbeat gap observed in the log, in milliseconds, ending at `end_ms`.
+struct Gap {
+    ms: u64,
+    end_ms: u64,
+    timeout_ms: Option<u64>,
+}
+
+/// Runs `ping-guard analyze` and returns the process exit code.
+pub fn run(args: AnalyzeArgs) -> i32 {
+    let file = match std::fs::File::open(&args.audit_log) {
+        Ok(file) => file,
+        Err(e) => {
+            error!(
+                "Failed to open audit log {}: {}",
+                args.audit_log.display(),
+                e
+            );
+            return 1;
+        }
+    };
+
+    let mut gaps: Vec<Gap> = Vec::new();
+    let mut timeouts = 0u64;
+    let mut sessions = 0u64;
+    let mut skipped = 0u64;
+    // State of the watchdog session currently being replayed.
+    let mut last_beat_ms: Option<u64> = None;
+    let mut session_timeout_ms: Option<u64> = None;
+
+    for line in BufReader::new(file).lines() {
+        let Ok(line) = line else {
+            skipped += 1;
+            continue;
+        };
+        let Ok(record) = serde_json::from_str::<Value>(&line) else {
+            skipped += 1;
+            continue;
+        };
+        let (Some(event), Some(ts_ms)) = (record["event"].as_str(), record["ts_ms"].as_u64())
+        else {
+            skipped += 1;
+            continue;
+        };
+        match event {
+            "start" => {
+                sessions += 1;
+                // The first gap of a session is measured from the watchdog's start, unless a
+                // startup timeout covered it: then it is boot time, not a heartbeat interval.
+                last_beat_ms = record["startup_timeout_ms"].is_null().then_some(ts_ms);
+                session_timeout_ms = record["timeout_ms"].as_u64();
+            }
+            "heartbeat" => {
+                if let Some(previous) = last_beat_ms {
+                    gaps.push(Gap {
+                        ms: ts_ms.saturating_sub(previous),
+                        end_ms: ts_ms,
+                        timeout_ms: session_timeout_ms,
+                    });
+                }
+                last_beat_ms = Some(ts_ms);
+            }
+            // Learned by --adaptive-timeout; it applies to the gaps that follow.
+            "adaptive_timeout" => session_timeout_ms = record["timeout_ms"].as_u64(),
+            "timeout" => {
+                timeouts += 1;
+                last_beat_ms = None;
+            }
+            _ => {}
+        }
+    }
+
+    if gaps.is_empty() {
+        error!(
+            "No heartbeat intervals found in {} (record heartbeats with --audit-log).",
+            args.audit_log.display()
+        );
+        return 1;
+    }
+
+    let mut sorted: Vec<u64> = gaps.iter().map(|gap| gap.ms).collect();
+    sorted.sort_unstable();
+    let percentile = |p: f64| -> u64 {
+        let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    };
+    let max_ms = *sorted.last().unwrap_or(&0);
+
+    let mut histogram = vec![0u64; HISTOGRAM_BOUNDS_MS.len() + 1];
+    for gap in &sorted {
+        let bucket = HISTOGRAM_BOUNDS_MS
+            .iter()
+            .position(|bound| gap < bound)
+            .unwrap_or(HISTOGRAM_BOUNDS_MS.len());
+        histogram[bucket] += 1;
+    }
+
+    let override_ms = args.timeout.map(|timeout| timeout.as_millis() as u64);
+    let near_timeouts: Vec<&Gap> = gaps
+        .iter()
+        .filter(|gap| match override_ms.or(gap.timeout_ms) {
+            Some(timeout_ms) => gap.ms as f64 >= timeout_ms as f64 * NEAR_TIMEOUT_FRACTION,
+            None => false,
+        })
+        .collect();
+
+    // Leave headroom over both the tail of the distribution and the worst gap seen.
+    let suggested_ms = (percentile(99.0) * 3).max(max_ms * 3 / 2).max(1000);
+    let suggested_secs = suggested_ms.div_ceil(1000);
+
+    if args.json {
+        let report = json!({
+            "sessions": sessions,
+            "intervals": sorted.len(),
+            "skipped_lines": skipped,
+            "timeouts": timeouts,
+            "percentiles_ms": {
+                "p50": percentile(50.0),
+                "p90": percentile(90.0),
+                "p99": percentile(99.0),
+                "p99.9": percentile(99.9),
+                "max": max_ms,
+            },
+            "histogram": histogram.iter().enumerate().map(|(i, count)| json!({
+                "lt_ms": HISTOGRAM_BOUNDS_MS.get(i),
+                "count": count,
+            })).collect::<Vec<_>>(),
+            "near_timeouts": near_timeouts.iter().map(|gap| json!({
+                "gap_ms": gap.ms,
+                "ts_ms": gap.end_ms,
+            })).collect::<Vec<_>>(),
+            "suggested_timeout_secs": suggested_secs,
+        });
+        println!("{}", report);
+        return 0;
+    }
+
+    println!("Audit log: {}", args.audit_log.display());
+    println!(
+        "Sessions: {}, heartbeat intervals: {}, timeouts: {}, unreadable lines: {}",
+        sessions,
+        sorted.len(),
+        timeouts,
+        skipped
+    );
+    println!();
+    println!("Interval percentiles:");
+    for (label, p) in [("p50", 50.0), ("p90", 90.0), ("p99", 99.0), ("p99.9", 99.9)] {
+        println!("  {:<6} {:>8} ms", label, percentile(p));
+    }
+    println!("  {:<6} {:>8} ms", "max", max_ms);
+    println!();
+    println!("Gap histogram:");
+    let widest = histogram.iter().copied().max().unwrap_or(1).max(1);
+    for (i, count) in histogram.iter().enumerate() {
+        let label = match HISTOGRAM_BOUNDS_MS.get(i) {
+            Some(bound) => format!("< {} ms", bound),
+            None => format!(
+                ">= {} ms",
+                HISTOGRAM_BOUNDS_MS[HISTOGRAM_BOUNDS_MS.len() - 1]
+            ),
+        };
+        let bar = "#".repeat(((count * 40).div_ceil(widest)) as usize);
+        println!(
+            "{}",
+            format!("  {:>12} {:>8} {}", label, count, bar).trim_end()
+        );
+    }
+    println!();
+    println!(
+        "Near-timeout gaps (>= {:.0}% of the timeout): {}",
+        NEAR_TIMEOUT_FRACTION * 100.0,
+        near_timeouts.len()
+    );
+    for gap in near_timeouts.iter().take(10) {
+        println!("  {} ms gap ending at ts_ms {}", gap.ms, gap.end_ms);
+    }
+    if near_timeouts.len() > 10 {
+        println!("  ... and {} more", near_timeouts.len() - 10);
+    }
+    println!();
+    println!(
+        "Suggested timeout: {} seconds (the larger of 3x p99 and 1.5x the largest gap).",
+        suggested_secs
+    );
+    0
+}
@@ -0,0 +1,76 @@
+use crate::child;
+use crate::procinfo::{self, ProcessIdentity};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// How often an attached process is checked for having exited; it is not the watchdog's
+/// child, so it cannot be waited for.
+const EXIT_POLL: Duration = Duration::from_millis(100);
+
+/// An already running process supervised in place of a spawned child (`--attach-pid`).
+#[derive(Debug)]
+pub struct Attached {
+    pub identity: ProcessIdentity,
+    /// Whether the process leads its own process group, which is then signalled as a whole,
+    /// like a spawned child's.
+    pub group: bool,
+}
+
+impl Attached {
+    /// Looks up the process with `pid`, which the watchdog must be allowed to signal.
+    pub fn new(pid: u32) -> Result<Self, String> {
+        if pid == std::process::id() {
+            return Err("it is the watchdog itself".to_string());
+        }
+        let pid_t = libc::pid_t::try_from(pid).map_err(|_| "no such process".to_string())?;
+        // Safety: signal 0 only checks that the process exists and may be signalled.
+        if unsafe { libc::kill(pid_t, 0) } == -1 {
+            let e = std::io::Error::last_os_error();
+            return Err(match e.raw_os_error() {
+                Some(libc::ESRCH) => "no such process".to_string(),
+                Some(libc::EPERM) => "not permitted to signal it".to_string(),
+                _ => e.to_string(),
+            });
+        }
+        let identity = ProcessIdentity::capture(pid);
+        if !identity.is_running() {
+            return Err("it has already exited".to_string());
+        }
+        // Safety: getpgid has no memory-safety preconditions.
+        let group = unsafe { libc::getpgid(pid_t) } == pid_t;
+        Ok(Attached { identity, group })
+    }
+
+    /// Waits for the process to exit. Its exit status is only known to its parent.
+    pub async fn wait(&self) -> child::Exit {
+        while self.identity.is_running() {
+            sleep(EXIT_POLL).await;
+        }
+        child::Exit {
+            message: format!(
+                "Attached process {} exited on its own; its exit status is unknown",
+                self.identity.pid
+            ),
+            code: None,
+            signal: None,
+        }
+    }
+
+    /// Sends `signal` to the process's group, if it leads one, or else to the process itself.
+    pub fn signal(&self, signal: libc::c_int) -> std::io::Result<()> {
+        if self.group {
+            procinfo::signal_process_group(&self.identity, signal)
+        } else {
+            procinfo::signal_process(&self.identity, signal)
+        }
+    }
+
+    /// "process group 123" or "process 123", as the signals reach it.
+    pub fn target(&self) -> String {
+        format!(
+            "process{} {}",
+            if self.group { " group" } else { "" },
+            self.identity.pid
+        )
+    }
+}
@@ -0,0 +1,38 @@
+use serde_json::{json, Value};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static AUDIT_LOG: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Opens (appending to) the audit log. Records are dropped silently until this is called.
+pub fn open(path: &Path) -> std::io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let _ = AUDIT_LOG.set(Mutex::new(file));
+    Ok(())
+}
+
+/// Milliseconds since the Unix epoch, as recorded in the `ts_ms` field.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Appends one JSON-lines record for `event`, merging in the given fields.
+pub fn record(event: &str, fields: Value) {
+    let Some(log) = AUDIT_LOG.get() else {
+        return;
+    };
+    let mut entry = json!({ "ts_ms": now_ms(), "event": event });
+    if let (Some(entry), Value::Object(fields)) = (entry.as_object_mut(), fields) {
+        entry.extend(fields);
+    }
+    if let Ok(mut file) = log.lock() {
+        // One write per record keeps lines intact even if several instances share a file.
+        let _ = file.write_all(format!("{}\n", entry).as_bytes());
+    }
+}
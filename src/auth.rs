@@ -0,0 +1,171 @@
+use crate::keyfile::KeyFile;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Shortest key accepted from a key file.
+const MIN_KEY_LEN: usize = 16;
+
+/// Clock skew allowed without `--hmac-max-skew`.
+pub const DEFAULT_MAX_SKEW: Duration = Duration::from_secs(30);
+
+/// Longest nonce accepted in a heartbeat.
+const MAX_NONCE_LEN: usize = 64;
+
+/// Why a heartbeat was rejected.
+#[derive(Debug)]
+pub enum Rejection {
+    /// Unsigned, malformed or wrongly signed.
+    Invalid(String),
+    /// Correctly signed, but sent before the allowed skew window or already seen: a captured
+    /// heartbeat being sent again.
+    Replay(String),
+}
+
+/// Checks heartbeats of the form `<unix-ms> <nonce> <hmac>\n<payload>`, where `<hmac>` is the
+/// hex HMAC-SHA256 of `<unix-ms> <nonce>\n<payload>` under the shared key. The timestamp must
+/// be within the allowed skew of this host's clock, and each nonce is only accepted once while
+/// its timestamp is within that window, so captured packets cannot be replayed.
+#[derive(Debug)]
+pub struct Authenticator {
+    keys: Arc<KeyFile<Vec<u8>>>,
+    max_skew: Duration,
+    /// Nonces seen recently, with the timestamp they were sent at.
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl Authenticator {
+    /// Reads the key from `path` (see [`read_key`]). After the file changes, the previous key
+    /// is still accepted for `overlap`.
+    pub fn load(path: &Path, max_skew: Duration, overlap: Duration) -> Result<Self, String> {
+        Ok(Authenticator {
+            keys: KeyFile::load(path, "HMAC", overlap, <[u8]>::to_vec)?,
+            max_skew,
+            seen: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The key file, to watch for rotation.
+    pub fn key_file(&self) -> Arc<KeyFile<Vec<u8>>> {
+        self.keys.clone()
+    }
+
+    /// Verifies a heartbeat that arrived at `arrived_ms` (Unix milliseconds) and returns the
+    /// time it was signed at and its payload, or why it was rejected.
+    pub fn verify<'a>(
+        &self,
+        data: &'a [u8],
+        arrived_ms: u64,
+    ) -> Result<(u64, &'a [u8]), Rejection> {
+        let invalid = |reason: &str| Rejection::Invalid(reason.to_string());
+        let (header, payload) = match data.iter().position(|&b| b == b'\n') {
+            Some(end) => (&data[..end], &data[end + 1..]),
+            None => (data, &data[data.len()..]),
+        };
+        let header = std::str::from_utf8(header)
+            .map_err(|_| invalid("no authentication header"))?
+            .trim_end_matches('\r');
+        let mut fields = header.split(' ');
+        let (Some(timestamp), Some(nonce), Some(mac), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid("no authentication header"));
+        };
+        let sent_ms = timestamp
+            .parse::<u64>()
+            .map_err(|_| Rejection::Invalid(format!("invalid timestamp '{}'", timestamp)))?;
+        if nonce.is_empty()
+            || nonce.len() > MAX_NONCE_LEN
+            || !nonce
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            return Err(invalid("invalid nonce"));
+        }
+        let mac = decode_hex(mac).ok_or_else(|| invalid("invalid HMAC encoding"))?;
+
+        self.keys
+            .check(|key| {
+                let mut expected =
+                    Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+                expected.update(timestamp.as_bytes());
+                expected.update(b" ");
+                expected.update(nonce.as_bytes());
+                expected.update(b"\n");
+                expected.update(payload);
+                expected.verify_slice(&mac)
+            })
+            .map_err(|_| invalid("HMAC mismatch"))?;
+
+        // Only a correctly signed packet gets this far, so its timestamp can be trusted.
+        let skew_ms = self.max_skew.as_millis() as u64;
+        let seconds = |ms: u64| ms as f64 / 1000.0;
+        if arrived_ms.saturating_sub(sent_ms) > skew_ms {
+            // Signed too long ago: a captured heartbeat being sent again.
+            return Err(Rejection::Replay(format!(
+                "signed {:.1}s ago (limit: {:.1?})",
+                seconds(arrived_ms - sent_ms),
+                self.max_skew
+            )));
+        }
+        if sent_ms.saturating_sub(arrived_ms) > skew_ms {
+            return Err(Rejection::Invalid(format!(
+                "timestamp {:.1}s ahead of this host's clock (limit: {:.1?})",
+                seconds(sent_ms - arrived_ms),
+                self.max_skew
+            )));
+        }
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        // Nonces older than the window would be rejected for their timestamp anyway.
+        seen.retain(|_, sent| sent.abs_diff(arrived_ms) <= skew_ms);
+        if seen.insert(nonce.to_string(), sent_ms).is_some() {
+            return Err(Rejection::Replay(format!("reused nonce '{}'", nonce)));
+        }
+        Ok((sent_ms, payload))
+    }
+}
+
+/// Reads a shared key: the file's contents, without a trailing newline. Logs an error if
+/// other users can access the file; `what` names the key in that message.
+// Permissions are only checked on Unix.
+#[cfg_attr(not(unix), allow(unused_variables))]
+pub fn read_key(path: &Path, what: &str) -> Result<Vec<u8>, String> {
+    let mut key = std::fs::read(path).map_err(|e| e.to_string())?;
+    while key.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+        key.pop();
+    }
+    if key.len() < MIN_KEY_LEN {
+        return Err(format!(
+            "the key must be at least {} bytes long (got {})",
+            MIN_KEY_LEN,
+            key.len()
+        ));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            if metadata.permissions().mode() & 0o077 != 0 {
+                error!(
+                    "{} key file {} is accessible by other users; restrict it with chmod 600.",
+                    what,
+                    path.display()
+                );
+            }
+        }
+    }
+    Ok(key)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,100 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How long restarts of a child that keeps exiting are put off (`--restart-backoff`): `base`
+/// doubled with every restart in a row, up to `max`, and spread by up to `jitter` either way
+/// so that watchdogs restarting at the same time drift apart. The delay starts over from
+/// `base` once a restarted child has stayed up for `min_uptime`.
+#[derive(Debug)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    /// Share of the delay it may be shortened or lengthened by, from 0 to 1.
+    jitter: f64,
+    min_uptime: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Restarts since the child last stayed up for `min_uptime`.
+    attempt: u32,
+    /// When the child was last restarted.
+    up_since: Option<Instant>,
+    /// The delay before the last restart.
+    delay: Option<Duration>,
+    /// When the restart that is put off is due.
+    due: Option<Instant>,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration, jitter_percent: u8, min_uptime: Duration) -> Self {
+        Backoff {
+            base,
+            max,
+            jitter: f64::from(jitter_percent) / 100.0,
+            min_uptime,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Puts off the next restart. Returns the delay and when the restart is due.
+    pub fn schedule(&self) -> (Duration, Instant) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let doubled = self
+            .base
+            .saturating_mul(1u32.checked_shl(state.attempt).unwrap_or(u32::MAX))
+            .min(self.max);
+        let delay = doubled
+            .mul_f64(1.0 + self.jitter * (2.0 * random_fraction() - 1.0))
+            .min(self.max);
+        state.attempt = state.attempt.saturating_add(1);
+        state.delay = Some(delay);
+        let due = Instant::now() + delay;
+        state.due = Some(due);
+        (delay, due)
+    }
+
+    /// Takes note that the child was restarted, whether or not the delay had run out.
+    pub fn restarted(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.due = None;
+        state.up_since = Some(Instant::now());
+    }
+
+    /// Starts over from `base` if the child, which is heartbeating, has been up for
+    /// `min_uptime` since its last restart. Returns how many restarts in a row there had been,
+    /// if any.
+    pub fn settle(&self) -> Option<u32> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let stable = state
+            .up_since
+            .is_some_and(|up_since| up_since.elapsed() >= self.min_uptime);
+        if state.attempt == 0 || state.due.is_some() || !stable {
+            return None;
+        }
+        Some(std::mem::take(&mut *state).attempt)
+    }
+
+    /// The backoff as reported by a status query.
+    pub fn report(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        serde_json::json!({
+            "attempt": state.attempt,
+            "delay_ms": state.delay.map(|delay| delay.as_millis() as u64),
+            "restart_in_ms": state
+                .due
+                .map(|due| due.saturating_duration_since(Instant::now()).as_millis() as u64),
+        })
+    }
+}
+
+/// A number in `[0, 1)`, random enough to spread restarts: every `RandomState` is seeded
+/// differently, so hashing nothing with one gives a fresh value.
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,178 @@
+use std::ffi::CString;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// How long removing the cgroup waits for the kernel to let go of processes that were just
+/// killed.
+const REMOVE_GRACE: Duration = Duration::from_millis(500);
+
+/// The cgroup the child runs in (`--cgroup`), removed again when the watchdog exits.
+static CGROUP: OnceLock<Cgroup> = OnceLock::new();
+
+/// A cgroup v2 of the watchdog's own, holding the child and everything it starts, however far
+/// it strays from the child's process group.
+#[derive(Debug)]
+pub struct Cgroup {
+    pub path: PathBuf,
+}
+
+/// Creates a cgroup for the child named `name`, under `parent` or else under the watchdog's
+/// own cgroup, and makes it the active one.
+pub fn create(parent: Option<&Path>, name: &str) -> Result<&'static Cgroup, String> {
+    let parent = match parent {
+        Some(parent) => parent.to_path_buf(),
+        None => own_cgroup()?,
+    };
+    let path = parent.join(name);
+    std::fs::create_dir(&path).map_err(|e| format!("create {}: {}", path.display(), e))?;
+    let cgroup = Cgroup { path };
+    if !cgroup.file("cgroup.procs").exists() {
+        let _ = std::fs::remove_dir(&cgroup.path);
+        return Err(format!(
+            "{} is not on a cgroup v2 hierarchy",
+            parent.display()
+        ));
+    }
+    let _ = CGROUP.set(cgroup);
+    Ok(CGROUP.get().expect("just set"))
+}
+
+/// The active cgroup, if the child runs in one.
+pub fn active() -> Option<&'static Cgroup> {
+    CGROUP.get()
+}
+
+/// The watchdog's own cgroup: its path in the unified hierarchy (`0::` in /proc/self/cgroup)
+/// under wherever cgroup2 is mounted.
+fn own_cgroup() -> Result<PathBuf, String> {
+    let cgroups = std::fs::read_to_string("/proc/self/cgroup")
+        .map_err(|e| format!("read /proc/self/cgroup: {}", e))?;
+    let own = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or("the watchdog is not in a cgroup v2 hierarchy")?;
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")
+        .map_err(|e| format!("read /proc/self/mountinfo: {}", e))?;
+    // Fields: ID, parent ID, major:minor, root, mount point, ..., "-", type, source, options.
+    let mount = mountinfo
+        .lines()
+        .find_map(|line| {
+            let (fields, rest) = line.split_once(" - ")?;
+            (rest.split_whitespace().next()? == "cgroup2")
+                .then(|| fields.split_whitespace().nth(4))
+                .flatten()
+        })
+        .ok_or("cgroup2 is not mounted")?;
+    Ok(Path::new(mount).join(own.trim_start_matches('/')))
+}
+
+impl Cgroup {
+    fn file(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+
+    /// Writes `value` to one of the cgroup's interface files, which are never created.
+    fn write(&self, name: &str, value: &str) -> io::Result<()> {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(self.file(name))?
+            .write_all(value.as_bytes())
+    }
+
+    /// Registers a pre-exec hook that moves the child into the cgroup before it runs, so that
+    /// nothing it starts can escape it.
+    pub fn attach(&self, command: &mut Command) {
+        let procs = CString::new(self.file("cgroup.procs").as_os_str().as_bytes())
+            .expect("cgroup paths have no NUL bytes");
+        // Safety: open, write and close are async-signal-safe; the path was prepared before
+        // the fork.
+        unsafe {
+            command.pre_exec(move || {
+                let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                // "0" stands for the writing process itself.
+                let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+                let error = io::Error::last_os_error();
+                libc::close(fd);
+                if written != 1 {
+                    return Err(error);
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// PIDs of the processes in the cgroup.
+    pub fn members(&self) -> Vec<u32> {
+        std::fs::read_to_string(self.file("cgroup.procs"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.parse().ok())
+            .collect()
+    }
+
+    /// Whether any process is left in the cgroup, as `cgroup.events` reports it.
+    pub fn populated(&self) -> bool {
+        std::fs::read_to_string(self.file("cgroup.events"))
+            .map(|events| events.lines().any(|line| line == "populated 1"))
+            .unwrap_or(false)
+    }
+
+    /// Freezes every process in the cgroup, or thaws them, through `cgroup.freeze`.
+    pub fn freeze(&self, frozen: bool) -> io::Result<()> {
+        self.write("cgroup.freeze", if frozen { "1" } else { "0" })
+    }
+
+    /// Kills every process in the cgroup at once through `cgroup.kill`, or one by one on
+    /// kernels older than 5.14, which lack it.
+    pub fn kill(&self) -> io::Result<()> {
+        match self.write("cgroup.kill", "1") {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                for pid in self.members() {
+                    // Safety: kill has no memory-safety preconditions; a PID listed in the
+                    // cgroup belongs to it until it is reaped.
+                    unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+                }
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+/// Removes the cgroup, once it is empty; one that still holds processes (e.g. with
+/// `--observe-only`) is left in place.
+pub fn remove() {
+    let Some(cgroup) = CGROUP.get() else {
+        return;
+    };
+    let deadline = std::time::Instant::now() + REMOVE_GRACE;
+    loop {
+        match std::fs::remove_dir(&cgroup.path) {
+            Ok(()) => {
+                info!("Removed cgroup {}.", cgroup.path.display());
+                return;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(_) if cgroup.populated() => {
+                info!(
+                    "Leaving cgroup {} in place: processes are still running in it.",
+                    cgroup.path.display()
+                );
+                return;
+            }
+            // Just emptied: the kernel may still be letting go of it.
+            Err(e) if std::time::Instant::now() >= deadline => {
+                error!("Failed to remove cgroup {}: {}", cgroup.path.display(), e);
+                return;
+            }
+            Err(_) => std::thread::sleep(Duration::from_millis(20)),
+        }
+    }
+}
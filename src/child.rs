@@ -0,0 +1,399 @@
+use clap::ValueEnum;
+use tokio::process::Command;
+
+/// Linux scheduling policies that can be applied to the child before exec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SchedPolicy {
+    /// SCHED_OTHER, the default time-sharing policy
+    Other,
+    /// SCHED_BATCH, for CPU-bound non-interactive work
+    Batch,
+    /// SCHED_IDLE, runs only when nothing else wants the CPU
+    Idle,
+    /// SCHED_FIFO, realtime first-in first-out (needs --sched-priority)
+    Fifo,
+    /// SCHED_RR, realtime round-robin (needs --sched-priority)
+    Rr,
+}
+
+impl SchedPolicy {
+    fn is_realtime(self) -> bool {
+        matches!(self, SchedPolicy::Fifo | SchedPolicy::Rr)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn as_raw(self) -> libc::c_int {
+        match self {
+            SchedPolicy::Other => libc::SCHED_OTHER,
+            SchedPolicy::Batch => libc::SCHED_BATCH,
+            SchedPolicy::Idle => libc::SCHED_IDLE,
+            SchedPolicy::Fifo => libc::SCHED_FIFO,
+            SchedPolicy::Rr => libc::SCHED_RR,
+        }
+    }
+}
+
+/// Checks that a scheduling policy/priority combination is usable on this platform.
+pub fn validate_sched(policy: Option<SchedPolicy>, priority: Option<i32>) -> Result<(), String> {
+    let Some(policy) = policy else {
+        return match priority {
+            Some(_) => Err("--sched-priority requires --sched-policy fifo or rr.".to_string()),
+            None => Ok(()),
+        };
+    };
+    if !cfg!(target_os = "linux") {
+        return Err("--sched-policy is only supported on Linux.".to_string());
+    }
+    match (policy.is_realtime(), priority) {
+        (true, None) => Err("--sched-policy fifo and rr require --sched-priority.".to_string()),
+        (false, Some(_)) => {
+            Err("--sched-priority only applies to fifo and rr policies.".to_string())
+        }
+        #[cfg(target_os = "linux")]
+        (true, Some(priority)) => {
+            // Safety: plain queries of the kernel's priority range for a valid policy.
+            let (min, max) = unsafe {
+                (
+                    libc::sched_get_priority_min(policy.as_raw()),
+                    libc::sched_get_priority_max(policy.as_raw()),
+                )
+            };
+            if priority < min || priority > max {
+                Err(format!(
+                    "--sched-priority must be between {} and {} for this policy.",
+                    min, max
+                ))
+            } else {
+                Ok(())
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Registers a pre-exec hook that switches the child to the requested scheduling class.
+#[cfg(target_os = "linux")]
+pub fn apply_sched(command: &mut Command, policy: SchedPolicy, priority: Option<i32>) {
+    let raw_policy = policy.as_raw();
+    let param = libc::sched_param {
+        sched_priority: priority.unwrap_or(0),
+    };
+    // Safety: sched_setscheduler is async-signal-safe and only affects the forked child.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::sched_setscheduler(0, raw_policy, &param) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply_sched(_command: &mut Command, _policy: SchedPolicy, _priority: Option<i32>) {}
+
+/// Registers a pre-exec hook that sets the child's nice value (`--nice`), from -20 (most
+/// favourable) to 19. Lowering it below the watchdog's needs `CAP_SYS_NICE`.
+#[cfg(unix)]
+pub fn apply_nice(command: &mut Command, nice: i32) {
+    // Safety: setpriority is async-signal-safe and only affects the forked child.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setpriority(libc::PRIO_PROCESS, 0, nice) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Linux I/O scheduling classes, as `ionice` names them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+    Idle,
+}
+
+/// An I/O scheduling class and, but for `idle`, a level from 0 (highest) to 7 (`--ionice`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoPriority {
+    pub class: IoClass,
+    pub level: u8,
+}
+
+impl IoPriority {
+    /// Parses `CLASS[:LEVEL]`, e.g. `idle`, `best-effort` or `realtime:2`. The level defaults
+    /// to 4, as for `ionice`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (class, level) = match value.split_once(':') {
+            Some((class, level)) => (class, Some(level)),
+            None => (value, None),
+        };
+        let class = match class {
+            "realtime" => IoClass::Realtime,
+            "best-effort" => IoClass::BestEffort,
+            "idle" => IoClass::Idle,
+            _ => {
+                return Err(format!(
+                    "unknown I/O class '{}' (expected realtime, best-effort or idle)",
+                    class
+                ))
+            }
+        };
+        let level = match (class, level) {
+            (IoClass::Idle, Some(_)) => return Err("the idle class takes no level".to_string()),
+            (IoClass::Idle, None) => 0,
+            (_, None) => 4,
+            (_, Some(level)) => match level.parse() {
+                Ok(level) if level <= 7 => level,
+                _ => return Err(format!("'{}' is not a level from 0 to 7", level)),
+            },
+        };
+        Ok(IoPriority { class, level })
+    }
+
+    /// The value ioprio_set takes: the class in the top bits, the level in the bottom ones.
+    #[cfg(target_os = "linux")]
+    fn as_raw(self) -> libc::c_int {
+        let class = match self.class {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        };
+        (class << 13) | libc::c_int::from(self.level)
+    }
+}
+
+/// Registers a pre-exec hook that sets the child's I/O scheduling class (`--ionice`). The
+/// realtime class needs `CAP_SYS_ADMIN`.
+#[cfg(target_os = "linux")]
+pub fn apply_io_priority(command: &mut Command, priority: IoPriority) {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    let raw = priority.as_raw();
+    // Safety: ioprio_set is a plain system call that only affects the forked child.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, raw) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// The most CPUs a `--cpu-affinity` list may name, as many as a `cpu_set_t` holds.
+const MAX_CPUS: usize = 1024;
+
+/// The CPUs the child may run on (`--cpu-affinity`), sorted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuList(pub Vec<usize>);
+
+/// Parses a list of CPUs as `taskset -c` takes it, e.g. `0-3,6`.
+pub fn parse_cpu_list(value: &str) -> Result<CpuList, String> {
+    let mut cpus = Vec::new();
+    for range in value.split(',') {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let (Ok(first), Ok(last)) = (first.trim().parse::<usize>(), last.trim().parse::<usize>())
+        else {
+            return Err(format!("'{}' is not a CPU or a range of CPUs", range));
+        };
+        if first > last {
+            return Err(format!("'{}' is an empty range", range));
+        }
+        if last >= MAX_CPUS {
+            return Err(format!("CPUs are numbered below {}", MAX_CPUS));
+        }
+        cpus.extend(first..=last);
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+    Ok(CpuList(cpus))
+}
+
+/// Registers a pre-exec hook that restricts the child to `cpus` (`--cpu-affinity`), which its
+/// own children inherit.
+#[cfg(target_os = "linux")]
+pub fn apply_cpu_affinity(command: &mut Command, cpus: &CpuList) {
+    // Safety: all-zero is an empty cpu_set_t.
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in &cpus.0 {
+        // Safety: parse_cpu_list keeps CPUs below MAX_CPUS, which the set holds.
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    // Safety: sched_setaffinity is async-signal-safe and only affects the forked child.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Linux namespaces the child can be given of its own (`--unshare`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Namespace {
+    /// A mount namespace, so that what the child mounts is its own
+    Mount,
+    /// A network namespace with only a loopback interface
+    Net,
+    /// A PID namespace in which the child is PID 1
+    Pid,
+}
+
+impl Namespace {
+    #[cfg(target_os = "linux")]
+    pub fn as_raw(self) -> libc::c_int {
+        match self {
+            Namespace::Mount => libc::CLONE_NEWNS,
+            Namespace::Net => libc::CLONE_NEWNET,
+            Namespace::Pid => libc::CLONE_NEWPID,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Namespace::Mount => "mount",
+            Namespace::Net => "net",
+            Namespace::Pid => "pid",
+        }
+    }
+}
+
+/// Registers a pre-exec hook that has the kernel send `signal` to the child when the watchdog
+/// dies (`--parent-death-signal`), even of `SIGKILL`. A watchdog that is already gone by the
+/// time the hook runs fails the spawn instead, since the signal would never come. The kernel
+/// sends it once the thread that spawned the child exits; the watchdog spawns from threads
+/// that live as long as it does.
+#[cfg(target_os = "linux")]
+pub fn set_parent_death_signal(command: &mut Command, signal: crate::signals::Signal) {
+    let watchdog = std::process::id() as libc::pid_t;
+    let number = signal.number as libc::c_ulong;
+    // Safety: prctl and getppid are async-signal-safe and only affect the forked child.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::prctl(libc::PR_SET_PDEATHSIG, number) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if libc::getppid() != watchdog {
+                return Err(std::io::Error::other(
+                    "the watchdog exited while the child was being started",
+                ));
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Builds a command that runs `command_line` through the platform shell (`/bin/sh -c`, or
+/// `cmd /C` on Windows), as used for probe and notification commands.
+pub fn shell(command_line: &str) -> Command {
+    #[cfg(unix)]
+    let mut command = Command::new("/bin/sh");
+    #[cfg(unix)]
+    command.arg("-c");
+    #[cfg(windows)]
+    let mut command = Command::new("cmd");
+    #[cfg(windows)]
+    command.arg("/C");
+    command.arg(command_line);
+    command
+}
+
+/// When a child that exited on its own is started again (`--restart`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RestartPolicy {
+    /// Exit the watchdog along with the child.
+    Never,
+    /// Restart a child that exited with a non-zero code or was killed by a signal.
+    OnFailure,
+    /// Restart the child however it exited.
+    Always,
+}
+
+impl RestartPolicy {
+    /// Whether a child that ended as `exit` says is restarted, with its status classified by
+    /// `codes`.
+    pub fn restarts(self, exit: &Exit, codes: &ExitCodes) -> bool {
+        match self {
+            _ if codes.no_restart(exit) => false,
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !codes.succeeded(exit),
+            RestartPolicy::Always => true,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RestartPolicy::Never => "never",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::Always => "always",
+        }
+    }
+}
+
+/// In which order a running child process and its replacement are stopped and started on a
+/// restart (`--restart-strategy`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RestartStrategy {
+    /// Terminate the child, then start the replacement.
+    StopFirst,
+    /// Start the replacement, and terminate the child once the replacement heartbeats.
+    StartFirst,
+}
+
+/// How a child that exited on its own ended.
+#[derive(Debug, Clone)]
+pub struct Exit {
+    /// What happened, e.g. "Child process exited on its own with status: exit status: 3".
+    pub message: String,
+    /// The exit code, or `None` if the child was killed by a signal.
+    pub code: Option<i64>,
+    /// The signal that killed the child, on Unix.
+    pub signal: Option<i32>,
+}
+
+impl Exit {
+    /// The status the watchdog exits with in the child's place: its exit code or, if a signal
+    /// killed it, 128 plus the signal's number, as a shell reports it.
+    pub fn status(&self) -> i32 {
+        match (self.code, self.signal) {
+            (Some(code), _) => code as i32,
+            (None, Some(signal)) => 128 + signal,
+            (None, None) => 1,
+        }
+    }
+}
+
+/// How the child's exit statuses are classified (`--success-codes`, `--no-restart-codes`).
+/// A status is a child's exit code or, if a signal killed it, 128 plus the signal's number.
+#[derive(Debug, Clone)]
+pub struct ExitCodes {
+    /// Statuses that count as success: not restarted by `--restart on-failure`, and the
+    /// watchdog exits with 0.
+    pub success: Vec<i32>,
+    /// Statuses after which the child is never restarted.
+    pub no_restart: Vec<i32>,
+}
+
+impl ExitCodes {
+    pub fn succeeded(&self, exit: &Exit) -> bool {
+        self.success.contains(&exit.status())
+    }
+
+    pub fn no_restart(&self, exit: &Exit) -> bool {
+        self.no_restart.contains(&exit.status())
+    }
+
+    /// The status the watchdog exits with after the child's `exit`.
+    pub fn watchdog_status(&self, exit: &Exit) -> i32 {
+        if self.succeeded(exit) {
+            0
+        } else {
+            exit.status()
+        }
+    }
+}
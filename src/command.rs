@@ -0,0 +1,120 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A control command sent in place of a heartbeat (`--allow-commands`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// No timeout before this much time from now, whatever the heartbeats say.
+    Extend(Duration),
+    /// Stop enforcing the timeout until `RESUME`.
+    Pause,
+    /// Enforce the timeout again, starting over now.
+    Resume,
+    /// Replace the normal timeout from now on.
+    Timeout(Duration),
+    /// Restart the child.
+    Restart,
+    /// Terminate the child and exit the watchdog.
+    Kill,
+}
+
+impl Command {
+    /// Parses the contents of a packet: `None` if it is not a command (so it may be a
+    /// heartbeat), an error if it names a command but is malformed. Surrounding whitespace is
+    /// ignored.
+    pub fn parse(data: &[u8]) -> Option<Result<Command, String>> {
+        let text = std::str::from_utf8(data).ok()?.trim();
+        let (word, argument) = match text.split_once(char::is_whitespace) {
+            Some((word, argument)) => (word, Some(argument.trim())),
+            None => (text, None),
+        };
+        let command = match (word, argument) {
+            ("EXTEND", Some(seconds)) => match seconds.parse::<u64>() {
+                Ok(seconds) if seconds > 0 => Command::Extend(Duration::from_secs(seconds)),
+                _ => return Some(Err(format!("invalid number of seconds '{}'", seconds))),
+            },
+            ("EXTEND", None) => return Some(Err("EXTEND needs a number of seconds".to_string())),
+            ("TIMEOUT", Some(value)) => match crate::parse_timeout(value) {
+                Ok(timeout) if !timeout.is_zero() => Command::Timeout(timeout),
+                _ => return Some(Err(format!("invalid timeout '{}'", value))),
+            },
+            ("TIMEOUT", None) => return Some(Err("TIMEOUT needs a duration".to_string())),
+            ("PAUSE", None) => Command::Pause,
+            ("RESUME", None) => Command::Resume,
+            ("RESTART", None) => Command::Restart,
+            ("KILL", None) => Command::Kill,
+            ("PAUSE" | "RESUME" | "RESTART" | "KILL", Some(_)) => {
+                return Some(Err(format!("{} takes no argument", word)))
+            }
+            _ => return None,
+        };
+        Some(Ok(command))
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Extend(by) => write!(f, "EXTEND {}", by.as_secs()),
+            Command::Timeout(timeout) => {
+                write!(f, "TIMEOUT {}", humantime::format_duration(*timeout))
+            }
+            Command::Pause => f.write_str("PAUSE"),
+            Command::Resume => f.write_str("RESUME"),
+            Command::Restart => f.write_str("RESTART"),
+            Command::Kill => f.write_str("KILL"),
+        }
+    }
+}
+
+/// How commands have changed the timeout: set by the monitor as it carries them out, and read
+/// by whoever reports the time left.
+#[derive(Debug, Default)]
+pub struct Control {
+    paused: AtomicBool,
+    extended_until: Mutex<Option<Instant>>,
+    /// The normal timeout set by `TIMEOUT`, replacing the configured one.
+    timeout: Mutex<Option<Duration>>,
+}
+
+impl Control {
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// How long an `EXTEND` still holds off the timeout.
+    pub fn extension_left(&self) -> Duration {
+        self.extended_until
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .map_or(Duration::ZERO, |until| {
+                until.saturating_duration_since(Instant::now())
+            })
+    }
+
+    /// The normal timeout: the last `TIMEOUT`, or `configured` if none was sent.
+    pub fn timeout(&self, configured: Duration) -> Duration {
+        self.timeout
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .unwrap_or(configured)
+    }
+
+    pub fn set_timeout(&self, timeout: Duration) {
+        *self.timeout.lock().unwrap_or_else(|e| e.into_inner()) = Some(timeout);
+    }
+
+    pub fn extend(&self, by: Duration) {
+        *self
+            .extended_until
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(Instant::now() + by);
+    }
+}
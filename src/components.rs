@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Parses a `--require-component NAME[=DURATION]` entry. Without a duration, the component
+/// gets the monitor's timeout.
+pub fn parse_requirement(value: &str) -> Result<(String, Option<Duration>), String> {
+    let (name, duration) = match value.split_once('=') {
+        Some((name, duration)) => (name.trim(), Some(duration.trim())),
+        None => (value.trim(), None),
+    };
+    if name.is_empty() {
+        return Err(format!("'{}' must be NAME or NAME=DURATION", value));
+    }
+    let timeout = duration
+        .map(|duration| {
+            let timeout = humantime::parse_duration(duration)
+                .map_err(|e| format!("invalid duration '{}': {}", duration, e))?;
+            if timeout.is_zero() {
+                return Err(format!("timeout for '{}' must be greater than 0", name));
+            }
+            Ok(timeout)
+        })
+        .transpose()?;
+    Ok((name.to_string(), timeout))
+}
+
+#[derive(Debug)]
+struct Clock {
+    last: Instant,
+    timeout: Duration,
+}
+
+/// Per-component heartbeat clocks (`--require-component`), every one of which must be within
+/// its timeout.
+///
+/// Like a liveness policy under `all`, the components are combined into the heartbeat time
+/// that puts the monitor's deadline at the earliest component deadline, so one silent
+/// component is enough for the timeout to run out however often the others beat.
+#[derive(Debug)]
+pub struct Components {
+    base: Duration,
+    clocks: Mutex<HashMap<String, Clock>>,
+}
+
+impl Components {
+    /// `base` is the monitor's timeout, which also applies to components without their own.
+    /// Every component's clock starts now, like the monitor's.
+    pub fn new(base: Duration, required: &[(String, Option<Duration>)]) -> Self {
+        let now = Instant::now();
+        let clocks = required
+            .iter()
+            .map(|(name, timeout)| {
+                let clock = Clock {
+                    last: now,
+                    timeout: timeout.unwrap_or(base),
+                };
+                (name.clone(), clock)
+            })
+            .collect();
+        Components {
+            base,
+            clocks: Mutex::new(clocks),
+        }
+    }
+
+    /// The required components with their timeouts, sorted by name.
+    pub fn timeouts(&self) -> Vec<(String, Duration)> {
+        let clocks = self.clocks.lock().unwrap_or_else(|e| e.into_inner());
+        let mut timeouts: Vec<_> = clocks
+            .iter()
+            .map(|(name, clock)| (name.clone(), clock.timeout))
+            .collect();
+        timeouts.sort();
+        timeouts
+    }
+
+    /// Records a heartbeat from `component` at `at`. Returns false if it is not required.
+    pub fn beat(&self, component: &str, at: Instant) -> bool {
+        let mut clocks = self.clocks.lock().unwrap_or_else(|e| e.into_inner());
+        match clocks.get_mut(component) {
+            Some(clock) => {
+                clock.last = clock.last.max(at);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The combined heartbeat time for the monitor.
+    pub fn current(&self) -> Instant {
+        let clocks = self.clocks.lock().unwrap_or_else(|e| e.into_inner());
+        clocks
+            .values()
+            .map(|clock| clock.last + clock.timeout)
+            .min()
+            .and_then(|deadline| deadline.checked_sub(self.base))
+            .unwrap_or_else(Instant::now)
+    }
+
+    /// Time since each component's last heartbeat, sorted by name.
+    pub fn ages(&self) -> Vec<(String, Duration)> {
+        let now = Instant::now();
+        let clocks = self.clocks.lock().unwrap_or_else(|e| e.into_inner());
+        let mut ages: Vec<_> = clocks
+            .iter()
+            .map(|(name, clock)| (name.clone(), now.saturating_duration_since(clock.last)))
+            .collect();
+        ages.sort();
+        ages
+    }
+
+    /// Describes the components that are past their timeout, as in "from component db for
+    /// ~2.10s (limit: 2.00s)". `limit` is the monitor's current timeout; a degraded child's
+    /// longer timeout extends every component's by the same amount.
+    pub fn describe_overdue(&self, limit: Duration) -> String {
+        let now = Instant::now();
+        let clocks = self.clocks.lock().unwrap_or_else(|e| e.into_inner());
+        let mut overdue: Vec<_> = clocks
+            .iter()
+            .map(|(name, clock)| {
+                let timeout = (clock.timeout + limit).saturating_sub(self.base);
+                (name.as_str(), now.duration_since(clock.last), timeout)
+            })
+            .filter(|(_, elapsed, timeout)| elapsed >= timeout)
+            .collect();
+        overdue.sort_by_key(|(name, _, _)| *name);
+        let components: Vec<String> = overdue
+            .into_iter()
+            .map(|(name, elapsed, timeout)| {
+                format!("{} for ~{:.2?} (limit: {:.2?})", name, elapsed, timeout)
+            })
+            .collect();
+        format!("from component {}", components.join(", "))
+    }
+}
@@ -0,0 +1,269 @@
+use crate::{events, liveness};
+use clap::error::ErrorKind;
+use clap::{ArgAction, Command, CommandFactory};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::Path;
+
+/// Sections whose `key = value` lines become `--<section> key=value` options.
+const MAPPED_SECTIONS: [&str; 2] = ["severity", "source-timeout"];
+
+/// Finds `--config PATH` (or `--config=PATH`) among the watchdog's own arguments.
+fn config_path(args: &[OsString]) -> Option<OsString> {
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return iter.next().cloned();
+        }
+        if let Some(path) = arg.to_str().and_then(|a| a.strip_prefix("--config=")) {
+            return Some(path.into());
+        }
+    }
+    None
+}
+
+/// Expands `--config` into the options it contains. The file's options are placed before the
+/// command line's, so anything given on the command line wins (repeatable options such as
+/// `--listen-addr` add to the file's values instead). Every problem in the file is reported
+/// at once, one `path:line: message` per line.
+pub fn expand_args<C: CommandFactory>(args: Vec<OsString>) -> Result<Vec<OsString>, String> {
+    let Some(path) = config_path(&args) else {
+        return Ok(args);
+    };
+    let path = Path::new(&path);
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+    let from_file = parse::<C>(&text).map_err(|problems| {
+        let mut report = format!(
+            "{} problem(s) in config file {}:",
+            problems.len(),
+            path.display()
+        );
+        for (line, problem) in problems {
+            report.push_str(&format!("\n  {}:{}: {}", path.display(), line, problem));
+        }
+        report
+    })?;
+    let mut expanded = Vec::with_capacity(args.len() + from_file.len());
+    expanded.extend(args.first().cloned());
+    expanded.extend(from_file.into_iter().map(OsString::from));
+    expanded.extend(args.into_iter().skip(1));
+    Ok(expanded)
+}
+
+/// Converts config file text into command-line options for `C`, or every problem found, each
+/// with its 1-based line number. Values are checked with the same parsers the command line
+/// uses, so an invalid duration or number is reported here rather than at startup.
+fn parse<C: CommandFactory>(text: &str) -> Result<Vec<String>, Vec<(usize, String)>> {
+    let command = C::command();
+    let mut args = Vec::new();
+    let mut problems = Vec::new();
+    // Where each option (or section entry) was first set, to catch contradicting repeats.
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    // Options this file actually turns on, for the conflict check.
+    let mut present: Vec<(String, usize)> = Vec::new();
+    let mut section: Option<&str> = None;
+    for (index, raw) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let name = name.trim();
+            if MAPPED_SECTIONS.contains(&name) {
+                section = Some(name);
+            } else {
+                problems.push((line_no, format!("unknown section [{}]", name)));
+                // Skip the unknown section's entries rather than reporting each of them.
+                section = Some("");
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            problems.push((line_no, format!("expected 'key = value', found '{}'", line)));
+            continue;
+        };
+        let key = key.trim();
+        let value = unquote(value.trim());
+        if let Some(section) = section {
+            if section.is_empty() {
+                continue;
+            }
+            let route = format!("{}={}", key, value);
+            let checked = match section {
+                "severity" => events::parse_route(&route).map(drop),
+                "source-timeout" => liveness::parse_source_timeout(&route).map(drop),
+                _ => Ok(()),
+            };
+            if let Err(e) = checked {
+                problems.push((line_no, e));
+                continue;
+            }
+            if let Some(first) = seen.insert(format!("[{}] {}", section, key), line_no) {
+                problems.push((
+                    line_no,
+                    format!(
+                        "'{}' is already set in [{}] on line {}",
+                        key, section, first
+                    ),
+                ));
+                continue;
+            }
+            args.push(format!("--{}", section));
+            args.push(route);
+            continue;
+        }
+
+        let Some(arg) = command.get_arguments().find(|arg| {
+            arg.get_long() == Some(key)
+                || arg
+                    .get_all_aliases()
+                    .is_some_and(|aliases| aliases.contains(&key))
+        }) else {
+            let suggestion = command
+                .get_arguments()
+                .filter_map(|arg| arg.get_long())
+                .find(|long| long.starts_with(key) || key.starts_with(long))
+                .map(|long| format!(" (did you mean '{}'?)", long))
+                .unwrap_or_default();
+            problems.push((line_no, format!("unknown option '{}'{}", key, suggestion)));
+            continue;
+        };
+        // Old names (such as 'timeout-secs') count as the option they stand for.
+        let key = arg.get_long().unwrap_or(key);
+        if key == "config" {
+            problems.push((
+                line_no,
+                "config files cannot include other config files".to_string(),
+            ));
+            continue;
+        }
+        let repeatable = matches!(arg.get_action(), ArgAction::Append);
+        match seen.get(key) {
+            Some(first) if !repeatable => {
+                problems.push((
+                    line_no,
+                    format!("'{}' is already set on line {}", key, first),
+                ));
+                continue;
+            }
+            Some(_) => {}
+            None => {
+                seen.insert(key.to_string(), line_no);
+            }
+        }
+        if arg.get_action().takes_values() {
+            if let Some(problem) = check_value(&command, key, value) {
+                problems.push((line_no, problem));
+                continue;
+            }
+            args.push(format!("--{}", key));
+            args.push(value.to_string());
+            present.push((key.to_string(), line_no));
+        } else {
+            match value {
+                "true" => {
+                    args.push(format!("--{}", key));
+                    present.push((key.to_string(), line_no));
+                }
+                "false" => {}
+                _ => problems.push((
+                    line_no,
+                    format!(
+                        "'{}' is a switch; use 'true' or 'false', not '{}'",
+                        key, value
+                    ),
+                )),
+            }
+        }
+    }
+
+    // Options that cannot be combined, both set in this file.
+    for (i, (key, line)) in present.iter().enumerate() {
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key.as_str()))
+        else {
+            continue;
+        };
+        let conflicts = command.get_arg_conflicts_with(arg);
+        for (other, other_line) in &present[..i] {
+            if other != key
+                && conflicts
+                    .iter()
+                    .any(|c| c.get_long() == Some(other.as_str()))
+            {
+                problems.push((
+                    *line,
+                    format!(
+                        "'{}' cannot be combined with '{}' (line {})",
+                        key, other, other_line
+                    ),
+                ));
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(args)
+    } else {
+        problems.sort_by_key(|(line, _)| *line);
+        Err(problems)
+    }
+}
+
+/// Runs `value` through the option's command-line parser and describes why it was rejected,
+/// if it was. Other errors (such as options the command line is expected to supply) are
+/// ignored here.
+fn check_value(command: &Command, key: &str, value: &str) -> Option<String> {
+    let error = command
+        .clone()
+        .try_get_matches_from(["ping-guard".to_string(), format!("--{}={}", key, value)])
+        .err()?;
+    if !matches!(
+        error.kind(),
+        ErrorKind::InvalidValue | ErrorKind::ValueValidation | ErrorKind::InvalidUtf8
+    ) {
+        return None;
+    }
+    // Condense clap's report to one line: the message, the parser's own detail when the
+    // message ends in a colon (e.g. a regex syntax error), and any list of possible values.
+    let rendered = error.to_string();
+    let paragraph: Vec<&str> = rendered
+        .lines()
+        .take_while(|line| !line.trim().is_empty())
+        .collect();
+    let first = paragraph.first().copied().unwrap_or_default();
+    let mut message = first.strip_prefix("error: ").unwrap_or(first).to_string();
+    if message.ends_with(':') {
+        if let Some(detail) = paragraph.last().filter(|_| paragraph.len() > 1) {
+            let detail = detail.trim();
+            message.push(' ');
+            message.push_str(detail.strip_prefix("error: ").unwrap_or(detail));
+        }
+    }
+    for line in &paragraph[1.min(paragraph.len())..] {
+        if line.trim_start().starts_with("[possible values:") {
+            message.push(' ');
+            message.push_str(line.trim());
+        }
+    }
+    Some(message)
+}
+
+/// Strips one pair of matching surrounding quotes, so values may keep leading spaces or `#`.
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
@@ -0,0 +1,44 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Notices a child that keeps being restarted (`--max-restarts` within `--restart-window`),
+/// so that a child that can never come up is not restarted forever.
+#[derive(Debug)]
+pub struct CrashLoop {
+    pub max: u32,
+    pub window: Duration,
+    /// Hook run before the watchdog gives up (`--crash-loop-exec`).
+    pub exec: Option<String>,
+    /// When the restarts within the window were made.
+    restarts: VecDeque<Instant>,
+}
+
+impl CrashLoop {
+    pub fn new(max: u32, window: Duration, exec: Option<String>) -> Self {
+        CrashLoop {
+            max,
+            window,
+            exec,
+            restarts: VecDeque::new(),
+        }
+    }
+
+    /// Takes note of a restart about to be made. Returns `false`, without taking note, if
+    /// there already were `max` restarts within the window.
+    pub fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        while self
+            .restarts
+            .front()
+            .is_some_and(|&at| now.duration_since(at) >= self.window)
+        {
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= self.max as usize {
+            return false;
+        }
+        self.restarts.push_back(now);
+        true
+    }
+}
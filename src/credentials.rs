@@ -0,0 +1,213 @@
+use std::ffi::{CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use tokio::process::Command;
+
+/// getgrouplist takes and returns `int`s on macOS, `gid_t`s elsewhere.
+#[cfg(target_os = "macos")]
+type GroupListEntry = libc::c_int;
+#[cfg(not(target_os = "macos"))]
+type GroupListEntry = libc::gid_t;
+
+/// The user and group the child runs as (`--user`, `--group`), resolved before it is spawned,
+/// since the user and group databases cannot be read between fork and exec.
+#[derive(Debug)]
+pub struct Credentials {
+    user: Option<User>,
+    gid: libc::gid_t,
+    /// Supplementary groups: the user's, or just `gid` without `--user`.
+    groups: Vec<libc::gid_t>,
+}
+
+#[derive(Debug)]
+struct User {
+    name: String,
+    uid: libc::uid_t,
+    home: PathBuf,
+}
+
+impl Credentials {
+    /// Looks up `user` and `group`, each a name or a numeric ID. Without `group`, the child
+    /// runs with the user's primary group.
+    pub fn resolve(user: Option<&str>, group: Option<&str>) -> Result<Self, String> {
+        let user = user.map(lookup_user).transpose()?;
+        let gid = match (group, &user) {
+            (Some(group), _) => lookup_group(group)?,
+            (None, Some((_, primary))) => *primary,
+            // Safety: getgid has no preconditions.
+            (None, None) => unsafe { libc::getgid() },
+        };
+        let groups = match &user {
+            Some((user, _)) => group_list(&user.name, gid)?,
+            None => vec![gid],
+        };
+        Ok(Credentials {
+            user: user.map(|(user, _)| user),
+            gid,
+            groups,
+        })
+    }
+
+    /// e.g. "user www-data (uid 33, gid 33)" or "gid 33".
+    pub fn describe(&self) -> String {
+        match &self.user {
+            Some(user) => format!("user {} (uid {}, gid {})", user.name, user.uid, self.gid),
+            None => format!("gid {}", self.gid),
+        }
+    }
+
+    /// Makes the child drop to these credentials before it runs, and sets `USER`, `LOGNAME`
+    /// and `HOME` for the user. Register this hook after any that need the watchdog's own
+    /// privileges, and before `--parent-death-signal`, which a change of credentials clears.
+    pub fn apply(&self, command: &mut Command) {
+        if let Some(user) = &self.user {
+            command
+                .env("USER", &user.name)
+                .env("LOGNAME", &user.name)
+                .env("HOME", &user.home);
+        }
+        let uid = self.user.as_ref().map(|user| user.uid);
+        let gid = self.gid;
+        let groups = self.groups.clone();
+        // Safety: setgroups, setgid and setuid are async-signal-safe; everything they need was
+        // looked up before the fork.
+        unsafe {
+            command.pre_exec(move || {
+                // Supplementary groups first, while the child may still change them.
+                if libc::setgroups(groups.len() as _, groups.as_ptr()) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                if libc::setgid(gid) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                if let Some(uid) = uid {
+                    if libc::setuid(uid) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+/// Looks up a user by name or UID, with its primary group.
+fn lookup_user(user: &str) -> Result<(User, libc::gid_t), String> {
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut found = std::ptr::null_mut();
+    let name = CString::new(user).map_err(|_| format!("invalid user '{}'", user))?;
+    // Safety: the entry and buffer outlive the call, which writes into them only.
+    let error = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        )
+    };
+    if found.is_null() {
+        if let Ok(uid) = user.parse::<libc::uid_t>() {
+            // Safety: as for getpwnam_r.
+            let error = unsafe {
+                libc::getpwuid_r(
+                    uid,
+                    &mut entry,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut found,
+                )
+            };
+            if found.is_null() {
+                return Err(not_found("user", user, error));
+            }
+        } else {
+            return Err(not_found("user", user, error));
+        }
+    }
+    // Safety: on success, the strings point into `buffer`, which is still alive.
+    let (name, home) = unsafe {
+        (
+            CStr::from_ptr(entry.pw_name).to_string_lossy().into_owned(),
+            PathBuf::from(std::ffi::OsStr::from_bytes(
+                CStr::from_ptr(entry.pw_dir).to_bytes(),
+            )),
+        )
+    };
+    Ok((
+        User {
+            name,
+            uid: entry.pw_uid,
+            home,
+        },
+        entry.pw_gid,
+    ))
+}
+
+/// Looks up a group by name or GID.
+fn lookup_group(group: &str) -> Result<libc::gid_t, String> {
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut found = std::ptr::null_mut();
+    let name = CString::new(group).map_err(|_| format!("invalid group '{}'", group))?;
+    // Safety: the entry and buffer outlive the call, which writes into them only.
+    let error = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        )
+    };
+    if !found.is_null() {
+        return Ok(entry.gr_gid);
+    }
+    // A GID needs no entry in the group database.
+    group.parse().map_err(|_| not_found("group", group, error))
+}
+
+fn not_found(what: &str, name: &str, error: libc::c_int) -> String {
+    if error == 0 {
+        format!("no such {} '{}'", what, name)
+    } else {
+        format!(
+            "failed to look up {} '{}': {}",
+            what,
+            name,
+            std::io::Error::from_raw_os_error(error)
+        )
+    }
+}
+
+/// The groups `user` belongs to, including `gid`.
+fn group_list(user: &str, gid: libc::gid_t) -> Result<Vec<libc::gid_t>, String> {
+    let name = CString::new(user).map_err(|_| format!("invalid user '{}'", user))?;
+    let mut groups: Vec<GroupListEntry> = vec![0; 64];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        // Safety: `count` is the capacity of `groups`, which the call writes up to.
+        let result = unsafe {
+            libc::getgrouplist(
+                name.as_ptr(),
+                gid as GroupListEntry,
+                groups.as_mut_ptr(),
+                &mut count,
+            )
+        };
+        if result != -1 {
+            groups.truncate(count as usize);
+            return Ok(groups
+                .into_iter()
+                .map(|group| group as libc::gid_t)
+                .collect());
+        }
+        // Too small: `count` says how large it must be, on most systems.
+        if groups.len() >= 65536 {
+            return Err(format!("user '{}' is in too many groups", user));
+        }
+        let needed = (count as usize).max(groups.len() * 2);
+        groups.resize(needed, 0);
+    }
+}
@@ -0,0 +1,27 @@
+use std::io;
+use std::sync::OnceLock;
+use std::time::Duration;
+use windows_sys::Win32::System::Console::{GenerateConsoleCtrlEvent, CTRL_BREAK_EVENT};
+
+/// How long the child gets to exit after Ctrl+Break before it is terminated (`--kill-grace`
+/// on Windows).
+static GRACE: OnceLock<Duration> = OnceLock::new();
+
+/// Asks the child to stop with Ctrl+Break from now on. Without it, it is terminated right away.
+pub fn configure(grace: Duration) {
+    let _ = GRACE.set(grace);
+}
+
+pub fn grace() -> Option<Duration> {
+    GRACE.get().copied()
+}
+
+/// Sends Ctrl+Break to the console process group led by the child with `pid`, which it was
+/// started in. Only works if the watchdog shares a console with the child.
+pub fn send(pid: u32) -> io::Result<()> {
+    // Safety: GenerateConsoleCtrlEvent has no memory-safety preconditions.
+    if unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use crate::audit;
+use crate::child;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// A command run against a child that missed its deadline before it is terminated
+/// (`--diagnose-exec`), so that evidence of why it hung (e.g. a thread dump) survives the kill.
+#[derive(Debug)]
+pub struct Diagnose {
+    /// The command line, with `{pid}` standing for the child's PID.
+    pub command: String,
+    /// How long the command may take before it is abandoned (`--diagnose-timeout`).
+    pub limit: Duration,
+}
+
+impl Diagnose {
+    /// Runs the command against `pid` and logs what it wrote to stdout and stderr, line by
+    /// line. The output is recorded in the audit log too.
+    pub async fn run(&self, pid: u32) {
+        let command_line = self.command.replace("{pid}", &pid.to_string());
+        info!(
+            "Running --diagnose-exec command before terminating the child: {}",
+            command_line
+        );
+        let mut shell = child::shell(&command_line);
+        shell
+            .env("PING_GUARD_CHILD_PID", pid.to_string())
+            .env("PING_GUARD_NAME", crate::log::instance_name().unwrap_or(""))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        let (status, output) = match timeout(self.limit, shell.output()).await {
+            Ok(Ok(output)) => {
+                let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+                text.push_str(&String::from_utf8_lossy(&output.stderr));
+                for line in text.lines() {
+                    info!("[diagnose] {}", line);
+                }
+                if !output.status.success() {
+                    error!("--diagnose-exec command failed: {}", output.status);
+                }
+                (output.status.to_string(), text)
+            }
+            Ok(Err(e)) => {
+                error!("Failed to run --diagnose-exec command: {}", e);
+                (format!("failed to run: {}", e), String::new())
+            }
+            Err(_) => {
+                error!(
+                    "--diagnose-exec command did not finish within {:?}; abandoned it.",
+                    self.limit
+                );
+                ("timed out".to_string(), String::new())
+            }
+        };
+        audit::record(
+            "diagnosis",
+            serde_json::json!({
+                "pid": pid,
+                "command": command_line,
+                "status": status,
+                "output": output,
+            }),
+        );
+    }
+}
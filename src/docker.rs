@@ -0,0 +1,615 @@
+#[cfg(unix)]
+use crate::output::OutputRules;
+use clap::ValueEnum;
+#[cfg(unix)]
+use serde::Deserialize;
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(unix)]
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(unix)]
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+#[cfg(unix)]
+use tokio::sync::mpsc;
+#[cfg(unix)]
+use tokio::task::JoinHandle;
+#[cfg(unix)]
+use tokio::time::sleep;
+
+/// Where the Docker daemon listens unless `DOCKER_HOST` names another Unix socket.
+#[cfg(unix)]
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+/// Longest response head (status line and headers) accepted from the daemon.
+#[cfg(unix)]
+const MAX_HEAD: usize = 16 * 1024;
+
+/// Upper bound on a partial line of container output kept while waiting for its newline;
+/// a longer one is forwarded as it stands, so output without newlines cannot grow the buffer
+/// without limit.
+#[cfg(unix)]
+const MAX_LINE: usize = 64 * 1024;
+
+/// How long to wait before asking the daemon again after the exit watch failed.
+#[cfg(unix)]
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Extra time allowed for a `docker stop` beyond the stop timeout itself (10 seconds unless
+/// the container or `--docker-stop-timeout` says otherwise).
+pub const STOP_GRACE: Duration = Duration::from_secs(5);
+
+/// What to do with the container when it misses its deadline or fails a health check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Action {
+    /// `docker stop`: SIGTERM, then SIGKILL after the stop timeout. The watchdog exits.
+    Stop,
+    /// `docker kill`: SIGKILL right away. The watchdog exits.
+    Kill,
+    /// `docker restart`, after which supervision continues.
+    Restart,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Stop => "stop",
+            Action::Kill => "kill",
+            Action::Restart => "restart",
+        }
+    }
+}
+
+/// The daemon socket: `--docker-socket`, else a `unix://` `DOCKER_HOST`, else the default.
+#[cfg(unix)]
+pub fn socket_path(configured: Option<PathBuf>) -> Result<PathBuf, String> {
+    if let Some(path) = configured {
+        return Ok(path);
+    }
+    match std::env::var("DOCKER_HOST") {
+        Ok(host) if !host.is_empty() => {
+            host.strip_prefix("unix://")
+                .map(PathBuf::from)
+                .ok_or_else(|| {
+                    format!(
+                        "DOCKER_HOST '{}' is not a unix:// socket; pass --docker-socket instead",
+                        host
+                    )
+                })
+        }
+        _ => Ok(PathBuf::from(DEFAULT_SOCKET)),
+    }
+}
+
+/// A response body, read as it arrives.
+#[cfg(unix)]
+struct Body {
+    stream: BufReader<UnixStream>,
+    chunked: bool,
+    /// Bytes left in the current chunk, or in the whole body with `Content-Length`.
+    remaining: Option<u64>,
+    done: bool,
+}
+
+#[cfg(unix)]
+impl Body {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, String> {
+        if self.done {
+            return Ok(0);
+        }
+        if self.chunked && self.remaining == Some(0) {
+            let mut line = String::new();
+            self.stream
+                .read_line(&mut line)
+                .await
+                .map_err(|e| format!("reading response failed: {}", e))?;
+            let size = line.trim().split(';').next().unwrap_or_default();
+            let size = u64::from_str_radix(size, 16)
+                .map_err(|_| format!("malformed chunk size '{}'", line.trim()))?;
+            if size == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+            self.remaining = Some(size);
+        }
+        let limit = match self.remaining {
+            Some(0) => {
+                self.done = true;
+                return Ok(0);
+            }
+            Some(remaining) => remaining.min(buf.len() as u64) as usize,
+            None => buf.len(),
+        };
+        let n = self
+            .stream
+            .read(&mut buf[..limit])
+            .await
+            .map_err(|e| format!("reading response failed: {}", e))?;
+        if n == 0 {
+            if self.remaining.is_some() {
+                return Err("connection closed mid-response".to_string());
+            }
+            self.done = true;
+            return Ok(0);
+        }
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= n as u64;
+            if self.chunked && *remaining == 0 {
+                // Each chunk is followed by CRLF.
+                let mut crlf = [0u8; 2];
+                self.stream
+                    .read_exact(&mut crlf)
+                    .await
+                    .map_err(|e| format!("reading response failed: {}", e))?;
+            }
+        }
+        Ok(n)
+    }
+
+    async fn read_to_end(mut self) -> Result<Vec<u8>, String> {
+        let mut body = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.read(&mut buf).await? {
+                0 => return Ok(body),
+                n => body.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+}
+
+/// A minimal Docker Engine API client: one HTTP/1.1 request per connection.
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct Client {
+    socket: PathBuf,
+}
+
+#[cfg(unix)]
+impl Client {
+    pub fn new(socket: PathBuf) -> Self {
+        Client { socket }
+    }
+
+    /// Sends a request and returns the status code and the body, unread.
+    async fn request(&self, method: &str, path: &str) -> Result<(u16, Body), String> {
+        let mut stream = UnixStream::connect(&self.socket)
+            .await
+            .map_err(|e| format!("cannot connect to {}: {}", self.socket.display(), e))?;
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: docker\r\nUser-Agent: ping-guard\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            method, path
+        );
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .map_err(|e| format!("request failed: {}", e))?;
+
+        let mut stream = BufReader::new(stream);
+        let mut head = Vec::new();
+        loop {
+            let start = head.len();
+            let n = stream
+                .read_until(b'\n', &mut head)
+                .await
+                .map_err(|e| format!("reading response failed: {}", e))?;
+            if n == 0 || head.len() > MAX_HEAD {
+                return Err("incomplete response from the Docker daemon".to_string());
+            }
+            if head[start..].trim_ascii().is_empty() {
+                break;
+            }
+        }
+        let head = String::from_utf8_lossy(&head);
+        let mut lines = head.lines();
+        let status = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| "no HTTP status line in response".to_string())?;
+        let mut chunked = false;
+        let mut length = None;
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            } else if name.eq_ignore_ascii_case("content-length") {
+                length = value.parse::<u64>().ok();
+            }
+        }
+        let body = Body {
+            stream,
+            chunked,
+            remaining: if chunked { Some(0) } else { length },
+            done: false,
+        };
+        Ok((status, body))
+    }
+
+    /// Sends a request and reads the whole body, failing on an error status.
+    async fn call(&self, method: &str, path: &str) -> Result<(u16, Vec<u8>), String> {
+        let (status, body) = self.request(method, path).await?;
+        let body = body.read_to_end().await?;
+        if status >= 400 {
+            return Err(error_message(status, &body));
+        }
+        Ok((status, body))
+    }
+}
+
+/// The daemon's error message for a failed request.
+#[cfg(unix)]
+fn error_message(status: u16, body: &[u8]) -> String {
+    #[derive(Deserialize)]
+    struct ErrorBody {
+        message: String,
+    }
+    match serde_json::from_slice::<ErrorBody>(body) {
+        Ok(error) => format!("{} (HTTP status {})", error.message, status),
+        Err(_) => format!("HTTP status {}", status),
+    }
+}
+
+#[cfg(unix)]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Inspect {
+    id: String,
+    state: InspectState,
+    config: InspectConfig,
+}
+
+#[cfg(unix)]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectState {
+    running: bool,
+    status: String,
+    pid: u32,
+}
+
+#[cfg(unix)]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InspectConfig {
+    #[serde(default)]
+    tty: bool,
+}
+
+#[cfg(unix)]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct WaitResponse {
+    status_code: i64,
+    #[serde(default)]
+    error: Option<WaitError>,
+}
+
+#[cfg(unix)]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct WaitError {
+    message: String,
+}
+
+/// Percent-encodes a container name or ID for use in a request path.
+#[cfg(unix)]
+fn encode(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// A running container supervised in place of a spawned child.
+#[cfg(unix)]
+pub struct Container {
+    client: Client,
+    pub name: String,
+    pub id: String,
+    /// PID of the container's main process on the host, as of the last (re)start.
+    pub pid: u32,
+    tty: bool,
+    stop_timeout: Option<u32>,
+    exits: mpsc::Receiver<Result<i64, String>>,
+    exit_tx: mpsc::Sender<Result<i64, String>>,
+    tasks: Vec<JoinHandle<()>>,
+    output: Option<Arc<OutputRules>>,
+}
+
+#[cfg(unix)]
+impl Container {
+    /// Looks up `name` and checks that it is running. Nothing is watched until [`Container::watch`].
+    pub async fn attach(
+        client: Client,
+        name: &str,
+        stop_timeout: Option<u32>,
+    ) -> Result<Self, String> {
+        let inspect = inspect(&client, name).await?;
+        if !inspect.state.running {
+            return Err(format!(
+                "container '{}' is not running (status: {})",
+                name, inspect.state.status
+            ));
+        }
+        let (exit_tx, exits) = mpsc::channel(1);
+        Ok(Container {
+            client,
+            name: name.to_string(),
+            id: inspect.id,
+            pid: inspect.state.pid,
+            tty: inspect.config.tty,
+            stop_timeout,
+            exits,
+            exit_tx,
+            tasks: Vec::new(),
+            output: None,
+        })
+    }
+
+    /// The short ID `docker ps` shows.
+    pub fn short_id(&self) -> &str {
+        &self.id[..self.id.len().min(12)]
+    }
+
+    /// Starts watching for the container's exit and forwarding its output (as `docker logs`
+    /// would) to `output`.
+    pub fn watch(&mut self, output: Arc<OutputRules>) {
+        self.output = Some(output);
+        self.spawn_tasks(None);
+    }
+
+    fn spawn_tasks(&mut self, since: Option<u64>) {
+        self.tasks.push(tokio::spawn(watch_exit(
+            self.client.clone(),
+            self.id.clone(),
+            self.exit_tx.clone(),
+        )));
+        if let Some(output) = &self.output {
+            self.tasks.push(tokio::spawn(follow_logs(
+                self.client.clone(),
+                self.id.clone(),
+                self.tty,
+                since,
+                output.clone(),
+            )));
+        }
+    }
+
+    fn abort_tasks(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+        // An exit reported by the old watch belongs to the old run.
+        while self.exits.try_recv().is_ok() {}
+    }
+
+    /// Waits for the container to stop and returns its exit code.
+    pub async fn wait(&mut self) -> Result<i64, String> {
+        match self.exits.recv().await {
+            Some(result) => result,
+            None => Err("exit watch stopped".to_string()),
+        }
+    }
+
+    /// `docker stop`: asks the container to stop and waits until it has.
+    pub async fn stop(&mut self) -> Result<(), String> {
+        self.abort_tasks();
+        let path = format!(
+            "/containers/{}/stop{}",
+            encode(&self.id),
+            self.timeout_query()
+        );
+        self.client.call("POST", &path).await.map(|_| ())
+    }
+
+    /// `docker kill`: sends SIGKILL to the container.
+    pub async fn kill(&mut self) -> Result<(), String> {
+        self.abort_tasks();
+        let path = format!("/containers/{}/kill", encode(&self.id));
+        match self.client.call("POST", &path).await {
+            Ok(_) => Ok(()),
+            // 409: not running, which is what was wanted.
+            Err(e) if e.ends_with("(HTTP status 409)") => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// `docker kill --signal`: sends `signal` (e.g. `SIGTERM`) and keeps watching the container.
+    pub async fn signal(&self, signal: &str) -> Result<(), String> {
+        let path = format!(
+            "/containers/{}/kill?signal={}",
+            encode(&self.id),
+            encode(signal)
+        );
+        self.client.call("POST", &path).await.map(|_| ())
+    }
+
+    /// `docker pause`: freezes every process in the container.
+    pub async fn pause(&self) -> Result<(), String> {
+        let path = format!("/containers/{}/pause", encode(&self.id));
+        self.client.call("POST", &path).await.map(|_| ())
+    }
+
+    /// `docker unpause`: thaws a paused container.
+    pub async fn unpause(&self) -> Result<(), String> {
+        let path = format!("/containers/{}/unpause", encode(&self.id));
+        self.client.call("POST", &path).await.map(|_| ())
+    }
+
+    /// `docker restart`, after which the container is watched again.
+    pub async fn restart(&mut self) -> Result<(), String> {
+        self.abort_tasks();
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        let path = format!(
+            "/containers/{}/restart{}",
+            encode(&self.id),
+            self.timeout_query()
+        );
+        let result = self.client.call("POST", &path).await.map(|_| ());
+        if let Ok(inspect) = inspect(&self.client, &self.id).await {
+            self.pid = inspect.state.pid;
+        }
+        // Watch again even if the restart failed, so an exit is still noticed.
+        self.spawn_tasks(Some(since));
+        result
+    }
+
+    fn timeout_query(&self) -> String {
+        self.stop_timeout
+            .map(|secs| format!("?t={}", secs))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(unix)]
+async fn inspect(client: &Client, name: &str) -> Result<Inspect, String> {
+    let (_, body) = client
+        .call("GET", &format!("/containers/{}/json", encode(name)))
+        .await?;
+    serde_json::from_slice(&body).map_err(|e| format!("unexpected inspect response: {}", e))
+}
+
+/// Reports the container's exit code once it stops. A failed watch is retried, so a daemon
+/// restart does not end supervision; only an actual exit is reported.
+#[cfg(unix)]
+async fn watch_exit(client: Client, id: String, exit_tx: mpsc::Sender<Result<i64, String>>) {
+    let path = format!("/containers/{}/wait?condition=not-running", encode(&id));
+    let mut reported = false;
+    loop {
+        let result = client.call("POST", &path).await.and_then(|(_, body)| {
+            serde_json::from_slice::<WaitResponse>(&body)
+                .map_err(|e| format!("unexpected wait response: {}", e))
+        });
+        match result {
+            Ok(response) => {
+                if let Some(error) = response.error {
+                    error!(
+                        "Docker reported an error waiting for the container: {}",
+                        error.message
+                    );
+                }
+                let _ = exit_tx.send(Ok(response.status_code)).await;
+                return;
+            }
+            // The container is gone altogether (e.g. removed with --rm).
+            Err(e) if e.ends_with("(HTTP status 404)") => {
+                let _ = exit_tx.send(Err(e)).await;
+                return;
+            }
+            Err(e) if !reported => {
+                error!("Lost track of the container's exit ({}); retrying.", e);
+                reported = true;
+            }
+            Err(_) => {}
+        }
+        sleep(RETRY_DELAY).await;
+    }
+}
+
+/// Forwards the container's output line by line, as the monitor does for a child's stdout
+/// and stderr. Without a TTY the daemon multiplexes both streams behind 8-byte frame headers.
+#[cfg(unix)]
+async fn follow_logs(
+    client: Client,
+    id: String,
+    tty: bool,
+    since: Option<u64>,
+    output: Arc<OutputRules>,
+) {
+    let window = match since {
+        Some(since) => format!("since={}", since),
+        None => "tail=0".to_string(),
+    };
+    let path = format!(
+        "/containers/{}/logs?follow=1&stdout=1&stderr=1&{}",
+        encode(&id),
+        window
+    );
+    let mut body = match client.request("GET", &path).await {
+        Ok((status, body)) if status < 400 => body,
+        Ok((status, body)) => {
+            let body = body.read_to_end().await.unwrap_or_default();
+            error!(
+                "Failed to follow the container's output: {}",
+                error_message(status, &body)
+            );
+            return;
+        }
+        Err(e) => {
+            error!("Failed to follow the container's output: {}", e);
+            return;
+        }
+    };
+
+    let mut pending = Vec::new();
+    // Partial lines per stream: stdout, stderr.
+    let mut lines = [Vec::new(), Vec::new()];
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = match body.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) => {
+                error!("Stopped following the container's output: {}", e);
+                break;
+            }
+        };
+        if tty {
+            forward(&mut lines[0], &buf[..n], true, &output);
+            continue;
+        }
+        pending.extend_from_slice(&buf[..n]);
+        while pending.len() >= 8 {
+            let size =
+                u32::from_be_bytes([pending[4], pending[5], pending[6], pending[7]]) as usize;
+            if pending.len() < 8 + size {
+                break;
+            }
+            let from_stdout = pending[0] != 2;
+            let stream = if from_stdout { 0 } else { 1 };
+            forward(
+                &mut lines[stream],
+                &pending[8..8 + size],
+                from_stdout,
+                &output,
+            );
+            pending.drain(..8 + size);
+        }
+    }
+}
+
+/// Appends `data` to the stream's partial line and forwards every complete line, and a partial
+/// one once it exceeds [`MAX_LINE`].
+#[cfg(unix)]
+fn forward(partial: &mut Vec<u8>, data: &[u8], from_stdout: bool, output: &OutputRules) {
+    partial.extend_from_slice(data);
+    loop {
+        let (end, next) = match partial.iter().position(|&b| b == b'\n') {
+            Some(end) => (end, end + 1),
+            None if partial.len() > MAX_LINE => (MAX_LINE, MAX_LINE),
+            None => break,
+        };
+        let line = String::from_utf8_lossy(&partial[..end]);
+        let line = line.trim_end_matches('\r');
+        if from_stdout {
+            info!("[container stdout] {}", line);
+        } else {
+            error!("[container stderr] {}", line);
+        }
+        output.on_line(line, from_stdout);
+        partial.drain(..next);
+    }
+}
@@ -0,0 +1,375 @@
+use crate::audit;
+use crate::auth;
+use crate::error::Error;
+use crate::heartbeat::HeartbeatSink;
+use crate::listener::{self, PacketPolicy, Verdict};
+use crate::stats::Stats;
+use hmac::{Hmac, Mac};
+use openssl::error::ErrorStack;
+use openssl::ex_data::Index;
+use openssl::ssl::{
+    ErrorCode, Ssl, SslContext, SslContextBuilder, SslFiletype, SslMethod, SslOptions, SslStream,
+    SslVerifyMode, SslVersion,
+};
+use sha2::Sha256;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+/// Largest datagram sent to a peer; longer handshake messages are fragmented to fit.
+const MTU: u32 = 1200;
+
+/// Handshakes in progress at once. Cookie exchange keeps spoofed senders from getting far, but
+/// each ClientHello still costs a session until it expires.
+const MAX_HANDSHAKES: usize = 256;
+
+/// Established sessions kept at once.
+const MAX_SESSIONS: usize = 1024;
+
+/// A handshake not completed within this long is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A session that has sent nothing for this long is dropped; the peer has to handshake again.
+const SESSION_IDLE: Duration = Duration::from_secs(600);
+
+/// How often sessions are checked for expiry when no datagrams arrive.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How DTLS peers authenticate (`--dtls-psk-file`, or `--dtls-cert`, `--dtls-key` and
+/// `--dtls-ca`).
+#[derive(Debug, Clone)]
+pub enum Credentials {
+    /// A pre-shared key, for any PSK identity.
+    Psk(PathBuf),
+    /// The watchdog's certificate and key, and the CA that client certificates must chain to.
+    Certificate {
+        cert: PathBuf,
+        key: PathBuf,
+        ca: PathBuf,
+    },
+}
+
+impl Credentials {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Credentials::Psk(_) => "a pre-shared key",
+            Credentials::Certificate { .. } => "client certificates",
+        }
+    }
+}
+
+/// Accepts DTLS 1.2 sessions on the heartbeat sockets (`--dtls`). Every application data
+/// record from an established session is handled like a plaintext UDP packet.
+pub struct Server {
+    context: SslContext,
+    /// Where each session's peer address is kept for the cookie callbacks.
+    peer_index: Index<Ssl, SocketAddr>,
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Server")
+    }
+}
+
+impl Server {
+    pub fn new(credentials: &Credentials) -> Result<Self, String> {
+        let psk = match credentials {
+            Credentials::Psk(path) => Some(
+                auth::read_key(path, "DTLS PSK")
+                    .map_err(|e| format!("{}: {}", path.display(), e))?,
+            ),
+            Credentials::Certificate { .. } => None,
+        };
+        Self::build(credentials, psk).map_err(|e| e.to_string())
+    }
+
+    fn build(credentials: &Credentials, psk: Option<Vec<u8>>) -> Result<Self, ErrorStack> {
+        let peer_index = Ssl::new_ex_index::<SocketAddr>()?;
+        let mut builder = SslContextBuilder::new(SslMethod::dtls())?;
+        builder.set_min_proto_version(Some(SslVersion::DTLS1_2))?;
+        // The MTU is set on each session, since datagrams go through an in-memory transport.
+        builder.set_options(SslOptions::COOKIE_EXCHANGE | SslOptions::NO_QUERY_MTU);
+
+        // Cookies tie a ClientHello to its source address without keeping state, so spoofed
+        // senders cannot make the watchdog send a certificate flight to someone else.
+        let mut secret = [0; 32];
+        openssl::rand::rand_bytes(&mut secret)?;
+        builder.set_cookie_generate_cb(move |ssl, cookie| {
+            let mac = cookie_mac(&secret, ssl.ex_data(peer_index)).finalize();
+            let mac = mac.into_bytes();
+            cookie[..mac.len()].copy_from_slice(&mac);
+            Ok(mac.len())
+        });
+        builder.set_cookie_verify_cb(move |ssl, cookie| {
+            cookie_mac(&secret, ssl.ex_data(peer_index))
+                .verify_slice(cookie)
+                .is_ok()
+        });
+
+        match credentials {
+            Credentials::Psk(_) => {
+                let key = psk.unwrap_or_default();
+                builder.set_cipher_list("PSK")?;
+                builder.set_psk_server_callback(move |_, _, psk| {
+                    if key.len() > psk.len() {
+                        return Err(ErrorStack::get());
+                    }
+                    psk[..key.len()].copy_from_slice(&key);
+                    Ok(key.len())
+                });
+            }
+            Credentials::Certificate { cert, key, ca } => {
+                builder.set_certificate_chain_file(cert)?;
+                builder.set_private_key_file(key, SslFiletype::PEM)?;
+                builder.check_private_key()?;
+                builder.set_ca_file(ca)?;
+                builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+            }
+        }
+        Ok(Server {
+            context: builder.build(),
+            peer_index,
+        })
+    }
+
+    fn session(&self, peer: SocketAddr, now: Instant) -> Result<Session, ErrorStack> {
+        let mut ssl = Ssl::new(&self.context)?;
+        ssl.set_ex_data(self.peer_index, peer);
+        ssl.set_mtu(MTU)?;
+        Ok(Session {
+            stream: SslStream::new(ssl, Datagrams::default())?,
+            established: false,
+            last_active: now,
+        })
+    }
+}
+
+fn cookie_mac(secret: &[u8], peer: Option<&SocketAddr>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    if let Some(peer) = peer {
+        mac.update(peer.to_string().as_bytes());
+    }
+    mac
+}
+
+/// In-memory transport for one session: datagrams received from the peer, and those to send
+/// back to it. Each read and write is one whole datagram, as DTLS requires.
+#[derive(Debug, Default)]
+struct Datagrams {
+    incoming: VecDeque<Vec<u8>>,
+    outgoing: Vec<Vec<u8>>,
+}
+
+impl Read for Datagrams {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(datagram) = self.incoming.pop_front() else {
+            return Err(io::ErrorKind::WouldBlock.into());
+        };
+        let len = datagram.len().min(buf.len());
+        buf[..len].copy_from_slice(&datagram[..len]);
+        Ok(len)
+    }
+}
+
+impl Write for Datagrams {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Session {
+    stream: SslStream<Datagrams>,
+    established: bool,
+    last_active: Instant,
+}
+
+/// Why a session ended.
+enum End {
+    HandshakeFailed(String),
+    Closed,
+    Failed(String),
+}
+
+impl Session {
+    /// Processes what the peer sent: advances the handshake, then passes the contents of every
+    /// complete record to `deliver`.
+    fn process(
+        &mut self,
+        peer: SocketAddr,
+        buf: &mut [u8],
+        mut deliver: impl FnMut(&[u8]),
+    ) -> Result<(), End> {
+        if !self.established {
+            match self.stream.accept() {
+                Ok(()) => {
+                    self.established = true;
+                    let ssl = self.stream.ssl();
+                    info!(
+                        "DTLS session established with {} ({}, {}).",
+                        peer,
+                        ssl.version_str(),
+                        ssl.current_cipher().map_or("no cipher", |c| c.name())
+                    );
+                }
+                Err(e) if e.code() == ErrorCode::WANT_READ => return Ok(()),
+                Err(e) => return Err(End::HandshakeFailed(e.to_string())),
+            }
+        }
+        loop {
+            match self.stream.ssl_read(buf) {
+                Ok(len) => deliver(&buf[..len]),
+                Err(e) if e.code() == ErrorCode::WANT_READ => return Ok(()),
+                Err(e) if e.code() == ErrorCode::ZERO_RETURN => return Err(End::Closed),
+                Err(e) => return Err(End::Failed(e.to_string())),
+            }
+        }
+    }
+}
+
+/// Receives DTLS datagrams on `socket`, keeping one session per peer, and forwards the
+/// heartbeats they carry to the monitor.
+pub async fn receive_loop(
+    socket: UdpSocket,
+    server: Arc<Server>,
+    policy: Arc<PacketPolicy>,
+    sink: HeartbeatSink,
+) {
+    let stats = &sink.stats;
+    let mut sessions: HashMap<SocketAddr, Session> = HashMap::new();
+    let mut datagram = vec![0; 16 * 1024];
+    let mut record = vec![0; 16 * 1024];
+    // Records that fail to authenticate are dropped silently in DTLS, so a peer with the wrong
+    // key shows up as a handshake that never completes; sweeping finds those without traffic.
+    let mut sweep = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        let (len, peer) = tokio::select! {
+            received = socket.recv_from(&mut datagram) => match received {
+                Ok(received) => received,
+                Err(e) => {
+                    Error::Receive {
+                        what: "DTLS socket".to_string(),
+                        source: e,
+                    }
+                    .report();
+                    return;
+                }
+            },
+            _ = sweep.tick() => {
+                expire(&mut sessions, Instant::now(), stats);
+                continue;
+            }
+        };
+        if !listener::admit_source(peer, &policy, stats) {
+            continue;
+        }
+        let now = Instant::now();
+        expire(&mut sessions, now, stats);
+        if !sessions.contains_key(&peer) {
+            let handshakes = sessions.values().filter(|s| !s.established).count();
+            if handshakes >= MAX_HANDSHAKES || sessions.len() >= MAX_SESSIONS {
+                handshake_failed(peer, "too many sessions", stats);
+                continue;
+            }
+            match server.session(peer, now) {
+                Ok(session) => {
+                    sessions.insert(peer, session);
+                }
+                Err(e) => {
+                    handshake_failed(peer, &e.to_string(), stats);
+                    continue;
+                }
+            }
+        }
+        let Some(session) = sessions.get_mut(&peer) else {
+            continue;
+        };
+        session.last_active = now;
+        session
+            .stream
+            .get_mut()
+            .incoming
+            .push_back(datagram[..len].to_vec());
+
+        let arrived_ms = audit::now_ms();
+        let mut verdicts = Vec::new();
+        let result = session.process(peer, &mut record, |data| {
+            verdicts.push(listener::accept_contents(
+                data, peer, arrived_ms, &policy, &sink,
+            ));
+        });
+        for verdict in verdicts {
+            let reply = match verdict {
+                Verdict::Heartbeat => {
+                    if !sink.beat_from(peer.ip()) {
+                        error!("Monitor task receiver dropped, stopping DTLS listener.");
+                        return;
+                    }
+                    policy.ack.then(|| policy.status.pong(&sink))
+                }
+                Verdict::Query => Some(policy.status.report(&sink)),
+                Verdict::Command(command) => Some(listener::submit_command(command, peer, &policy)),
+                Verdict::Rejected => None,
+            };
+            if let Some(reply) = reply {
+                // Sent below, along with anything else the session has to send.
+                let _ = session.stream.ssl_write(&reply);
+            }
+        }
+        // Handshake replies and alerts, including the one reporting a failed handshake.
+        for reply in std::mem::take(&mut session.stream.get_mut().outgoing) {
+            let _ = socket.send_to(&reply, peer).await;
+        }
+        match result {
+            Ok(()) => {}
+            Err(End::HandshakeFailed(reason)) => {
+                sessions.remove(&peer);
+                handshake_failed(peer, &reason, stats);
+            }
+            Err(End::Closed) => {
+                sessions.remove(&peer);
+                info!("DTLS session with {} closed by the peer.", peer);
+            }
+            Err(End::Failed(reason)) => {
+                sessions.remove(&peer);
+                error!("DTLS session with {} failed: {}", peer, reason);
+            }
+        }
+    }
+}
+
+/// Drops idle sessions and handshakes that ran out of time; the latter count as failed.
+fn expire(sessions: &mut HashMap<SocketAddr, Session>, now: Instant, stats: &Stats) {
+    sessions.retain(|peer, session| {
+        let idle = now.duration_since(session.last_active);
+        if session.established {
+            return idle < SESSION_IDLE;
+        }
+        if idle < HANDSHAKE_TIMEOUT {
+            return true;
+        }
+        let reason = format!("not completed within {}s", HANDSHAKE_TIMEOUT.as_secs());
+        handshake_failed(*peer, &reason, stats);
+        false
+    });
+}
+
+fn handshake_failed(peer: SocketAddr, reason: &str, stats: &Stats) {
+    let failed = Stats::bump(&stats.dtls_handshake_failures);
+    if failed == 1 || failed.is_multiple_of(100) {
+        error!(
+            "DTLS handshake with {} failed: {} ({} failed so far).",
+            peer, reason, failed
+        );
+    }
+}
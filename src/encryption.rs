@@ -0,0 +1,47 @@
+use crate::keyfile::KeyFile;
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Length of the random nonce that starts every encrypted heartbeat.
+const NONCE_LEN: usize = 12;
+
+/// Decrypts heartbeats of the form `<nonce><ciphertext>` (`--encryption-key-file`): a
+/// 12-byte nonce, never reused with the same key, followed by the payload sealed with
+/// ChaCha20-Poly1305 under the SHA-256 of the shared key. Decryption fails for anything not
+/// sealed with the key, so it authenticates the sender as well as hiding the payload.
+#[derive(Debug)]
+pub struct Decryptor {
+    ciphers: Arc<KeyFile<ChaCha20Poly1305>>,
+}
+
+impl Decryptor {
+    /// Reads the key from `path` (see [`crate::auth::read_key`]). After the file changes, the
+    /// previous key is still accepted for `overlap`.
+    pub fn load(path: &Path, overlap: Duration) -> Result<Self, String> {
+        Ok(Decryptor {
+            ciphers: KeyFile::load(path, "Encryption", overlap, |key| {
+                ChaCha20Poly1305::new(&Sha256::digest(key))
+            })?,
+        })
+    }
+
+    /// The key file, to watch for rotation.
+    pub fn key_file(&self) -> Arc<KeyFile<ChaCha20Poly1305>> {
+        self.ciphers.clone()
+    }
+
+    /// Returns the payload of an encrypted heartbeat, or why it could not be decrypted.
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        if data.len() < NONCE_LEN {
+            return Err("too short to be encrypted".to_string());
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        self.ciphers
+            .check(|cipher| cipher.decrypt(Nonce::from_slice(nonce), ciphertext))
+            .map_err(|_| "not sealed with the key".to_string())
+    }
+}
@@ -0,0 +1,92 @@
+use crate::exit::ExitReason;
+use std::io;
+use thiserror::Error;
+
+/// Failures the watchdog reports. Each has a stable `kind`, which `--log-format json` output
+/// carries in its `error` field, and fatal ones map onto the exit reason they exit with.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Options that cannot be used together or have invalid values.
+    #[error("{0}")]
+    Config(String),
+    // Every option is supported on Linux.
+    #[cfg_attr(target_os = "linux", allow(dead_code))]
+    #[error("{option} is only supported on {platform}.")]
+    Unsupported {
+        option: &'static str,
+        platform: &'static str,
+    },
+    #[error("Failed to resolve {what}: {source}")]
+    Resolve {
+        what: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to bind {what}: {source}")]
+    Bind {
+        what: String,
+        #[source]
+        source: io::Error,
+    },
+    /// A file, device, queue or socket the watchdog needs could not be opened or set up.
+    #[error("Failed to {what}: {reason}")]
+    Open { what: String, reason: String },
+    #[error("Failed to receive on {what}: {source}")]
+    Receive {
+        what: String,
+        #[source]
+        source: io::Error,
+    },
+    // Containers and running processes can only be attached to on Unix.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    #[error("Failed to attach to {name}: {reason}")]
+    Attach { name: String, reason: String },
+    #[error("Failed to spawn child process '{path}': {source}")]
+    Spawn {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Could not get PID of spawned child process.")]
+    NoPid,
+    #[error("Failed to {what}: {reason}")]
+    Kill { what: String, reason: String },
+    #[error("Failed to wait for {what} to exit: {reason}")]
+    Wait { what: String, reason: String },
+    /// A bug: a watchdog task panicked.
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl Error {
+    /// Machine-readable name of this kind of failure.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Config(_) => "invalid_config",
+            Error::Unsupported { .. } => "unsupported_platform",
+            Error::Resolve { .. } => "resolve_failed",
+            Error::Bind { .. } => "bind_failed",
+            Error::Open { .. } => "open_failed",
+            Error::Receive { .. } => "receive_failed",
+            Error::Attach { .. } => "attach_failed",
+            Error::Spawn { .. } | Error::NoPid => "spawn_failed",
+            Error::Kill { .. } => "kill_failed",
+            Error::Wait { .. } => "wait_failed",
+            Error::Internal(_) => "internal_error",
+        }
+    }
+
+    /// The exit reason when this failure ends the watchdog.
+    pub fn exit_reason(&self) -> ExitReason {
+        match self {
+            Error::Wait { .. } => ExitReason::WaitFailed,
+            Error::Internal(_) => ExitReason::Internal,
+            _ => ExitReason::Startup,
+        }
+    }
+
+    /// Logs the failure, tagged with its kind.
+    pub fn report(&self) {
+        crate::log::error(Some(self.kind()), format_args!("{}", self));
+    }
+}
@@ -0,0 +1,53 @@
+use crate::signals::Signal;
+use std::fmt;
+use std::time::Duration;
+
+/// A step taken when the child has been silent for a while, short of the timeout
+/// (`--escalate AFTER=ACTION`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+    /// Time without a heartbeat after which the step is taken.
+    pub after: Duration,
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Log the silence and raise an `escalation` event.
+    Warn,
+    /// Also send this signal to the child.
+    Signal(Signal),
+}
+
+impl Step {
+    /// Parses `AFTER=ACTION`, e.g. `10s=warn` or `20s=signal:TERM`.
+    pub fn parse(value: &str) -> Result<Step, String> {
+        let (after, action) = value
+            .split_once('=')
+            .ok_or_else(|| format!("expected AFTER=ACTION, got '{}'", value))?;
+        let after = crate::parse_timeout(after)?;
+        if after.is_zero() {
+            return Err("the time before a step must be greater than 0".to_string());
+        }
+        let action = match action.split_once(':') {
+            None if action == "warn" => Action::Warn,
+            Some(("signal", signal)) => Action::Signal(Signal::parse(signal)?),
+            _ => {
+                return Err(format!(
+                    "unknown action '{}'; expected warn or signal:<SIG>",
+                    action
+                ))
+            }
+        };
+        Ok(Step { after, action })
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Warn => write!(f, "warn"),
+            Action::Signal(signal) => write!(f, "signal:{}", signal),
+        }
+    }
+}
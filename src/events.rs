@@ -0,0 +1,233 @@
+use crate::child;
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// How long a notification command may run before it is abandoned.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Watchdog events that can be routed by severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Event {
+    /// A heartbeat arrived after most of the timeout had already elapsed.
+    LateHeartbeat,
+    /// An `--interval` passed without a heartbeat, short of the deadline.
+    MissedHeartbeat,
+    /// Most of the timeout elapsed without a heartbeat (`--warn-at`).
+    DeadlineWarning,
+    /// A step of `--escalate` was taken short of the deadline.
+    Escalation,
+    /// The deadline passed without a heartbeat.
+    Timeout,
+    /// The first deadline passed without the child ever sending a heartbeat.
+    NoHeartbeat,
+    /// The child ran longer than `--max-runtime`.
+    MaxRuntime,
+    /// The child was restarted `--max-restarts` times within `--restart-window`.
+    CrashLoop,
+    /// A health probe failed often enough to act on.
+    ProbeFailure,
+    /// The child could not be killed cleanly.
+    KillFailure,
+    /// The child's output showed it is ready (`--ready-regex`).
+    Ready,
+    /// The child exited on its own.
+    ChildExit,
+    /// The watchdog was asked to shut down.
+    Shutdown,
+    /// The child breached a soft resource limit and is being throttled.
+    Throttle,
+    /// Heartbeat timestamps show a sender's clock, or senders' clocks, drifting apart.
+    ClockSkew,
+    /// A control command was carried out (`--allow-commands`).
+    RemoteCommand,
+}
+
+impl Event {
+    fn default_severity(self) -> Severity {
+        match self {
+            Event::LateHeartbeat
+            | Event::MissedHeartbeat
+            | Event::Ready
+            | Event::ChildExit
+            | Event::Shutdown
+            | Event::Throttle => Severity::Log,
+            Event::DeadlineWarning
+            | Event::Escalation
+            | Event::Timeout
+            | Event::NoHeartbeat
+            | Event::MaxRuntime
+            | Event::ProbeFailure
+            | Event::ClockSkew
+            | Event::RemoteCommand => Severity::Notify,
+            Event::KillFailure | Event::CrashLoop => Severity::Page,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Event::LateHeartbeat => "late-heartbeat",
+            Event::MissedHeartbeat => "missed-heartbeat",
+            Event::DeadlineWarning => "deadline-warning",
+            Event::Escalation => "escalation",
+            Event::Timeout => "timeout",
+            Event::NoHeartbeat => "no-heartbeat",
+            Event::MaxRuntime => "max-runtime",
+            Event::CrashLoop => "crash-loop",
+            Event::ProbeFailure => "probe-failure",
+            Event::KillFailure => "kill-failure",
+            Event::Ready => "ready",
+            Event::ChildExit => "child-exit",
+            Event::Shutdown => "shutdown",
+            Event::Throttle => "throttle",
+            Event::ClockSkew => "clock-skew",
+            Event::RemoteCommand => "remote-command",
+        }
+    }
+}
+
+/// Where an event goes: only the log, the notification command, or the paging command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Severity {
+    Log,
+    Notify,
+    Page,
+}
+
+impl Severity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Severity::Log => "log",
+            Severity::Notify => "notify",
+            Severity::Page => "page",
+        }
+    }
+}
+
+/// Parses a `--severity EVENT=LEVEL` route.
+pub fn parse_route(value: &str) -> Result<(Event, Severity), String> {
+    let (event, severity) = value
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' must be EVENT=LEVEL", value))?;
+    let event = Event::from_str(event.trim(), true).map_err(|_| {
+        format!(
+            "unknown event '{}' (expected one of: {})",
+            event.trim(),
+            possible_values::<Event>()
+        )
+    })?;
+    let severity = Severity::from_str(severity.trim(), true).map_err(|_| {
+        format!(
+            "unknown severity '{}' (expected one of: {})",
+            severity.trim(),
+            possible_values::<Severity>()
+        )
+    })?;
+    Ok((event, severity))
+}
+
+fn possible_values<T: ValueEnum>() -> String {
+    T::value_variants()
+        .iter()
+        .filter_map(|v| v.to_possible_value())
+        .map(|v| v.get_name().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Severity routes and the commands notifications are delivered through.
+#[derive(Debug, Default)]
+pub struct Routing {
+    pub routes: HashMap<Event, Severity>,
+    pub notify_exec: Option<String>,
+    pub page_exec: Option<String>,
+}
+
+static ROUTING: OnceLock<Routing> = OnceLock::new();
+
+/// Installs the routing. Until this is called every event uses its default severity and no
+/// commands are run.
+pub fn configure(routing: Routing) {
+    let _ = ROUTING.set(routing);
+}
+
+/// Extra time a shutdown should allow for a notification command to finish.
+pub fn delivery_grace() -> Duration {
+    match ROUTING.get() {
+        Some(routing) if routing.notify_exec.is_some() || routing.page_exec.is_some() => {
+            NOTIFY_TIMEOUT
+        }
+        _ => Duration::ZERO,
+    }
+}
+
+/// Routes `event` by its configured severity. `log` events have already been logged by the
+/// caller; `notify` and `page` events also run the matching command (a page falls back to the
+/// notification command when no paging command is set). Await this before exiting so the
+/// command gets to run.
+pub async fn emit(event: Event, message: String) {
+    let Some(routing) = ROUTING.get() else {
+        return;
+    };
+    let severity = routing
+        .routes
+        .get(&event)
+        .copied()
+        .unwrap_or(event.default_severity());
+    let command = match severity {
+        Severity::Log => None,
+        Severity::Notify => routing.notify_exec.as_ref(),
+        Severity::Page => routing.page_exec.as_ref().or(routing.notify_exec.as_ref()),
+    };
+    let Some(command) = command else {
+        return;
+    };
+
+    info!(
+        "Routing {} event as '{}' via: {}",
+        event.as_str(),
+        severity.as_str(),
+        command
+    );
+    let mut shell = child::shell(command);
+    shell
+        .env("PING_GUARD_EVENT", event.as_str())
+        .env("PING_GUARD_SEVERITY", severity.as_str())
+        .env("PING_GUARD_MESSAGE", &message);
+    run(
+        shell,
+        &format!("{} command for {} event", severity.as_str(), event.as_str()),
+    )
+    .await;
+}
+
+/// Runs a hook command given to `option` (e.g. `--warn-exec`) through the shell with `env`,
+/// bounded like notification commands. Await this before exiting so the command gets to run.
+pub async fn run_hook(option: &str, command: &str, env: &[(&str, String)]) {
+    info!("Running {} command: {}", option, command);
+    let mut shell = child::shell(command);
+    for (name, value) in env {
+        shell.env(name, value);
+    }
+    run(shell, &format!("{} command", option)).await;
+}
+
+/// Runs `shell`, described as `what` in errors, for at most [`NOTIFY_TIMEOUT`].
+async fn run(mut shell: tokio::process::Command, what: &str) {
+    shell
+        .env("PING_GUARD_NAME", crate::log::instance_name().unwrap_or(""))
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    match timeout(NOTIFY_TIMEOUT, shell.status()).await {
+        Ok(Ok(status)) if status.success() => {}
+        Ok(Ok(status)) => error!("{} failed: {}", what, status),
+        Ok(Err(e)) => error!("Failed to run {}: {}", what, e),
+        Err(_) => error!(
+            "{} did not finish within {:?}; abandoned it.",
+            what, NOTIFY_TIMEOUT
+        ),
+    }
+}
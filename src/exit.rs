@@ -0,0 +1,166 @@
+use crate::error::Error;
+use serde_json::{json, Value};
+
+/// Why the watchdog exited. This is the single source of truth for ping-guard's exit codes;
+/// `--explain-exit-codes` prints it for tooling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    ChildExited,
+    Shutdown,
+    Startup,
+    /// Rejected command line; exits are made by the argument parser itself.
+    Usage,
+    Timeout,
+    NoHeartbeat,
+    Unhealthy,
+    /// A `KILL` command (`--allow-commands`).
+    RemoteKill,
+    WaitFailed,
+    SourcesLost,
+    /// The UDP listener could not be set up, even after `--bind-retries`.
+    ListenFailed,
+    /// The child ran longer than `--max-runtime`.
+    MaxRuntime,
+    /// The child was restarted `--max-restarts` times within `--restart-window`.
+    CrashLoop,
+    Signal,
+    Internal,
+}
+
+impl ExitReason {
+    pub const ALL: [ExitReason; 15] = [
+        ExitReason::ChildExited,
+        ExitReason::Shutdown,
+        ExitReason::Startup,
+        ExitReason::Usage,
+        ExitReason::Timeout,
+        ExitReason::NoHeartbeat,
+        ExitReason::Unhealthy,
+        ExitReason::RemoteKill,
+        ExitReason::WaitFailed,
+        ExitReason::SourcesLost,
+        ExitReason::ListenFailed,
+        ExitReason::MaxRuntime,
+        ExitReason::CrashLoop,
+        ExitReason::Signal,
+        ExitReason::Internal,
+    ];
+
+    pub fn code(self) -> i32 {
+        match self {
+            ExitReason::ChildExited | ExitReason::Shutdown | ExitReason::RemoteKill => 0,
+            ExitReason::Startup
+            | ExitReason::Timeout
+            | ExitReason::NoHeartbeat
+            | ExitReason::Unhealthy => 1,
+            ExitReason::Usage | ExitReason::WaitFailed => 2,
+            ExitReason::SourcesLost => 3,
+            ExitReason::ListenFailed => 4,
+            ExitReason::MaxRuntime => 5,
+            ExitReason::CrashLoop => 6,
+            // 128 + SIGINT, as a shell reports an interrupted command.
+            ExitReason::Signal => 130,
+            // EX_SOFTWARE from sysexits.h.
+            ExitReason::Internal => 70,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ExitReason::ChildExited => "child_exited",
+            ExitReason::Shutdown => "shutdown",
+            ExitReason::Startup => "startup_error",
+            ExitReason::Usage => "usage_error",
+            ExitReason::Timeout => "timeout",
+            ExitReason::NoHeartbeat => "no_heartbeat",
+            ExitReason::Unhealthy => "probe_failed",
+            ExitReason::RemoteKill => "remote_kill",
+            ExitReason::WaitFailed => "wait_failed",
+            ExitReason::SourcesLost => "heartbeat_sources_lost",
+            ExitReason::ListenFailed => "listen_failed",
+            ExitReason::MaxRuntime => "max_runtime",
+            ExitReason::CrashLoop => "crash_loop",
+            ExitReason::Signal => "signal",
+            ExitReason::Internal => "internal_error",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            ExitReason::ChildExited => {
+                "The child exited on its own; the watchdog exits with the child's exit code (0 here) or, if signal N killed it, 128 + N."
+            }
+            ExitReason::Shutdown => {
+                "The watchdog received a termination signal and terminated the child."
+            }
+            ExitReason::Startup => {
+                "Invalid configuration, or a heartbeat source or the child could not be set up."
+            }
+            ExitReason::Usage => "Unknown or invalid command-line arguments; nothing was started.",
+            ExitReason::Timeout => {
+                "No heartbeat arrived within the timeout; the child was terminated."
+            }
+            ExitReason::NoHeartbeat => {
+                "No heartbeat ever arrived, so the first timeout expired; the child was terminated."
+            }
+            ExitReason::Unhealthy => {
+                "A health check failed (a probe, a watched pipe or the dead output pattern); the child was terminated."
+            }
+            ExitReason::RemoteKill => {
+                "An authenticated KILL command arrived; the child was terminated."
+            }
+            ExitReason::WaitFailed => "Waiting for the child failed; its state is unknown.",
+            ExitReason::SourcesLost => "Every heartbeat source stopped; the child was terminated.",
+            ExitReason::ListenFailed => {
+                "The UDP listener could not be set up (e.g. its port stayed in use); the child was terminated."
+            }
+            ExitReason::MaxRuntime => {
+                "The child ran for longer than --max-runtime, whatever its heartbeats; it was terminated."
+            }
+            ExitReason::CrashLoop => {
+                "The child was restarted --max-restarts times within --restart-window and failed again; the watchdog gave up on it."
+            }
+            ExitReason::Signal => {
+                "A termination signal arrived after monitoring had already stopped."
+            }
+            ExitReason::Internal => "The watchdog hit an internal error; the child's state is unknown.",
+        }
+    }
+}
+
+/// Exits the watchdog with the code assigned to `reason`.
+pub fn exit(reason: ExitReason) -> ! {
+    exit_with(reason, reason.code())
+}
+
+/// Exits the watchdog for `reason` with another code, such as the child's own exit status.
+pub fn exit_with(reason: ExitReason, code: i32) -> ! {
+    #[cfg(target_os = "linux")]
+    crate::cgroup::remove();
+    crate::log::flush();
+    crate::log::exit(reason.name(), code);
+    std::process::exit(code)
+}
+
+/// Reports a fatal failure and exits with the reason it maps to.
+pub fn fail(error: Error) -> ! {
+    error.report();
+    exit(error.exit_reason())
+}
+
+/// The exit-code mapping as JSON, limited to the reasons `applies` keeps for the current
+/// configuration.
+pub fn explain(applies: impl Fn(ExitReason) -> bool) -> Value {
+    let codes: Vec<Value> = ExitReason::ALL
+        .into_iter()
+        .filter(|&reason| applies(reason))
+        .map(|reason| {
+            json!({
+                "code": reason.code(),
+                "reason": reason.name(),
+                "description": reason.description(),
+            })
+        })
+        .collect();
+    json!({ "exit_codes": codes })
+}
@@ -0,0 +1,30 @@
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+
+/// Reads from a non-blocking descriptor registered with the reactor, waiting for readiness.
+/// Returns `Ok(0)` at end of file.
+pub async fn read(fd: &AsyncFd<OwnedFd>, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        let mut guard = fd.readable().await?;
+        let result = guard.try_io(|inner| {
+            // Safety: `buf` is valid for writes of `buf.len()` bytes.
+            let n = unsafe {
+                libc::read(
+                    inner.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if n < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(n as usize)
+            }
+        });
+        match result {
+            Ok(read) => return read,
+            Err(_would_block) => continue,
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use clap::ValueEnum;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Well-known address scopes that can be allowed without writing CIDR ranges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Scope {
+    /// 127.0.0.0/8 and ::1
+    Loopback,
+    /// 169.254.0.0/16 and fe80::/10
+    LinkLocal,
+    /// 10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16 and fc00::/7
+    Private,
+}
+
+impl Scope {
+    fn contains(self, ip: IpAddr) -> bool {
+        match (self, ip) {
+            (Scope::Loopback, ip) => ip.is_loopback(),
+            (Scope::LinkLocal, IpAddr::V4(v4)) => v4.is_link_local(),
+            (Scope::LinkLocal, IpAddr::V6(v6)) => (v6.segments()[0] & 0xffc0) == 0xfe80,
+            (Scope::Private, IpAddr::V4(v4)) => v4.is_private(),
+            (Scope::Private, IpAddr::V6(v6)) => (v6.segments()[0] & 0xfe00) == 0xfc00,
+        }
+    }
+}
+
+/// An address range such as `10.0.0.0/8` or `fd00::/8`. A bare address is a range of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    fn contains(self, ip: IpAddr) -> bool {
+        ip.is_ipv4() == self.network.is_ipv4() && mask(ip, self.prefix_len) == self.network
+    }
+}
+
+/// Clears all but the first `prefix_len` bits of `addr`.
+fn mask(addr: IpAddr, prefix_len: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX
+                .checked_shl(32 - u32::from(prefix_len))
+                .unwrap_or(0);
+            IpAddr::V4((u32::from(addr) & mask).into())
+        }
+        IpAddr::V6(addr) => {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(prefix_len))
+                .unwrap_or(0);
+            IpAddr::V6((u128::from(addr) & mask).into())
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match value.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (value, None),
+        };
+        let network: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("'{}' is not an IP address", addr))?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .parse::<u8>()
+                .ok()
+                .filter(|&len| len <= max_len)
+                .ok_or_else(|| format!("prefix length must be 0-{} (got '{}')", max_len, len))?,
+            None => max_len,
+        };
+        // Host bits usually mean a typo in the prefix length, so don't guess what was meant.
+        let masked = mask(network, prefix_len);
+        if masked != network {
+            return Err(format!(
+                "'{}' has host bits set; did you mean {}/{}?",
+                value, masked, prefix_len
+            ));
+        }
+        Ok(Cidr {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// Decides which heartbeat senders are accepted: those in any allowed scope or range. An
+/// empty filter accepts everyone.
+#[derive(Debug, Clone, Default)]
+pub struct SourceFilter {
+    scopes: Vec<Scope>,
+    ranges: Vec<Cidr>,
+}
+
+impl SourceFilter {
+    pub fn new(scopes: Vec<Scope>, ranges: Vec<Cidr>) -> Self {
+        SourceFilter { scopes, ranges }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scopes.is_empty() && self.ranges.is_empty()
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        // IPv4 senders arrive as ::ffff:a.b.c.d on dual-stack IPv6 sockets.
+        let ip = ip.to_canonical();
+        self.scopes.iter().any(|scope| scope.contains(ip))
+            || self.ranges.iter().any(|range| range.contains(ip))
+    }
+
+    pub fn describe(&self) -> String {
+        self.scopes
+            .iter()
+            .map(|scope| {
+                scope
+                    .to_possible_value()
+                    .map(|v| v.get_name().to_string())
+                    .unwrap_or_default()
+            })
+            .chain(self.ranges.iter().map(Cidr::to_string))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
@@ -0,0 +1,28 @@
+use crate::procinfo::{self, ProcessIdentity};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set while the child is frozen by a pause (`--freeze-on-pause`), so that it is thawed before
+/// it is stopped, restarted or left running.
+static FROZEN: AtomicBool = AtomicBool::new(false);
+
+pub fn frozen() -> bool {
+    FROZEN.load(Ordering::Relaxed)
+}
+
+pub fn set_frozen(frozen: bool) {
+    FROZEN.store(frozen, Ordering::Relaxed);
+}
+
+/// Freezes or thaws the child's process group: through `cgroup.freeze` if the child runs in a
+/// cgroup of its own (`--cgroup`), which also holds what left the group, or else with SIGSTOP
+/// and SIGCONT. Returns what was frozen or thawed, e.g. "process group 123".
+pub fn process_group(identity: &ProcessIdentity, freeze: bool) -> Result<String, String> {
+    #[cfg(target_os = "linux")]
+    if let Some(cgroup) = crate::cgroup::active() {
+        cgroup.freeze(freeze).map_err(|e| e.to_string())?;
+        return Ok(format!("cgroup {}", cgroup.path.display()));
+    }
+    let signal = if freeze { libc::SIGSTOP } else { libc::SIGCONT };
+    procinfo::signal_process_group(identity, signal).map_err(|e| e.to_string())?;
+    Ok(format!("process group {}", identity.pid))
+}
@@ -0,0 +1,351 @@
+use crate::audit;
+use crate::components::Components;
+use crate::liveness::{Liveness, Policy};
+use crate::metrics::Metrics;
+use crate::payload;
+use crate::quorum::Quorum;
+use crate::skew::ClockSkew;
+use crate::stats::Stats;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+
+/// Environment variable that tells the child which generation its heartbeats belong to.
+pub const GENERATION_ENV: &str = "PING_GUARD_GENERATION";
+
+/// Picks the generation for a newly spawned child. Generations are based on the wall clock so
+/// they stay unique across watchdog restarts too (e.g. a socket-activated port still holding
+/// pings queued by the previous instance's child), and always move forward.
+pub fn next_generation(previous: u64) -> u64 {
+    audit::now_ms().max(previous + 1)
+}
+
+/// Operating state a child can report in structured heartbeat payloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildState {
+    Normal,
+    /// The child is in a planned slow phase; the degraded policy applies.
+    Degraded,
+}
+
+impl ChildState {
+    /// Maps a reported state string onto a known state. Anything other than "degraded"
+    /// (e.g. "ok") clears the degraded state.
+    pub fn from_reported(state: &str) -> Self {
+        if state.eq_ignore_ascii_case("degraded") {
+            ChildState::Degraded
+        } else {
+            ChildState::Normal
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChildState::Normal => "normal",
+            ChildState::Degraded => "degraded",
+        }
+    }
+}
+
+/// The generation of the child being supervised, and the newest generation heard from that is
+/// ahead of it: a replacement started before the child it replaces is terminated
+/// (`--restart-strategy start-first`). Unlike a [`HeartbeatSink`], it does not keep the
+/// monitor's heartbeat channel open, so restarts can hold on to it.
+#[derive(Debug, Clone)]
+pub struct Generation {
+    current: Arc<AtomicU64>,
+    replacement: Arc<watch::Sender<u64>>,
+}
+
+impl Generation {
+    fn new(generation: u64) -> Self {
+        Generation {
+            current: Arc::new(AtomicU64::new(generation)),
+            replacement: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    /// The generation of the child currently being supervised.
+    pub fn current(&self) -> u64 {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    /// Moves on to a newly spawned child's generation; heartbeats from the previous one are
+    /// stale from now on.
+    pub fn set(&self, generation: u64) {
+        self.current.store(generation, Ordering::Relaxed);
+    }
+
+    /// Records a heartbeat for a `generation` ahead of the current one, which only a replacement
+    /// child can send.
+    fn hear_replacement(&self, generation: u64) {
+        self.replacement.send_if_modified(|heard| {
+            let newer = generation > *heard;
+            if newer {
+                *heard = generation;
+            }
+            newer
+        });
+    }
+
+    /// Waits for the first heartbeat of the replacement child of `generation`.
+    pub async fn replacement_heard(&self, generation: u64) {
+        let mut heard = self.replacement.subscribe();
+        // The sender lives as long as this handle, so waiting cannot fail.
+        let _ = heard.wait_for(|&heard| heard >= generation).await;
+    }
+}
+
+/// Asks the monitor to act on the child, for watchers that are not heartbeat sources (e.g.
+/// `--dead-regex`). Unlike a [`HeartbeatSink`], it does not keep the monitor's heartbeat channel
+/// open, so the monitor still notices once every source has stopped.
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    trigger_tx: mpsc::Sender<String>,
+}
+
+impl Trigger {
+    /// Like [`HeartbeatSink::trigger`], but for definitive failures that no liveness policy
+    /// can outweigh (e.g. the child reporting a fatal error).
+    pub fn declare_dead(&self, reason: String) -> bool {
+        !matches!(
+            self.trigger_tx.try_send(reason),
+            Err(mpsc::error::TrySendError::Closed(_))
+        )
+    }
+}
+
+/// Handle through which every heartbeat source delivers heartbeats (and any child-reported
+/// state) to the monitor.
+#[derive(Debug, Clone)]
+pub struct HeartbeatSink {
+    signal_tx: watch::Sender<Instant>,
+    state_tx: watch::Sender<ChildState>,
+    trigger: Trigger,
+    generation: Generation,
+    /// Which source this handle belongs to; see [`HeartbeatSink::for_source`].
+    source: &'static str,
+    liveness: Option<Arc<Liveness>>,
+    quorum: Option<Arc<Quorum>>,
+    components: Option<Arc<Components>>,
+    clock_skew: Option<Arc<ClockSkew>>,
+    pub stats: Arc<Stats>,
+    /// Metrics reported in structured payloads.
+    pub metrics: Arc<Metrics>,
+}
+
+impl HeartbeatSink {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        signal_tx: watch::Sender<Instant>,
+        state_tx: watch::Sender<ChildState>,
+        trigger_tx: mpsc::Sender<String>,
+        generation: u64,
+        stats: Arc<Stats>,
+        liveness: Option<Arc<Liveness>>,
+        quorum: Option<Arc<Quorum>>,
+        components: Option<Arc<Components>>,
+        clock_skew: Option<Arc<ClockSkew>>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        HeartbeatSink {
+            signal_tx,
+            state_tx,
+            trigger: Trigger { trigger_tx },
+            generation: Generation::new(generation),
+            source: "",
+            liveness,
+            quorum,
+            components,
+            clock_skew,
+            stats,
+            metrics,
+        }
+    }
+
+    /// A handle for one heartbeat source (one of [`crate::liveness::SOURCES`]). With a
+    /// liveness policy, the source joins it from now on.
+    pub fn for_source(&self, source: &'static str) -> Self {
+        if let Some(liveness) = &self.liveness {
+            liveness.register(source);
+        }
+        HeartbeatSink {
+            source,
+            ..self.clone()
+        }
+    }
+
+    /// Records an accepted heartbeat. Returns false once the monitor has gone away.
+    pub fn beat(&self) -> bool {
+        self.record(None)
+    }
+
+    /// Records an accepted heartbeat from a network `sender`. With `--require-senders`, it
+    /// only counts towards the quorum. Returns false once the monitor has gone away.
+    pub fn beat_from(&self, sender: IpAddr) -> bool {
+        self.record(Some(sender))
+    }
+
+    fn record(&self, sender: Option<IpAddr>) -> bool {
+        Stats::bump(&self.stats.heartbeats_accepted);
+        audit::record("heartbeat", serde_json::json!({ "source": self.source }));
+        self.advance(sender, Instant::now());
+        !self.signal_tx.is_closed()
+    }
+
+    /// Moves the monitor's heartbeat time forward for a heartbeat at `at`. A quorum of senders,
+    /// required components and a liveness policy each turn it into a combined time first.
+    fn advance(&self, sender: Option<IpAddr>, at: Instant) {
+        let quorum_at = match (&self.quorum, sender) {
+            (Some(quorum), Some(sender)) => match quorum.beat(sender, at) {
+                Some(at) => Some(at),
+                None => return,
+            },
+            _ => None,
+        };
+        let at = match (self.components(), quorum_at) {
+            (Some(components), Some(quorum_at)) => components.current().min(quorum_at),
+            (Some(components), None) => components.current(),
+            (None, Some(quorum_at)) => quorum_at,
+            (None, None) => at,
+        };
+        match &self.liveness {
+            Some(liveness) => self.publish(liveness.beat(self.source, at)),
+            None => self.publish(at),
+        }
+    }
+
+    /// The required components, if this source's heartbeats can name them (see
+    /// [`crate::payload::SOURCES`]).
+    fn components(&self) -> Option<&Components> {
+        self.components
+            .as_deref()
+            .filter(|_| payload::SOURCES.contains(&self.source))
+    }
+
+    /// Records that a structured payload named `component`, in a heartbeat that arrived at
+    /// `at`. Returns false unless `--require-component` names it.
+    pub fn hear_component(&self, component: &str, at: Instant) -> bool {
+        self.components()
+            .is_some_and(|components| components.beat(component, at))
+    }
+
+    /// The required components (`--require-component`), for status reports.
+    pub fn required_components(&self) -> Option<&Components> {
+        self.components.as_deref()
+    }
+
+    /// Publishes the combined heartbeat time once every source has joined the liveness policy
+    /// (or, without one, the required components' time), so per-source and per-component
+    /// timeouts shorter than the monitor's apply from the start.
+    pub fn publish_combined(&self) {
+        let combined = match (&self.liveness, &self.components) {
+            (Some(liveness), _) => liveness.current(),
+            (None, Some(components)) => components.current(),
+            (None, None) => return,
+        };
+        self.signal_tx.send_modify(|last| *last = combined);
+    }
+
+    /// Moves the monitor's last-heartbeat time forward to `at`, if it is newer.
+    fn publish(&self, at: Instant) {
+        self.signal_tx.send_if_modified(|last| {
+            let newer = at > *last;
+            if newer {
+                *last = at;
+            }
+            newer
+        });
+    }
+
+    /// Records a heartbeat from `sender` that arrived at `at` but is only being read now
+    /// (low-power mode). The last-heartbeat time never moves backwards.
+    #[cfg(unix)]
+    pub fn beat_at(&self, sender: IpAddr, at: Instant) {
+        Stats::bump(&self.stats.heartbeats_accepted);
+        let age_ms = Instant::now().saturating_duration_since(at).as_millis() as u64;
+        audit::record(
+            "heartbeat",
+            serde_json::json!({
+                "ts_ms": audit::now_ms().saturating_sub(age_ms),
+                "source": self.source,
+            }),
+        );
+        self.advance(Some(sender), at);
+    }
+
+    /// Records the sender's clock reading from a heartbeat that arrived at `arrived_ms`, for
+    /// `--clock-skew-alarm`.
+    pub fn observe_clock(&self, sender: &str, sent_ms: u64, arrived_ms: u64) {
+        if let Some(clock_skew) = &self.clock_skew {
+            clock_skew.observe(sender, sent_ms, arrived_ms);
+        }
+    }
+
+    /// The generation of the child currently being supervised.
+    pub fn generation(&self) -> u64 {
+        self.generation.current()
+    }
+
+    /// The supervised child's generation, for restarts.
+    pub fn generation_handle(&self) -> Generation {
+        self.generation.clone()
+    }
+
+    /// Records a heartbeat for a `generation` ahead of the current one, which only a replacement
+    /// child can send.
+    pub fn hear_replacement(&self, generation: u64) {
+        self.generation.hear_replacement(generation);
+    }
+
+    /// When the monitor last counted a heartbeat; with a liveness policy, the combined time.
+    pub fn last_heartbeat(&self) -> Instant {
+        *self.signal_tx.borrow()
+    }
+
+    /// The state the child last reported.
+    pub fn state(&self) -> ChildState {
+        *self.state_tx.borrow()
+    }
+
+    /// Publishes a state reported by the child; the monitor is only woken on actual changes.
+    pub fn report_state(&self, state: ChildState) {
+        self.state_tx.send_if_modified(|current| {
+            let changed = *current != state;
+            *current = state;
+            changed
+        });
+    }
+
+    /// Asks the monitor to act on the child now, without waiting for the timeout. A trigger
+    /// already pending is enough, so further ones are dropped. Under the `any` liveness policy
+    /// one failing source is not enough, so the trigger is only logged. Returns false once the
+    /// monitor has gone away.
+    pub fn trigger(&self, reason: String) -> bool {
+        if self
+            .liveness
+            .as_ref()
+            .is_some_and(|liveness| liveness.policy() == Policy::Any)
+        {
+            info!(
+                "Not acting under the 'any' liveness policy (the {} source's timeout still applies): {}",
+                self.source, reason
+            );
+            return !self.trigger.trigger_tx.is_closed();
+        }
+        self.trigger.declare_dead(reason)
+    }
+
+    /// A handle that can only declare the child dead, for watchers that are not heartbeat
+    /// sources.
+    pub fn trigger_handle(&self) -> Trigger {
+        self.trigger.clone()
+    }
+
+    /// True once the monitor has stopped listening.
+    pub fn is_closed(&self) -> bool {
+        self.signal_tx.is_closed()
+    }
+}
@@ -0,0 +1,199 @@
+use crate::error::Error;
+use crate::fdio;
+use crate::heartbeat::{HeartbeatSink, Trigger};
+use crate::stats::Stats;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::process::Command;
+
+/// Environment variable that tells the child which descriptor to write heartbeats to.
+pub const FD_ENV: &str = "PING_GUARD_FD";
+
+/// A pipe whose write end is handed to the child as a fixed descriptor number. Writes on it
+/// are heartbeats that cannot be spoofed from the network.
+pub struct HeartbeatPipe {
+    read: OwnedFd,
+    write: OwnedFd,
+    child_fd: i32,
+}
+
+/// Checks that a requested child descriptor number doesn't clash with the child's standard streams.
+pub fn validate_child_fd(child_fd: i32) -> Result<(), String> {
+    if child_fd < 3 {
+        return Err(format!(
+            "--heartbeat-fd must be 3 or higher, not {}; 0, 1 and 2 are the child's stdin, stdout and stderr.",
+            child_fd
+        ));
+    }
+    Ok(())
+}
+
+/// Creates a pipe with both ends close-on-exec; only descriptors dup2'd in `pre_exec` survive
+/// into the child. Returns the read and write ends.
+fn cloexec_pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    #[cfg(target_os = "linux")]
+    let created = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) };
+    #[cfg(not(target_os = "linux"))]
+    let created = unsafe {
+        let created = libc::pipe(fds.as_mut_ptr());
+        if created == 0 {
+            libc::fcntl(fds[0], libc::F_SETFD, libc::FD_CLOEXEC);
+            libc::fcntl(fds[1], libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+        created
+    };
+    if created != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safety: pipe(2) just returned these two descriptors and nothing else owns them.
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Sets `O_NONBLOCK` on a descriptor we own.
+fn set_nonblocking(fd: &OwnedFd) -> io::Result<()> {
+    // Safety: fcntl on a descriptor we own.
+    unsafe {
+        let flags = libc::fcntl(fd.as_raw_fd(), libc::F_GETFL);
+        if flags == -1 || libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+impl HeartbeatPipe {
+    pub fn create(child_fd: i32) -> io::Result<Self> {
+        let (read, write) = cloexec_pipe()?;
+        Ok(HeartbeatPipe {
+            read,
+            write,
+            child_fd,
+        })
+    }
+
+    /// Arranges for the write end to appear as `child_fd` in the child and advertises it
+    /// through `PING_GUARD_FD`.
+    pub fn attach(&self, command: &mut Command) {
+        let write_fd = self.write.as_raw_fd();
+        let child_fd = self.child_fd;
+        command.env(FD_ENV, child_fd.to_string());
+        // Safety: dup2 and fcntl are async-signal-safe and only touch the forked child.
+        unsafe {
+            command.pre_exec(move || {
+                if write_fd == child_fd {
+                    // dup2 onto itself keeps close-on-exec set, so clear it explicitly.
+                    if libc::fcntl(child_fd, libc::F_SETFD, 0) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                } else if libc::dup2(write_fd, child_fd) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Drops the watchdog's copy of the write end (so end-of-file is seen once the child's
+    /// copies are closed) and returns the read end ready for async reads.
+    /// Call this after the child has been spawned.
+    pub fn into_reader(self) -> io::Result<AsyncFd<OwnedFd>> {
+        drop(self.write);
+        set_nonblocking(&self.read)?;
+        AsyncFd::new(self.read)
+    }
+}
+
+/// A pipe whose read end becomes the child's stdin while the watchdog holds the write end.
+/// Nothing is ever written; the write end reports an error as soon as the child (and anything
+/// it handed its stdin to) has closed the read end, which is how the child going away is
+/// noticed even before it is reaped.
+pub struct StdinPipe {
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+impl StdinPipe {
+    pub fn create() -> io::Result<Self> {
+        let (read, write) = cloexec_pipe()?;
+        Ok(StdinPipe { read, write })
+    }
+
+    /// Arranges for the read end to become the child's stdin.
+    pub fn attach(&self, command: &mut Command) {
+        let read_fd = self.read.as_raw_fd();
+        // Safety: dup2 is async-signal-safe and only touches the forked child.
+        unsafe {
+            command.pre_exec(move || {
+                if libc::dup2(read_fd, libc::STDIN_FILENO) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Drops the watchdog's copy of the read end (so only the child holds it) and returns the
+    /// write end ready to be watched. Call this after the child has been spawned.
+    pub fn into_watcher(self) -> io::Result<AsyncFd<OwnedFd>> {
+        drop(self.read);
+        set_nonblocking(&self.write)?;
+        AsyncFd::with_interest(self.write, Interest::WRITABLE)
+    }
+}
+
+/// Treats every write on the heartbeat pipe as a heartbeat until the child closes it. With
+/// `close_kills`, the close itself asks the monitor to act instead of waiting for the timeout.
+pub async fn run_pipe_listener(reader: AsyncFd<OwnedFd>, close_kills: bool, sink: HeartbeatSink) {
+    let mut buf = [0u8; 256];
+    loop {
+        match fdio::read(&reader, &mut buf).await {
+            Ok(0) if close_kills => {
+                info!("Heartbeat pipe closed by the child.");
+                sink.trigger("child closed its heartbeat pipe".to_string());
+                break;
+            }
+            Ok(0) => {
+                info!("Heartbeat pipe closed by the child; no more heartbeats will arrive on it.");
+                break;
+            }
+            Ok(_) => {
+                Stats::bump(&sink.stats.packets_received);
+                if !sink.beat() {
+                    break;
+                }
+            }
+            Err(e) => {
+                Error::Receive {
+                    what: "heartbeat pipe".to_string(),
+                    source: e,
+                }
+                .report();
+                break;
+            }
+        }
+    }
+}
+
+/// Waits until the child has closed its stdin pipe and then asks the monitor to act.
+pub async fn run_stdin_watcher(watcher: AsyncFd<OwnedFd>, trigger: Trigger) {
+    loop {
+        match watcher.writable().await {
+            // The pipe is writable from the start; only the edge to "closed" matters, and
+            // readiness is edge-triggered, so clearing it waits for the next change.
+            Ok(mut guard) if !guard.ready().is_write_closed() => guard.clear_ready(),
+            Ok(_) => {
+                info!("Child closed its stdin pipe.");
+                trigger.declare_dead("child closed its stdin".to_string());
+                break;
+            }
+            Err(e) => {
+                error!("Error watching the child's stdin pipe: {}. Stopping.", e);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use crate::audit;
+use crate::output::OutputTail;
+use crate::stats::Stats;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+/// Where a report on a child that missed its deadline is written before the child is
+/// terminated (`--incident-report`), so that a post-mortem has more to go on than the log.
+#[derive(Debug)]
+pub struct IncidentReport {
+    pub dir: PathBuf,
+}
+
+/// What happened, as the monitor saw it.
+pub struct Incident<'a> {
+    pub reason: &'a str,
+    pub message: &'a str,
+    pub pid: u32,
+    pub elapsed_ms: u64,
+    pub limit_ms: u64,
+    pub last_heartbeat_ms: Option<u64>,
+    pub action: &'a str,
+}
+
+impl IncidentReport {
+    /// Writes a report on `incident` to a file of its own, e.g.
+    /// `incident-1718000000000-4242.json`, with what the child's process looked like and the
+    /// last lines of its output. Returns the path it was written to.
+    pub fn write(
+        &self,
+        incident: &Incident,
+        stats: &Stats,
+        output: Option<&OutputTail>,
+    ) -> std::io::Result<PathBuf> {
+        let now = audit::now_ms();
+        let mut report = json!({
+            "ts_ms": now,
+            "reason": incident.reason,
+            "message": incident.message,
+            "action": incident.action,
+            "elapsed_ms": incident.elapsed_ms,
+            "limit_ms": incident.limit_ms,
+            "last_heartbeat_ms": incident.last_heartbeat_ms,
+            "heartbeats": stats.report(),
+            "child": process(incident.pid),
+            "output": output.map_or(Value::Null, |output| {
+                output
+                    .lines()
+                    .into_iter()
+                    .map(|(stream, line)| json!({ "stream": stream, "line": line }))
+                    .collect()
+            }),
+        });
+        if let Some(name) = crate::log::instance_name() {
+            report["name"] = name.into();
+        }
+        let path = self
+            .dir
+            .join(format!("incident-{}-{}.json", now, incident.pid));
+        let mut text = serde_json::to_string_pretty(&report).map_err(std::io::Error::other)?;
+        text.push('\n');
+        std::fs::write(&path, text)?;
+        Ok(path)
+    }
+}
+
+/// The child's process as /proc shows it: its status and, for each thread, its state, what
+/// it waits in and its kernel stack (readable by root only).
+#[cfg(target_os = "linux")]
+fn process(pid: u32) -> Value {
+    let read = |path: String| std::fs::read_to_string(path).ok();
+    let status: serde_json::Map<String, Value> = read(format!("/proc/{}/status", pid))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.to_string(), value.trim().into()))
+        .collect();
+    let mut tids: Vec<u32> = std::fs::read_dir(format!("/proc/{}/task", pid))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    tids.sort_unstable();
+    let threads: Vec<Value> = tids
+        .into_iter()
+        .map(|tid| {
+            let task = format!("/proc/{}/task/{}", pid, tid);
+            // The state follows the name, which is in parentheses and may contain anything.
+            let state = read(format!("{}/stat", task)).and_then(|stat| {
+                Some(
+                    stat.rsplit_once(") ")?
+                        .1
+                        .split_whitespace()
+                        .next()?
+                        .to_string(),
+                )
+            });
+            json!({
+                "tid": tid,
+                "name": read(format!("{}/comm", task)).map(|name| name.trim().to_string()),
+                "state": state,
+                "wchan": read(format!("{}/wchan", task)),
+                "stack": read(format!("{}/stack", task))
+                    .map(|stack| stack.lines().map(str::to_string).collect::<Vec<_>>()),
+            })
+        })
+        .collect();
+    json!({ "pid": pid, "status": status, "threads": threads })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process(pid: u32) -> Value {
+    json!({ "pid": pid })
+}
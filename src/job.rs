@@ -0,0 +1,208 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tokio::process::{Child, Command};
+use windows_sys::Win32::Foundation::{CloseHandle, HANDLE, INVALID_HANDLE_VALUE};
+use windows_sys::Win32::System::Diagnostics::ToolHelp::{
+    CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32,
+};
+use windows_sys::Win32::System::JobObjects::{
+    AssignProcessToJobObject, CreateJobObjectW, JobObjectBasicAccountingInformation,
+    JobObjectExtendedLimitInformation, QueryInformationJobObject, SetInformationJobObject,
+    TerminateJobObject, JOBOBJECT_BASIC_ACCOUNTING_INFORMATION,
+    JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+};
+use windows_sys::Win32::System::Threading::{
+    OpenThread, ResumeThread, CREATE_SUSPENDED, THREAD_SUSPEND_RESUME,
+};
+
+/// Whether children are put in Job Objects: unless they are to be left running when the
+/// watchdog exits (`--observe-only`, `--no-kill-on-exit`).
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The Job Object of each child that runs in one, by the child's PID. The handles are never
+/// closed while the child may still be running: Windows closes them when the watchdog exits,
+/// however it exits, which kills whatever is left in the jobs.
+static JOBS: Mutex<Vec<(u32, Job)>> = Mutex::new(Vec::new());
+
+/// A Job Object holding a child and all the processes it starts, which are killed together
+/// when the job is terminated or its last handle is closed.
+struct Job(HANDLE);
+
+// Safety: a job handle may be used from any thread.
+unsafe impl Send for Job {}
+
+impl Job {
+    fn new() -> io::Result<Self> {
+        // Safety: an unnamed job with default security; the handle is owned by the Job.
+        let handle = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let job = Job(handle);
+        // Safety: all-zero is a valid JOBOBJECT_EXTENDED_LIMIT_INFORMATION (no limits).
+        let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { std::mem::zeroed() };
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        // Safety: the buffer is the structure the information class calls for.
+        let set = unsafe {
+            SetInformationJobObject(
+                job.0,
+                JobObjectExtendedLimitInformation,
+                (&limits as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION).cast(),
+                std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            )
+        };
+        if set == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(job)
+    }
+
+    /// How many processes are still running in the job.
+    fn active_processes(&self) -> io::Result<u32> {
+        // Safety: all-zero is a valid JOBOBJECT_BASIC_ACCOUNTING_INFORMATION.
+        let mut accounting: JOBOBJECT_BASIC_ACCOUNTING_INFORMATION = unsafe { std::mem::zeroed() };
+        // Safety: the buffer is the structure the information class calls for.
+        let queried = unsafe {
+            QueryInformationJobObject(
+                self.0,
+                JobObjectBasicAccountingInformation,
+                (&mut accounting as *mut JOBOBJECT_BASIC_ACCOUNTING_INFORMATION).cast(),
+                std::mem::size_of::<JOBOBJECT_BASIC_ACCOUNTING_INFORMATION>() as u32,
+                std::ptr::null_mut(),
+            )
+        };
+        if queried == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(accounting.ActiveProcesses)
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        // Safety: the handle is owned by the Job and closed only here.
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Spawns the child with the process creation `flags`, in a Job Object of its own, so that it
+/// is killed along with everything it starts. The child is created suspended and only resumed
+/// once it is in the job, so that nothing it starts escapes the job; should the job fail, it
+/// runs without one.
+pub fn spawn(command: &mut Command, flags: u32) -> io::Result<Child> {
+    if !enabled() {
+        command.creation_flags(flags);
+        return command.spawn();
+    }
+    command.creation_flags(flags | CREATE_SUSPENDED);
+    let mut child = command.spawn()?;
+    let pid = child.id().unwrap_or_default();
+    if let Err(e) = assign(&child) {
+        error!(
+            "Failed to put child process {} in a Job Object; only the child itself can be killed: {}",
+            pid, e
+        );
+    }
+    if let Err(e) = resume(pid) {
+        let _ = child.start_kill();
+        return Err(io::Error::new(
+            e.kind(),
+            format!("failed to resume the suspended child: {}", e),
+        ));
+    }
+    Ok(child)
+}
+
+fn assign(child: &Child) -> io::Result<()> {
+    let (Some(pid), Some(process)) = (child.id(), child.raw_handle()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "the child has already exited",
+        ));
+    };
+    let job = Job::new()?;
+    // Safety: both handles are valid for the duration of the call.
+    if unsafe { AssignProcessToJobObject(job.0, process as HANDLE) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    JOBS.lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push((pid, job));
+    Ok(())
+}
+
+/// Resumes the threads of the process with `pid`; a process created suspended has just one.
+fn resume(pid: u32) -> io::Result<()> {
+    // Safety: the snapshot handle is closed below.
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) };
+    if snapshot == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    // Safety: all-zero is a valid THREADENTRY32 once its size is set.
+    let mut entry: THREADENTRY32 = unsafe { std::mem::zeroed() };
+    entry.dwSize = std::mem::size_of::<THREADENTRY32>() as u32;
+    let mut result = Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "the process has no threads",
+    ));
+    // Safety: the snapshot is valid and the entry's size is set.
+    let mut more = unsafe { Thread32First(snapshot, &mut entry) } != 0;
+    while more {
+        if entry.th32OwnerProcessID == pid {
+            // Safety: the thread handle is closed right after use.
+            let thread = unsafe { OpenThread(THREAD_SUSPEND_RESUME, 0, entry.th32ThreadID) };
+            result = if thread.is_null() {
+                Err(io::Error::last_os_error())
+            } else {
+                // Safety: a thread handle opened with THREAD_SUSPEND_RESUME.
+                let resumed = unsafe { ResumeThread(thread) };
+                let error = io::Error::last_os_error();
+                // Safety: the handle was opened above.
+                unsafe { CloseHandle(thread) };
+                if resumed == u32::MAX {
+                    Err(error)
+                } else {
+                    Ok(())
+                }
+            };
+        }
+        // Safety: as for Thread32First.
+        more = unsafe { Thread32Next(snapshot, &mut entry) } != 0;
+    }
+    // Safety: the snapshot was opened above.
+    unsafe { CloseHandle(snapshot) };
+    result
+}
+
+/// Whether the child with `pid` runs in a Job Object.
+pub fn has_job(pid: u32) -> bool {
+    JOBS.lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .any(|(job_pid, _)| *job_pid == pid)
+}
+
+/// Kills every process in the job of the child with `pid`. Returns `None` if the child does
+/// not run in a job, or else how many processes were left running in the job right after.
+pub fn terminate(pid: u32) -> Option<io::Result<u32>> {
+    let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+    let index = jobs.iter().position(|(job_pid, _)| *job_pid == pid)?;
+    let (_, job) = &jobs[index];
+    // Safety: the handle is valid while the Job is in JOBS.
+    if unsafe { TerminateJobObject(job.0, 1) } == 0 {
+        return Some(Err(io::Error::last_os_error()));
+    }
+    let left = job.active_processes();
+    // Closing the handle kills anything that still makes it into the job.
+    jobs.remove(index);
+    Some(left)
+}
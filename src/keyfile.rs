@@ -0,0 +1,135 @@
+use crate::auth;
+use std::fmt;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::time::{interval, MissedTickBehavior};
+
+/// How long the previous key is still accepted after the key file changes, without
+/// `--key-rotation-overlap`.
+pub const DEFAULT_OVERLAP: Duration = Duration::from_secs(300);
+
+/// How often the key file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What identifies a version of the key file without reading it.
+type Stamp = (Option<SystemTime>, u64);
+
+fn stamp(metadata: &Metadata) -> Stamp {
+    (metadata.modified().ok(), metadata.len())
+}
+
+/// A shared key read from a file (see [`auth::read_key`]) that is reloaded when the file
+/// changes, so keys can be rotated without restarting the watchdog. After a change the
+/// previous key stays valid for the overlap window, giving senders time to switch.
+pub struct KeyFile<T> {
+    path: PathBuf,
+    /// Names the key in log messages, e.g. "HMAC".
+    what: &'static str,
+    overlap: Duration,
+    /// Turns the key file's contents into what is used to check packets.
+    derive: fn(&[u8]) -> T,
+    keys: RwLock<Keys<T>>,
+}
+
+struct Keys<T> {
+    raw: Vec<u8>,
+    stamp: Option<Stamp>,
+    current: T,
+    /// The key before the last change, and until when it is accepted.
+    previous: Option<(T, Instant)>,
+}
+
+// The keys stay out of debug output.
+impl<T> fmt::Debug for KeyFile<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KeyFile")
+            .field("path", &self.path)
+            .field("overlap", &self.overlap)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Send + Sync + 'static> KeyFile<T> {
+    pub fn load(
+        path: &Path,
+        what: &'static str,
+        overlap: Duration,
+        derive: fn(&[u8]) -> T,
+    ) -> Result<Arc<Self>, String> {
+        let stamp = std::fs::metadata(path)
+            .ok()
+            .map(|metadata| stamp(&metadata));
+        let raw = auth::read_key(path, what)?;
+        Ok(Arc::new(KeyFile {
+            path: path.to_path_buf(),
+            what,
+            overlap,
+            derive,
+            keys: RwLock::new(Keys {
+                current: derive(&raw),
+                raw,
+                stamp,
+                previous: None,
+            }),
+        }))
+    }
+
+    /// Tries `check` with the current key, then with the previous one while the overlap window
+    /// lasts. Returns the first success, or the current key's error.
+    pub fn check<R, E>(&self, mut check: impl FnMut(&T) -> Result<R, E>) -> Result<R, E> {
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+        let error = match check(&keys.current) {
+            Ok(result) => return Ok(result),
+            Err(e) => e,
+        };
+        match &keys.previous {
+            Some((previous, until)) if Instant::now() < *until => {
+                check(previous).map_err(|_| error)
+            }
+            _ => Err(error),
+        }
+    }
+
+    /// Checks the key file for changes until the process exits. A file that cannot be read or
+    /// holds an invalid key is reported once per change, and the current key stays in use.
+    pub async fn watch(self: Arc<Self>) {
+        let mut ticker = interval(POLL_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let Ok(metadata) = std::fs::metadata(&self.path) else {
+                // Missing for a moment while being replaced; the next change is picked up.
+                continue;
+            };
+            let stamp = Some(stamp(&metadata));
+            if stamp == self.keys.read().unwrap_or_else(|e| e.into_inner()).stamp {
+                continue;
+            }
+            let raw = auth::read_key(&self.path, self.what);
+            let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+            keys.stamp = stamp;
+            match raw {
+                Ok(raw) if raw == keys.raw => {}
+                Ok(raw) => {
+                    let current = std::mem::replace(&mut keys.current, (self.derive)(&raw));
+                    keys.previous = Some((current, Instant::now() + self.overlap));
+                    keys.raw = raw;
+                    info!(
+                        "{} key file {} changed; accepting the previous key for another {:?}.",
+                        self.what,
+                        self.path.display(),
+                        self.overlap
+                    );
+                }
+                Err(e) => error!(
+                    "{} key file {} could not be reloaded, keeping the current key: {}",
+                    self.what,
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+    }
+}
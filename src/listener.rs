@@ -0,0 +1,1161 @@
+use crate::audit;
+use crate::auth::{Authenticator, Rejection};
+use crate::command::Command;
+use crate::encryption::Decryptor;
+use crate::error::Error;
+use crate::filter::SourceFilter;
+use crate::heartbeat::HeartbeatSink;
+use crate::payload::{self, Codec, Expected};
+use crate::protocol;
+use crate::ratelimit::{Exceeded, RateLimiter};
+use crate::sampler::Sampler;
+use crate::stats::Stats;
+use crate::status::{self, Status};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::sync::mpsc;
+
+/// Largest payload a UDP datagram can carry.
+pub const MAX_UDP_PAYLOAD: usize = 65507;
+
+/// Controls how the UDP listener sockets are bound, on dual-stack and multi-homed hosts.
+#[derive(Debug, Clone)]
+pub struct BindOptions {
+    /// Set IPV6_V6ONLY on IPv6 sockets so they never see IPv4-mapped traffic.
+    pub ipv6_only: bool,
+    /// Also bind the other address family on the same port for every listen address.
+    pub dual_stack: bool,
+    /// SO_RCVBUF to request (`--recv-buffer-size`).
+    pub recv_buffer_size: Option<usize>,
+    /// Set SO_REUSEADDR before binding (`--reuse-addr`).
+    pub reuse_addr: bool,
+    /// Interface to bind to with SO_BINDTODEVICE (`--bind-device`, Linux only).
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub bind_device: Option<String>,
+    /// Have the kernel report every packet's TTL or hop limit, for `--min-ttl` (Linux only).
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub report_ttl: bool,
+    /// How often to retry an address that is in use or not available yet (`--bind-retries`).
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every further one (`--bind-backoff`).
+    pub backoff: Duration,
+}
+
+/// Longest delay between bind retries, however many there are.
+const MAX_BIND_BACKOFF: Duration = Duration::from_secs(5);
+
+/// What a received packet must satisfy to count as a heartbeat, and how it is decoded.
+#[derive(Debug)]
+pub struct PacketPolicy {
+    /// With `--rate-limit` or `--source-rate-limit`, packets beyond the limits are dropped
+    /// before anything else.
+    pub rate_limit: RateLimiter,
+    pub filter: SourceFilter,
+    /// Largest packet (or DTLS record) processed (`--max-packet-size`); larger ones are
+    /// dropped, since a datagram read into a buffer of this size would be cut short.
+    pub max_packet_size: usize,
+    /// With `--log-payloads`, every packet's contents are logged before they are checked.
+    pub log_payloads: bool,
+    /// Which accepted heartbeats to log (`--log-heartbeats`), if any.
+    pub log_heartbeats: Option<Sampler>,
+    /// With `--strict-protocol`, packets must be framed (see [`protocol::unframe`]).
+    pub strict_protocol: bool,
+    /// Lowest TTL or IPv6 hop limit accepted (`--min-ttl`).
+    pub min_ttl: Option<u8>,
+    /// With `--dtls`, heartbeats arrive in DTLS sessions; the rest applies to their records.
+    #[cfg(feature = "dtls")]
+    pub dtls: Option<Arc<crate::dtls::Server>>,
+    /// With `--encryption-key-file`, packets must be encrypted; the rest applies to their
+    /// decrypted contents.
+    pub decrypt: Option<Decryptor>,
+    /// With `--hmac-key-file`, packets must be signed.
+    pub auth: Option<Authenticator>,
+    /// With `--expect-payload`, packets must carry the token.
+    pub expect: Option<Expected>,
+    pub codec: Codec,
+    /// What pongs and status replies report.
+    pub status: Status,
+    /// With `--ack`, every accepted heartbeat is answered with a pong.
+    pub ack: bool,
+    /// With `--status-query`, status queries are answered instead of counting as heartbeats.
+    pub status_query: bool,
+    /// With `--allow-commands`, where control commands are sent, with the sender they came
+    /// from; without it, a packet that looks like a command is just a heartbeat.
+    pub commands: Option<mpsc::Sender<(Command, String)>>,
+    /// With `--allow-extend` but not `--allow-commands`, only `EXTEND` is accepted.
+    pub extend_only: bool,
+    /// With `--max-extension`, a longer `EXTEND` is granted only this much.
+    pub max_extension: Option<Duration>,
+    /// Whether the child can be restarted, so `RESTART` is accepted.
+    pub restartable: bool,
+}
+
+/// What a received packet turned out to be.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Heartbeat,
+    /// A status query (`--status-query`), to be answered with [`Status::report`].
+    Query,
+    /// A control command (`--allow-commands`), or why it is malformed; to be handed to
+    /// [`submit_command`].
+    Command(Result<Command, String>),
+    /// Dropped by the source filter or one of the checks, or not a heartbeat for another
+    /// reason; already counted and logged.
+    Rejected,
+}
+
+/// Resolves the configured listen addresses into the concrete socket addresses to bind,
+/// adding the opposite-family counterpart of each address in dual-stack mode.
+pub async fn resolve_listen_addrs(
+    listen_addrs: &[String],
+    dual_stack: bool,
+) -> std::io::Result<Vec<SocketAddr>> {
+    let mut resolved: Vec<SocketAddr> = Vec::new();
+    for listen_addr in listen_addrs {
+        let addr = lookup_host(listen_addr.as_str())
+            .await?
+            .next()
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("'{}' did not resolve to any address", listen_addr),
+                )
+            })?;
+        if !resolved.contains(&addr) {
+            resolved.push(addr);
+        }
+        if dual_stack {
+            match dual_stack_counterpart(addr) {
+                Some(other) if !resolved.contains(&other) => resolved.push(other),
+                Some(_) => {}
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "--dual-stack can only mirror wildcard or loopback addresses, not {}; pass --listen-addr once per family instead",
+                            addr
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+/// Maps a wildcard or loopback address to the equivalent address in the other family.
+fn dual_stack_counterpart(addr: SocketAddr) -> Option<SocketAddr> {
+    let ip = match addr.ip() {
+        IpAddr::V4(v4) if v4.is_unspecified() => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        IpAddr::V4(v4) if v4.is_loopback() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        IpAddr::V6(v6) if v6.is_unspecified() => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(v6) if v6.is_loopback() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, addr.port()))
+}
+
+/// Binds a UDP socket, always setting IPV6_V6ONLY explicitly for IPv6 addresses
+/// instead of relying on the platform default (on for Windows/BSD, off for Linux).
+fn bind_udp(addr: SocketAddr, v6_only: bool, options: &BindOptions) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(v6_only)?;
+    }
+    if options.reuse_addr {
+        socket.set_reuse_address(true)?;
+    }
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(device) = &options.bind_device {
+        socket.bind_device(Some(device.as_bytes()))?;
+    }
+    #[cfg(target_os = "linux")]
+    if options.report_ttl {
+        report_ttl(&socket, addr.is_ipv6())?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Has the kernel attach the TTL (IPv4) or hop limit (IPv6) to every packet received, as read
+/// by [`recv_batch`]. Linux only enforces `IP_MINTTL` for TCP, so `--min-ttl` is checked here.
+/// IPv6 sockets also report the TTL of IPv4-mapped senders.
+#[cfg(target_os = "linux")]
+fn report_ttl(socket: &impl std::os::fd::AsRawFd, ipv6: bool) -> std::io::Result<()> {
+    let mut options = vec![(libc::IPPROTO_IP, libc::IP_RECVTTL)];
+    if ipv6 {
+        options.push((libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT));
+    }
+    let enable: libc::c_int = 1;
+    for (level, option) in options {
+        // Safety: setsockopt on a socket we own, with a correctly sized int option.
+        let set = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                option,
+                &enable as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if set != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Environment variables used by systemd socket activation (see sd_listen_fds(3)).
+pub const SOCKET_ACTIVATION_ENV: [&str; 3] = ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"];
+
+/// Takes ownership of the datagram sockets passed in by systemd socket activation, if any.
+/// Must run before the child is spawned: the descriptors are marked close-on-exec here so
+/// the child never inherits the watchdog's port.
+#[cfg(unix)]
+pub fn take_activated_sockets() -> Vec<std::net::UdpSocket> {
+    use std::os::fd::FromRawFd;
+    const SD_LISTEN_FDS_START: i32 = 3;
+
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<i32>().ok())
+        .unwrap_or(0);
+    if !for_us || count <= 0 {
+        return Vec::new();
+    }
+
+    let mut sockets = Vec::new();
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+        // Safety: systemd hands these descriptors to this process and nothing else owns them.
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        if let Err(e) = socket.set_cloexec(true) {
+            error!("Failed to set close-on-exec on activated fd {}: {}", fd, e);
+        }
+        match socket.r#type() {
+            Ok(Type::DGRAM) => sockets.push(socket.into()),
+            _ => {
+                error!(
+                    "Ignoring activated fd {}: not a datagram socket (use ListenDatagram= in the .socket unit).",
+                    fd
+                );
+                // Leave the descriptor open; it is not ours to close.
+                std::mem::forget(socket);
+            }
+        }
+    }
+    sockets
+}
+
+#[cfg(not(unix))]
+pub fn take_activated_sockets() -> Vec<std::net::UdpSocket> {
+    Vec::new()
+}
+
+/// Binds every listen address (or adopts the socket-activated sockets), unless the sockets
+/// were `opened` already, and forwards received packets to the monitor as heartbeats.
+/// If the sockets cannot be set up, sends why on `failed` (so the monitor can terminate the
+/// child) and returns. Also returns (dropping `signal_tx`) if any socket stops receiving.
+#[allow(clippy::too_many_arguments)]
+pub async fn run_udp_listener(
+    listen_addrs: Vec<String>,
+    activated: Vec<std::net::UdpSocket>,
+    opened: Option<Vec<UdpSocket>>,
+    options: BindOptions,
+    policy: Arc<PacketPolicy>,
+    sink: HeartbeatSink,
+    low_power: bool,
+    failed: mpsc::Sender<Error>,
+) {
+    if !policy.rate_limit.is_empty() {
+        info!("Dropping packets beyond {}.", policy.rate_limit.describe());
+    }
+    if !policy.filter.is_empty() {
+        info!(
+            "Accepting heartbeats only from: {}",
+            policy.filter.describe()
+        );
+    }
+    if policy.log_payloads {
+        info!("Logging the contents of every packet.");
+    }
+    if let Some(sampler) = &policy.log_heartbeats {
+        info!("Logging accepted heartbeats: {}.", sampler.describe());
+    }
+    if let Some(min_ttl) = policy.min_ttl {
+        info!(
+            "Ignoring packets with a TTL or hop limit below {}.",
+            min_ttl
+        );
+    }
+    if policy.strict_protocol {
+        info!(
+            "Accepting only packets framed for protocol version {}.",
+            protocol::VERSION
+        );
+    }
+    #[cfg(feature = "dtls")]
+    if policy.dtls.is_some() {
+        info!("Accepting heartbeats only over DTLS.");
+    }
+    if policy.decrypt.is_some() {
+        info!("Accepting only encrypted heartbeats.");
+    }
+    if policy.auth.is_some() {
+        info!("Accepting only HMAC-authenticated heartbeats.");
+    }
+    if policy.expect.is_some() {
+        info!("Accepting only heartbeats carrying the expected payload.");
+    }
+    if policy.ack {
+        info!("Answering every accepted heartbeat with a pong.");
+    }
+    if policy.status_query {
+        info!("Answering status queries.");
+    }
+    if policy.commands.is_some() {
+        info!(
+            "Accepting {} from authenticated senders{}.",
+            if policy.extend_only {
+                "EXTEND requests"
+            } else {
+                "control commands"
+            },
+            policy
+                .max_extension
+                .map(|max| format!(" (extensions of up to {:?})", max))
+                .unwrap_or_default()
+        );
+    }
+    let sockets = match opened {
+        Some(sockets) => sockets,
+        None => match open_sockets(&listen_addrs, activated, &options).await {
+            Ok(sockets) => sockets,
+            Err(e) => {
+                // Sent before `sink` is dropped, so the monitor sees why the listener stopped.
+                let _ = failed.try_send(e);
+                return;
+            }
+        },
+    };
+    *sink
+        .stats
+        .udp_addrs
+        .lock()
+        .unwrap_or_else(|e| e.into_inner()) = sockets
+        .iter()
+        .filter_map(|socket| socket.local_addr().ok())
+        .collect();
+
+    #[cfg(unix)]
+    if low_power {
+        if let Err(e) = defer_sockets(sockets, policy, sink) {
+            let _ = failed.try_send(Error::Open {
+                what: "set up low-power UDP receive".to_string(),
+                reason: e.to_string(),
+            });
+        }
+        return;
+    }
+    #[cfg(not(unix))]
+    let _ = low_power;
+
+    let mut receivers = tokio::task::JoinSet::new();
+    for socket in sockets {
+        #[cfg(feature = "dtls")]
+        if let Some(server) = &policy.dtls {
+            receivers.spawn(crate::dtls::receive_loop(
+                socket,
+                server.clone(),
+                policy.clone(),
+                sink.clone(),
+            ));
+            continue;
+        }
+        #[cfg(target_os = "linux")]
+        receivers.spawn(receive_loop_batched(socket, policy.clone(), sink.clone()));
+        #[cfg(not(target_os = "linux"))]
+        receivers.spawn(receive_loop(socket, policy.clone(), sink.clone()));
+    }
+    drop(sink);
+    // The first socket to stop receiving ends the listener, mirroring the single-socket behavior.
+    receivers.join_next().await;
+}
+
+/// Binds every listen address, or adopts the socket-activated sockets if there are any.
+pub async fn open_sockets(
+    listen_addrs: &[String],
+    activated: Vec<std::net::UdpSocket>,
+    options: &BindOptions,
+) -> Result<Vec<UdpSocket>, Error> {
+    if activated.is_empty() {
+        return bind_listen_addrs(listen_addrs, options).await;
+    }
+    info!(
+        "Using {} UDP socket(s) passed by systemd socket activation; --listen-addr is ignored.",
+        activated.len()
+    );
+    let mut sockets = Vec::with_capacity(activated.len());
+    for socket in activated {
+        let adopted = socket.set_nonblocking(true);
+        #[cfg(target_os = "linux")]
+        let adopted = adopted.and_then(|()| match options.report_ttl {
+            true => report_ttl(&socket, socket.local_addr()?.is_ipv6()),
+            false => Ok(()),
+        });
+        let socket = adopted
+            .and_then(|()| UdpSocket::from_std(socket))
+            .map_err(|e| Error::Open {
+                what: "adopt activated UDP socket".to_string(),
+                reason: e.to_string(),
+            })?;
+        if let Ok(addr) = socket.local_addr() {
+            info!("UDP listener adopted activated socket on {}.", addr);
+        }
+        sockets.push(socket);
+    }
+    Ok(sockets)
+}
+
+/// Waits for the first packet from an allowed source on any of `sockets` (`--spawn-on-first-ping`)
+/// and returns its sender. The packet is left queued, so that the listener takes it as the first
+/// heartbeat once it starts; packets from disallowed sources are dropped.
+pub async fn first_ping(sockets: &[UdpSocket], filter: &SourceFilter) -> SocketAddr {
+    loop {
+        let (index, sender) = std::future::poll_fn(|cx| {
+            for (index, socket) in sockets.iter().enumerate() {
+                if let Poll::Ready(sender) = socket.poll_peek_sender(cx) {
+                    return Poll::Ready((index, sender));
+                }
+            }
+            Poll::Pending
+        })
+        .await;
+        match sender {
+            Ok(sender) if filter.allows(sender.ip()) => return sender,
+            Ok(sender) => info!(
+                "Ignoring UDP packet from disallowed source {} while waiting for the first heartbeat.",
+                sender
+            ),
+            Err(e) => error!("Failed to receive on UDP socket: {}", e),
+        }
+        // Drop the packet (or the error) so that the next one can be looked at.
+        let _ = sockets[index].try_recv_from(&mut [0; 1]);
+    }
+}
+
+/// Resolves and binds the configured listen addresses, logging any failure.
+async fn bind_listen_addrs(
+    listen_addrs: &[String],
+    options: &BindOptions,
+) -> Result<Vec<UdpSocket>, Error> {
+    info!(
+        "Starting UDP signal listener on {}",
+        listen_addrs.join(", ")
+    );
+    let addrs = resolve_listen_addrs(listen_addrs, options.dual_stack)
+        .await
+        .map_err(|e| Error::Resolve {
+            what: "listen address".to_string(),
+            source: e,
+        })?;
+
+    let mut sockets = Vec::with_capacity(addrs.len());
+    for addr in &addrs {
+        // An IPv6 wildcard socket without V6ONLY would collide with an IPv4 socket on the same port.
+        let v6_only = options.ipv6_only
+            || addrs
+                .iter()
+                .any(|other| other.is_ipv4() && other.port() == addr.port());
+        match bind_udp_retrying(*addr, v6_only, options).await {
+            Ok(socket) => {
+                if addr.is_ipv6() {
+                    info!(
+                        "UDP listener bound successfully on {} (IPV6_V6ONLY={}).",
+                        addr, v6_only
+                    );
+                } else {
+                    info!("UDP listener bound successfully on {}.", addr);
+                }
+                if let Some(requested) = options.recv_buffer_size {
+                    match socket2::SockRef::from(&socket).recv_buffer_size() {
+                        Ok(size) => info!(
+                            "Receive buffer on {}: {} bytes (requested {}).",
+                            addr, size, requested
+                        ),
+                        Err(e) => {
+                            error!("Could not read the receive buffer size on {}: {}", addr, e)
+                        }
+                    }
+                }
+                sockets.push(socket);
+            }
+            Err(e) => {
+                return Err(Error::Bind {
+                    what: format!("UDP socket on {}", addr),
+                    source: e,
+                });
+            }
+        }
+    }
+    Ok(sockets)
+}
+
+/// Like [`bind_udp`], but retries with exponential backoff while the address is in use (e.g.
+/// still held by a previous instance) or not available yet (e.g. not yet assigned at boot).
+async fn bind_udp_retrying(
+    addr: SocketAddr,
+    v6_only: bool,
+    options: &BindOptions,
+) -> std::io::Result<UdpSocket> {
+    let mut delay = options.backoff;
+    let mut attempt = 0;
+    loop {
+        let error = match bind_udp(addr, v6_only, options) {
+            Ok(socket) => return Ok(socket),
+            Err(e) => e,
+        };
+        let transient = matches!(
+            error.kind(),
+            std::io::ErrorKind::AddrInUse | std::io::ErrorKind::AddrNotAvailable
+        );
+        if !transient || attempt >= options.retries {
+            return Err(error);
+        }
+        attempt += 1;
+        error!(
+            "Failed to bind UDP socket on {}: {}; retrying in {:?} ({} of {}).",
+            addr, error, delay, attempt, options.retries
+        );
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_BIND_BACKOFF);
+    }
+}
+
+async fn receive_loop(socket: UdpSocket, policy: Arc<PacketPolicy>, sink: HeartbeatSink) {
+    // One byte more than the largest packet, so that a longer one is seen to be cut short.
+    let mut buf = vec![0; policy.max_packet_size + 1];
+    loop {
+        match socket.recv_from(&mut buf).await {
+            Ok((len, src_addr)) => {
+                if !handle_datagram(&socket, &buf[..len], src_addr, None, &policy, &sink).await {
+                    break;
+                }
+            }
+            Err(e) => {
+                // Errors here might indicate network issues or socket closure
+                Error::Receive {
+                    what: "UDP socket".to_string(),
+                    source: e,
+                }
+                .report();
+                break;
+            }
+        }
+    }
+}
+
+/// Like [`receive_loop`], but reads up to [`BATCH`] queued datagrams per system call, which
+/// matters when many senders heartbeat into one port at high rates.
+#[cfg(target_os = "linux")]
+async fn receive_loop_batched(socket: UdpSocket, policy: Arc<PacketPolicy>, sink: HeartbeatSink) {
+    let mut bufs = vec![vec![0; policy.max_packet_size + 1]; BATCH];
+    let mut received = Vec::with_capacity(BATCH);
+    loop {
+        let batch = match socket.readable().await {
+            Ok(()) => socket.try_io(tokio::io::Interest::READABLE, || {
+                recv_batch(&socket, &mut bufs, &mut received)
+            }),
+            Err(e) => Err(e),
+        };
+        match batch {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            // Some sandboxes do not implement recvmmsg.
+            Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => {
+                info!("Batched receive is not available; reading UDP packets one at a time.");
+                if policy.min_ttl.is_some() {
+                    error!(
+                        "Packet TTLs cannot be read without it, so --min-ttl ignores every packet."
+                    );
+                }
+                return receive_loop(socket, policy, sink).await;
+            }
+            Err(e) => {
+                Error::Receive {
+                    what: "UDP socket".to_string(),
+                    source: e,
+                }
+                .report();
+                break;
+            }
+        }
+        for (buf, &(len, src_addr, ttl)) in bufs.iter().zip(&received) {
+            if !handle_datagram(&socket, &buf[..len], src_addr, ttl, &policy, &sink).await {
+                return;
+            }
+        }
+    }
+}
+
+/// Datagrams read per `recvmmsg` call.
+#[cfg(target_os = "linux")]
+const BATCH: usize = 32;
+
+/// Reads queued datagrams into `bufs` with one `recvmmsg` call, replacing `received` with the
+/// length and sender of each. Fails with `WouldBlock` once the queue is empty.
+#[cfg(target_os = "linux")]
+fn recv_batch(
+    socket: &UdpSocket,
+    bufs: &mut [Vec<u8>],
+    received: &mut Vec<(usize, SocketAddr, Option<u8>)>,
+) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    received.clear();
+    // Safety: all-zero is a valid value for these plain C structs; every header is pointed at
+    // buffers that outlive the recvmmsg call below.
+    let mut addrs: [libc::sockaddr_storage; BATCH] = unsafe { std::mem::zeroed() };
+    let mut iovs: Vec<libc::iovec> = bufs
+        .iter_mut()
+        .map(|buf| libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        })
+        .collect();
+    // u64 elements keep the control buffers aligned for cmsghdr.
+    let mut controls = [[0u64; 8]; BATCH];
+    let mut msgs: [libc::mmsghdr; BATCH] = unsafe { std::mem::zeroed() };
+    for (((msg, addr), iov), control) in msgs
+        .iter_mut()
+        .zip(&mut addrs)
+        .zip(&mut iovs)
+        .zip(&mut controls)
+    {
+        msg.msg_hdr.msg_name = addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+        msg.msg_hdr.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        msg.msg_hdr.msg_iov = iov;
+        msg.msg_hdr.msg_iovlen = 1;
+        msg.msg_hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_hdr.msg_controllen = std::mem::size_of_val(control) as _;
+    }
+
+    let count = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            msgs.as_mut_ptr(),
+            iovs.len() as libc::c_uint,
+            libc::MSG_DONTWAIT as _,
+            std::ptr::null_mut(),
+        )
+    };
+    if count < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    for (msg, addr) in msgs.iter().zip(addrs).take(count as usize) {
+        // Safety: recvmmsg filled in `addr` and set `msg_namelen` to its length.
+        let src_addr = unsafe { socket2::SockAddr::new(addr, msg.msg_hdr.msg_namelen) }
+            .as_socket()
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "unexpected sender address")
+            })?;
+        received.push((msg.msg_len as usize, src_addr, received_ttl(&msg.msg_hdr)));
+    }
+    Ok(())
+}
+
+/// The TTL or hop limit the kernel attached to a received packet, if asked to (see
+/// [`report_ttl`]).
+#[cfg(target_os = "linux")]
+fn received_ttl(msg: &libc::msghdr) -> Option<u8> {
+    let mut ttl = None;
+    // Safety: the CMSG_* helpers walk the control buffer recvmmsg just filled in.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            let level = (*cmsg).cmsg_level;
+            let kind = (*cmsg).cmsg_type;
+            if (level == libc::IPPROTO_IP && kind == libc::IP_TTL)
+                || (level == libc::IPPROTO_IPV6 && kind == libc::IPV6_HOPLIMIT)
+            {
+                let value = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+                ttl = u8::try_from(value).ok();
+            }
+            cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+        }
+    }
+    ttl
+}
+
+/// Checks a datagram from `src_addr` and acts on what it turned out to be, answering the sender
+/// if needed. Returns false once the monitor has gone away.
+async fn handle_datagram(
+    socket: &UdpSocket,
+    data: &[u8],
+    src_addr: SocketAddr,
+    ttl: Option<u8>,
+    policy: &PacketPolicy,
+    sink: &HeartbeatSink,
+) -> bool {
+    let reply = match accept_packet(data, src_addr, ttl, audit::now_ms(), policy, sink) {
+        Verdict::Heartbeat => {
+            if !sink.beat_from(src_addr.ip()) {
+                // This happens if the monitor task has already exited
+                error!("Monitor task receiver dropped, stopping UDP listener.");
+                return false;
+            }
+            policy.ack.then(|| policy.status.pong(sink))
+        }
+        Verdict::Query => Some(policy.status.report(sink)),
+        Verdict::Command(command) => Some(submit_command(command, src_addr, policy)),
+        Verdict::Rejected => None,
+    };
+    if let Some(reply) = reply {
+        // Best effort: a sender that cannot be reached just gets no reply.
+        let _ = socket.send_to(&reply, src_addr).await;
+    }
+    true
+}
+
+/// Applies the source filter, the minimum TTL, decryption, authentication and any structured
+/// payload to a packet that arrived at `arrived_ms` (Unix milliseconds) with `ttl`, if known.
+fn accept_packet(
+    data: &[u8],
+    src_addr: SocketAddr,
+    ttl: Option<u8>,
+    arrived_ms: u64,
+    policy: &PacketPolicy,
+    sink: &HeartbeatSink,
+) -> Verdict {
+    if !admit_source(src_addr, policy, &sink.stats) {
+        return Verdict::Rejected;
+    }
+    if let Some(min_ttl) = policy.min_ttl {
+        // A packet whose TTL is unknown cannot be shown to come from close enough.
+        if ttl.is_none_or(|ttl| ttl < min_ttl) {
+            let low = Stats::bump(&sink.stats.low_ttl);
+            if low == 1 || low.is_multiple_of(100) {
+                let ttl = ttl.map_or("unknown".to_string(), |ttl| ttl.to_string());
+                error!(
+                    "Ignoring UDP packet from {} with TTL {} below --min-ttl {} ({} ignored so far).",
+                    src_addr, ttl, min_ttl, low
+                );
+            }
+            return Verdict::Rejected;
+        }
+    }
+    accept_contents(data, src_addr, arrived_ms, policy, sink)
+}
+
+/// Counts a packet from `src_addr` and applies the rate limits and the source filter. Returns
+/// whether the packet should be processed.
+pub fn admit_source(src_addr: SocketAddr, policy: &PacketPolicy, stats: &Stats) -> bool {
+    Stats::bump(&stats.packets_received);
+    if let Err(exceeded) = policy.rate_limit.admit(src_addr.ip()) {
+        let limited = Stats::bump(&stats.rate_limited);
+        if policy.rate_limit.should_report() {
+            let limit = match exceeded {
+                Exceeded::Source => "its sender's",
+                Exceeded::Global => "the overall",
+            };
+            error!(
+                "Dropping UDP packet from {} over {} rate limit ({} dropped so far).",
+                src_addr, limit, limited
+            );
+        }
+        return false;
+    }
+    if !policy.filter.allows(src_addr.ip()) {
+        let rejected = Stats::bump(&stats.rejected_source);
+        // Log the first rejection and then every 100th to keep floods out of the log.
+        if rejected == 1 || rejected.is_multiple_of(100) {
+            error!(
+                "Ignoring UDP packet from disallowed source {} ({} rejected so far).",
+                src_addr, rejected
+            );
+        }
+        return false;
+    }
+    true
+}
+
+/// Applies the size limit, protocol frame, decryption, authentication and any structured
+/// payload to the contents of a packet from an allowed source. Returns what the packet turned
+/// out to be.
+pub fn accept_contents(
+    data: &[u8],
+    src_addr: SocketAddr,
+    arrived_ms: u64,
+    policy: &PacketPolicy,
+    sink: &HeartbeatSink,
+) -> Verdict {
+    let stats = &sink.stats;
+    if policy.log_payloads {
+        let size = if data.len() > policy.max_packet_size {
+            format!("more than {} bytes", policy.max_packet_size)
+        } else {
+            format!("{} bytes", data.len())
+        };
+        info!(
+            "Packet from {} ({}): {}",
+            src_addr,
+            size,
+            data.escape_ascii()
+        );
+    }
+    if data.len() > policy.max_packet_size {
+        let oversized = Stats::bump(&stats.oversized);
+        if oversized == 1 || oversized.is_multiple_of(100) {
+            error!(
+                "Ignoring UDP packet from {} larger than --max-packet-size {} bytes ({} rejected so far).",
+                src_addr, policy.max_packet_size, oversized
+            );
+        }
+        return Verdict::Rejected;
+    }
+    let data = match protocol::unframe(data, policy.strict_protocol) {
+        Ok(contents) => contents,
+        Err(e) => {
+            let nonconforming = Stats::bump(&stats.nonconforming);
+            if nonconforming == 1 || nonconforming.is_multiple_of(100) {
+                error!(
+                    "Ignoring UDP packet from {} that does not conform to the protocol: {} ({} rejected so far).",
+                    src_addr, e, nonconforming
+                );
+            }
+            return Verdict::Rejected;
+        }
+    };
+    let decrypted;
+    let data = match &policy.decrypt {
+        Some(decryptor) => match decryptor.decrypt(data) {
+            Ok(plaintext) => {
+                decrypted = plaintext;
+                &decrypted[..]
+            }
+            Err(e) => {
+                let undecryptable = Stats::bump(&stats.undecryptable);
+                if undecryptable == 1 || undecryptable.is_multiple_of(100) {
+                    error!(
+                        "Ignoring UDP packet from {} that failed to decrypt: {} ({} rejected so far).",
+                        src_addr, e, undecryptable
+                    );
+                }
+                return Verdict::Rejected;
+            }
+        },
+        None => data,
+    };
+    let data = match &policy.auth {
+        Some(auth) => match auth.verify(data, arrived_ms) {
+            Ok((sent_ms, payload)) => {
+                sink.observe_clock(&src_addr.ip().to_string(), sent_ms, arrived_ms);
+                payload
+            }
+            Err(Rejection::Invalid(e)) => {
+                let unauthenticated = Stats::bump(&stats.unauthenticated);
+                if unauthenticated == 1 || unauthenticated.is_multiple_of(100) {
+                    error!(
+                        "Ignoring unauthenticated UDP packet from {}: {} ({} rejected so far).",
+                        src_addr, e, unauthenticated
+                    );
+                }
+                return Verdict::Rejected;
+            }
+            Err(Rejection::Replay(e)) => {
+                let replayed = Stats::bump(&stats.replayed);
+                if replayed == 1 || replayed.is_multiple_of(100) {
+                    error!(
+                        "Ignoring replayed UDP packet from {}: {} ({} replays rejected so far).",
+                        src_addr, e, replayed
+                    );
+                }
+                return Verdict::Rejected;
+            }
+        },
+        None => data,
+    };
+    // Queries and commands are not heartbeats, so they need no heartbeat token.
+    if policy.status_query && status::is_query(data) {
+        return Verdict::Query;
+    }
+    if policy.commands.is_some() {
+        if let Some(command) = Command::parse(data) {
+            return Verdict::Command(command);
+        }
+    }
+    if let Some(expect) = &policy.expect {
+        if !expect.matches(data) {
+            let unexpected = Stats::bump(&stats.unexpected_payload);
+            if unexpected == 1 || unexpected.is_multiple_of(100) {
+                error!(
+                    "Ignoring UDP packet from {} without the expected payload ({} rejected so far).",
+                    src_addr, unexpected
+                );
+            }
+            return Verdict::Rejected;
+        }
+    }
+    // Clocks belong to hosts, so senders are told apart by address alone.
+    if !payload::apply(data, policy.codec, src_addr.ip(), arrived_ms, sink) {
+        return Verdict::Rejected;
+    }
+    if let Some(skipped) = policy.log_heartbeats.as_ref().and_then(Sampler::sample) {
+        let skipped = match skipped {
+            0 => String::new(),
+            n => format!(" ({} more since the last one logged)", n),
+        };
+        info!(
+            "Heartbeat from {} ({} bytes): {}{}",
+            src_addr,
+            data.len(),
+            summarize(data),
+            skipped
+        );
+    }
+    Verdict::Heartbeat
+}
+
+/// Longest part of a heartbeat's contents that `--log-heartbeats` shows.
+const SUMMARY_LEN: usize = 64;
+
+/// The start of a heartbeat's contents, escaped for the log.
+fn summarize(data: &[u8]) -> String {
+    match data.get(..SUMMARY_LEN) {
+        Some(start) if data.len() > SUMMARY_LEN => format!("{}...", start.escape_ascii()),
+        _ => data.escape_ascii().to_string(),
+    }
+}
+
+/// Hands a command from `from` to the monitor, unless it is malformed or cannot be carried
+/// out. Returns the reply for the sender: whether the command was accepted, and if not, why.
+pub fn submit_command(
+    command: Result<Command, String>,
+    from: SocketAddr,
+    policy: &PacketPolicy,
+) -> Vec<u8> {
+    let result = command.and_then(|command| {
+        if policy.extend_only && !matches!(command, Command::Extend(_)) {
+            return Err(format!(
+                "{}: only EXTEND is allowed (--allow-extend)",
+                command
+            ));
+        }
+        let command = match (command, policy.max_extension) {
+            (Command::Extend(by), Some(max)) if by > max => {
+                info!(
+                    "{} from {} exceeds --max-extension; granting {:?}.",
+                    command, from, max
+                );
+                Command::Extend(max)
+            }
+            _ => command,
+        };
+        if command == Command::Restart && !policy.restartable {
+            return Err(format!(
+                "{}: restarting a child process with --heartbeat-fd, --stdin-pipe or resource limits is not supported",
+                command
+            ));
+        }
+        let Some(commands) = &policy.commands else {
+            return Err(format!("{}: commands are not enabled", command));
+        };
+        commands
+            .try_send((command, from.to_string()))
+            .map(|()| command)
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => {
+                    format!("{}: too many commands pending", command)
+                }
+                mpsc::error::TrySendError::Closed(_) => {
+                    format!("{}: the watchdog is shutting down", command)
+                }
+            })
+    });
+    let reply = match result {
+        Ok(command) => serde_json::json!({ "command": command.to_string(), "accepted": true }),
+        Err(e) => {
+            error!("Rejected command from {}: {}", from, e);
+            serde_json::json!({ "accepted": false, "error": e })
+        }
+    };
+    reply.to_string().into_bytes()
+}
+
+/// UDP sockets that are read in one batch whenever the monitor wakes up, instead of waking the
+/// watchdog for every packet (`--low-power`). The kernel's receive timestamps keep heartbeat
+/// times exact even though packets are read late.
+#[cfg(unix)]
+struct DeferredSockets {
+    sockets: Vec<std::net::UdpSocket>,
+    policy: Arc<PacketPolicy>,
+    /// Dropped once a socket stops receiving, as the listener task would drop its handle, so
+    /// the monitor notices when every heartbeat source has stopped.
+    sink: std::sync::Mutex<Option<HeartbeatSink>>,
+}
+
+#[cfg(unix)]
+static DEFERRED: std::sync::OnceLock<DeferredSockets> = std::sync::OnceLock::new();
+
+/// Takes the sockets off the reactor (so arriving packets no longer wake anything) and hands
+/// them to `drain_deferred`.
+#[cfg(unix)]
+fn defer_sockets(
+    sockets: Vec<UdpSocket>,
+    policy: Arc<PacketPolicy>,
+    sink: HeartbeatSink,
+) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let mut deferred = Vec::with_capacity(sockets.len());
+    for socket in sockets {
+        let socket = socket.into_std()?;
+        let enable: libc::c_int = 1;
+        // Safety: setsockopt on a socket we own, with a correctly sized int option.
+        let set = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_TIMESTAMP,
+                &enable as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if set != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        deferred.push(socket);
+    }
+    let _ = DEFERRED.set(DeferredSockets {
+        sockets: deferred,
+        policy,
+        sink: std::sync::Mutex::new(Some(sink)),
+    });
+    Ok(())
+}
+
+/// Reads every packet queued on the deferred sockets, recording heartbeats at their arrival
+/// times. Does nothing unless the listener runs in low-power mode, or once a socket has
+/// stopped receiving.
+#[cfg(unix)]
+pub fn drain_deferred() {
+    let Some(deferred) = DEFERRED.get() else {
+        return;
+    };
+    let mut held = deferred.sink.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(sink) = held.as_ref() else {
+        return;
+    };
+    let mut failed = false;
+    let mut buf = vec![0; deferred.policy.max_packet_size + 1];
+    'sockets: for socket in &deferred.sockets {
+        loop {
+            match recv_timestamped(socket, &mut buf) {
+                Ok(Some((len, src_addr, arrived))) => {
+                    let age = tokio::time::Instant::now().saturating_duration_since(arrived);
+                    let arrived_ms = audit::now_ms().saturating_sub(age.as_millis() as u64);
+                    let verdict = accept_packet(
+                        &buf[..len],
+                        src_addr,
+                        None,
+                        arrived_ms,
+                        &deferred.policy,
+                        sink,
+                    );
+                    if verdict == Verdict::Heartbeat {
+                        sink.beat_at(src_addr.ip(), arrived);
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    Error::Receive {
+                        what: "UDP socket".to_string(),
+                        source: e,
+                    }
+                    .report();
+                    // The first socket to stop receiving ends the listener, as without
+                    // --low-power.
+                    failed = true;
+                    break 'sockets;
+                }
+            }
+        }
+    }
+    if failed {
+        *held = None;
+    }
+}
+
+/// Non-blocking receive that also returns the kernel's arrival timestamp. Returns `Ok(None)`
+/// once the queue is empty.
+#[cfg(unix)]
+fn recv_timestamped(
+    socket: &std::net::UdpSocket,
+    buf: &mut [u8],
+) -> std::io::Result<Option<(usize, SocketAddr, tokio::time::Instant)>> {
+    use std::os::fd::AsRawFd;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    // Safety: all-zero is a valid value for these plain C structs; msghdr is pointed at
+    // buffers that outlive the recvmsg call below.
+    let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // u64 elements keep the control buffer aligned for cmsghdr.
+    let mut control = [0u64; 16];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = &mut addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    let received = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_DONTWAIT) };
+    if received < 0 {
+        let err = std::io::Error::last_os_error();
+        return match err.kind() {
+            std::io::ErrorKind::WouldBlock => Ok(None),
+            _ => Err(err),
+        };
+    }
+
+    let mut arrived = None;
+    // Safety: the CMSG_* helpers walk the control buffer recvmsg just filled in.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_TIMESTAMP {
+                let tv = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::timeval);
+                arrived =
+                    Some(UNIX_EPOCH + Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000));
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    // Translate the wall-clock arrival time onto the monotonic clock the monitor uses.
+    let now = tokio::time::Instant::now();
+    let arrived = arrived
+        .and_then(|at| SystemTime::now().duration_since(at).ok())
+        .map_or(now, |age| now.checked_sub(age).unwrap_or(now));
+
+    // Safety: recvmsg filled in `addr` and set `msg_namelen` to its length.
+    let src_addr = unsafe { socket2::SockAddr::new(addr, msg.msg_namelen) }
+        .as_socket()
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "unexpected sender address")
+        })?;
+    Ok(Some((received as usize, src_addr, arrived)))
+}
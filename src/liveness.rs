@@ -0,0 +1,149 @@
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Names of the heartbeat sources, as used by `--source-timeout` and in audit records.
+pub const SOURCES: [&str; 13] = [
+    "udp", "fd", "serial", "mq", "vsock", "stdout", "output", "log", "http", "exec", "tcp", "ping",
+    "udp-echo",
+];
+
+/// How the heartbeats of several sources combine into one verdict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Policy {
+    /// The child is alive while at least one source is within its timeout.
+    Any,
+    /// The child is alive only while every source is within its timeout.
+    All,
+}
+
+impl Policy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Policy::Any => "any",
+            Policy::All => "all",
+        }
+    }
+}
+
+/// Parses a `--source-timeout SOURCE=DURATION` entry.
+pub fn parse_source_timeout(value: &str) -> Result<(String, Duration), String> {
+    let (source, duration) = value
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' must be SOURCE=DURATION", value))?;
+    let source = source.trim();
+    if !SOURCES.contains(&source) {
+        return Err(format!(
+            "unknown source '{}' (expected one of: {})",
+            source,
+            SOURCES.join(", ")
+        ));
+    }
+    let duration = humantime::parse_duration(duration.trim())
+        .map_err(|e| format!("invalid duration '{}': {}", duration.trim(), e))?;
+    if duration.is_zero() {
+        return Err(format!("timeout for '{}' must be greater than 0", source));
+    }
+    Ok((source.to_string(), duration))
+}
+
+#[derive(Debug)]
+struct Clock {
+    last: Instant,
+    timeout: Duration,
+}
+
+/// Per-source heartbeat clocks combined under a policy.
+///
+/// The monitor only knows one heartbeat time and one timeout, so every beat is translated into
+/// the heartbeat time that puts the monitor's deadline where the policy's deadline is: the
+/// latest per-source deadline for `any`, the earliest for `all`.
+#[derive(Debug)]
+pub struct Liveness {
+    policy: Policy,
+    base: Duration,
+    timeouts: HashMap<String, Duration>,
+    clocks: Mutex<HashMap<&'static str, Clock>>,
+}
+
+impl Liveness {
+    /// `base` is the monitor's timeout, which also applies to sources without their own.
+    pub fn new(policy: Policy, base: Duration, timeouts: HashMap<String, Duration>) -> Self {
+        Liveness {
+            policy,
+            base,
+            timeouts,
+            clocks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    /// The timeout that applies to `source`.
+    pub fn timeout_for(&self, source: &str) -> Duration {
+        self.timeouts.get(source).copied().unwrap_or(self.base)
+    }
+
+    /// Adds `source` to the policy; its clock starts now, like the monitor's.
+    pub fn register(&self, source: &'static str) {
+        let timeout = self.timeout_for(source);
+        let mut clocks = self.clocks.lock().unwrap_or_else(|e| e.into_inner());
+        clocks.entry(source).or_insert(Clock {
+            last: Instant::now(),
+            timeout,
+        });
+    }
+
+    /// Records a heartbeat from `source` at `at` and returns the combined heartbeat time for
+    /// the monitor, which never moves backwards.
+    pub fn beat(&self, source: &'static str, at: Instant) -> Instant {
+        let mut clocks = self.clocks.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(clock) = clocks.get_mut(source) {
+            clock.last = clock.last.max(at);
+        }
+        self.combine(&clocks).unwrap_or(at)
+    }
+
+    /// The combined heartbeat time for the monitor.
+    pub fn current(&self) -> Instant {
+        let clocks = self.clocks.lock().unwrap_or_else(|e| e.into_inner());
+        self.combine(&clocks).unwrap_or_else(Instant::now)
+    }
+
+    /// Describes the sources that are past their timeout, as in "from udp for ~2.10s (limit:
+    /// 2.00s)". `limit` is the monitor's current timeout; a degraded child's longer timeout
+    /// extends every source's by the same amount.
+    pub fn describe_overdue(&self, limit: Duration) -> String {
+        let now = Instant::now();
+        let clocks = self.clocks.lock().unwrap_or_else(|e| e.into_inner());
+        let mut overdue: Vec<_> = clocks
+            .iter()
+            .map(|(source, clock)| {
+                let timeout = (clock.timeout + limit).saturating_sub(self.base);
+                (*source, now.duration_since(clock.last), timeout)
+            })
+            .filter(|(_, elapsed, timeout)| elapsed >= timeout)
+            .collect();
+        overdue.sort_by_key(|(source, _, _)| *source);
+        let sources: Vec<String> = overdue
+            .into_iter()
+            .map(|(source, elapsed, timeout)| {
+                format!("{} for ~{:.2?} (limit: {:.2?})", source, elapsed, timeout)
+            })
+            .collect();
+        format!("from {}", sources.join(", "))
+    }
+
+    fn combine(&self, clocks: &HashMap<&'static str, Clock>) -> Option<Instant> {
+        let deadlines = clocks.values().map(|clock| clock.last + clock.timeout);
+        let deadline = match self.policy {
+            Policy::Any => deadlines.max(),
+            Policy::All => deadlines.min(),
+        }?;
+        deadline.checked_sub(self.base)
+    }
+}
@@ -0,0 +1,150 @@
+use clap::ValueEnum;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+
+static INSTANCE_NAME: OnceLock<String> = OnceLock::new();
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+
+/// How log lines are written (`--log-format`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Plain lines, prefixed with `[name] ` when an instance name is set.
+    Text,
+    /// One JSON object per line, with `ts_ms`, `level`, `message`, `name` (when set) and, for
+    /// typed failures, `error` (the failure's kind).
+    Json,
+}
+
+/// Informational lines held back until the next `flush` (low-power mode).
+static BATCH: OnceLock<Mutex<String>> = OnceLock::new();
+
+/// Batched output is written early once it grows past this many bytes.
+const BATCH_LIMIT: usize = 64 * 1024;
+
+/// Sets the instance name used to label log lines. Only the first call has an effect.
+pub fn set_instance_name(name: String) {
+    let _ = INSTANCE_NAME.set(name);
+}
+
+/// The configured instance name, if any.
+pub fn instance_name() -> Option<&'static str> {
+    INSTANCE_NAME.get().map(String::as_str)
+}
+
+/// Sets the log format. Only the first call has an effect; until then lines are plain text.
+pub fn set_format(format: Format) {
+    let _ = FORMAT.set(format);
+}
+
+fn is_json() -> bool {
+    FORMAT.get() == Some(&Format::Json)
+}
+
+/// Prefix prepended to every log line: `[name] ` when an instance name is set.
+pub fn prefix() -> String {
+    match instance_name() {
+        Some(name) => format!("[{}] ", name),
+        None => String::new(),
+    }
+}
+
+/// Holds informational output back until `flush` instead of writing every line as it happens,
+/// so a quiet watchdog only touches stdout when it wakes up anyway.
+pub fn enable_batching() {
+    let _ = BATCH.set(Mutex::new(String::new()));
+}
+
+/// Formats one log line (without its newline) in the configured format.
+fn line(level: &str, kind: Option<&str>, args: fmt::Arguments) -> String {
+    if !is_json() {
+        return match kind {
+            Some(_) => format!("{}Error: {}", prefix(), args),
+            None => format!("{}{}", prefix(), args),
+        };
+    }
+    let mut record = serde_json::json!({
+        "ts_ms": crate::audit::now_ms(),
+        "level": level,
+        "message": args.to_string(),
+    });
+    if let Some(name) = instance_name() {
+        record["name"] = name.into();
+    }
+    if let Some(kind) = kind {
+        record["error"] = kind.into();
+    }
+    record.to_string()
+}
+
+/// Writes one informational line, or queues it while batching.
+pub fn info(args: fmt::Arguments) {
+    let line = line("info", None, args);
+    let Some(batch) = BATCH.get() else {
+        println!("{}", line);
+        return;
+    };
+    if let Ok(mut batch) = batch.lock() {
+        batch.push_str(&line);
+        batch.push('\n');
+        if batch.len() >= BATCH_LIMIT {
+            write_out(&mut batch);
+        }
+    }
+}
+
+/// Writes one error line, tagged with the failure's kind if it has one. Errors are never
+/// batched; pending informational lines are written first to keep the output in order.
+pub fn error(kind: Option<&str>, args: fmt::Arguments) {
+    flush();
+    eprintln!("{}", line("error", kind, args));
+}
+
+/// Writes a record of the watchdog exiting (`--log-format json` only).
+pub fn exit(reason: &str, code: i32) {
+    if is_json() {
+        let mut record = serde_json::json!({
+            "ts_ms": crate::audit::now_ms(),
+            "level": "info",
+            "message": format!("Exiting: {}.", reason),
+            "exit_reason": reason,
+            "exit_code": code,
+        });
+        if let Some(name) = instance_name() {
+            record["name"] = name.into();
+        }
+        println!("{}", record);
+    }
+}
+
+/// Writes any batched lines. Called when the monitor wakes up and before exiting.
+pub fn flush() {
+    if let Some(Ok(mut batch)) = BATCH.get().map(Mutex::lock) {
+        write_out(&mut batch);
+    }
+}
+
+fn write_out(batch: &mut String) {
+    if !batch.is_empty() {
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout.write_all(batch.as_bytes());
+        let _ = stdout.flush();
+        batch.clear();
+    }
+}
+
+/// Like `println!`, but labelled with the instance name.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::info(format_args!($($arg)*))
+    };
+}
+
+/// Like `eprintln!`, but labelled with the instance name. Errors are never batched; pending
+/// informational lines are written first to keep the output in order.
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::error(None, format_args!($($arg)*))
+    };
+}
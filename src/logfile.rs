@@ -0,0 +1,159 @@
+use crate::heartbeat::HeartbeatSink;
+use crate::stats::Stats;
+use regex::Regex;
+use std::fs::{File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::{interval, MissedTickBehavior};
+
+/// How often the log file is checked for growth.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Upper bound on a partial line kept while waiting for its newline, so a file that is
+/// never terminated cannot grow the buffer without limit.
+const MAX_LINE: usize = 64 * 1024;
+
+/// Identifies the file behind a path, so a rotated log (renamed away and recreated) is
+/// noticed even if the new file is already larger than the old one.
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.dev(), metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+/// The file currently being followed and how far it has been read.
+struct Tail {
+    file: File,
+    id: (u64, u64),
+    offset: u64,
+}
+
+impl Tail {
+    /// Opens `path` to read from its start, or only what is appended from now on.
+    fn open(path: &Path, at_end: bool) -> io::Result<Tail> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let offset = if at_end { metadata.len() } else { 0 };
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Tail {
+            file,
+            id: file_id(&metadata),
+            offset,
+        })
+    }
+
+    /// Reads whatever was appended since the last call.
+    fn read_new(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let n = self.file.read_to_end(buf)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+/// Follows the log file at `path` and forwards heartbeats to the monitor: any growth counts
+/// when `filter` is `None`, otherwise only complete lines that match it do. Content present
+/// at startup is skipped. A file that does not exist yet is waited for, and a rotated or
+/// truncated file is followed from its start.
+pub async fn run_logfile_listener(path: PathBuf, filter: Option<Regex>, sink: HeartbeatSink) {
+    let mut ticker = interval(POLL_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut tail = match Tail::open(&path, true) {
+        Ok(tail) => Some(tail),
+        Err(e) => {
+            info!(
+                "Log file {} is not readable yet ({}); waiting for it to appear.",
+                path.display(),
+                e
+            );
+            None
+        }
+    };
+    let mut reported = false;
+    let mut partial = Vec::new();
+    let mut buf = Vec::new();
+    loop {
+        ticker.tick().await;
+        if sink.is_closed() {
+            return;
+        }
+
+        // Switch to a new file if the path now names a different or shorter one.
+        let replaced = match (&tail, std::fs::metadata(&path)) {
+            (Some(tail), Ok(metadata)) => {
+                file_id(&metadata) != tail.id || metadata.len() < tail.offset
+            }
+            (None, Ok(_)) => true,
+            (_, Err(_)) => false,
+        };
+        if replaced {
+            // Drain the old file first: a rotated log may have been written to after the
+            // last check. Its last line ends with the file even without a newline.
+            if let Some(old) = tail.as_mut() {
+                if old.read_new(&mut buf).is_ok() && (!partial.is_empty() || !buf.is_empty()) {
+                    buf.push(b'\n');
+                }
+            }
+            match Tail::open(&path, false) {
+                Ok(new) => {
+                    if tail.is_some() {
+                        info!(
+                            "Log file {} was rotated or truncated; following the new file.",
+                            path.display()
+                        );
+                    } else {
+                        info!("Following log file {}.", path.display());
+                    }
+                    tail = Some(new);
+                    reported = false;
+                }
+                Err(e) if !reported => {
+                    error!("Failed to open log file {}: {}", path.display(), e);
+                    reported = true;
+                }
+                Err(_) => {}
+            }
+        }
+
+        if let Some(tail) = tail.as_mut() {
+            if let Err(e) = tail.read_new(&mut buf) {
+                if !reported {
+                    error!("Error reading log file {}: {}", path.display(), e);
+                    reported = true;
+                }
+            }
+        }
+        if buf.is_empty() {
+            continue;
+        }
+
+        Stats::bump(&sink.stats.packets_received);
+        let alive = match &filter {
+            None => true,
+            Some(regex) => {
+                partial.append(&mut buf);
+                let complete = partial
+                    .iter()
+                    .rposition(|&b| b == b'\n')
+                    .map_or(0, |i| i + 1);
+                let matched = partial[..complete]
+                    .split(|&b| b == b'\n')
+                    .any(|line| regex.is_match(&String::from_utf8_lossy(line)));
+                partial.drain(..complete);
+                if partial.len() > MAX_LINE {
+                    partial.clear();
+                }
+                matched
+            }
+        };
+        buf.clear();
+        if alive && !sink.beat() {
+            return;
+        }
+    }
+}
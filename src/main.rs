@@ -1,358 +1,3859 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 use std::process::Stdio;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::net::UdpSocket;
 use tokio::process::{Child, Command};
-use tokio::sync::watch;
+use tokio::sync::{mpsc, watch};
 use tokio::time::{sleep, Instant};
 
-// Platform-specific imports
+use error::Error;
+use exit::ExitReason;
+use heartbeat::{ChildState, HeartbeatSink};
+use procinfo::ProcessIdentity;
+use stats::Stats;
+use timeout_action::TimeoutAction;
+
+// Declared first so the logging macros are visible in every other module.
+#[macro_use]
+mod log;
+
+mod adaptive;
+mod analyze;
+#[cfg(unix)]
+mod attach;
+mod audit;
+mod auth;
+mod backoff;
+#[cfg(target_os = "linux")]
+mod cgroup;
+mod child;
+mod command;
+mod components;
+mod config;
+mod crash_loop;
+#[cfg(unix)]
+mod credentials;
+#[cfg(windows)]
+mod ctrl_break;
+mod diagnose;
+mod docker;
+#[cfg(feature = "dtls")]
+mod dtls;
+mod encryption;
+mod error;
+mod escalation;
+mod events;
+mod exit;
+#[cfg(unix)]
+mod fdio;
+mod filter;
 #[cfg(unix)]
-use libc;
+mod freeze;
+mod heartbeat;
+#[cfg(unix)]
+mod heartbeat_pipe;
+mod incident;
+#[cfg(windows)]
+mod job;
+mod keyfile;
+mod listener;
+mod liveness;
+mod logfile;
+mod metrics;
+#[cfg(target_os = "linux")]
+mod mq;
+mod output;
+mod payload;
+mod probe;
+mod procinfo;
+mod protocol;
+mod quorum;
+mod ratelimit;
+mod sampler;
+#[cfg(unix)]
+mod sandbox;
+#[cfg(target_os = "linux")]
+mod sd_notify;
+mod sequence;
+#[cfg(unix)]
+mod serial;
+mod signals;
+mod skew;
+mod stats;
+mod status;
+#[cfg(unix)]
+mod stop;
+#[cfg(target_os = "linux")]
+mod subreaper;
+mod suspend;
+mod throttle;
+mod timeout_action;
+#[cfg(target_os = "linux")]
+mod vsock;
+
 // Signal handling
 #[cfg(unix)]
-use tokio::signal::unix::{signal, SignalKind};
+use tokio::signal::unix::SignalKind;
 #[cfg(windows)]
 use tokio::signal::windows;
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Cli {
-    #[arg(short, long, value_name = "IP:PORT", default_value = "0.0.0.0:12345")]
-    listen_addr: String,
+#[derive(Parser, Debug)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_override_self = true,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    #[arg(long, value_name = "NAME")]
+    name: Option<String>,
+
+    #[arg(long, value_enum, value_name = "FORMAT", default_value = "text")]
+    log_format: log::Format,
+
+    #[arg(short, long, value_name = "IP:PORT", default_value = "0.0.0.0:12345")]
+    listen_addr: Vec<String>,
+
+    #[arg(long)]
+    ipv6_only: bool,
+
+    #[arg(long)]
+    dual_stack: bool,
+
+    #[arg(long, value_name = "BYTES")]
+    recv_buffer_size: Option<usize>,
+
+    #[arg(long)]
+    reuse_addr: bool,
+
+    #[arg(long, value_name = "IFACE")]
+    bind_device: Option<String>,
+
+    #[arg(long, value_name = "TTL", conflicts_with = "low_power")]
+    min_ttl: Option<u8>,
+
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    bind_retries: u32,
+
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "200ms")]
+    bind_backoff: Duration,
+
+    #[arg(long, conflicts_with = "low_power")]
+    ack: bool,
+
+    #[arg(long, conflicts_with = "low_power")]
+    status_query: bool,
+
+    #[arg(long, conflicts_with = "low_power")]
+    allow_commands: bool,
+
+    #[arg(long, conflicts_with = "low_power")]
+    allow_extend: bool,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_timeout)]
+    max_extension: Option<Duration>,
+
+    #[arg(long, value_name = "BYTES", default_value_t = 512)]
+    max_packet_size: usize,
+
+    #[arg(long)]
+    log_payloads: bool,
+
+    #[arg(long)]
+    log_heartbeats: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        requires = "log_heartbeats"
+    )]
+    log_heartbeats_every: u64,
+
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, requires = "log_heartbeats")]
+    log_heartbeats_interval: Option<Duration>,
+
+    #[arg(long)]
+    strict_protocol: bool,
+
+    #[arg(long, value_name = "N")]
+    require_senders: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "NAME[=DURATION]",
+        value_parser = components::parse_requirement
+    )]
+    require_component: Vec<(String, Option<Duration>)>,
+
+    #[arg(long, value_name = "PACKETS_PER_SEC")]
+    rate_limit: Option<u32>,
+
+    #[arg(long, value_name = "PACKETS_PER_SEC")]
+    source_rate_limit: Option<u32>,
+
+    #[arg(long, value_enum, value_name = "SCOPE")]
+    allow_scope: Vec<filter::Scope>,
+
+    #[arg(long, value_name = "CIDR")]
+    allow_from: Vec<filter::Cidr>,
+
+    #[cfg(feature = "dtls")]
+    #[arg(long, conflicts_with = "low_power")]
+    dtls: bool,
+
+    #[cfg(feature = "dtls")]
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "dtls",
+        conflicts_with = "dtls_cert"
+    )]
+    dtls_psk_file: Option<PathBuf>,
+
+    #[cfg(feature = "dtls")]
+    #[arg(long, value_name = "PATH", requires_all = ["dtls", "dtls_key", "dtls_ca"])]
+    dtls_cert: Option<PathBuf>,
+
+    #[cfg(feature = "dtls")]
+    #[arg(long, value_name = "PATH", requires = "dtls_cert")]
+    dtls_key: Option<PathBuf>,
+
+    #[cfg(feature = "dtls")]
+    #[arg(long, value_name = "PATH", requires = "dtls_cert")]
+    dtls_ca: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH")]
+    encryption_key_file: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH")]
+    hmac_key_file: Option<PathBuf>,
+
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, requires = "hmac_key_file")]
+    hmac_max_skew: Option<Duration>,
+
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    key_rotation_overlap: Option<Duration>,
+
+    #[arg(short, long, alias = "timeout-secs", value_name = "DURATION", value_parser = parse_timeout)]
+    timeout: Option<Duration>,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_timeout, conflicts_with = "timeout")]
+    interval: Option<Duration>,
+
+    #[arg(long, value_name = "N", default_value_t = 3, requires = "interval")]
+    max_misses: u32,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["interval", "low_power"],
+        requires_all = ["adaptive_min", "adaptive_max"]
+    )]
+    adaptive_timeout: bool,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_timeout, requires = "adaptive_timeout")]
+    adaptive_min: Option<Duration>,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_timeout, requires = "adaptive_timeout")]
+    adaptive_max: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        default_value_t = 3.0,
+        requires = "adaptive_timeout"
+    )]
+    adaptive_multiplier: f64,
+
+    #[arg(long, alias = "degraded-timeout-secs", value_name = "DURATION", value_parser = parse_timeout)]
+    degraded_timeout: Option<Duration>,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_timeout)]
+    startup_timeout: Option<Duration>,
+
+    #[arg(long, conflicts_with_all = ["startup_timeout", "low_power"])]
+    arm_on_first_ping: bool,
+
+    #[arg(long, conflicts_with = "docker_container")]
+    spawn_on_first_ping: bool,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_timeout)]
+    max_runtime: Option<Duration>,
+
+    #[arg(long)]
+    degraded_notify_only: bool,
+
+    #[arg(
+        long,
+        value_name = "ACTION",
+        value_parser = timeout_action::TimeoutAction::parse,
+        default_value = "kill"
+    )]
+    on_timeout: timeout_action::TimeoutAction,
+
+    #[arg(long, value_name = "COMMAND")]
+    on_timeout_exec: Option<String>,
+
+    #[arg(long, value_name = "COMMAND")]
+    diagnose_exec: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_timeout,
+        default_value = "10s",
+        requires = "diagnose_exec"
+    )]
+    diagnose_timeout: Duration,
+
+    #[arg(long, value_name = "DIR", conflicts_with = "observe_only")]
+    incident_report: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 100,
+        requires = "incident_report"
+    )]
+    incident_output_lines: usize,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["on_timeout", "allow_commands", "docker_action", "cpu_limit", "io_limit"]
+    )]
+    observe_only: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "heartbeat_fd",
+            "stdin_pipe",
+            "stdout_heartbeat",
+            "alive_regex",
+            "dead_regex",
+            "ready_regex",
+            "cpu_limit",
+            "io_limit",
+        ]
+    )]
+    no_kill_on_exit: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        default_value = "never",
+        conflicts_with = "observe_only"
+    )]
+    restart: child::RestartPolicy,
+
+    #[arg(long, value_name = "CODES", value_delimiter = ',', default_value = "0")]
+    success_codes: Vec<i32>,
+
+    #[arg(long, value_name = "CODES", value_delimiter = ',')]
+    no_restart_codes: Vec<i32>,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_timeout, default_value = "1s")]
+    restart_backoff: Duration,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_timeout, default_value = "60s")]
+    restart_backoff_max: Duration,
+
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        value_parser = clap::value_parser!(u8).range(0..=100),
+        default_value = "10"
+    )]
+    restart_jitter: u8,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_timeout, default_value = "10s")]
+    min_uptime: Duration,
+
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_restarts: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_timeout,
+        default_value = "60s",
+        requires = "max_restarts"
+    )]
+    restart_window: Duration,
+
+    #[arg(long, value_name = "COMMAND", requires = "max_restarts")]
+    crash_loop_exec: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "STRATEGY",
+        default_value = "stop-first",
+        conflicts_with = "docker_container"
+    )]
+    restart_strategy: child::RestartStrategy,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_timeout, default_value = "30s")]
+    replacement_timeout: Duration,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_timeout,
+        conflicts_with = "docker_container"
+    )]
+    kill_grace: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "SIGNAL",
+        value_parser = signals::Signal::parse,
+        requires = "kill_grace"
+    )]
+    stop_signal: Option<signals::Signal>,
+
+    #[arg(long, value_name = "SIGNAL", value_parser = signals::parse_catchable)]
+    forward_signal: Vec<signals::Signal>,
+
+    #[arg(
+        long,
+        value_name = "SIGNAL=DISPOSITION",
+        value_parser = signals::parse_disposition,
+        value_delimiter = ','
+    )]
+    signal_map: Vec<(signals::Signal, signals::Disposition)>,
+
+    #[arg(
+        long,
+        value_name = "SIGNAL",
+        value_parser = signals::Signal::parse,
+        conflicts_with_all = ["docker_container", "attach_pid", "observe_only", "no_kill_on_exit"]
+    )]
+    parent_death_signal: Option<signals::Signal>,
+
+    #[arg(long, conflicts_with_all = ["docker_container", "attach_pid"])]
+    subreaper: bool,
+
+    #[arg(long, conflicts_with_all = ["docker_container", "attach_pid"])]
+    cgroup: bool,
+
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["docker_container", "attach_pid"])]
+    chdir: Option<PathBuf>,
+
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["docker_container", "attach_pid"])]
+    chroot: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "NAMESPACES",
+        value_delimiter = ',',
+        conflicts_with_all = ["docker_container", "attach_pid"]
+    )]
+    unshare: Vec<child::Namespace>,
+
+    #[arg(long, value_name = "USER", conflicts_with_all = ["docker_container", "attach_pid"])]
+    user: Option<String>,
+
+    #[arg(long, value_name = "GROUP", conflicts_with_all = ["docker_container", "attach_pid"])]
+    group: Option<String>,
+
+    #[arg(long, conflicts_with = "observe_only")]
+    freeze_on_pause: bool,
+
+    #[arg(long)]
+    no_job_object: bool,
+
+    #[arg(long, value_name = "DIR", requires = "cgroup")]
+    cgroup_parent: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_timeout,
+        conflicts_with = "docker_container"
+    )]
+    kill_timeout: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        value_parser = clap::value_parser!(u8).range(1..100),
+        conflicts_with = "low_power"
+    )]
+    warn_at: Option<u8>,
+
+    #[arg(long, value_name = "COMMAND", requires = "warn_at")]
+    warn_exec: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "AFTER=ACTION",
+        value_parser = escalation::Step::parse,
+        conflicts_with = "low_power"
+    )]
+    escalate: Vec<escalation::Step>,
+
+    #[arg(long, value_enum, value_name = "MODE", default_value = "ignore")]
+    suspend_time: suspend::SuspendTime,
+
+    #[arg(
+        long,
+        value_name = "[SOURCE=]CODEC",
+        value_parser = payload::parse_codec_setting
+    )]
+    payload_codec: Vec<(Option<String>, payload::Codec)>,
+
+    #[arg(long, value_name = "TOKEN", value_parser = payload::Expected::parse)]
+    expect_payload: Option<payload::Expected>,
+
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    clock_skew_alarm: Option<Duration>,
+
+    #[arg(long, value_name = "PATH")]
+    metrics_file: Option<PathBuf>,
+
+    #[arg(long, value_name = "PORT")]
+    vsock_port: Option<u32>,
+
+    #[arg(long, value_name = "CID", requires = "vsock_port")]
+    vsock_cid: Option<u32>,
+
+    #[arg(long, value_name = "/NAME")]
+    mq_name: Option<String>,
+
+    #[arg(long, value_name = "DEVICE")]
+    serial: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "RATE",
+        default_value_t = 115200,
+        requires = "serial"
+    )]
+    baud: u32,
+
+    #[arg(long, value_name = "SEQUENCE", requires = "serial")]
+    serial_magic: Option<String>,
+
+    #[arg(long, value_name = "FD", num_args = 0..=1, default_missing_value = "3")]
+    heartbeat_fd: Option<i32>,
+
+    #[arg(long, requires = "heartbeat_fd")]
+    kill_on_fd_close: bool,
+
+    #[arg(long)]
+    stdin_pipe: bool,
+
+    #[arg(long)]
+    stdout_heartbeat: bool,
+
+    #[arg(long, value_name = "REGEX", requires = "stdout_heartbeat")]
+    stdout_heartbeat_regex: Option<regex::Regex>,
+
+    #[arg(long, value_name = "REGEX")]
+    alive_regex: Option<regex::Regex>,
+
+    #[arg(long, value_name = "PATH")]
+    watch_log: Option<PathBuf>,
+
+    #[arg(long, value_name = "REGEX", requires = "watch_log")]
+    watch_log_regex: Option<regex::Regex>,
+
+    #[arg(long, value_name = "REGEX")]
+    dead_regex: Option<regex::Regex>,
+
+    #[arg(long, value_name = "REGEX")]
+    ready_regex: Option<regex::Regex>,
+
+    #[arg(long, value_name = "URL")]
+    probe_http: Option<String>,
+
+    #[arg(long, value_name = "COMMAND")]
+    probe_exec: Option<String>,
+
+    #[arg(long, value_name = "HOST:PORT")]
+    probe_tcp: Option<String>,
+
+    #[arg(long, value_name = "HOST")]
+    probe_ping: Option<String>,
+
+    #[arg(long, value_name = "HOST:PORT")]
+    probe_udp_echo: Option<String>,
+
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "2s")]
+    probe_interval: Duration,
+
+    #[arg(long, value_name = "COUNT", default_value_t = 3)]
+    probe_failures: u32,
+
+    #[arg(long, value_enum, value_name = "POLICY")]
+    liveness: Option<liveness::Policy>,
+
+    #[arg(
+        long,
+        value_name = "SOURCE=DURATION",
+        value_parser = liveness::parse_source_timeout,
+        requires = "liveness"
+    )]
+    source_timeout: Vec<(String, Duration)>,
+
+    #[arg(long, value_enum, value_name = "POLICY")]
+    sched_policy: Option<child::SchedPolicy>,
+
+    #[arg(long, value_name = "PRIORITY")]
+    sched_priority: Option<i32>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(i32).range(-20..=19),
+        allow_negative_numbers = true
+    )]
+    nice: Option<i32>,
+
+    #[arg(long, value_name = "CLASS[:LEVEL]", value_parser = child::IoPriority::parse)]
+    ionice: Option<child::IoPriority>,
+
+    #[arg(long, value_name = "CPUS", value_parser = child::parse_cpu_list)]
+    cpu_affinity: Option<child::CpuList>,
+
+    #[arg(long, value_name = "PERCENT")]
+    cpu_limit: Option<f64>,
+
+    #[arg(long, value_name = "BYTES_PER_SEC", value_parser = throttle::parse_rate)]
+    io_limit: Option<u64>,
+
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "250ms")]
+    throttle_stop: Duration,
+
+    #[arg(long, value_name = "EVENT=LEVEL", value_parser = events::parse_route)]
+    severity: Vec<(events::Event, events::Severity)>,
+
+    #[arg(long, value_name = "COMMAND")]
+    notify_exec: Option<String>,
+
+    #[arg(long, value_name = "COMMAND")]
+    page_exec: Option<String>,
+
+    #[arg(long, value_name = "PATH")]
+    audit_log: Option<PathBuf>,
+
+    #[arg(long)]
+    low_power: bool,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        conflicts_with_all = [
+            "child_binary_path",
+            "child_args",
+            "heartbeat_fd",
+            "stdin_pipe",
+            "sched_policy",
+            "nice",
+            "ionice",
+            "cpu_affinity",
+            "cpu_limit",
+            "io_limit",
+        ]
+    )]
+    docker_container: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "docker_container",
+        conflicts_with = "child_binary_path"
+    )]
+    docker_socket: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ACTION",
+        requires = "docker_container",
+        conflicts_with = "child_binary_path"
+    )]
+    docker_action: Option<docker::Action>,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        requires = "docker_container",
+        conflicts_with = "child_binary_path"
+    )]
+    docker_stop_timeout: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "PID",
+        conflicts_with_all = [
+            "child_binary_path",
+            "child_args",
+            "docker_container",
+            "spawn_on_first_ping",
+            "heartbeat_fd",
+            "stdin_pipe",
+            "stdout_heartbeat",
+            "alive_regex",
+            "dead_regex",
+            "ready_regex",
+            "sched_policy",
+            "nice",
+            "ionice",
+            "cpu_affinity",
+            "cpu_limit",
+            "io_limit",
+        ]
+    )]
+    attach_pid: Option<u32>,
+
+    #[arg(long)]
+    explain_exit_codes: bool,
+
+    #[arg(
+        value_name = "BINARY_PATH",
+        required_unless_present_any = ["explain_exit_codes", "docker_container", "attach_pid"]
+    )]
+    child_binary_path: Option<PathBuf>,
+
+    #[arg(last = true, value_name = "CHILD_ARGS")]
+    child_args: Vec<String>,
+}
+
+impl Cli {
+    /// Whether `reason` can occur with this configuration.
+    fn applies(&self, reason: ExitReason) -> bool {
+        match reason {
+            ExitReason::RemoteKill => self.allow_commands,
+            ExitReason::ChildExited => {
+                self.restart != child::RestartPolicy::Always || !self.no_restart_codes.is_empty()
+            }
+            ExitReason::MaxRuntime => self.max_runtime.is_some() && !self.observe_only,
+            ExitReason::CrashLoop => self.max_restarts.is_some(),
+            ExitReason::Timeout | ExitReason::NoHeartbeat => {
+                !(self.observe_only || self.on_timeout.leaves_running())
+            }
+            ExitReason::Unhealthy if self.observe_only => false,
+            ExitReason::Unhealthy => {
+                self.probe_http.is_some()
+                    || self.probe_exec.is_some()
+                    || self.probe_tcp.is_some()
+                    || self.probe_ping.is_some()
+                    || self.probe_udp_echo.is_some()
+                    || self.dead_regex.is_some()
+                    || self.stdin_pipe
+                    || self.kill_on_fd_close
+            }
+            _ => true,
+        }
+    }
+
+    /// The probes selected by the `--probe-*` options, at most one of each kind.
+    fn probes(&self) -> Result<Vec<probe::Probe>, String> {
+        let mut probes = Vec::new();
+        if let Some(url) = &self.probe_http {
+            let target = probe::HttpTarget::parse(url)
+                .map_err(|e| format!("Invalid --probe-http: {}", e))?;
+            probes.push(probe::Probe::Http(target));
+        }
+        if let Some(command) = &self.probe_exec {
+            probes.push(probe::Probe::Exec(command.clone()));
+        }
+        if let Some(addr) = &self.probe_tcp {
+            probe::validate_host_port(addr).map_err(|e| format!("Invalid --probe-tcp: {}", e))?;
+            probes.push(probe::Probe::Tcp(addr.clone()));
+        }
+        #[cfg(unix)]
+        if let Some(host) = &self.probe_ping {
+            probe::check_ping_permitted(host).map_err(|e| format!("--probe-ping: {}", e))?;
+            probes.push(probe::Probe::Ping(host.clone()));
+        }
+        #[cfg(not(unix))]
+        if self.probe_ping.is_some() {
+            return Err("--probe-ping is only supported on Unix.".to_string());
+        }
+        if let Some(addr) = &self.probe_udp_echo {
+            probe::validate_host_port(addr)
+                .map_err(|e| format!("Invalid --probe-udp-echo: {}", e))?;
+            probes.push(probe::Probe::UdpEcho(addr.clone()));
+        }
+        Ok(probes)
+    }
+
+    /// The heartbeat sources this configuration runs (see `liveness::SOURCES`).
+    fn sources(&self, probes: &[probe::Probe]) -> Vec<&'static str> {
+        let mut sources = vec!["udp"];
+        if self.heartbeat_fd.is_some() {
+            sources.push("fd");
+        }
+        if self.serial.is_some() {
+            sources.push("serial");
+        }
+        if self.mq_name.is_some() {
+            sources.push("mq");
+        }
+        if self.vsock_port.is_some() {
+            sources.push("vsock");
+        }
+        if self.stdout_heartbeat {
+            sources.push("stdout");
+        }
+        if self.alive_regex.is_some() {
+            sources.push("output");
+        }
+        if self.watch_log.is_some() {
+            sources.push("log");
+        }
+        sources.extend(probes.iter().map(|probe| probe.source()));
+        sources
+    }
+}
+
+/// Parses a `--timeout` value: a duration such as `500ms` or `2m30s`, or a bare number of
+/// seconds as accepted before durations were.
+fn parse_timeout(value: &str) -> Result<Duration, String> {
+    match value.parse::<u64>() {
+        Ok(secs) => Ok(Duration::from_secs(secs)),
+        Err(_) => humantime::parse_duration(value).map_err(|e| e.to_string()),
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Summarize heartbeat intervals recorded with --audit-log and suggest a timeout
+    Analyze(analyze::AnalyzeArgs),
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = match config::expand_args::<Cli>(std::env::args_os().collect()) {
+        Ok(args) => Cli::parse_from(args),
+        Err(e) => {
+            exit::fail(Error::Config(e));
+        }
+    };
+    if let Some(Commands::Analyze(args)) = cli.command {
+        std::process::exit(analyze::run(args));
+    }
+    if cli.explain_exit_codes {
+        println!("{:#}", exit::explain(|reason| cli.applies(reason)));
+        return;
+    }
+    if let Some(name) = &cli.name {
+        log::set_instance_name(name.clone());
+    }
+    log::set_format(cli.log_format);
+    if let Some(path) = &cli.config {
+        info!("Loaded options from config file {}.", path.display());
+    }
+    #[cfg(not(unix))]
+    if cli.low_power {
+        exit::fail(Error::Unsupported {
+            option: "--low-power",
+            platform: "Unix",
+        });
+    }
+    if cli.low_power {
+        info!("Low-power mode: heartbeats are read in batches at each deadline; log output is batched.");
+        log::enable_batching();
+    }
+    events::configure(events::Routing {
+        routes: cli.severity.iter().copied().collect(),
+        notify_exec: cli.notify_exec.clone(),
+        page_exec: cli.page_exec.clone(),
+    });
+    // Guaranteed by clap unless a subcommand, --explain-exit-codes, a container or a process to
+    // attach to is given.
+    let child_binary_path = cli.child_binary_path.clone().unwrap_or_default();
+
+    match (&cli.docker_container, cli.attach_pid) {
+        (Some(name), _) => info!(
+            "Supervising container {} through the Docker API (on failure: {}).",
+            name,
+            cli.docker_action.unwrap_or(docker::Action::Stop).as_str()
+        ),
+        (None, Some(pid)) => info!("Supervising already running process {}.", pid),
+        (None, None) => info!(
+            "Launching child process: {} with args: {:?}",
+            child_binary_path.display(),
+            cli.child_args
+        ),
+    }
+    info!(
+        "Listening for UDP signals on: {}{}",
+        cli.listen_addr.join(", "),
+        if cli.dual_stack { " (dual-stack)" } else { "" }
+    );
+    if cli.interval.is_some_and(|interval| interval.is_zero()) || cli.max_misses == 0 {
+        exit::fail(Error::Config(
+            "--interval and --max-misses must be greater than 0.".to_string(),
+        ));
+    }
+    let timeout_duration = match cli.interval {
+        Some(interval) => {
+            info!(
+                "Expecting a heartbeat every {:?}; acting after {} missed in a row.",
+                interval, cli.max_misses
+            );
+            interval * cli.max_misses
+        }
+        None => cli.timeout.unwrap_or(DEFAULT_TIMEOUT),
+    };
+    info!("Timeout set to: {:?}", timeout_duration);
+
+    if timeout_duration.is_zero() {
+        exit::fail(Error::Config("Timeout must be greater than 0.".to_string()));
+    }
+    if cli
+        .degraded_timeout
+        .is_some_and(|timeout| timeout.is_zero())
+    {
+        exit::fail(Error::Config(
+            "Degraded timeout must be greater than 0.".to_string(),
+        ));
+    }
+    if let Some(startup) = cli.startup_timeout {
+        if startup.is_zero() {
+            exit::fail(Error::Config(
+                "Startup timeout must be greater than 0.".to_string(),
+            ));
+        }
+        info!("Startup timeout until the first heartbeat: {:?}", startup);
+    }
+    let adaptive = match (cli.adaptive_min, cli.adaptive_max) {
+        (Some(min), Some(max)) if cli.adaptive_timeout => {
+            if min.is_zero() || min > max {
+                exit::fail(Error::Config(
+                    "--adaptive-min must be greater than 0 and at most --adaptive-max.".to_string(),
+                ));
+            }
+            if !cli.adaptive_multiplier.is_finite() || cli.adaptive_multiplier <= 0.0 {
+                exit::fail(Error::Config(
+                    "--adaptive-multiplier must be greater than 0.".to_string(),
+                ));
+            }
+            info!(
+                "Learning the timeout from the heartbeat cadence: {}x the p99 interval, between {:?} and {:?}, once {} intervals have been seen.",
+                cli.adaptive_multiplier,
+                min,
+                max,
+                adaptive::MIN_SAMPLES
+            );
+            Some(Arc::new(adaptive::Adaptive::new(
+                cli.adaptive_multiplier,
+                min,
+                max,
+            )))
+        }
+        _ => None,
+    };
+    if let Some(max_runtime) = cli.max_runtime {
+        if max_runtime.is_zero() {
+            exit::fail(Error::Config(
+                "--max-runtime must be greater than 0.".to_string(),
+            ));
+        }
+        info!(
+            "The child is terminated after running for {:?}, whatever its heartbeats.",
+            max_runtime
+        );
+    }
+    if cli.suspend_time == suspend::SuspendTime::Count {
+        if !suspend::SuspendClock::supported() {
+            exit::fail(Error::Unsupported {
+                option: "--suspend-time count",
+                platform: "Linux and macOS",
+            });
+        }
+        if cli.low_power {
+            exit::fail(Error::Config(
+                "--suspend-time count cannot be combined with --low-power.".to_string(),
+            ));
+        }
+        info!("Time the system spends suspended counts against the timeout.");
+    }
+    #[cfg(unix)]
+    if let Some(grace) = cli.kill_grace {
+        let signal = cli.stop_signal.unwrap_or(signals::Signal {
+            number: libc::SIGTERM,
+        });
+        info!(
+            "Stopping the child with {} and killing its process group after {:?} at the latest.",
+            signal, grace
+        );
+        stop::configure(stop::GracefulStop { signal, grace });
+    }
+    #[cfg(unix)]
+    if let Some(timeout) = cli.kill_timeout {
+        stop::set_kill_timeout(timeout);
+    }
+    #[cfg(windows)]
+    if let Some(grace) = cli.kill_grace {
+        info!(
+            "Stopping the child with Ctrl+Break and terminating it after {:?} at the latest.",
+            grace
+        );
+        ctrl_break::configure(grace);
+    }
+    #[cfg(not(unix))]
+    if cli.kill_timeout.is_some() {
+        exit::fail(Error::Unsupported {
+            option: "--kill-timeout",
+            platform: "Unix",
+        });
+    }
+    if cli.observe_only {
+        info!("Observe-only: the child is never terminated, restarted or signalled; missed deadlines and failed health checks are only reported.");
+    }
+    if cli.no_kill_on_exit {
+        info!("The child is left running when the watchdog is stopped (--no-kill-on-exit); its output is not forwarded.");
+    }
+    if cli.arm_on_first_ping {
+        info!("The timeout is armed by the first heartbeat; until then only the child's exit ends the watchdog.");
+    }
+    if !(1..=listener::MAX_UDP_PAYLOAD).contains(&cli.max_packet_size) {
+        exit::fail(Error::Config(format!(
+            "--max-packet-size must be between 1 and {} bytes.",
+            listener::MAX_UDP_PAYLOAD
+        )));
+    }
+    if cli.rate_limit == Some(0) || cli.source_rate_limit == Some(0) {
+        exit::fail(Error::Config(
+            "--rate-limit and --source-rate-limit must be greater than 0.".to_string(),
+        ));
+    }
+    if cli.log_heartbeats_every == 0 {
+        exit::fail(Error::Config(
+            "--log-heartbeats-every must be greater than 0.".to_string(),
+        ));
+    }
+    if cli.require_senders == Some(0) {
+        exit::fail(Error::Config(
+            "--require-senders must be greater than 0.".to_string(),
+        ));
+    }
+    if cli.recv_buffer_size == Some(0) || cli.min_ttl == Some(0) {
+        exit::fail(Error::Config(
+            "--recv-buffer-size and --min-ttl must be greater than 0.".to_string(),
+        ));
+    }
+    if cli.bind_device.as_deref().is_some_and(str::is_empty) {
+        exit::fail(Error::Config(
+            "--bind-device must name an interface.".to_string(),
+        ));
+    }
+    #[cfg(not(target_os = "linux"))]
+    if cli.bind_device.is_some() || cli.min_ttl.is_some() {
+        exit::fail(Error::Unsupported {
+            option: if cli.bind_device.is_some() {
+                "--bind-device"
+            } else {
+                "--min-ttl"
+            },
+            platform: "Linux",
+        });
+    }
+    #[cfg(feature = "dtls")]
+    if cli.dtls && cli.min_ttl.is_some() {
+        exit::fail(Error::Config(
+            "--min-ttl cannot be used with --dtls.".to_string(),
+        ));
+    }
+    // Commands must not be replayable: a captured KILL would otherwise work forever.
+    let replay_protected = cli.hmac_key_file.is_some();
+    #[cfg(feature = "dtls")]
+    let replay_protected = replay_protected || cli.dtls;
+    if (cli.allow_commands || cli.allow_extend) && !replay_protected {
+        exit::fail(Error::Config(format!(
+            "{} needs --hmac-key-file or --dtls, which reject replayed packets.",
+            if cli.allow_commands {
+                "--allow-commands"
+            } else {
+                "--allow-extend"
+            }
+        )));
+    }
+    if cli.max_extension.is_some() && !(cli.allow_commands || cli.allow_extend) {
+        exit::fail(Error::Config(
+            "--max-extension needs --allow-commands or --allow-extend.".to_string(),
+        ));
+    }
+    if cli
+        .max_extension
+        .is_some_and(|max| max < Duration::from_secs(1))
+    {
+        exit::fail(Error::Config(
+            "--max-extension must be at least 1s, the shortest EXTEND.".to_string(),
+        ));
+    }
+    let probes = match cli.probes() {
+        Ok(probes) => probes,
+        Err(e) => {
+            exit::fail(Error::Config(e));
+        }
+    };
+    if cli.probe_interval.is_zero() || cli.probe_failures == 0 {
+        exit::fail(Error::Config(
+            "--probe-interval and --probe-failures must be greater than 0.".to_string(),
+        ));
+    }
+    // Let the consecutive-failure count decide, rather than the timeout firing first. Only the
+    // default timeout is raised for that; one that was asked for is never silently overridden.
+    let needed = cli.probe_interval * (cli.probe_failures + 1);
+    let timeout_duration = match probes.first() {
+        Some(probe) if timeout_duration < needed => {
+            let probe = if probes.len() == 1 {
+                probe.describe()
+            } else {
+                "a probe".to_string()
+            };
+            if cli.timeout.is_some() || cli.interval.is_some() {
+                exit::fail(Error::Config(format!(
+                    "The timeout ({:.2?}) would fire before {} consecutive failures of {} do; \
+                     it must be at least {:.2?} (--probe-interval times --probe-failures + 1).",
+                    timeout_duration, cli.probe_failures, probe, needed
+                )));
+            }
+            info!(
+                "Timeout raised to {:.2?} so that {} consecutive failures of {} decide when to act.",
+                needed, cli.probe_failures, probe
+            );
+            needed
+        }
+        _ => timeout_duration,
+    };
+    let sources = cli.sources(&probes);
+    if let Some((source, _)) = cli
+        .source_timeout
+        .iter()
+        .find(|(source, _)| !sources.contains(&source.as_str()))
+    {
+        exit::fail(Error::Config(format!(
+            "--source-timeout names '{}', which is not configured (active sources: {}).",
+            source,
+            sources.join(", ")
+        )));
+    }
+    if let Some(source) = cli
+        .payload_codec
+        .iter()
+        .filter_map(|(source, _)| source.as_deref())
+        .find(|source| !sources.contains(source))
+    {
+        exit::fail(Error::Config(format!(
+            "--payload-codec names '{}', which is not configured (active sources: {}).",
+            source,
+            sources.join(", ")
+        )));
+    }
+    #[cfg(feature = "dtls")]
+    let dtls = cli.dtls.then(|| {
+        let credentials = match (
+            &cli.dtls_psk_file,
+            &cli.dtls_cert,
+            &cli.dtls_key,
+            &cli.dtls_ca,
+        ) {
+            (Some(path), _, _, _) => dtls::Credentials::Psk(path.clone()),
+            (None, Some(cert), Some(key), Some(ca)) => dtls::Credentials::Certificate {
+                cert: cert.clone(),
+                key: key.clone(),
+                ca: ca.clone(),
+            },
+            _ => exit::fail(Error::Config(
+                "--dtls needs --dtls-psk-file, or --dtls-cert, --dtls-key and --dtls-ca."
+                    .to_string(),
+            )),
+        };
+        let server = dtls::Server::new(&credentials).unwrap_or_else(|e| {
+            exit::fail(Error::Open {
+                what: format!("set up DTLS with {}", credentials.describe()),
+                reason: e,
+            })
+        });
+        Arc::new(server)
+    });
+    let overlap = cli.key_rotation_overlap.unwrap_or(keyfile::DEFAULT_OVERLAP);
+    let decrypt = cli.encryption_key_file.as_ref().map(|path| {
+        let decryptor = encryption::Decryptor::load(path, overlap).unwrap_or_else(|e| {
+            exit::fail(Error::Open {
+                what: format!("read encryption key file {}", path.display()),
+                reason: e,
+            })
+        });
+        tokio::spawn(decryptor.key_file().watch());
+        decryptor
+    });
+    let auth = cli.hmac_key_file.as_ref().map(|path| {
+        let max_skew = cli.hmac_max_skew.unwrap_or(auth::DEFAULT_MAX_SKEW);
+        let authenticator =
+            auth::Authenticator::load(path, max_skew, overlap).unwrap_or_else(|e| {
+                exit::fail(Error::Open {
+                    what: format!("read HMAC key file {}", path.display()),
+                    reason: e,
+                })
+            });
+        tokio::spawn(authenticator.key_file().watch());
+        authenticator
+    });
+    let liveness = cli.liveness.map(|policy| {
+        let liveness = liveness::Liveness::new(
+            policy,
+            timeout_duration,
+            cli.source_timeout.iter().cloned().collect(),
+        );
+        info!(
+            "Liveness policy '{}' over sources: {}.",
+            policy.as_str(),
+            sources
+                .iter()
+                .map(|source| format!("{} ({:.2?})", source, liveness.timeout_for(source)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Arc::new(liveness)
+    });
+    let quorum = cli.require_senders.map(|required| {
+        info!(
+            "Resetting the timer only while at least {} distinct UDP senders are heard from.",
+            required
+        );
+        Arc::new(quorum::Quorum::new(required))
+    });
+    let components = (!cli.require_component.is_empty()).then(|| {
+        let components = components::Components::new(timeout_duration, &cli.require_component);
+        info!(
+            "Requiring heartbeats from components: {}.",
+            components
+                .timeouts()
+                .iter()
+                .map(|(name, timeout)| format!("{} ({:.2?})", name, timeout))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Arc::new(components)
+    });
+    let degraded_policy = DegradedPolicy {
+        timeout: cli.degraded_timeout.unwrap_or(timeout_duration),
+        notify_only: cli.degraded_notify_only,
+    };
+    let warning = cli.warn_at.map(|percent| {
+        info!(
+            "Warning once {}% of the timeout has passed without a heartbeat.",
+            percent
+        );
+        DeadlineWarning {
+            percent,
+            exec: cli.warn_exec.clone(),
+        }
+    });
+    let mut escalation = cli.escalate.clone();
+    escalation.sort_by_key(|step| step.after);
+    if let Some(step) = escalation
+        .last()
+        .filter(|step| step.after >= timeout_duration)
+    {
+        exit::fail(Error::Config(format!(
+            "--escalate {:?}: every step must come before the timeout ({:?}).",
+            step.after, timeout_duration
+        )));
+    }
+    if cli.observe_only
+        && escalation
+            .iter()
+            .any(|step| matches!(step.action, escalation::Action::Signal(_)))
+    {
+        exit::fail(Error::Config(
+            "--observe-only cannot be combined with --escalate steps that send signals."
+                .to_string(),
+        ));
+    }
+    if !escalation.is_empty() {
+        info!(
+            "Escalating before the timeout: {}.",
+            escalation
+                .iter()
+                .map(|step| format!("{} after {:?}", step.action, step.after))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    #[cfg(target_os = "linux")]
+    let throttle_limits = throttle::Limits {
+        cpu_percent: cli.cpu_limit,
+        io_bytes_per_sec: cli.io_limit,
+    };
+    #[cfg(target_os = "linux")]
+    if cli
+        .cpu_limit
+        .is_some_and(|limit| !limit.is_finite() || limit <= 0.0)
+    {
+        exit::fail(Error::Config(
+            "--cpu-limit must be a percentage greater than 0.".to_string(),
+        ));
+    }
+    #[cfg(target_os = "linux")]
+    if cli.throttle_stop.is_zero() || cli.throttle_stop >= throttle::SAMPLE_INTERVAL {
+        exit::fail(Error::Config(format!(
+            "--throttle-stop must be greater than 0 and shorter than {:?}.",
+            throttle::SAMPLE_INTERVAL
+        )));
+    }
+    #[cfg(not(target_os = "linux"))]
+    if cli.cpu_limit.is_some() || cli.io_limit.is_some() {
+        exit::fail(Error::Unsupported {
+            option: if cli.cpu_limit.is_some() {
+                "--cpu-limit"
+            } else {
+                "--io-limit"
+            },
+            platform: "Linux",
+        });
+    }
+    if let Err(e) = child::validate_sched(cli.sched_policy, cli.sched_priority) {
+        exit::fail(Error::Config(e));
+    }
+    // The same command spawns the child again for a restart, unless it hands the child pipes
+    // that were set up for the first one only.
+    let respawnable = cli.heartbeat_fd.is_none() && !cli.stdin_pipe;
+    // Checked before anything is spawned, so that a rejected combination leaves no child behind.
+    // A container is restarted through Docker; an attached process cannot be restarted at all.
+    let restarting = if cli.on_timeout == timeout_action::TimeoutAction::Restart {
+        Some("--on-timeout restart")
+    } else if cli.restart != child::RestartPolicy::Never {
+        Some("--restart")
+    } else if cli.restart_strategy == child::RestartStrategy::StartFirst {
+        Some("--restart-strategy start-first")
+    } else {
+        None
+    };
+    let restartable = cli.docker_container.is_some() || (cli.attach_pid.is_none() && respawnable);
+    if let Some(option) = restarting.filter(|_| !restartable) {
+        exit::fail(Error::Config(format!(
+            "{} cannot be combined with --attach-pid, --heartbeat-fd or --stdin-pipe.",
+            option
+        )));
+    }
+    if cli.max_restarts.is_some() && restarting.is_none() {
+        exit::fail(Error::Config(
+            "--max-restarts needs --restart or --on-timeout restart.".to_string(),
+        ));
+    }
+
+    if let Some(path) = &cli.audit_log {
+        if let Err(e) = audit::open(path) {
+            exit::fail(Error::Open {
+                what: format!("open audit log {}", path.display()),
+                reason: e.to_string(),
+            });
+        }
+    }
+    if let Some(dir) = &cli.incident_report {
+        if !dir.is_dir() {
+            exit::fail(Error::Open {
+                what: format!("write incident reports to {}", dir.display()),
+                reason: "not a directory".to_string(),
+            });
+        }
+    }
+
+    // Adopt socket-activated sockets before spawning so the child can't inherit them.
+    let mut activated_sockets = listener::take_activated_sockets();
+
+    // Bind the vsock listener up front so a bad CID/port fails before the child is started.
+    #[cfg(target_os = "linux")]
+    let vsock_listener = match cli.vsock_port {
+        Some(port) => {
+            let cid = cli.vsock_cid.unwrap_or(libc::VMADDR_CID_ANY);
+            match vsock::VsockListener::bind(cid, port) {
+                Ok(listener) => {
+                    info!("Listening for vsock heartbeats on port {}.", port);
+                    Some(listener)
+                }
+                Err(e) => {
+                    exit::fail(Error::Bind {
+                        what: format!("vsock listener on port {}", port),
+                        source: e,
+                    });
+                }
+            }
+        }
+        None => None,
+    };
+    #[cfg(not(target_os = "linux"))]
+    if cli.vsock_port.is_some() {
+        exit::fail(Error::Unsupported {
+            option: "--vsock-port",
+            platform: "Linux",
+        });
+    }
+
+    // Open the message queue up front as well, for the same reason.
+    #[cfg(target_os = "linux")]
+    let message_queue = match &cli.mq_name {
+        Some(name) => {
+            if let Err(e) = mq::validate_name(name) {
+                exit::fail(Error::Config(e));
+            }
+            match mq::MessageQueue::open(name) {
+                Ok(queue) => {
+                    info!("Listening for heartbeats on message queue {}.", name);
+                    Some((name.clone(), queue))
+                }
+                Err(e) => {
+                    exit::fail(Error::Open {
+                        what: format!("open message queue {}", name),
+                        reason: e.to_string(),
+                    });
+                }
+            }
+        }
+        None => None,
+    };
+    #[cfg(not(target_os = "linux"))]
+    if cli.mq_name.is_some() {
+        exit::fail(Error::Unsupported {
+            option: "--mq-name",
+            platform: "Linux",
+        });
+    }
+
+    // Open the serial device up front as well, for the same reason.
+    #[cfg(unix)]
+    let serial_port = match &cli.serial {
+        Some(path) => {
+            let magic = match cli.serial_magic.as_deref().map(serial::parse_magic) {
+                Some(Err(e)) => {
+                    exit::fail(Error::Config(format!("Invalid --serial-magic: {}", e)));
+                }
+                Some(Ok(magic)) => Some(magic),
+                None => None,
+            };
+            if let Err(e) = serial::validate_baud(cli.baud) {
+                exit::fail(Error::Config(e));
+            }
+            match serial::open(path, cli.baud) {
+                Ok(port) => {
+                    info!(
+                        "Listening for serial heartbeats on {} at {} baud.",
+                        path.display(),
+                        cli.baud
+                    );
+                    Some((path.clone(), port, magic))
+                }
+                Err(e) => {
+                    exit::fail(Error::Open {
+                        what: format!("open serial device {}", path.display()),
+                        reason: e.to_string(),
+                    });
+                }
+            }
+        }
+        None => None,
+    };
+    #[cfg(not(unix))]
+    if cli.serial.is_some() {
+        exit::fail(Error::Unsupported {
+            option: "--serial",
+            platform: "Unix",
+        });
+    }
+
+    // Look the container up before anything else starts, so a typo fails fast.
+    #[cfg(unix)]
+    let container = match &cli.docker_container {
+        Some(name) => {
+            let client = match docker::socket_path(cli.docker_socket.clone()) {
+                Ok(socket) => docker::Client::new(socket),
+                Err(e) => {
+                    exit::fail(Error::Config(e));
+                }
+            };
+            match docker::Container::attach(client, name, cli.docker_stop_timeout).await {
+                Ok(container) => Some(container),
+                Err(e) => {
+                    exit::fail(Error::Attach {
+                        name: format!("container {}", name),
+                        reason: e,
+                    });
+                }
+            }
+        }
+        None => None,
+    };
+    #[cfg(not(unix))]
+    if cli.docker_container.is_some() {
+        exit::fail(Error::Unsupported {
+            option: "--docker-container",
+            platform: "Unix",
+        });
+    }
+    // Likewise the process to attach to.
+    #[cfg(unix)]
+    let attached = cli.attach_pid.map(|pid| match attach::Attached::new(pid) {
+        Ok(attached) => attached,
+        Err(e) => exit::fail(Error::Attach {
+            name: format!("process {}", pid),
+            reason: e,
+        }),
+    });
+    #[cfg(not(unix))]
+    if cli.attach_pid.is_some() {
+        exit::fail(Error::Unsupported {
+            option: "--attach-pid",
+            platform: "Unix",
+        });
+    }
+
+    // --- Setup command with platform-specific process group handling ---
+    // With --chdir, a binary given by a relative path is still found from where the watchdog
+    // was started, whatever the platform would make of it; under --chroot, it is found within
+    // the new root instead.
+    let child_binary_path = match &cli.chdir {
+        Some(_)
+            if cli.chroot.is_none()
+                && child_binary_path.components().count() > 1
+                && child_binary_path.is_relative() =>
+        {
+            std::path::absolute(&child_binary_path).unwrap_or(child_binary_path)
+        }
+        _ => child_binary_path,
+    };
+    let mut command = Command::new(&child_binary_path);
+    command.args(&cli.child_args);
+    if let Some(dir) = &cli.chdir {
+        // Under --chroot, the directory is one within the new root, which the child changes to
+        // once it is there.
+        let path = match &cli.chroot {
+            Some(root) => root.join(dir.strip_prefix("/").unwrap_or(dir)),
+            None => dir.clone(),
+        };
+        if !path.is_dir() {
+            exit::fail(Error::Open {
+                what: format!("use {} as the child's working directory", path.display()),
+                reason: "not a directory".to_string(),
+            });
+        }
+        info!("Running the child in {}.", dir.display());
+        if cli.chroot.is_none() {
+            command.current_dir(dir);
+        }
+    }
+    // A child left running by --no-kill-on-exit must not write to pipes that are gone with the
+    // watchdog, so it writes to the watchdog's own stdout and stderr instead.
+    if cli.no_kill_on_exit {
+        command.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+    } else {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    // On Windows, a Job Object holds the child and everything it starts, which are killed
+    // together, also when the watchdog exits however it exits.
+    // --no-job-object lets the child join a job of its own choosing; its descendants are then
+    // found and terminated one by one.
+    #[cfg(windows)]
+    if !(cli.observe_only || cli.no_kill_on_exit || cli.no_job_object) {
+        job::enable();
+    }
+    #[cfg(not(windows))]
+    if cli.no_job_object {
+        exit::fail(Error::Unsupported {
+            option: "--no-job-object",
+            platform: "Windows",
+        });
+    }
+    // Tag this child's heartbeats so stale pings from an earlier generation can be told apart.
+    let generation = heartbeat::next_generation(0);
+    command.env(heartbeat::GENERATION_ENV, generation.to_string());
+    // Under a systemd Type=notify unit, the child is given a notification socket of the
+    // watchdog's, through which its readiness is passed on.
+    #[cfg(target_os = "linux")]
+    if let Some(notify_socket) = sd_notify::init() {
+        info!(
+            "Running as a systemd Type=notify service ({}={}); telling systemd the service is ready once the child sends READY=1{}.",
+            sd_notify::NOTIFY_SOCKET_ENV,
+            notify_socket,
+            if cli.ready_regex.is_some() {
+                " or its output matches --ready-regex"
+            } else {
+                ""
+            }
+        );
+        match sd_notify::bind_child_socket() {
+            Ok((socket, name)) => {
+                command.env(sd_notify::NOTIFY_SOCKET_ENV, name);
+                tokio::spawn(sd_notify::run_child_socket(socket));
+            }
+            Err(e) => {
+                error!(
+                    "Failed to set up a notification socket for the child: {}",
+                    e
+                );
+                command.env_remove(sd_notify::NOTIFY_SOCKET_ENV);
+            }
+        }
+    }
+    if !activated_sockets.is_empty() {
+        for var in listener::SOCKET_ACTIVATION_ENV {
+            command.env_remove(var);
+        }
+    }
+
+    #[cfg(unix)]
+    {
+        // Place the child process in its own process group.
+        // The PGID will be the same as the child's PID.
+        command.process_group(0);
+    }
+    if let Some(policy) = cli.sched_policy {
+        child::apply_sched(&mut command, policy, cli.sched_priority);
+    }
+    // Like the scheduling class, these are set before the child's credentials are dropped, and
+    // apply again to every restarted child.
+    #[cfg(unix)]
+    if let Some(nice) = cli.nice {
+        child::apply_nice(&mut command, nice);
+    }
+    #[cfg(not(unix))]
+    if cli.nice.is_some() {
+        exit::fail(Error::Unsupported {
+            option: "--nice",
+            platform: "Unix",
+        });
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(priority) = cli.ionice {
+        child::apply_io_priority(&mut command, priority);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(cpus) = &cli.cpu_affinity {
+        child::apply_cpu_affinity(&mut command, cpus);
+    }
+    #[cfg(not(target_os = "linux"))]
+    if cli.ionice.is_some() || cli.cpu_affinity.is_some() {
+        exit::fail(Error::Unsupported {
+            option: if cli.ionice.is_some() {
+                "--ionice"
+            } else {
+                "--cpu-affinity"
+            },
+            platform: "Linux",
+        });
+    }
+    // With --subreaper, descendants of the child that are orphaned are adopted by the watchdog
+    // rather than by init, so that they can be reaped and killed along with the child.
+    #[cfg(target_os = "linux")]
+    if cli.subreaper {
+        match subreaper::enable() {
+            Ok(()) => {
+                info!("Adopting orphaned descendants of the child (--subreaper).");
+                tokio::spawn(subreaper::run_reaper());
+            }
+            Err(e) => exit::fail(Error::Open {
+                what: "make the watchdog a subreaper".to_string(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    if cli.subreaper {
+        exit::fail(Error::Unsupported {
+            option: "--subreaper",
+            platform: "Linux",
+        });
+    }
+    // With --cgroup, the child and everything it starts run in a cgroup of their own, which is
+    // killed as a whole.
+    #[cfg(target_os = "linux")]
+    if cli.cgroup {
+        if cli.restart_strategy == child::RestartStrategy::StartFirst {
+            exit::fail(Error::Config(
+                "--cgroup cannot be combined with --restart-strategy start-first: the replacement would be killed along with the child it replaces.".to_string(),
+            ));
+        }
+        let name = format!("ping-guard-{}", std::process::id());
+        match cgroup::create(cli.cgroup_parent.as_deref(), &name) {
+            Ok(cgroup) => {
+                info!("Running the child in cgroup {}.", cgroup.path.display());
+                cgroup.attach(&mut command);
+            }
+            Err(e) => exit::fail(Error::Open {
+                what: "create a cgroup for the child".to_string(),
+                reason: e,
+            }),
+        }
+    }
+    #[cfg(not(unix))]
+    if cli.freeze_on_pause {
+        exit::fail(Error::Unsupported {
+            option: "--freeze-on-pause",
+            platform: "Unix",
+        });
+    }
+    #[cfg(not(target_os = "linux"))]
+    if cli.cgroup {
+        exit::fail(Error::Unsupported {
+            option: "--cgroup",
+            platform: "Linux",
+        });
+    }
+    // The child is confined while it still has the watchdog's privileges: first to its own
+    // namespaces, whose mounts are made private through the host's root, then to its new root.
+    #[cfg(target_os = "linux")]
+    if !cli.unshare.is_empty() {
+        info!(
+            "Running the child in new {} namespaces (--unshare).",
+            cli.unshare
+                .iter()
+                .map(|namespace| namespace.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        sandbox::unshare(&mut command, &cli.unshare);
+    }
+    #[cfg(not(target_os = "linux"))]
+    if !cli.unshare.is_empty() {
+        exit::fail(Error::Unsupported {
+            option: "--unshare",
+            platform: "Linux",
+        });
+    }
+    #[cfg(unix)]
+    if let Some(root) = &cli.chroot {
+        if !root.is_dir() {
+            exit::fail(Error::Open {
+                what: format!("use {} as the child's root directory", root.display()),
+                reason: "not a directory".to_string(),
+            });
+        }
+        info!(
+            "Running the child with {} as its root (--chroot).",
+            root.display()
+        );
+        if let Err(e) = sandbox::chroot(&mut command, root, cli.chdir.as_deref()) {
+            exit::fail(Error::Config(e));
+        }
+    }
+    #[cfg(not(unix))]
+    if cli.chroot.is_some() {
+        exit::fail(Error::Unsupported {
+            option: "--chroot",
+            platform: "Unix",
+        });
+    }
+    // Drop the child's credentials once nothing else needs the watchdog's, but before the
+    // parent-death signal is set, which the kernel clears when they change.
+    #[cfg(unix)]
+    if cli.user.is_some() || cli.group.is_some() {
+        match credentials::Credentials::resolve(cli.user.as_deref(), cli.group.as_deref()) {
+            Ok(credentials) => {
+                info!("Running the child as {}.", credentials.describe());
+                credentials.apply(&mut command);
+            }
+            Err(e) => exit::fail(Error::Config(format!(
+                "Cannot run the child with --user/--group: {}.",
+                e
+            ))),
+        }
+    }
+    #[cfg(not(unix))]
+    if cli.user.is_some() || cli.group.is_some() {
+        exit::fail(Error::Unsupported {
+            option: if cli.user.is_some() {
+                "--user"
+            } else {
+                "--group"
+            },
+            platform: "Unix",
+        });
+    }
+    // Should the watchdog die without cleaning up, even of SIGKILL, the child is told.
+    #[cfg(target_os = "linux")]
+    if let Some(signal) = cli.parent_death_signal {
+        info!(
+            "The child is sent {} should the watchdog die (--parent-death-signal).",
+            signal
+        );
+        child::set_parent_death_signal(&mut command, signal);
+    }
+    #[cfg(not(target_os = "linux"))]
+    if cli.parent_death_signal.is_some() {
+        exit::fail(Error::Unsupported {
+            option: "--parent-death-signal",
+            platform: "Linux",
+        });
+    }
+    // Last, so that everything above also applies to the process between the watchdog and the
+    // child.
+    #[cfg(target_os = "linux")]
+    if cli.unshare.contains(&child::Namespace::Pid) {
+        sandbox::enter_pid_namespace(&mut command);
+    }
+
+    // Make the child's stdin a pipe the watchdog holds, if requested. This is attached before
+    // the heartbeat pipe, whose dup2 may reuse the read end's descriptor number.
+    #[cfg(unix)]
+    let stdin_pipe = if cli.stdin_pipe {
+        match heartbeat_pipe::StdinPipe::create() {
+            Ok(pipe) => {
+                pipe.attach(&mut command);
+                Some(pipe)
+            }
+            Err(e) => {
+                exit::fail(Error::Open {
+                    what: "create stdin pipe".to_string(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+    } else {
+        None
+    };
+    #[cfg(not(unix))]
+    if cli.stdin_pipe {
+        exit::fail(Error::Unsupported {
+            option: "--stdin-pipe",
+            platform: "Unix",
+        });
+    }
+
+    // Hand the child the write end of a private heartbeat pipe, if requested.
+    #[cfg(unix)]
+    let heartbeat_pipe = match cli.heartbeat_fd {
+        Some(child_fd) => {
+            if let Err(e) = heartbeat_pipe::validate_child_fd(child_fd) {
+                exit::fail(Error::Config(e));
+            }
+            match heartbeat_pipe::HeartbeatPipe::create(child_fd) {
+                Ok(pipe) => {
+                    pipe.attach(&mut command);
+                    Some(pipe)
+                }
+                Err(e) => {
+                    exit::fail(Error::Open {
+                        what: "create heartbeat pipe".to_string(),
+                        reason: e.to_string(),
+                    });
+                }
+            }
+        }
+        None => None,
+    };
+    #[cfg(not(unix))]
+    if cli.heartbeat_fd.is_some() {
+        exit::fail(Error::Unsupported {
+            option: "--heartbeat-fd",
+            platform: "Unix",
+        });
+    }
+
+    let bind_options = listener::BindOptions {
+        ipv6_only: cli.ipv6_only,
+        dual_stack: cli.dual_stack,
+        recv_buffer_size: cli.recv_buffer_size,
+        reuse_addr: cli.reuse_addr,
+        bind_device: cli.bind_device.clone(),
+        report_ttl: cli.min_ttl.is_some(),
+        retries: cli.bind_retries,
+        backoff: cli.bind_backoff,
+    };
+    // With --spawn-on-first-ping the listen sockets are opened now, and the child is only
+    // spawned once one of them is sent a heartbeat.
+    let opened_sockets = if cli.spawn_on_first_ping {
+        let sockets = match listener::open_sockets(
+            &cli.listen_addr,
+            std::mem::take(&mut activated_sockets),
+            &bind_options,
+        )
+        .await
+        {
+            Ok(sockets) => sockets,
+            Err(e) => exit::fail(e),
+        };
+        info!("Waiting for the first heartbeat before starting the child (--spawn-on-first-ping).");
+        let filter = filter::SourceFilter::new(cli.allow_scope.clone(), cli.allow_from.clone());
+        let sender = listener::first_ping(&sockets, &filter).await;
+        info!(
+            "First heartbeat received from {}; starting the child.",
+            sender
+        );
+        audit::record(
+            "first_ping",
+            serde_json::json!({ "from": sender.to_string() }),
+        );
+        Some(sockets)
+    } else {
+        None
+    };
+
+    // --- Spawn the child process, unless a container or running process is supervised instead ---
+    #[cfg(unix)]
+    let supervised = match (container, attached) {
+        (Some(container), _) => {
+            info!(
+                "Attached to container {} ({}, PID: {}).",
+                container.name,
+                container.short_id(),
+                container.pid
+            );
+            Supervised::Container(container, cli.docker_action.unwrap_or(docker::Action::Stop))
+        }
+        (None, Some(attached)) => {
+            info!(
+                "Attached to process {}; on failure, {} is killed.",
+                attached.identity.pid,
+                attached.target()
+            );
+            Supervised::Attached(attached)
+        }
+        (None, None) => spawn_child(&mut command, &child_binary_path).await,
+    };
+    #[cfg(not(unix))]
+    let supervised = spawn_child(&mut command, &child_binary_path).await;
+    let child_identity = supervised.identity();
+    let child_pid = supervised.pid();
+    audit::record(
+        "start",
+        serde_json::json!({
+            "pid": supervised.pid(),
+            "container": cli.docker_container,
+            "attached": cli.attach_pid.is_some(),
+            "generation": generation,
+            "name": cli.name,
+            "timeout_ms": timeout_duration.as_millis() as u64,
+            "interval_ms": cli.interval.map(|interval| interval.as_millis() as u64),
+            "startup_timeout_ms": cli.startup_timeout.map(|timeout| timeout.as_millis() as u64),
+        }),
+    );
+
+    // Channel to notify the monitor about received signals
+    let (signal_tx, signal_rx) = watch::channel(Instant::now());
+    // Channel for the operating state the child reports in structured heartbeats
+    let (state_tx, state_rx) = watch::channel(ChildState::Normal);
+    // Channel for sources that want the monitor to act before the timeout (e.g. failed probes)
+    let (trigger_tx, trigger_rx) = mpsc::channel(1);
+    // Channel for control commands (--allow-commands, and pausing by signal), and what they
+    // have changed
+    let (command_tx, command_rx) = mpsc::channel(16);
+    // Channel for signals the watchdog received that are passed on to the child
+    let (forward_tx, forward_rx) = mpsc::channel(16);
+    let (listen_failed_tx, listen_failed_rx) = mpsc::channel(1);
+    let control = Arc::new(command::Control::default());
+
+    // Create a channel for propagating termination signals
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+
+    // --- Task 0: Set up signal handling ---
+    // Killing a process group is immediate, unless it first gets --kill-grace to stop, but
+    // making sure it is gone may take up to --kill-timeout; stopping a container takes up to
+    // its stop timeout.
+    #[cfg(unix)]
+    let kill_timeout = stop::kill_timeout();
+    #[cfg(not(unix))]
+    let kill_timeout = Duration::ZERO;
+    let cleanup = match cli.docker_container {
+        None => Duration::from_millis(200) + cli.kill_grace.unwrap_or_default() + kill_timeout,
+        Some(_) => {
+            docker::STOP_GRACE + Duration::from_secs(cli.docker_stop_timeout.unwrap_or(10).into())
+        }
+    };
+    let detach = cli.observe_only || cli.no_kill_on_exit;
+    // --forward-signal SIGNAL is short for --signal-map SIGNAL=forward.
+    #[cfg(unix)]
+    let dispositions = signals::dispositions(
+        &cli.forward_signal
+            .iter()
+            .map(|&signal| (signal, signals::Disposition::Forward))
+            .chain(cli.signal_map.iter().copied())
+            .collect::<Vec<_>>(),
+    );
+    #[cfg(unix)]
+    if !cli.forward_signal.is_empty() || !cli.signal_map.is_empty() {
+        let map: Vec<String> = dispositions
+            .iter()
+            .map(|(signal, disposition)| format!("{}={}", signal, disposition.as_str()))
+            .collect();
+        info!("Signal dispositions: {}.", map.join(", "));
+    }
+    #[cfg(not(unix))]
+    let dispositions = Vec::new();
+    tokio::spawn(handle_termination_signals(
+        child_identity.filter(|_| !detach),
+        cleanup,
+        dispositions,
+        command_tx.clone(),
+        forward_tx,
+        shutdown_tx,
+    ));
+
+    // Limits conflict with --docker-container, so there is always a process group here.
+    #[cfg(target_os = "linux")]
+    if let Some(child_identity) =
+        child_identity.filter(|_| cli.cpu_limit.is_some() || cli.io_limit.is_some())
+    {
+        info!(
+            "Throttling the child above{}{}.",
+            cli.cpu_limit
+                .map(|limit| format!(" {}% CPU", limit))
+                .unwrap_or_default(),
+            cli.io_limit
+                .map(|limit| format!(" {} B/s of I/O", limit))
+                .unwrap_or_default()
+        );
+        tokio::spawn(throttle::run_throttle(
+            child_identity,
+            throttle_limits,
+            cli.throttle_stop,
+        ));
+    }
+
+    // --- Task 1: Listen for signals via UDP ---
+    let stats = Arc::new(Stats::default());
+    let clock_skew = cli.clock_skew_alarm.map(|limit| {
+        info!(
+            "Reporting clock skew over {:?} in heartbeat timestamps.",
+            limit
+        );
+        Arc::new(skew::ClockSkew::new(limit))
+    });
+    // Senders that have not reported metrics within the timeout no longer count.
+    let metrics = Arc::new(metrics::Metrics::new(timeout_duration));
+    if let Some(path) = &cli.metrics_file {
+        info!(
+            "Writing metrics reported in heartbeats to {}.",
+            path.display()
+        );
+        tokio::spawn(metrics::run_writer(metrics.clone(), path.clone()));
+    }
+    let sink = HeartbeatSink::new(
+        signal_tx,
+        state_tx,
+        trigger_tx,
+        generation,
+        stats.clone(),
+        liveness.clone(),
+        quorum.clone(),
+        components.clone(),
+        clock_skew,
+        metrics,
+    );
+    let supervised = match supervised {
+        Supervised::Process(child, identity, _) if respawnable => Supervised::Process(
+            child,
+            identity,
+            Some(Box::new(Respawn {
+                command,
+                path: child_binary_path.clone(),
+                generation: sink.generation_handle(),
+                start_first: (cli.restart_strategy == child::RestartStrategy::StartFirst)
+                    .then_some(cli.replacement_timeout),
+                #[cfg(target_os = "linux")]
+                throttle: (cli.cpu_limit.is_some() || cli.io_limit.is_some())
+                    .then_some((throttle_limits, cli.throttle_stop)),
+            })),
+        ),
+        supervised => supervised,
+    };
+    let restartable = supervised.restartable();
+    if cli.restart_strategy == child::RestartStrategy::StartFirst {
+        info!(
+            "A running child is replaced by starting its replacement first and terminating it once the replacement heartbeats, or after {:?}.",
+            cli.replacement_timeout
+        );
+    }
+    let crash_loop = cli.max_restarts.map(|max| {
+        info!(
+            "Giving up on the child once it has been restarted {} times within {:?}.",
+            max, cli.restart_window
+        );
+        crash_loop::CrashLoop::new(max, cli.restart_window, cli.crash_loop_exec.clone())
+    });
+    let backoff = Arc::new(backoff::Backoff::new(
+        cli.restart_backoff,
+        cli.restart_backoff_max,
+        cli.restart_jitter,
+        cli.min_uptime,
+    ));
+    if cli.restart != child::RestartPolicy::Never {
+        info!(
+            "Restarting the child when it exits (--restart {}), after {:?} doubling up to {:?} with {}% jitter.",
+            cli.restart.as_str(),
+            cli.restart_backoff,
+            cli.restart_backoff_max,
+            cli.restart_jitter
+        );
+    }
+    #[cfg(unix)]
+    if let Some((path, port, magic)) = serial_port {
+        tokio::spawn(serial::run_serial_listener(
+            path,
+            cli.baud,
+            port,
+            magic,
+            sink.for_source("serial"),
+        ));
+    }
+    #[cfg(unix)]
+    if let Some(pipe) = heartbeat_pipe {
+        match pipe.into_reader() {
+            Ok(reader) => {
+                info!(
+                    "Listening for heartbeats on inherited fd {} ({}={}).",
+                    cli.heartbeat_fd.unwrap_or_default(),
+                    heartbeat_pipe::FD_ENV,
+                    cli.heartbeat_fd.unwrap_or_default()
+                );
+                tokio::spawn(heartbeat_pipe::run_pipe_listener(
+                    reader,
+                    cli.kill_on_fd_close,
+                    sink.for_source("fd"),
+                ));
+            }
+            Err(e) => error!("Failed to set up heartbeat pipe reader: {}", e),
+        }
+    }
+    #[cfg(unix)]
+    if let Some(pipe) = stdin_pipe {
+        match pipe.into_watcher() {
+            Ok(watcher) => {
+                info!("Watching the child's stdin pipe; closing it ends supervision.");
+                tokio::spawn(heartbeat_pipe::run_stdin_watcher(
+                    watcher,
+                    sink.trigger_handle(),
+                ));
+            }
+            Err(e) => error!("Failed to watch the child's stdin pipe: {}", e),
+        }
+    }
+    for probe in probes {
+        let probe_sink = sink.for_source(probe.source());
+        info!(
+            "Probing {} every {:?}; acting after {} consecutive failures.",
+            probe.describe(),
+            cli.probe_interval,
+            cli.probe_failures
+        );
+        tokio::spawn(probe::run_probe(
+            probe,
+            cli.probe_interval,
+            cli.probe_failures,
+            probe_sink,
+        ));
+    }
+    #[cfg(target_os = "linux")]
+    if let Some((name, queue)) = message_queue {
+        tokio::spawn(mq::run_mq_listener(
+            queue,
+            name,
+            payload::codec_for(&cli.payload_codec, "mq"),
+            sink.for_source("mq"),
+        ));
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(vsock_listener) = vsock_listener {
+        tokio::spawn(vsock::run_vsock_listener(
+            vsock_listener,
+            sink.for_source("vsock"),
+        ));
+    }
+    if let Some(path) = &cli.watch_log {
+        info!(
+            "Counting growth of log file {}{} as heartbeats.",
+            path.display(),
+            cli.watch_log_regex
+                .as_ref()
+                .map(|regex| format!(" (lines that match '{}')", regex))
+                .unwrap_or_default()
+        );
+        tokio::spawn(logfile::run_logfile_listener(
+            path.clone(),
+            cli.watch_log_regex.clone(),
+            sink.for_source("log"),
+        ));
+    }
+    let stdout_heartbeat = cli.stdout_heartbeat.then(|| {
+        info!(
+            "Counting lines the child writes to stdout{} as heartbeats.",
+            cli.stdout_heartbeat_regex
+                .as_ref()
+                .map(|regex| format!(" that match '{}'", regex))
+                .unwrap_or_default()
+        );
+        (
+            sink.for_source("stdout"),
+            cli.stdout_heartbeat_regex.clone(),
+        )
+    });
+    let alive = cli.alive_regex.clone().map(|regex| {
+        info!(
+            "Counting output lines that match '{}' as heartbeats.",
+            regex
+        );
+        (sink.for_source("output"), regex)
+    });
+    let dead = cli.dead_regex.clone().map(|regex| {
+        info!(
+            "Acting on the child as soon as its output matches '{}'.",
+            regex
+        );
+        (sink.trigger_handle(), regex)
+    });
+    let ready = cli.ready_regex.clone().map(|regex| {
+        info!(
+            "The timeout is armed once the child's output matches '{}'.",
+            regex
+        );
+        (watch::channel(None).0, regex)
+    });
+    let output_rules = Arc::new(output::OutputRules {
+        stdout_heartbeat,
+        alive,
+        dead,
+        ready,
+        tail: cli
+            .incident_report
+            .as_ref()
+            .map(|_| output::OutputTail::new(cli.incident_output_lines)),
+    });
+    let udp_sink = sink.for_source("udp");
+    // Every source has joined by now. Drop this handle so that the monitor notices once the
+    // sources themselves have all stopped.
+    sink.publish_combined();
+    drop(sink);
+    let signal_listener = tokio::spawn(listener::run_udp_listener(
+        cli.listen_addr.clone(),
+        activated_sockets,
+        opened_sockets,
+        bind_options,
+        Arc::new(listener::PacketPolicy {
+            rate_limit: ratelimit::RateLimiter::new(cli.rate_limit, cli.source_rate_limit),
+            filter: filter::SourceFilter::new(cli.allow_scope.clone(), cli.allow_from.clone()),
+            max_packet_size: cli.max_packet_size,
+            log_payloads: cli.log_payloads,
+            log_heartbeats: cli.log_heartbeats.then(|| {
+                sampler::Sampler::new(cli.log_heartbeats_every, cli.log_heartbeats_interval)
+            }),
+            strict_protocol: cli.strict_protocol,
+            min_ttl: cli.min_ttl,
+            #[cfg(feature = "dtls")]
+            dtls,
+            decrypt,
+            auth,
+            expect: cli.expect_payload.clone(),
+            codec: payload::codec_for(&cli.payload_codec, "udp"),
+            status: status::Status::new(
+                timeout_duration,
+                cli.startup_timeout,
+                cli.arm_on_first_ping,
+                degraded_policy.timeout,
+                adaptive.clone(),
+                (cli.restart != child::RestartPolicy::Never).then(|| backoff.clone()),
+                child_pid,
+                control.clone(),
+            ),
+            ack: cli.ack,
+            status_query: cli.status_query,
+            commands: (cli.allow_commands || cli.allow_extend).then_some(command_tx),
+            extend_only: !cli.allow_commands,
+            max_extension: cli.max_extension,
+            restartable,
+        }),
+        udp_sink,
+        cli.low_power,
+        listen_failed_tx,
+    ));
 
-    #[arg(short, long, value_name = "SECONDS", default_value_t = 5)]
-    timeout_secs: u64,
+    // --- Task 2: Monitor for timeout and child exit ---
+    let monitor_task = tokio::spawn(monitor_timeout(Monitor {
+        supervised,
+        signal_rx,
+        state_rx,
+        trigger_rx,
+        command_rx,
+        forward_rx,
+        listen_failed_rx,
+        control,
+        timeout_duration,
+        interval: cli.interval,
+        startup_timeout: cli.startup_timeout,
+        arm_on_first_ping: cli.arm_on_first_ping,
+        max_runtime: cli.max_runtime,
+        degraded: degraded_policy,
+        adaptive,
+        warning,
+        escalation,
+        suspend_time: cli.suspend_time,
+        on_timeout: if cli.observe_only {
+            TimeoutAction::None
+        } else {
+            cli.on_timeout.clone()
+        },
+        on_timeout_exec: cli.on_timeout_exec.clone(),
+        diagnose: cli.diagnose_exec.clone().map(|command| diagnose::Diagnose {
+            command,
+            limit: cli.diagnose_timeout,
+        }),
+        incident_report: cli
+            .incident_report
+            .clone()
+            .map(|dir| incident::IncidentReport { dir }),
+        observe_only: cli.observe_only,
+        no_kill_on_exit: cli.no_kill_on_exit,
+        freeze_on_pause: cli.freeze_on_pause,
+        restart_policy: cli.restart,
+        exit_codes: child::ExitCodes {
+            success: cli.success_codes.clone(),
+            no_restart: cli.no_restart_codes.clone(),
+        },
+        backoff,
+        crash_loop,
+        shutdown_rx,
+        low_power: cli.low_power,
+        liveness,
+        quorum,
+        components,
+        output_rules,
+        stats,
+    }));
 
-    #[arg(value_name = "BINARY_PATH")]
-    child_binary_path: PathBuf,
+    // Wait for the monitor task to complete (it will exit the process internally)
+    // Or handle potential errors from the monitor task itself
+    if let Err(e) = monitor_task.await {
+        // Abort the listener if it's still running
+        signal_listener.abort();
+        exit::fail(Error::Internal(format!("Monitor task failed: {}", e)));
+    }
 
-    #[arg(last = true, value_name = "CHILD_ARGS")]
-    child_args: Vec<String>,
+    // In the normal case, monitor_task calls std::process::exit, so this part might not be reached.
+    info!("Watchdog main function finished cleanly (unexpected).");
+    signal_listener.abort(); // Ensure listener stops if monitor task somehow returned Ok
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = Cli::parse();
+/// Handles termination signals and initiates child process cleanup. `child_identity` is
+/// `None` for a container, which is left to the monitor; `cleanup` bounds how long the monitor
+/// gets to finish before the watchdog exits anyway. On Unix, what a signal does is looked up
+/// in `dispositions` (`--signal-map`): it may instead be passed on to the child through
+/// `forward`, or pause or resume the timeout through `commands`.
+#[cfg_attr(not(unix), allow(unused_variables))]
+async fn handle_termination_signals(
+    child_identity: Option<ProcessIdentity>,
+    cleanup: Duration,
+    dispositions: Vec<(signals::Signal, signals::Disposition)>,
+    commands: mpsc::Sender<(command::Command, String)>,
+    forward: mpsc::Sender<signals::Signal>,
+    shutdown_tx: tokio::sync::oneshot::Sender<()>,
+) {
+    info!("Setting up signal handlers for graceful shutdown...");
+    #[cfg(unix)]
+    let mut received = {
+        let (received_tx, received) = mpsc::channel(16);
+        for (signal, disposition) in dispositions {
+            tokio::spawn(receive_signal(signal, disposition, received_tx.clone()));
+        }
+        received
+    };
 
-    println!(
-        "Launching child process: {} with args: {:?}",
-        cli.child_binary_path.display(),
-        cli.child_args
-    );
-    println!("Listening for UDP signals on: {}", cli.listen_addr);
-    println!("Timeout set to: {} seconds", cli.timeout_secs);
+    #[cfg(unix)]
+    loop {
+        // Signals that do not shut the watchdog down are dealt with as they arrive.
+        let Some((signal, disposition)) = received.recv().await else {
+            // No handler could be set up: only the monitor ends the watchdog.
+            return std::future::pending().await;
+        };
+        match disposition {
+            signals::Disposition::Graceful => {
+                info!(
+                    "Received {} signal{}. Initiating shutdown...",
+                    signal,
+                    if signal.number == libc::SIGINT {
+                        " (Ctrl+C)"
+                    } else {
+                        ""
+                    }
+                );
+                break;
+            }
+            signals::Disposition::Kill => {
+                info!(
+                    "Received {} signal. Initiating shutdown, killing the child right away...",
+                    signal
+                );
+                stop::skip_grace();
+                break;
+            }
+            signals::Disposition::Ignore => {
+                info!("Received {} signal; ignoring it (--signal-map).", signal);
+            }
+            signals::Disposition::Forward => {
+                let _ = forward.send(signal).await;
+            }
+            signals::Disposition::Pause => {
+                let _ = commands
+                    .send((command::Command::Pause, signal.to_string()))
+                    .await;
+            }
+            signals::Disposition::Resume => {
+                let _ = commands
+                    .send((command::Command::Resume, signal.to_string()))
+                    .await;
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        // On Windows, we handle Ctrl+C and Ctrl+Break, plus the console window being closed,
+        // the user logging off and the system shutting down. For the last three Windows ends
+        // the process as soon as the handler returns; tokio's handler never returns, so the
+        // cleanup below still runs (within the few seconds the system allows).
+        let mut ctrl_c = windows::ctrl_c().expect("Failed to set up Ctrl+C handler");
+        let mut ctrl_break = windows::ctrl_break().expect("Failed to set up Ctrl+Break handler");
+        let mut ctrl_close = windows::ctrl_close().expect("Failed to set up Ctrl+Close handler");
+        let mut ctrl_logoff = windows::ctrl_logoff().expect("Failed to set up Ctrl+Logoff handler");
+        let mut ctrl_shutdown =
+            windows::ctrl_shutdown().expect("Failed to set up Ctrl+Shutdown handler");
 
-    if cli.timeout_secs == 0 {
-        eprintln!("Error: Timeout must be greater than 0 seconds.");
-        std::process::exit(1);
+        tokio::select! {
+            _ = ctrl_c.recv() => {
+                info!("Received Ctrl+C signal. Initiating shutdown...");
+            }
+            _ = ctrl_break.recv() => {
+                info!("Received Ctrl+Break signal. Initiating shutdown...");
+            }
+            _ = ctrl_close.recv() => {
+                info!("Console window is closing. Initiating shutdown...");
+            }
+            _ = ctrl_logoff.recv() => {
+                info!("User is logging off. Initiating shutdown...");
+            }
+            _ = ctrl_shutdown.recv() => {
+                info!("System is shutting down. Initiating shutdown...");
+            }
+        }
     }
-    let timeout_duration = Duration::from_secs(cli.timeout_secs);
 
-    // --- Setup command with platform-specific process group handling ---
-    let mut command = Command::new(&cli.child_binary_path);
-    command
-        .args(&cli.child_args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
+    // Send shutdown signal to monitor task
+    if shutdown_tx.send(()).is_err() {
+        // If the receiver is dropped, it means the monitor task has already exited.
+        // In that case, we'll try to kill the child process directly.
+        info!("Monitor task already exited. Attempting to kill child process directly.");
+
+        // The child may have been reaped long ago, so its PID could have been recycled.
+        #[cfg(unix)]
+        if let Some(child_identity) = child_identity {
+            info!("Sending SIGKILL to process group {}.", child_identity.pid);
+            if let Err(e) = procinfo::signal_process_group(&child_identity, libc::SIGKILL) {
+                error!("Not killing process group {}: {}", child_identity.pid, e);
+                events::emit(
+                    events::Event::KillFailure,
+                    format!("Not killing process group {}: {}", child_identity.pid, e),
+                )
+                .await;
+            }
+        }
+
+        // Its Job Object takes the whole tree with it; without one, the child is opened by PID.
+        #[cfg(windows)]
+        if let Some(child_identity) = child_identity {
+            let pid = child_identity.pid;
+            let result = match job::terminate(pid) {
+                Some(Ok(_)) => {
+                    info!("Terminated the Job Object of child process {}.", pid);
+                    Ok(())
+                }
+                Some(Err(e)) => {
+                    error!(
+                        "Failed to terminate the Job Object of child process {}: {}",
+                        pid, e
+                    );
+                    terminate_descendants(pid, &procinfo::descendants(pid)).await;
+                    info!("Terminating child process {}.", pid);
+                    procinfo::terminate_process(&child_identity, 1)
+                }
+                None => {
+                    terminate_descendants(pid, &procinfo::descendants(pid)).await;
+                    info!("Terminating child process {}.", pid);
+                    procinfo::terminate_process(&child_identity, 1)
+                }
+            };
+            if let Err(e) = result {
+                error!("Not killing child process {}: {}", pid, e);
+                events::emit(
+                    events::Event::KillFailure,
+                    format!("Not killing child process {}: {}", pid, e),
+                )
+                .await;
+            }
+        }
+    } else {
+        info!("Shutdown signal sent to monitor task. Waiting for cleanup to complete...");
+        // Give the monitor a moment to handle the shutdown (and deliver its notification)
+        sleep(cleanup + events::delivery_grace()).await;
+    }
+
+    // Exit the process
+    info!("Signal handler exiting the watchdog process.");
+    exit::exit(ExitReason::Signal);
+}
+
+/// Passes `signal` on with its `disposition` each time the watchdog receives it.
+#[cfg(unix)]
+async fn receive_signal(
+    signal: signals::Signal,
+    disposition: signals::Disposition,
+    received: mpsc::Sender<(signals::Signal, signals::Disposition)>,
+) {
+    let mut stream = match tokio::signal::unix::signal(SignalKind::from_raw(signal.number)) {
+        Ok(stream) => stream,
+        Err(e) => {
+            error!(
+                "Failed to set up a {} handler; it does not {}: {}",
+                signal,
+                match disposition {
+                    signals::Disposition::Forward => "reach the child",
+                    signals::Disposition::Ignore => "get ignored",
+                    signals::Disposition::Graceful | signals::Disposition::Kill => {
+                        "shut the watchdog down"
+                    }
+                    signals::Disposition::Pause => "pause the timeout",
+                    signals::Disposition::Resume => "resume the timeout",
+                },
+                e
+            );
+            return;
+        }
+    };
+    while stream.recv().await.is_some() {
+        if received.send((signal, disposition)).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Attempts to kill the process group on Unix, or just the process on Windows.
+async fn kill_child_process_tree(child: &mut Child, identity: ProcessIdentity) {
+    let pid = identity.pid;
+    info!(
+        "Terminating child process{} (PID: {})...",
+        if cfg!(unix) { " group" } else { "" },
+        pid
+    );
 
     #[cfg(unix)]
     {
-        // Place the child process in its own process group.
-        // The PGID will be the same as the child's PID.
-        command.process_group(0);
+        // PGID is the same as PID because we used command.process_group(0).
+        let pgid = pid as i32; // Cast PID to i32 for libc functions
+                               // Descendants that left the group (e.g. daemons) are found while their parents still
+                               // lead back to the child.
+        #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+        let mut tree = procinfo::descendants(pid);
+        // With --subreaper, so are those that were orphaned and adopted by the watchdog.
+        #[cfg(target_os = "linux")]
+        tree.extend(subreaper::adopted_tree());
+
+        // With --kill-grace, ask the group to stop first; whatever is left of it is killed
+        // once the child has exited or the grace period is over.
+        let mut exited = false;
+        if let Some(stop) = stop::graceful() {
+            info!(
+                "Sending {} to process group {}; killing it after {:?} at the latest.",
+                stop.signal, pgid, stop.grace
+            );
+            match procinfo::signal_process_group(&identity, stop.signal.number) {
+                Ok(()) => match tokio::time::timeout(stop.grace, child.wait()).await {
+                    Ok(Ok(status)) => {
+                        exited = true;
+                        info!(
+                            "Child process exited within the grace period with status: {}",
+                            status
+                        );
+                    }
+                    _ => info!("Child process did not exit within the grace period."),
+                },
+                Err(e) => error!(
+                    "Failed to send {} to process group {}: {}",
+                    stop.signal, pgid, e
+                ),
+            }
+        }
+        // A child that exited on its own (before a restart) may have left nothing behind.
+        exited |= matches!(child.try_wait(), Ok(Some(_)));
+        // Send SIGKILL to the entire process group.
+        info!("Attempting to send SIGKILL to process group {}.", pgid);
+        match procinfo::signal_process_group(&identity, libc::SIGKILL) {
+            // The whole group is gone already.
+            Err(err) if exited && err.raw_os_error() == Some(libc::ESRCH) => {
+                info!("No processes left in process group {}.", pgid);
+            }
+            Err(err) => {
+                // EINVAL: pgid <= 0. ESRCH: No process/group found. EPERM: No permission.
+                Error::Kill {
+                    what: format!("kill process group {} with killpg", pgid),
+                    reason: format!("{}; falling back to killing PID {}", err, pid),
+                }
+                .report();
+                // Fallback: Attempt to kill the direct child process if killpg fails or if the process is not in the group somehow
+                let fallback = if let Err(e) = child.start_kill() {
+                    // `start_kill` is non-blocking
+                    Error::Kill {
+                        what: format!("kill child process {} (fallback)", pid),
+                        reason: e.to_string(),
+                    }
+                    .report();
+                    format!("fallback kill failed: {}", e)
+                } else {
+                    info!("Fallback kill signal sent to PID {}.", pid);
+                    "fallback kill signal sent".to_string()
+                };
+                events::emit(
+                    events::Event::KillFailure,
+                    format!(
+                        "Failed to kill process group {}: {}; {}",
+                        pgid, err, fallback
+                    ),
+                )
+                .await;
+            }
+            Ok(()) => info!("Sent SIGKILL to process group {}.", pgid),
+        }
+        // With --cgroup, whatever left the group is still in the cgroup.
+        #[cfg(target_os = "linux")]
+        if let Some(cgroup) = cgroup::active() {
+            match cgroup.kill() {
+                Ok(()) => info!("Killed cgroup {}.", cgroup.path.display()),
+                Err(e) => {
+                    let error = Error::Kill {
+                        what: format!("kill cgroup {}", cgroup.path.display()),
+                        reason: e.to_string(),
+                    };
+                    error.report();
+                    events::emit(events::Event::KillFailure, error.to_string()).await;
+                }
+            }
+        }
+        verify_killed(Some(child), identity, true, &tree).await;
     }
 
-    // --- Spawn the child process ---
-    let mut child = match command.spawn() {
-        Ok(child) => child,
+    #[cfg(windows)]
+    {
+        // Without a Job Object, the descendants are found while their parents still lead back
+        // to the child.
+        let tree = if job::has_job(pid) {
+            Vec::new()
+        } else {
+            procinfo::descendants(pid)
+        };
+        // With --kill-grace, ask the child to stop first, as SIGTERM would on Unix, unless it
+        // exited already (before a restart).
+        if let Some(grace) = ctrl_break::grace().filter(|_| matches!(child.try_wait(), Ok(None))) {
+            info!(
+                "Sending Ctrl+Break to child process {}; terminating it after {:?} at the latest.",
+                pid, grace
+            );
+            match ctrl_break::send(pid) {
+                Ok(()) => match tokio::time::timeout(grace, child.wait()).await {
+                    Ok(Ok(status)) => info!(
+                        "Child process exited within the grace period with status: {}",
+                        status
+                    ),
+                    _ => info!("Child process did not exit within the grace period."),
+                },
+                Err(e) => error!("Failed to send Ctrl+Break to child process {}: {}", pid, e),
+            }
+        }
+        // The child's Job Object holds everything it started; without one, its descendants are
+        // terminated one by one, the deepest first.
+        match job::terminate(pid) {
+            Some(Ok(0)) => info!("Terminated the Job Object of child process {} and all its processes.", pid),
+            Some(Ok(left)) => info!(
+                "Terminated the Job Object of child process {}; {} of its processes are still exiting.",
+                pid, left
+            ),
+            Some(Err(e)) => {
+                let error = Error::Kill {
+                    what: format!("terminate the Job Object of child process {}", pid),
+                    reason: e.to_string(),
+                };
+                error.report();
+                events::emit(events::Event::KillFailure, error.to_string()).await;
+                terminate_descendants(pid, &procinfo::descendants(pid)).await;
+            }
+            None => terminate_descendants(pid, &tree).await,
+        }
+        info!("Attempting to kill process {} (Windows).", pid);
+        if let Err(e) = child.start_kill() {
+            let error = Error::Kill {
+                what: format!("kill child process {}", pid),
+                reason: e.to_string(),
+            };
+            error.report();
+            events::emit(events::Event::KillFailure, error.to_string()).await;
+        } else {
+            info!("Kill signal sent to PID {}.", pid);
+        }
+    }
+
+    // Give a brief moment for the signal to take effect.
+    #[cfg(windows)]
+    sleep(Duration::from_millis(100)).await;
+
+    // Optionally, explicitly wait for the child to exit after sending kill signal
+    match child.try_wait() {
+        Ok(Some(status)) => info!(
+            "Child process confirmed exit after kill signal with status: {}",
+            status
+        ),
+        Ok(None) => {
+            info!(
+                "Child process still running shortly after kill signal, continuing watchdog exit."
+            );
+            // It might take longer, but the watchdog is exiting anyway.
+        }
+        Err(e) => Error::Wait {
+            what: format!("child process {} after kill", pid),
+            reason: e.to_string(),
+        }
+        .report(),
+    }
+}
+
+/// Kills an attached process (`--attach-pid`), and its group if it leads one, the way
+/// [`kill_child_process_tree`] kills a spawned child's.
+#[cfg(unix)]
+async fn kill_attached(attached: &attach::Attached) {
+    let target = attached.target();
+    info!("Terminating attached {}...", target);
+    let tree = procinfo::descendants(attached.identity.pid);
+    if let Some(stop) = stop::graceful() {
+        info!(
+            "Sending {} to {}; killing it after {:?} at the latest.",
+            stop.signal, target, stop.grace
+        );
+        match attached.signal(stop.signal.number) {
+            Ok(()) => match tokio::time::timeout(stop.grace, attached.wait()).await {
+                Ok(_) => info!("Attached process exited within the grace period."),
+                Err(_) => info!("Attached process did not exit within the grace period."),
+            },
+            Err(e) => error!("Failed to send {} to {}: {}", stop.signal, target, e),
+        }
+    }
+    info!("Attempting to send SIGKILL to {}.", target);
+    match attached.signal(libc::SIGKILL) {
+        Ok(()) => info!("Sent SIGKILL to {}.", target),
+        // Gone already, e.g. within the grace period.
+        Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {
+            info!(
+                "Attached process {} is gone already.",
+                attached.identity.pid
+            );
+        }
         Err(e) => {
-            eprintln!(
-                "Failed to spawn child process '{}': {}",
-                cli.child_binary_path.display(),
-                e
+            let error = Error::Kill {
+                what: format!("kill {}", target),
+                reason: e.to_string(),
+            };
+            error.report();
+            events::emit(events::Event::KillFailure, error.to_string()).await;
+        }
+    }
+    verify_killed(None, attached.identity, attached.group, &tree).await;
+}
+
+/// Kills the processes the watchdog adopted (`--subreaper`) from a child that exited on its
+/// own, which would otherwise outlive it unsupervised.
+#[cfg(target_os = "linux")]
+async fn kill_adopted() {
+    let adopted = subreaper::adopted_tree();
+    if adopted.is_empty() {
+        return;
+    }
+    let pids: Vec<String> = adopted
+        .iter()
+        .map(|process| process.pid.to_string())
+        .collect();
+    info!(
+        "The child left adopted processes running: PIDs {}; killing them.",
+        pids.join(", ")
+    );
+    for process in &adopted {
+        if let Err(e) = procinfo::signal_process(process, libc::SIGKILL) {
+            if e.raw_os_error() != Some(libc::ESRCH) {
+                let error = Error::Kill {
+                    what: format!("kill adopted process {}", process.pid),
+                    reason: e.to_string(),
+                };
+                error.report();
+                events::emit(events::Event::KillFailure, error.to_string()).await;
+            }
+        }
+    }
+    audit::record("adopted_killed", serde_json::json!({ "pids": pids }));
+}
+
+/// Makes sure that the child's process group (or, if `group` is false, the process itself),
+/// and the descendants in `tree` that left it, are gone after being sent SIGKILL: whatever is
+/// still running is killed again until it is, or until `--kill-timeout` runs out and the
+/// survivors are reported. `child` is reaped along the way, if the watchdog spawned it.
+#[cfg(unix)]
+async fn verify_killed(
+    mut child: Option<&mut Child>,
+    identity: ProcessIdentity,
+    group: bool,
+    tree: &[ProcessIdentity],
+) {
+    let started = Instant::now();
+    let mut attempts = 0;
+    loop {
+        // Reaped, the child no longer counts as a member of its group.
+        if let Some(child) = child.as_deref_mut() {
+            let _ = child.try_wait();
+        }
+        let mut survivors = if !group {
+            Vec::from_iter(identity.is_running().then_some(identity.pid))
+        } else if identity.group_is_ours() {
+            procinfo::group_members(identity.pid)
+        } else {
+            Vec::new()
+        };
+        for process in tree.iter().filter(|process| process.is_running()) {
+            if !survivors.contains(&process.pid) {
+                survivors.push(process.pid);
+            }
+        }
+        // The spawned child's cgroup, with --cgroup, is only empty once all of it is gone.
+        #[cfg(target_os = "linux")]
+        if let Some(cgroup) = cgroup::active().filter(|_| child.is_some()) {
+            if cgroup.populated() {
+                for pid in cgroup.members() {
+                    if !survivors.contains(&pid) {
+                        survivors.push(pid);
+                    }
+                }
+            }
+        }
+        if survivors.is_empty() {
+            info!(
+                "Process{} {} and its descendants are gone{}.",
+                if group { " group" } else { "" },
+                identity.pid,
+                match attempts {
+                    0 => String::new(),
+                    _ => format!(" after {} more kill attempt(s)", attempts),
+                }
+            );
+            return;
+        }
+        let waited = started.elapsed();
+        let pids: Vec<String> = survivors.iter().map(u32::to_string).collect();
+        if waited >= stop::kill_timeout() {
+            let message = format!(
+                "Processes of {} {} still running {:.2?} after it was killed (--kill-timeout): PIDs {}",
+                if child.is_some() { "child process" } else { "attached process" },
+                identity.pid,
+                waited,
+                pids.join(", ")
             );
-            std::process::exit(1);
+            error!("{}", message);
+            audit::record(
+                "kill_leftovers",
+                serde_json::json!({ "pid": identity.pid, "leftovers": survivors }),
+            );
+            events::emit(events::Event::KillFailure, message).await;
+            return;
+        }
+        if attempts == 0 {
+            info!(
+                "Still running after SIGKILL: PIDs {}; killing them again.",
+                pids.join(", ")
+            );
+        }
+        attempts += 1;
+        if group {
+            let _ = procinfo::signal_process_group(&identity, libc::SIGKILL);
+        } else {
+            let _ = procinfo::signal_process(&identity, libc::SIGKILL);
+        }
+        for process in tree {
+            let _ = procinfo::signal_process(process, libc::SIGKILL);
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(cgroup) = cgroup::active().filter(|_| child.is_some()) {
+            let _ = cgroup.kill();
+        }
+        sleep(KILL_POLL).await;
+    }
+}
+
+/// Spawns the child, or its replacement, so that with `--subreaper` it is told apart from the
+/// processes the watchdog adopts.
+fn spawn_command(command: &mut Command) -> std::io::Result<Child> {
+    #[cfg(target_os = "linux")]
+    return subreaper::spawn(command);
+    // The child leads a console process group of its own, so that Ctrl+Break (--kill-grace)
+    // reaches it and its descendants but not the watchdog.
+    #[cfg(windows)]
+    return job::spawn(
+        command,
+        windows_sys::Win32::System::Threading::CREATE_NEW_PROCESS_GROUP,
+    );
+    #[cfg(not(any(target_os = "linux", windows)))]
+    command.spawn()
+}
+
+/// Terminates the descendants of the child with `pid` that were found in `tree`, for a child
+/// that runs without a Job Object.
+#[cfg(windows)]
+async fn terminate_descendants(pid: u32, tree: &[ProcessIdentity]) {
+    if tree.is_empty() {
+        return;
+    }
+    let (terminated, errors) = procinfo::terminate_tree(tree);
+    info!(
+        "Terminated {} descendant process(es) of child process {}.",
+        terminated, pid
+    );
+    for (descendant, e) in errors {
+        let error = Error::Kill {
+            what: format!(
+                "terminate process {}, a descendant of child process {}",
+                descendant, pid
+            ),
+            reason: e.to_string(),
+        };
+        error.report();
+        events::emit(events::Event::KillFailure, error.to_string()).await;
+    }
+}
+
+/// Spawns the child, exiting the watchdog if that fails.
+async fn spawn_child(command: &mut Command, child_binary_path: &std::path::Path) -> Supervised {
+    let mut child = match spawn_command(command) {
+        Ok(child) => child,
+        Err(e) => {
+            exit::fail(Error::Spawn {
+                path: child_binary_path.display().to_string(),
+                source: e,
+            });
         }
     };
     // Get the PID *before* potentially moving the child into the monitor task
     let child_pid = match child.id() {
         Some(pid) => pid,
         None => {
-            eprintln!("Error: Could not get PID of spawned child process.");
+            Error::NoPid.report();
             // Ensure kill is attempted if spawn succeeded but PID failed
             if let Err(kill_err) = child.start_kill() {
-                eprintln!(
+                error!(
                     "Error attempting to kill child process after failing to get PID: {}",
                     kill_err
                 );
             }
             // Don't await here indefinitely, just try to wait briefly
             let _ = tokio::time::timeout(Duration::from_secs(1), child.wait()).await;
-            std::process::exit(1);
+            exit::exit(ExitReason::Startup);
         }
     };
-    println!("Child process launched (PID: {}).", child_pid);
+    info!("Child process launched (PID: {}).", child_pid);
+    // Record the child's start time now, while the PID is guaranteed to be ours.
+    let child_identity = ProcessIdentity::capture(child_pid);
+    Supervised::Process(child, child_identity, None)
+}
 
-    // Channel to notify the monitor about received signals
-    let (signal_tx, signal_rx) = watch::channel(Instant::now());
+/// What it takes to spawn the child again, for a restart.
+struct Respawn {
+    command: Command,
+    path: PathBuf,
+    /// Told the restarted child's generation, so the previous one's heartbeats are stale.
+    generation: heartbeat::Generation,
+    /// With `--restart-strategy start-first`, how long a replacement gets to heartbeat before
+    /// the child it replaces is terminated anyway.
+    start_first: Option<Duration>,
+    /// The limits the restarted child is throttled to, and for how long it is stopped
+    /// (`--cpu-limit`, `--io-limit`).
+    #[cfg(target_os = "linux")]
+    throttle: Option<(throttle::Limits, Duration)>,
+}
 
-    // Create a channel for propagating termination signals
-    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+/// What the watchdog supervises: the child it spawned, a running container, or a running
+/// process it attached to.
+enum Supervised {
+    Process(Child, ProcessIdentity, Option<Box<Respawn>>),
+    #[cfg(unix)]
+    Container(docker::Container, docker::Action),
+    #[cfg(unix)]
+    Attached(attach::Attached),
+}
 
-    // --- Task 0: Set up signal handling ---
-    let child_pid_for_signal = child_pid;
-    tokio::spawn(async move {
-        handle_termination_signals(child_pid_for_signal, shutdown_tx).await;
-    });
+impl Supervised {
+    /// The child's process group, if the watchdog spawned it or attached to its leader.
+    fn identity(&self) -> Option<ProcessIdentity> {
+        match self {
+            Supervised::Process(_, identity, _) => Some(*identity),
+            #[cfg(unix)]
+            Supervised::Container(..) => None,
+            #[cfg(unix)]
+            Supervised::Attached(attached) => attached.group.then_some(attached.identity),
+        }
+    }
 
-    // --- Task 1: Listen for signals via UDP ---
-    let listener_addr_clone = cli.listen_addr.clone();
-    let signal_listener = tokio::spawn(async move {
-        println!("Starting UDP signal listener on {}", listener_addr_clone);
-        let socket = match UdpSocket::bind(&listener_addr_clone).await {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("Failed to bind UDP socket: {}", e);
-                return; // Exit this task if binding fails
+    /// Whether [`Supervised::restart`] can restart the child.
+    fn restartable(&self) -> bool {
+        match self {
+            Supervised::Process(_, _, respawn) => respawn.is_some(),
+            #[cfg(unix)]
+            Supervised::Container(..) => true,
+            #[cfg(unix)]
+            Supervised::Attached(..) => false,
+        }
+    }
+
+    /// Restarts the child in place: kills a child process's group and spawns it again (or,
+    /// with `--restart-strategy start-first`, the other way round), or restarts the container.
+    /// Call [`Supervised::forward_output`] afterwards.
+    async fn restart(&mut self) -> Result<(), String> {
+        self.thaw().await;
+        match self {
+            Supervised::Process(_, _, None) => {
+                Err("restarting this child process is not supported".to_string())
             }
-        };
-        println!("UDP listener bound successfully.");
-        let mut buf = [0; 10]; // Small buffer suffices
-        loop {
-            match socket.recv_from(&mut buf).await {
-                Ok((_len, _src_addr)) => {
-                    let now = Instant::now();
-                    // Optional: Reduce log noise by commenting this out in production
-                    // println!("UDP Signal received from: {} at: {:?}", src_addr, now);
-                    if signal_tx.send(now).is_err() {
-                        // This happens if the monitor task has already exited
-                        eprintln!("Monitor task receiver dropped, stopping UDP listener.");
-                        break;
+            Supervised::Process(child, identity, Some(respawn)) => {
+                // Only a child that is still running can overlap with its replacement.
+                let start_first = respawn
+                    .start_first
+                    .filter(|_| matches!(child.try_wait(), Ok(None)));
+                if start_first.is_none() {
+                    kill_child_process_tree(child, *identity).await;
+                    // Reap the old child, so its exit is not taken for the new one's.
+                    let _ = tokio::time::timeout(OUTPUT_GRACE, child.wait()).await;
+                }
+                let generation = heartbeat::next_generation(respawn.generation.current());
+                respawn
+                    .command
+                    .env(heartbeat::GENERATION_ENV, generation.to_string());
+                let mut restarted = spawn_command(&mut respawn.command)
+                    .map_err(|e| format!("failed to spawn {}: {}", respawn.path.display(), e))?;
+                let Some(pid) = restarted.id() else {
+                    return Err("the restarted child exited right away".to_string());
+                };
+                if let Some(wait) = start_first {
+                    info!(
+                        "Replacement child process started (PID: {}, generation {}); terminating child process ({}) once it heartbeats.",
+                        pid, generation, identity.pid
+                    );
+                    tokio::select! {
+                        _ = respawn.generation.replacement_heard(generation) => {
+                            info!("Replacement child process ({}) sent its first heartbeat.", pid);
+                        }
+                        status = restarted.wait() => {
+                            let status = status.map_or_else(|e| e.to_string(), |s| s.to_string());
+                            return Err(format!(
+                                "the replacement exited before its first heartbeat with status: {}",
+                                status
+                            ));
+                        }
+                        _ = sleep(wait) => {
+                            error!(
+                                "Replacement child process ({}) sent no heartbeat within {:?}; replacing child process ({}) anyway.",
+                                pid, wait, identity.pid
+                            );
+                        }
                     }
+                    kill_child_process_tree(child, *identity).await;
+                    let _ = tokio::time::timeout(OUTPUT_GRACE, child.wait()).await;
                 }
-                Err(e) => {
-                    // Errors here might indicate network issues or socket closure
-                    eprintln!("Error receiving UDP packet: {}. Stopping listener.", e);
-                    break;
+                respawn.generation.set(generation);
+                info!(
+                    "Child process restarted (PID: {}, generation {}).",
+                    pid, generation
+                );
+                audit::record(
+                    "restart",
+                    serde_json::json!({
+                        "pid": pid,
+                        "generation": generation,
+                        "start_first": start_first.is_some(),
+                    }),
+                );
+                *child = restarted;
+                *identity = ProcessIdentity::capture(pid);
+                #[cfg(target_os = "linux")]
+                if let Some((limits, stop)) = respawn.throttle {
+                    tokio::spawn(throttle::run_throttle(*identity, limits, stop));
                 }
+                Ok(())
+            }
+            #[cfg(unix)]
+            Supervised::Container(container, _) => container.restart().await,
+            #[cfg(unix)]
+            Supervised::Attached(..) => {
+                Err("restarting an attached process is not supported".to_string())
             }
         }
-    });
+    }
 
-    // --- Task 2: Monitor for timeout and child exit ---
-    let monitor_task = tokio::spawn(monitor_timeout(
-        child,
-        signal_rx,
-        timeout_duration,
-        child_pid,
-        shutdown_rx,
-    ));
+    /// Spawns tasks forwarding a newly spawned child process's output, whose lines may also be
+    /// heartbeats or failures, or show that it is ready. A container's output is followed by
+    /// [`docker::Container::watch`]; an attached process's is not the watchdog's to read.
+    fn forward_output(
+        &mut self,
+        output_rules: &Arc<output::OutputRules>,
+        forwarders: &mut Vec<tokio::task::JoinHandle<()>>,
+    ) {
+        output_rules.restarted();
+        let (stdout, stderr) = match self {
+            Supervised::Process(child, ..) => (child.stdout.take(), child.stderr.take()),
+            #[cfg(unix)]
+            Supervised::Container(..) | Supervised::Attached(..) => return,
+        };
+        // Output of a child that was restarted meanwhile is no longer waited for.
+        forwarders.retain(|forwarder| !forwarder.is_finished());
 
-    // Wait for the monitor task to complete (it will exit the process internally)
-    // Or handle potential errors from the monitor task itself
-    if let Err(e) = monitor_task.await {
-        eprintln!("Monitor task failed: {:?}", e);
-        // Abort the listener if it's still running
-        signal_listener.abort();
-        return Err(e.into()); // Propagate join error if any
-    }
+        if let Some(stdout) = stdout {
+            let output_rules = output_rules.clone();
+            forwarders.push(tokio::spawn(async move {
+                use tokio::io::{AsyncBufReadExt, BufReader};
+                let mut reader = BufReader::new(stdout).lines();
 
-    // In the normal case, monitor_task calls std::process::exit, so this part might not be reached.
-    println!("Watchdog main function finished cleanly (unexpected).");
-    signal_listener.abort(); // Ensure listener stops if monitor task somehow returned Ok
+                while let Ok(Some(line)) = reader.next_line().await {
+                    info!("[child stdout] {}", line);
+                    output_rules.on_line(&line, true);
+                }
+            }));
+        }
 
-    Ok(())
-}
+        if let Some(stderr) = stderr {
+            let output_rules = output_rules.clone();
+            forwarders.push(tokio::spawn(async move {
+                use tokio::io::{AsyncBufReadExt, BufReader};
+                let mut reader = BufReader::new(stderr).lines();
 
-/// Handles termination signals and initiates child process cleanup
-async fn handle_termination_signals(child_pid: u32, shutdown_tx: tokio::sync::oneshot::Sender<()>) {
-    println!("Setting up signal handlers for graceful shutdown...");
+                while let Ok(Some(line)) = reader.next_line().await {
+                    error!("[child stderr] {}", line);
+                    output_rules.on_line(&line, false);
+                }
+            }));
+        }
+    }
 
-    #[cfg(unix)]
-    {
-        // Set up handlers for common termination signals on Unix
-        let mut sigterm =
-            signal(SignalKind::terminate()).expect("Failed to set up SIGTERM handler");
-        let mut sigint = signal(SignalKind::interrupt()).expect("Failed to set up SIGINT handler");
-        let mut sighup = signal(SignalKind::hangup()).expect("Failed to set up SIGHUP handler");
+    /// PID of the child, or of the container's main process on the host.
+    fn pid(&self) -> u32 {
+        match self {
+            Supervised::Process(_, identity, _) => identity.pid,
+            #[cfg(unix)]
+            Supervised::Container(container, _) => container.pid,
+            #[cfg(unix)]
+            Supervised::Attached(attached) => attached.identity.pid,
+        }
+    }
 
-        tokio::select! {
-            _ = sigterm.recv() => {
-                println!("Received SIGTERM signal. Initiating shutdown...");
+    fn describe(&self) -> String {
+        match self {
+            Supervised::Process(_, identity, _) => format!("child process ({})", identity.pid),
+            #[cfg(unix)]
+            Supervised::Container(container, _) => {
+                format!("container {} ({})", container.name, container.short_id())
             }
-            _ = sigint.recv() => {
-                println!("Received SIGINT signal (Ctrl+C). Initiating shutdown...");
-            }
-            _ = sighup.recv() => {
-                println!("Received SIGHUP signal. Initiating shutdown...");
+            #[cfg(unix)]
+            Supervised::Attached(attached) => {
+                format!("attached process ({})", attached.identity.pid)
             }
         }
     }
 
-    #[cfg(windows)]
-    {
-        // On Windows, we handle Ctrl+C and Ctrl+Break
-        let mut ctrl_c = windows::ctrl_c().expect("Failed to set up Ctrl+C handler");
-        let mut ctrl_break = windows::ctrl_break().expect("Failed to set up Ctrl+Break handler");
+    /// Waits for the child to exit on its own.
+    async fn wait(&mut self) -> Result<child::Exit, String> {
+        match self {
+            Supervised::Process(child, ..) => child
+                .wait()
+                .await
+                .map(|status| child::Exit {
+                    message: format!("Child process exited on its own with status: {}", status),
+                    code: status.code().map(i64::from),
+                    #[cfg(unix)]
+                    signal: std::os::unix::process::ExitStatusExt::signal(&status),
+                    #[cfg(not(unix))]
+                    signal: None,
+                })
+                .map_err(|e| e.to_string()),
+            #[cfg(unix)]
+            Supervised::Container(container, _) => container.wait().await.map(|code| child::Exit {
+                message: format!(
+                    "Container {} exited on its own with exit code {}",
+                    container.name, code
+                ),
+                code: Some(code),
+                signal: None,
+            }),
+            #[cfg(unix)]
+            Supervised::Attached(attached) => Ok(attached.wait().await),
+        }
+    }
 
-        tokio::select! {
-            _ = ctrl_c.recv() => {
-                println!("Received Ctrl+C signal. Initiating shutdown...");
+    /// Sends `signal` to the child's process group, or to the container or attached process.
+    async fn signal(&self, signal: signals::Signal) -> Result<(), String> {
+        match self {
+            #[cfg(unix)]
+            Supervised::Process(_, identity, _) => {
+                procinfo::signal_process_group(identity, signal.number).map_err(|e| e.to_string())
             }
-            _ = ctrl_break.recv() => {
-                println!("Received Ctrl+Break signal. Initiating shutdown...");
+            #[cfg(not(unix))]
+            Supervised::Process(..) => Err(format!("cannot send {} on this platform", signal)),
+            #[cfg(unix)]
+            Supervised::Container(container, _) => container.signal(&signal.to_string()).await,
+            #[cfg(unix)]
+            Supervised::Attached(attached) => {
+                attached.signal(signal.number).map_err(|e| e.to_string())
             }
         }
     }
 
-    // Send shutdown signal to monitor task
-    if shutdown_tx.send(()).is_err() {
-        // If the receiver is dropped, it means the monitor task has already exited.
-        // In that case, we'll try to kill the child process directly.
-        println!("Monitor task already exited. Attempting to kill child process directly.");
+    /// Freezes the child (`--freeze-on-pause`), or thaws it: stops every process in its
+    /// process group or cgroup, the attached process's group or the container, so that it
+    /// does nothing until resumed. Returns what was frozen or thawed.
+    #[cfg(unix)]
+    async fn freeze(&self, freeze: bool) -> Result<String, String> {
+        let signal = if freeze { libc::SIGSTOP } else { libc::SIGCONT };
+        let target = match self {
+            Supervised::Process(_, identity, _) => freeze::process_group(identity, freeze)?,
+            Supervised::Container(container, _) => {
+                if freeze {
+                    container.pause().await?;
+                } else {
+                    container.unpause().await?;
+                }
+                format!("container {}", container.name)
+            }
+            Supervised::Attached(attached) => {
+                attached.signal(signal).map_err(|e| e.to_string())?;
+                attached.target()
+            }
+        };
+        freeze::set_frozen(freeze);
+        Ok(target)
+    }
 
+    /// Thaws the child if a pause froze it or throttling stopped it, so that it can be stopped,
+    /// restarted or left running.
+    async fn thaw(&self) {
+        #[cfg(target_os = "linux")]
+        if let Supervised::Process(_, identity, _) = self {
+            throttle::release(identity);
+        }
         #[cfg(unix)]
-        unsafe {
-            println!("Sending SIGKILL to process group {}.", child_pid as i32);
-            // Safety: We're sending a signal to a valid process group
-            libc::killpg(child_pid as i32, libc::SIGKILL);
+        if freeze::frozen() {
+            match self.freeze(false).await {
+                Ok(target) => info!("Thawed {}.", target),
+                Err(e) => error!("Failed to thaw {}: {}", self.describe(), e),
+            }
         }
+    }
 
-        #[cfg(windows)]
-        {
-            println!("Windows: Cannot directly kill the child process outside the original Child structure.");
-            // On Windows, we don't have a direct way to kill a process by PID in this context.
-            // A more comprehensive solution would require the windows_sys crate to use TerminateProcess.
+    /// What acting on a failed child means, e.g. "Terminating child".
+    fn acting(&self) -> &'static str {
+        match self {
+            Supervised::Process(..) => "Terminating child",
+            #[cfg(unix)]
+            Supervised::Attached(..) => "Terminating attached process",
+            #[cfg(unix)]
+            Supervised::Container(_, docker::Action::Stop) => "Stopping container",
+            #[cfg(unix)]
+            Supervised::Container(_, docker::Action::Kill) => "Killing container",
+            #[cfg(unix)]
+            Supervised::Container(_, docker::Action::Restart) => "Restarting container",
         }
-    } else {
-        println!("Shutdown signal sent to monitor task. Waiting for cleanup to complete...");
-        // Give the monitor a moment to handle the shutdown
-        sleep(Duration::from_millis(200)).await;
     }
 
-    // Exit the process
-    println!("Signal handler exiting the watchdog process.");
-    std::process::exit(130); // 128 + signal number (SIGINT=2)
-}
-
-/// Attempts to kill the process group on Unix, or just the process on Windows.
-/// Takes ownership of the Child to ensure it's handled correctly.
-async fn kill_child_process_tree(mut child: Child, pid: u32) {
-    println!(
-        "Terminating child process{} (PID: {})...",
-        if cfg!(unix) { " group" } else { "" },
-        pid
-    );
+    /// The action as recorded in the audit log.
+    fn audit_action(&self) -> &'static str {
+        match self {
+            Supervised::Process(..) => "kill",
+            #[cfg(unix)]
+            Supervised::Attached(..) => "kill",
+            #[cfg(unix)]
+            Supervised::Container(_, action) => action.as_str(),
+        }
+    }
 
-    #[cfg(unix)]
-    unsafe {
-        // Send SIGKILL to the entire process group.
-        // PGID is the same as PID because we used command.process_group(0).
-        let pgid = pid as i32; // Cast PID to i32 for libc functions
-        println!("Attempting to send SIGKILL to process group {}.", pgid);
-        if libc::killpg(pgid, libc::SIGKILL) == -1 {
-            // EINVAL: pgid <= 0. ESRCH: No process/group found. EPERM: No permission.
-            let err = std::io::Error::last_os_error();
-            eprintln!(
-                "Failed to kill process group {} with killpg: {}. Falling back to killing PID {}.",
-                pgid, err, pid
-            );
-            // Fallback: Attempt to kill the direct child process if killpg fails or if the process is not in the group somehow
-            if let Err(e) = child.start_kill() {
-                // `start_kill` is non-blocking
-                eprintln!(
-                    "Fallback attempt to kill child process {} failed: {}",
-                    pid, e
-                );
-            } else {
-                println!("Fallback kill signal sent to PID {}.", pid);
+    /// Acts on a failed child: terminates it, or restarts the container if so configured.
+    /// Returns whether supervision continues, and what happened (e.g. "child terminated").
+    async fn act(&mut self) -> (bool, &'static str) {
+        self.thaw().await;
+        #[cfg(unix)]
+        if let Supervised::Container(container, docker::Action::Restart) = self {
+            match container.restart().await {
+                Ok(()) => return (true, "container restarted"),
+                Err(e) => error!(
+                    "Failed to restart container {}: {}. Stopping it instead.",
+                    container.name, e
+                ),
             }
-        } else {
-            println!("Sent SIGKILL to process group {}.", pgid);
         }
+        self.terminate().await
     }
 
-    #[cfg(windows)]
-    {
-        // On Windows, child.kill() or start_kill() terminates the direct process.
-        // Terminating grandchildren requires Job Objects, which is more complex.
-        println!("Attempting to kill process {} (Windows).", pid);
-        if let Err(e) = child.start_kill() {
-            eprintln!("Failed to initiate kill for child process {}: {}", pid, e);
-        } else {
-            println!("Kill signal sent to PID {}.", pid);
+    /// Terminates the child: kills its process group (or the attached process), or stops (or
+    /// kills) the container.
+    async fn terminate(&mut self) -> (bool, &'static str) {
+        self.thaw().await;
+        match self {
+            Supervised::Process(child, identity, _) => {
+                kill_child_process_tree(child, *identity).await;
+                (false, "child terminated")
+            }
+            #[cfg(unix)]
+            Supervised::Attached(attached) => {
+                kill_attached(attached).await;
+                (false, "attached process terminated")
+            }
+            #[cfg(unix)]
+            Supervised::Container(container, action) => {
+                // A restart that failed falls back to stopping the container.
+                let (result, verb, outcome) = match action {
+                    _ if stop::immediate() => {
+                        info!("Killing container {}...", container.name);
+                        (container.kill().await, "kill", "container killed")
+                    }
+                    docker::Action::Kill => {
+                        info!("Killing container {}...", container.name);
+                        (container.kill().await, "kill", "container killed")
+                    }
+                    _ => {
+                        info!("Stopping container {}...", container.name);
+                        (container.stop().await, "stop", "container stopped")
+                    }
+                };
+                if let Err(e) = result {
+                    let error = Error::Kill {
+                        what: format!("{} container {}", verb, container.name),
+                        reason: e,
+                    };
+                    error.report();
+                    events::emit(events::Event::KillFailure, error.to_string()).await;
+                }
+                (false, outcome)
+            }
         }
     }
+}
 
-    // Give a brief moment for the signal to take effect.
-    sleep(Duration::from_millis(100)).await;
+/// Policy applied instead of the normal timeout while the child reports itself degraded.
+#[derive(Debug, Clone, Copy)]
+struct DegradedPolicy {
+    timeout: Duration,
+    /// Log missed deadlines instead of killing the child.
+    notify_only: bool,
+}
 
-    // Optionally, explicitly wait for the child to exit after sending kill signal
-    match child.try_wait() {
-        Ok(Some(status)) => println!(
-            "Child process confirmed exit after kill signal with status: {}",
+/// Early warning before the deadline (`--warn-at`).
+#[derive(Debug, Clone)]
+struct DeadlineWarning {
+    /// Share of the timeout, in percent, after which to warn.
+    percent: u8,
+    /// Hook run with the warning (`--warn-exec`).
+    exec: Option<String>,
+}
+
+/// Where supervision stands once a missed deadline has been acted on.
+enum AfterTimeout {
+    /// The child was terminated; the watchdog exits.
+    Ended,
+    /// The child was restarted and gets a fresh timeout.
+    Restarted,
+    /// The child was left running (`--on-timeout signal`, `exec` or `none`).
+    LeftRunning,
+}
+
+/// The timeout when neither `--timeout` nor `--interval` is given.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a trigger waits for the child to be reaped, in case it is exiting anyway.
+const EXIT_GRACE: Duration = Duration::from_millis(100);
+
+/// How often a killed process tree is checked for survivors.
+#[cfg(unix)]
+const KILL_POLL: Duration = Duration::from_millis(50);
+
+/// How often to check whether the system was suspended, when that time counts.
+const SUSPEND_CHECK: Duration = Duration::from_secs(1);
+
+/// How long the output a child wrote just before exiting gets to be forwarded. Grandchildren
+/// still holding its pipes open would otherwise keep the watchdog waiting.
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// Reports a child that exited on its own (as described by `exit` from
+/// [`Supervised::wait`]) and exits the watchdog with the child's status, as `codes` classify
+/// it, once `forwarders` have passed on its output.
+async fn child_exited(
+    exit: child::Exit,
+    codes: &child::ExitCodes,
+    forwarders: &mut Vec<tokio::task::JoinHandle<()>>,
+) -> ! {
+    let drained = async {
+        for forwarder in forwarders.drain(..) {
+            let _ = forwarder.await;
+        }
+    };
+    let _ = tokio::time::timeout(OUTPUT_GRACE, drained).await;
+    let status = codes.watchdog_status(&exit);
+    if status != exit.status() {
+        info!(
+            "{}; status {} is one of --success-codes. Exiting watchdog with status {}.",
+            exit.message,
+            exit.status(),
             status
-        ),
-        Ok(None) => {
-            println!(
-                "Child process still running shortly after kill signal, continuing watchdog exit."
+        );
+    } else {
+        info!("{}. Exiting watchdog with status {}.", exit.message, status);
+    }
+    audit::record(
+        "child_exit",
+        serde_json::json!({
+            "code": exit.code,
+            "signal": exit.signal,
+            "success": codes.succeeded(&exit),
+        }),
+    );
+    events::emit(events::Event::ChildExit, exit.message.clone()).await;
+    exit::exit_with(ExitReason::ChildExited, status);
+}
+
+/// Gives up on a child that keeps being restarted (`--max-restarts`), which has been
+/// terminated or has exited, `failed` as described (e.g. "exited again"): runs the
+/// `--crash-loop-exec` hook and exits the watchdog.
+async fn crash_loop_detected(crash_loop: &crash_loop::CrashLoop, pid: u32, failed: &str) -> ! {
+    let message = format!(
+        "Crash loop: the child {} after {} restarts within {:?}",
+        failed, crash_loop.max, crash_loop.window
+    );
+    error!("{}. Giving up on it (--max-restarts).", message);
+    audit::record(
+        "crash_loop",
+        serde_json::json!({
+            "restarts": crash_loop.max,
+            "window_ms": crash_loop.window.as_millis() as u64,
+        }),
+    );
+    if let Some(command) = &crash_loop.exec {
+        let env = [
+            ("PING_GUARD_MESSAGE", message.clone()),
+            ("PING_GUARD_RESTARTS", crash_loop.max.to_string()),
+            (
+                "PING_GUARD_WINDOW_MS",
+                crash_loop.window.as_millis().to_string(),
+            ),
+            ("PING_GUARD_CHILD_PID", pid.to_string()),
+        ];
+        events::run_hook("--crash-loop-exec", command, &env).await;
+    }
+    events::emit(
+        events::Event::CrashLoop,
+        format!("{}; gave up on it.", message),
+    )
+    .await;
+    info!("Exiting watchdog due to a crash loop.");
+    exit::exit(ExitReason::CrashLoop);
+}
+
+/// Puts off the restart of a child that exited as `exit` says and is restarted under `policy`
+/// (`--restart`), by the backoff, and returns when it is due. Gives up on a child that is
+/// crash-looping instead (`--max-restarts`).
+async fn restart_due(
+    exit: &child::Exit,
+    policy: child::RestartPolicy,
+    backoff: &backoff::Backoff,
+    crash_loop: &mut Option<crash_loop::CrashLoop>,
+    pid: u32,
+) -> Instant {
+    if let Some(crash_loop) = crash_loop {
+        if !crash_loop.allow() {
+            info!("{}.", exit.message);
+            audit::record(
+                "child_exit",
+                serde_json::json!({ "code": exit.code, "signal": exit.signal }),
             );
-            // It might take longer, but the watchdog is exiting anyway.
+            crash_loop_detected(crash_loop, pid, "exited again").await;
         }
-        Err(e) => eprintln!("Error checking child process status after kill: {}", e),
     }
+    let (delay, due) = backoff.schedule();
+    let restarting = if delay.is_zero() {
+        "Restarting it".to_string()
+    } else {
+        format!("Restarting it in {:.2?}", delay)
+    };
+    info!(
+        "{}. {} (--restart {}).",
+        exit.message,
+        restarting,
+        policy.as_str()
+    );
+    audit::record(
+        "child_exit",
+        serde_json::json!({
+            "code": exit.code,
+            "signal": exit.signal,
+            "restart": true,
+            "delay_ms": delay.as_millis() as u64,
+        }),
+    );
+    tokio::spawn(events::emit(
+        events::Event::ChildExit,
+        format!("{}; {}.", exit.message, restarting.to_lowercase()),
+    ));
+    due
+}
+
+/// Exits once every heartbeat source has stopped, terminating the child first unless only
+/// observing it.
+async fn sources_lost(supervised: &mut Supervised, observe_only: bool) -> ! {
+    // The sender (signal listener) was dropped. This is unexpected.
+    if observe_only {
+        error!("Signal sender dropped unexpectedly. Exiting watchdog; child left running.");
+    } else {
+        error!("Signal sender dropped unexpectedly. Terminating child and exiting watchdog.");
+        // Attempt to kill the child process tree just in case.
+        supervised.terminate().await;
+    }
+    exit::exit(ExitReason::SourcesLost);
 }
 
-/// Monitors for signal timeout or child process exit. Exits the watchdog process.
-async fn monitor_timeout(
-    mut child: Child, // Takes ownership
-    mut signal_rx: watch::Receiver<Instant>,
+/// Everything [`monitor_timeout`] supervises the child with.
+struct Monitor {
+    /// The child process, container or attached process.
+    supervised: Supervised,
+    /// When the last heartbeat was counted.
+    signal_rx: watch::Receiver<Instant>,
+    /// The state the child last reported.
+    state_rx: watch::Receiver<ChildState>,
+    /// Requests to act on the child now, e.g. a failed probe or `--dead-regex`.
+    trigger_rx: mpsc::Receiver<String>,
+    /// Control commands, with who sent them.
+    command_rx: mpsc::Receiver<(command::Command, String)>,
+    /// Signals to forward to the child.
+    forward_rx: mpsc::Receiver<signals::Signal>,
+    /// Why the UDP listener could not be set up.
+    listen_failed_rx: mpsc::Receiver<Error>,
+    control: Arc<command::Control>,
     timeout_duration: Duration,
-    child_pid: u32,
-    mut shutdown_rx: tokio::sync::oneshot::Receiver<()>,
-) -> Result<(), String> {
-    // Return type might not be reached due to std::process::exit
-    println!(
-        "Monitoring for signal timeout ({:.2?}) and child process ({}) exit...",
-        timeout_duration, child_pid
-    );
+    interval: Option<Duration>,
+    startup_timeout: Option<Duration>,
+    arm_on_first_ping: bool,
+    max_runtime: Option<Duration>,
+    degraded: DegradedPolicy,
+    adaptive: Option<Arc<adaptive::Adaptive>>,
+    warning: Option<DeadlineWarning>,
+    escalation: Vec<escalation::Step>,
+    suspend_time: suspend::SuspendTime,
+    on_timeout: timeout_action::TimeoutAction,
+    on_timeout_exec: Option<String>,
+    diagnose: Option<diagnose::Diagnose>,
+    incident_report: Option<incident::IncidentReport>,
+    observe_only: bool,
+    no_kill_on_exit: bool,
+    freeze_on_pause: bool,
+    restart_policy: child::RestartPolicy,
+    exit_codes: child::ExitCodes,
+    backoff: Arc<backoff::Backoff>,
+    crash_loop: Option<crash_loop::CrashLoop>,
+    /// Fires when the watchdog itself is asked to stop.
+    shutdown_rx: tokio::sync::oneshot::Receiver<()>,
+    low_power: bool,
+    liveness: Option<Arc<liveness::Liveness>>,
+    quorum: Option<Arc<quorum::Quorum>>,
+    components: Option<Arc<components::Components>>,
+    output_rules: Arc<output::OutputRules>,
+    stats: Arc<Stats>,
+}
 
-    // Get stdout and stderr handles from the child process
-    let stdout = child.stdout.take();
-    let stderr = child.stderr.take();
+/// Monitors for signal timeout or child process exit. Exits the watchdog process.
+#[cfg_attr(not(unix), allow(unused_variables))]
+async fn monitor_timeout(monitor: Monitor) -> Result<(), String> {
+    let Monitor {
+        mut supervised,
+        mut signal_rx,
+        mut state_rx,
+        mut trigger_rx,
+        mut command_rx,
+        mut forward_rx,
+        mut listen_failed_rx,
+        control,
+        timeout_duration,
+        interval,
+        startup_timeout,
+        arm_on_first_ping,
+        max_runtime,
+        degraded,
+        adaptive,
+        warning,
+        escalation,
+        suspend_time,
+        on_timeout,
+        on_timeout_exec,
+        diagnose,
+        incident_report,
+        observe_only,
+        no_kill_on_exit,
+        freeze_on_pause,
+        restart_policy,
+        exit_codes,
+        backoff,
+        mut crash_loop,
+        mut shutdown_rx,
+        low_power,
+        liveness,
+        quorum,
+        components,
+        output_rules,
+        stats,
+    } = monitor;
 
-    // Spawn a task to forward stdout if available
-    if let Some(mut stdout) = stdout {
-        tokio::spawn(async move {
-            use tokio::io::{AsyncBufReadExt, BufReader};
-            let mut reader = BufReader::new(stdout).lines();
+    // Return type might not be reached due to std::process::exit
+    info!(
+        "Monitoring for signal timeout ({:.2?}) and {} exit...",
+        timeout_duration,
+        supervised.describe()
+    );
 
-            while let Ok(Some(line)) = reader.next_line().await {
-                println!("[child stdout] {}", line);
-            }
-        });
+    let mut forwarders = Vec::new();
+    supervised.forward_output(&output_rules, &mut forwarders);
+    // A container's output is followed through the Docker API.
+    #[cfg(unix)]
+    if let Supervised::Container(container, _) = &mut supervised {
+        container.watch(output_rules.clone());
     }
+    // With --ready-regex, the line of the child's output that showed it is ready.
+    let mut ready_rx = output_rules
+        .ready
+        .as_ref()
+        .map_or_else(|| watch::channel(None).1, |(ready, _)| ready.subscribe());
 
-    // Spawn a task to forward stderr if available
-    if let Some(mut stderr) = stderr {
-        tokio::spawn(async move {
-            use tokio::io::{AsyncBufReadExt, BufReader};
-            let mut reader = BufReader::new(stderr).lines();
-
-            while let Ok(Some(line)) = reader.next_line().await {
-                eprintln!("[child stderr] {}", line);
-            }
-        });
-    }
+    // The limit and action depend on the state the child last reported. The normal limit may
+    // have been changed by a TIMEOUT command and, with --adaptive-timeout, is learned from the
+    // heartbeats.
+    let policy_for = |state: ChildState, starting: bool| match (state, startup_timeout) {
+        (ChildState::Normal, Some(startup)) if starting => (startup, false),
+        (ChildState::Normal, _) => {
+            let timeout = control.timeout(timeout_duration);
+            (
+                adaptive
+                    .as_ref()
+                    .map_or(timeout, |adaptive| adaptive.timeout(timeout)),
+                false,
+            )
+        }
+        (ChildState::Degraded, _) => (degraded.timeout, degraded.notify_only),
+    };
+    // With --interval, the interval at which misses are counted while `limit` is the one the
+    // interval and --max-misses made up.
+    let miss_interval = |limit: Duration| interval.filter(|_| limit == timeout_duration);
+    // What was missed, e.g. "for ~5.01s (limit: 5.00s)"; with a liveness policy the monitor's
+    // heartbeat time is a combination, so name the sources (or, with required components, the
+    // components) that are overdue instead. With a quorum, also say how many senders are left.
+    let describe_miss = |elapsed: Duration, limit: Duration| {
+        let missed = match (&liveness, &components) {
+            (Some(liveness), _) => liveness.describe_overdue(limit),
+            (None, Some(components)) => components.describe_overdue(limit),
+            (None, None) => match miss_interval(limit) {
+                Some(interval) => format!(
+                    "for ~{:.2?} ({} missed heartbeats at {:.2?} intervals)",
+                    elapsed,
+                    limit.as_nanos() / interval.as_nanos(),
+                    interval
+                ),
+                None => format!("for ~{:.2?} (limit: {:.2?})", elapsed, limit),
+            },
+        };
+        match &quorum {
+            Some(quorum) => format!(
+                "{} from {} senders, with only {} heard from within the limit",
+                missed,
+                quorum.required(),
+                quorum.heard_within(limit)
+            ),
+            None => missed,
+        }
+    };
+    // Last heartbeat for which a notify-only timeout was already reported.
+    let mut reported_for: Option<Instant> = None;
+    // When a restarted container got a fresh timeout; earlier heartbeats no longer count.
+    let mut restarted_at: Option<Instant> = None;
+    let mut state_open = true;
+    let mut awaiting_first_ping = false;
+    // When --max-runtime runs out, counted from the start of monitoring.
+    let mut runtime_deadline = max_runtime.map(|max_runtime| Instant::now() + max_runtime);
+    // Last heartbeat for which a --warn-at warning was already given.
+    let mut warned_for: Option<Instant> = None;
+    // Heartbeat time the reported misses count from, and how many have been reported.
+    let mut misses: (Instant, u32) = (Instant::now(), 0);
+    // Heartbeat time the --escalate steps count from, and how many have been taken.
+    let mut escalated: (Instant, usize) = (Instant::now(), 0);
+    // Notices suspend and resume; with --suspend-time count, the heartbeat time the suspended
+    // time counts from and how much of it counts.
+    let mut suspend_clock = suspend::SuspendClock::new();
+    let mut suspended: (Instant, Duration) = (Instant::now(), Duration::ZERO);
+    // With --restart, a restart put off by --restart-backoff: when it is due and how the child
+    // exited, in case it cannot be restarted.
+    let mut respawn_at: Option<(Instant, child::Exit)> = None;
+    // Starts the --on-timeout-exec command for a missed deadline, described by `message`, that
+    // `action` was taken on; await it before exiting so the command gets to run.
+    let run_timeout_exec = |message: &str,
+                            elapsed: Duration,
+                            limit: Duration,
+                            last_heartbeat: Option<Duration>,
+                            action: &str,
+                            pid: u32| {
+        on_timeout_exec.clone().map(|command| {
+            let env = [
+                ("PING_GUARD_MESSAGE", message.to_string()),
+                ("PING_GUARD_ELAPSED_MS", elapsed.as_millis().to_string()),
+                ("PING_GUARD_TIMEOUT_MS", limit.as_millis().to_string()),
+                (
+                    "PING_GUARD_LAST_HEARTBEAT_MS",
+                    last_heartbeat.map_or(String::new(), |age| age.as_millis().to_string()),
+                ),
+                ("PING_GUARD_ACTION", action.to_string()),
+                ("PING_GUARD_CHILD_PID", pid.to_string()),
+            ];
+            tokio::spawn(async move {
+                events::run_hook("--on-timeout-exec", &command, &env).await;
+            })
+        })
+    };
+    // The backoff starts over once a restarted child has been up and heartbeating for
+    // --min-uptime.
+    let settle_backoff = || {
+        if stats.starting() {
+            return;
+        }
+        if let Some(attempts) = backoff.settle() {
+            info!(
+                "The restarted child has stayed up for --min-uptime; the restart backoff starts over after {} restart{} in a row.",
+                attempts,
+                if attempts == 1 { "" } else { "s" }
+            );
+        }
+    };
 
     loop {
+        // In low-power mode this is the only place heartbeats are read and logs are written,
+        // and so where the heartbeat sources are found to have stopped.
+        #[cfg(unix)]
+        if low_power {
+            listener::drain_deferred();
+            if signal_rx.has_changed().is_err() {
+                sources_lost(&mut supervised, observe_only).await;
+            }
+        }
+        log::flush();
         // Calculate time until next potential timeout *relative to the last known signal*
-        let last_signal_time = *signal_rx.borrow();
-        let elapsed_since_last_signal = Instant::now().duration_since(last_signal_time);
+        let last_signal_time =
+            restarted_at.map_or(*signal_rx.borrow(), |at| at.max(*signal_rx.borrow()));
+        let slept = suspend_clock.observe();
+        if !slept.is_zero() && suspend_time == suspend::SuspendTime::Ignore {
+            // The child was suspended too and may not have heartbeated yet; start over.
+            info!(
+                "The system was suspended for {:.2?}; the timeout starts over.",
+                slept
+            );
+            audit::record(
+                "suspend",
+                serde_json::json!({ "suspended_ms": slept.as_millis() as u64, "counted": false }),
+            );
+            restarted_at = Some(Instant::now());
+            if let Some(adaptive) = &adaptive {
+                adaptive.restart();
+            }
+            continue;
+        }
+        if !slept.is_zero() {
+            // A heartbeat since the last check may have come after the wake-up, so the
+            // suspension only counts if there was none.
+            let counted = suspended.0 == last_signal_time;
+            if counted {
+                suspended.1 += slept;
+            }
+            info!(
+                "The system was suspended for {:.2?}; {}.",
+                slept,
+                if counted {
+                    "that counts against the timeout"
+                } else {
+                    "a heartbeat has arrived since"
+                }
+            );
+            audit::record(
+                "suspend",
+                serde_json::json!({ "suspended_ms": slept.as_millis() as u64, "counted": counted }),
+            );
+        }
+        if suspended.0 != last_signal_time {
+            suspended = (last_signal_time, Duration::ZERO);
+        }
+        let (limit, notify_only) = policy_for(*state_rx.borrow(), stats.starting());
+        let elapsed_since_last_signal =
+            Instant::now().duration_since(last_signal_time) + suspended.1;
         // If timeout already passed, sleep for a very short duration just to yield
-        let time_to_next_check = timeout_duration.saturating_sub(elapsed_since_last_signal);
+        let time_to_next_check = limit
+            .saturating_sub(elapsed_since_last_signal)
+            .max(control.extension_left());
+        // A notify-only timeout is reported once per missed deadline, not in a tight loop, and
+        // nothing times out while paused by a command or, with --arm-on-first-ping, before the
+        // child's first heartbeat or, with --ready-regex, before it is ready.
+        let reported =
+            (notify_only || on_timeout.leaves_running()) && reported_for == Some(last_signal_time);
+        let unarmed = arm_on_first_ping && stats.starting();
+        if awaiting_first_ping && !unarmed {
+            info!("First heartbeat received; the timeout is now armed.");
+        }
+        awaiting_first_ping = unarmed;
+        let unready = output_rules.ready.is_some() && ready_rx.borrow().is_none();
+        let armed = !(control.paused() || reported || unarmed || unready || respawn_at.is_some());
+        settle_backoff();
+        let respawn_due = respawn_at.as_ref().map(|(due, _)| *due);
+        // Each interval without a heartbeat short of the deadline is reported as a miss. In
+        // low-power mode heartbeats are only read at the deadline, so misses are not reported.
+        if misses.0 != last_signal_time {
+            misses = (last_signal_time, 0);
+        }
+        let miss_due = miss_interval(limit)
+            .filter(|_| !low_power && armed && control.extension_left().is_zero())
+            .filter(|&interval| interval * (misses.1 + 1) < limit);
+        let time_to_next_miss = miss_due.map_or(Duration::ZERO, |interval| {
+            (interval * (misses.1 + 1)).saturating_sub(elapsed_since_last_signal)
+        });
+        // A deadline that is getting close is warned about once (--warn-at).
+        let warning_due = warning
+            .as_ref()
+            .filter(|_| armed && warned_for != Some(last_signal_time))
+            .filter(|_| control.extension_left().is_zero());
+        let time_to_warning = warning_due.map_or(Duration::ZERO, |warning| {
+            limit
+                .mul_f64(f64::from(warning.percent) / 100.0)
+                .saturating_sub(elapsed_since_last_signal)
+        });
+        // Steps short of the current limit are taken one by one (--escalate).
+        if escalated.0 != last_signal_time {
+            escalated = (last_signal_time, 0);
+        }
+        let step_due = escalation
+            .get(escalated.1)
+            .filter(|step| armed && step.after < limit)
+            .filter(|_| control.extension_left().is_zero());
+        let time_to_step = step_due.map_or(Duration::ZERO, |step| {
+            step.after.saturating_sub(elapsed_since_last_signal)
+        });
 
         tokio::select! {
             // Biased select ensures we check child exit/signal first if ready
@@ -360,71 +3861,727 @@ async fn monitor_timeout(
 
             // NEW BRANCH: Check for shutdown signal from signal handlers
             _ = &mut shutdown_rx => {
-                println!("Received shutdown signal. Terminating child process...");
-                kill_child_process_tree(child, child_pid).await;
-                println!("Exiting watchdog due to shutdown signal.");
-                std::process::exit(0);
+                audit::record(
+                    "shutdown",
+                    serde_json::json!({ "detached": observe_only || no_kill_on_exit }),
+                );
+                let outcome = if observe_only {
+                    info!("Received shutdown signal. Observe-only: leaving child running.");
+                    "child left running"
+                } else if no_kill_on_exit {
+                    supervised.thaw().await;
+                    info!(
+                        "Received shutdown signal. Detaching from {}, which is left running (--no-kill-on-exit).",
+                        supervised.describe()
+                    );
+                    "child left running"
+                } else {
+                    info!("Received shutdown signal. Terminating child process...");
+                    supervised.terminate().await;
+                    "child terminated"
+                };
+                events::emit(
+                    events::Event::Shutdown,
+                    format!("Watchdog shut down by signal; {}.", outcome),
+                )
+                .await;
+                info!("Exiting watchdog due to shutdown signal.");
+                exit::exit(ExitReason::Shutdown);
             }
 
             // Branch 1: Wait for the child process to exit on its own
-            // Note: child.wait() consumes the `child` variable when polled the first time.
-            wait_result = child.wait() => {
+            // Skipped while a restart is put off: the exited child would be reported again.
+            wait_result = supervised.wait(), if respawn_at.is_none() => {
+                 // With --subreaper, what the child left behind goes with it.
+                 #[cfg(target_os = "linux")]
+                 if wait_result.is_ok() {
+                     kill_adopted().await;
+                 }
                  match wait_result {
-                    Ok(status) => {
-                        println!("Child process exited on its own with status: {}. Exiting watchdog.", status);
-                        std::process::exit(0); // Exit normally
+                    // With --restart, a child that exited is started again, after the backoff,
+                    // and gets a fresh timeout.
+                    Ok(exit) if restart_policy.restarts(&exit, &exit_codes) => {
+                        // It may have stayed up long enough since the last check.
+                        settle_backoff();
+                        let due = restart_due(&exit, restart_policy, &backoff, &mut crash_loop, supervised.pid()).await;
+                        respawn_at = Some((due, exit));
+                    }
+                    Ok(exit) => {
+                        if restart_policy != child::RestartPolicy::Never && exit_codes.no_restart(&exit) {
+                            info!(
+                                "Not restarting the child: its exit status {} is one of --no-restart-codes.",
+                                exit.status()
+                            );
+                        }
+                        child_exited(exit, &exit_codes, &mut forwarders).await
                     }
                     Err(e) => {
-                        eprintln!("Error waiting for child process exit: {}. Exiting watchdog.", e);
                         // Child might be unrecoverable, exit watchdog with error code
-                        std::process::exit(2); // Exit with different code for error
+                        exit::fail(Error::Wait { what: supervised.describe(), reason: e });
                     }
                  }
-                 // If wait() completed, the child variable is consumed, so we must exit.
-                 // The std::process::exit calls above handle this.
+            }
+
+            // Branch 1b: The UDP listener could not be set up (e.g. its port stayed in use).
+            // Checked before the signal sender, which the failed listener drops right after.
+            Some(error) = listen_failed_rx.recv() => {
+                error.report();
+                if observe_only {
+                    error!("No UDP listener. Exiting watchdog; child left running.");
+                } else {
+                    error!("No UDP listener. Terminating child and exiting watchdog.");
+                    supervised.terminate().await;
+                }
+                exit::exit(ExitReason::ListenFailed);
+            }
+
+            // Branch 1c: The child ran out of --max-runtime; heartbeats make no difference.
+            _ = tokio::time::sleep_until(runtime_deadline.unwrap_or_else(Instant::now)), if runtime_deadline.is_some() => {
+                let message = format!(
+                    "{} ran for longer than --max-runtime ({:.2?})",
+                    supervised.describe(),
+                    max_runtime.unwrap_or_default()
+                );
+                audit::record(
+                    "max_runtime",
+                    serde_json::json!({
+                        "max_runtime_ms": max_runtime.unwrap_or_default().as_millis() as u64,
+                    }),
+                );
+                if observe_only {
+                    // Reported once; the child runs on.
+                    error!("{}. Observe-only: leaving it running.", message);
+                    runtime_deadline = None;
+                    tokio::spawn(events::emit(
+                        events::Event::MaxRuntime,
+                        format!("{}; child left running.", message),
+                    ));
+                    continue;
+                }
+                error!("{}. Terminating it.", message);
+                // A restart would not end the run, so the child is always terminated here.
+                let (_, outcome) = supervised.terminate().await;
+                events::emit(events::Event::MaxRuntime, format!("{}; {}.", message, outcome)).await;
+                info!("Exiting watchdog due to the maximum runtime.");
+                exit::exit(ExitReason::MaxRuntime);
+            }
+
+            // Branch 1d: A restart put off by --restart-backoff is due
+            _ = tokio::time::sleep_until(respawn_due.unwrap_or_else(Instant::now)), if respawn_due.is_some() => {
+                let Some((_, exit)) = respawn_at.take() else {
+                    continue;
+                };
+                backoff.restarted();
+                if let Err(e) = supervised.restart().await {
+                    error!("Failed to restart {}: {}", supervised.describe(), e);
+                    child_exited(exit, &exit_codes, &mut forwarders).await;
+                }
+                supervised.forward_output(&output_rules, &mut forwarders);
+                stats.record_restart();
+                restarted_at = Some(Instant::now());
+                if let Some(adaptive) = &adaptive {
+                    adaptive.restart();
+                }
             }
 
             // Branch 2: Wait for a new signal notification
-            changed_result = signal_rx.changed() => {
+            // Skipped in low-power mode: heartbeats then only matter at the deadline.
+            changed_result = signal_rx.changed(), if !low_power => {
                 if changed_result.is_err() {
-                    // The sender (signal listener) was dropped. This is unexpected.
-                    eprintln!("Signal sender dropped unexpectedly. Terminating child and exiting watchdog.");
-                    // Attempt to kill the child process tree just in case.
-                    // Since wait() hasn't completed, `child` should still be available here.
-                    kill_child_process_tree(child, child_pid).await; // kill_child_process_tree consumes child
-                    std::process::exit(3); // Exit with code indicating listener failure
+                    sources_lost(&mut supervised, observe_only).await;
                 }
                 // New signal received, print status and loop continues.
-                 let _latest_signal_time = *signal_rx.borrow(); // Get the updated time
+                 let latest_signal_time = *signal_rx.borrow(); // Get the updated time
+                 if let Some((timeout, p99)) = adaptive.as_ref().and_then(|adaptive| adaptive.beat(latest_signal_time)) {
+                     info!(
+                         "Adaptive timeout is now {:.2?} (p99 heartbeat interval: {:.2?}).",
+                         timeout, p99
+                     );
+                     audit::record(
+                         "adaptive_timeout",
+                         serde_json::json!({
+                             "timeout_ms": timeout.as_millis() as u64,
+                             "p99_ms": p99.as_millis() as u64,
+                         }),
+                     );
+                 }
                  // Optional: Reduce log noise
-                 // println!("Monitor notified of new signal received at {:?}.", latest_signal_time);
-                 // No action needed here, the loop will recalculate sleep duration
+                 // info!("Monitor notified of new signal received at {:?}.", latest_signal_time);
+                 // Flag heartbeats that only just made it; otherwise the loop recalculates the sleep.
+                 let gap = latest_signal_time.duration_since(last_signal_time);
+                 if gap.as_secs_f64() >= limit.as_secs_f64() * analyze::NEAR_TIMEOUT_FRACTION {
+                     let message = format!(
+                         "Late heartbeat: {:.2?} since the previous one (limit: {:.2?}).",
+                         gap, limit
+                     );
+                     info!("{}", message);
+                     tokio::spawn(events::emit(events::Event::LateHeartbeat, message));
+                 }
+            }
+
+            // Branch 2b: The child switched between normal and degraded operation
+            changed_result = state_rx.changed(), if state_open => {
+                if changed_result.is_err() {
+                    state_open = false;
+                    continue;
+                }
+                let state = *state_rx.borrow();
+                let (limit, notify_only) = policy_for(state, stats.starting());
+                audit::record("state", serde_json::json!({ "state": state.as_str() }));
+                info!(
+                    "Child reported state '{}': timeout is now {:.2?}{}.",
+                    state.as_str(),
+                    limit,
+                    if notify_only { " (notify-only)" } else { "" }
+                );
+            }
+
+            // Branch 2c: A source reported the child unhealthy (e.g. consecutive probe failures)
+            Some(reason) = trigger_rx.recv() => {
+                // The child is down until its restart is due; a failed check says nothing new.
+                if respawn_at.is_some() {
+                    continue;
+                }
+                // A closed pipe usually means the child is exiting; let that be reported as such.
+                if let Ok(Ok(exit)) = tokio::time::timeout(EXIT_GRACE, supervised.wait()).await {
+                    #[cfg(target_os = "linux")]
+                    kill_adopted().await;
+                    if restart_policy.restarts(&exit, &exit_codes) {
+                        settle_backoff();
+                        let due = restart_due(&exit, restart_policy, &backoff, &mut crash_loop, supervised.pid()).await;
+                        respawn_at = Some((due, exit));
+                        continue;
+                    }
+                    child_exited(exit, &exit_codes, &mut forwarders).await;
+                }
+                if observe_only {
+                    error!("Health check failed: {}. Observe-only: leaving child running.", reason);
+                    audit::record(
+                        "unhealthy",
+                        serde_json::json!({ "reason": reason, "action": "none" }),
+                    );
+                    tokio::spawn(events::emit(
+                        events::Event::ProbeFailure,
+                        format!("Health check failed: {}; child left running.", reason),
+                    ));
+                    continue;
+                }
+                error!("Health check failed: {}. {}.", reason, supervised.acting());
+                audit::record(
+                    "unhealthy",
+                    serde_json::json!({ "reason": reason, "action": supervised.audit_action() }),
+                );
+                let (continues, outcome) = supervised.act().await;
+                let message = format!("Health check failed: {}; {}.", reason, outcome);
+                if continues {
+                    info!("Container restarted after a failed health check; the timeout starts over.");
+                    stats.record_restart();
+                    tokio::spawn(events::emit(events::Event::ProbeFailure, message));
+                    restarted_at = Some(Instant::now());
+                    if let Some(adaptive) = &adaptive {
+                        adaptive.restart();
+                    }
+                    continue;
+                }
+                events::emit(events::Event::ProbeFailure, message).await;
+                info!("Exiting watchdog due to failed health check.");
+                exit::exit(ExitReason::Unhealthy);
+            }
+
+            // Branch 2d: An authenticated sender sent a control command
+            Some((command, from)) = command_rx.recv() => {
+                let message = format!("Command {} from {}", command, from);
+                audit::record(
+                    "command",
+                    serde_json::json!({ "command": command.to_string(), "from": from }),
+                );
+                match command {
+                    command::Command::Extend(by) => {
+                        control.extend(by);
+                        info!("{}: no timeout for the next {:.0?}.", message, by);
+                    }
+                    command::Command::Timeout(timeout) => {
+                        let previous = control.timeout(timeout_duration);
+                        control.set_timeout(timeout);
+                        info!("{}: timeout changed from {:.2?} to {:.2?}.", message, previous, timeout);
+                    }
+                    #[cfg(unix)]
+                    command::Command::Pause if freeze_on_pause => {
+                        control.set_paused(true);
+                        match supervised.freeze(true).await {
+                            Ok(target) => {
+                                audit::record("freeze", serde_json::json!({ "frozen": true, "target": target }));
+                                info!("{}: timeout paused and {} frozen until resumed.", message, target);
+                            }
+                            Err(e) => error!(
+                                "{}: timeout paused, but failed to freeze {}: {}",
+                                message,
+                                supervised.describe(),
+                                e
+                            ),
+                        }
+                    }
+                    command::Command::Pause => {
+                        control.set_paused(true);
+                        info!("{}: timeout paused; the child is left running until resumed.", message);
+                    }
+                    command::Command::Resume => {
+                        control.set_paused(false);
+                        #[cfg(unix)]
+                        if freeze_on_pause && freeze::frozen() {
+                            match supervised.freeze(false).await {
+                                Ok(target) => {
+                                    audit::record("freeze", serde_json::json!({ "frozen": false, "target": target }));
+                                    info!("Thawed {}.", target);
+                                }
+                                Err(e) => error!("Failed to thaw {}: {}", supervised.describe(), e),
+                            }
+                        }
+                        // The child may not have heartbeated while paused; give it a fresh timeout.
+                        restarted_at = Some(Instant::now());
+                        if let Some(adaptive) = &adaptive {
+                            adaptive.restart();
+                        }
+                        info!("{}: timeout resumed and started over.", message);
+                    }
+                    command::Command::Restart => {
+                        info!("{}: restarting {}...", message, supervised.describe());
+                        match supervised.restart().await {
+                            Ok(()) => {
+                                // A restart that was put off is no longer needed.
+                                respawn_at = None;
+                                backoff.restarted();
+                                supervised.forward_output(&output_rules, &mut forwarders);
+                                stats.record_restart();
+                                restarted_at = Some(Instant::now());
+                                if let Some(adaptive) = &adaptive {
+                                    adaptive.restart();
+                                }
+                                info!("Restarted; the timeout starts over.");
+                            }
+                            Err(e) => {
+                                error!("Failed to restart {}: {}", supervised.describe(), e);
+                                tokio::spawn(events::emit(
+                                    events::Event::RemoteCommand,
+                                    format!("{}; restart failed: {}.", message, e),
+                                ));
+                                continue;
+                            }
+                        }
+                    }
+                    command::Command::Kill => {
+                        info!("{}. {}.", message, supervised.acting());
+                        supervised.terminate().await;
+                        events::emit(
+                            events::Event::RemoteCommand,
+                            format!("{}; child terminated.", message),
+                        )
+                        .await;
+                        info!("Exiting watchdog due to KILL command.");
+                        exit::exit(ExitReason::RemoteKill);
+                    }
+                }
+                tokio::spawn(events::emit(events::Event::RemoteCommand, format!("{}.", message)));
+            }
+
+            // Branch 2e: An interval passed without a heartbeat (--interval)
+            _ = sleep(time_to_next_miss), if miss_due.is_some() => {
+                let latest = restarted_at.map_or(*signal_rx.borrow(), |at| at.max(*signal_rx.borrow()));
+                let Some(interval) = miss_due.filter(|_| latest == last_signal_time) else {
+                    continue;
+                };
+                misses.1 += 1;
+                let message = format!(
+                    "Missed heartbeat {} of {}: none for {:.2?} (interval: {:.2?}).",
+                    misses.1,
+                    limit.as_nanos() / interval.as_nanos(),
+                    Instant::now().duration_since(last_signal_time) + suspended.1,
+                    interval
+                );
+                error!("{}", message);
+                tokio::spawn(events::emit(events::Event::MissedHeartbeat, message));
+            }
+
+            // Branch 2f: Most of the timeout passed without a heartbeat (--warn-at)
+            _ = sleep(time_to_warning), if warning_due.is_some() => {
+                let latest = restarted_at.map_or(*signal_rx.borrow(), |at| at.max(*signal_rx.borrow()));
+                let Some(warning) = warning_due.filter(|_| latest == last_signal_time) else {
+                    continue;
+                };
+                warned_for = Some(last_signal_time);
+                let elapsed = Instant::now().duration_since(last_signal_time) + suspended.1;
+                let message = format!(
+                    "Deadline warning: no heartbeat for {:.2?}, {}% of the timeout ({:.2?}); the deadline is in {:.2?}.",
+                    elapsed,
+                    warning.percent,
+                    limit,
+                    limit.saturating_sub(elapsed)
+                );
+                error!("{}", message);
+                audit::record(
+                    "warning",
+                    serde_json::json!({
+                        "elapsed_ms": elapsed.as_millis() as u64,
+                        "limit_ms": limit.as_millis() as u64,
+                    }),
+                );
+                if let Some(command) = warning.exec.clone() {
+                    let env = [
+                        ("PING_GUARD_MESSAGE", message.clone()),
+                        ("PING_GUARD_ELAPSED_MS", elapsed.as_millis().to_string()),
+                        ("PING_GUARD_TIMEOUT_MS", limit.as_millis().to_string()),
+                        ("PING_GUARD_CHILD_PID", supervised.pid().to_string()),
+                    ];
+                    tokio::spawn(async move {
+                        events::run_hook("--warn-exec", &command, &env).await;
+                    });
+                }
+                tokio::spawn(events::emit(events::Event::DeadlineWarning, message));
+            }
+
+            // Branch 2g: The next escalation step is due (--escalate)
+            _ = sleep(time_to_step), if step_due.is_some() => {
+                let latest = restarted_at.map_or(*signal_rx.borrow(), |at| at.max(*signal_rx.borrow()));
+                let Some(step) = step_due.filter(|_| latest == last_signal_time) else {
+                    continue;
+                };
+                escalated.1 += 1;
+                let elapsed = Instant::now().duration_since(last_signal_time) + suspended.1;
+                let mut message = format!(
+                    "Escalation step {} of {}: no heartbeat for {:.2?} (timeout: {:.2?})",
+                    escalated.1,
+                    escalation.len(),
+                    elapsed,
+                    limit
+                );
+                if let escalation::Action::Signal(signal) = step.action {
+                    message = match supervised.signal(signal).await {
+                        Ok(()) => format!("{}; sent {} to {}.", message, signal, supervised.describe()),
+                        Err(e) => format!(
+                            "{}; failed to send {} to {}: {}.",
+                            message,
+                            signal,
+                            supervised.describe(),
+                            e
+                        ),
+                    };
+                } else {
+                    message.push('.');
+                }
+                error!("{}", message);
+                audit::record(
+                    "escalation",
+                    serde_json::json!({
+                        "elapsed_ms": elapsed.as_millis() as u64,
+                        "limit_ms": limit.as_millis() as u64,
+                        "action": step.action.to_string(),
+                    }),
+                );
+                tokio::spawn(events::emit(events::Event::Escalation, message));
+            }
+
+            // Branch 2h: The child's output showed it is ready (--ready-regex)
+            Ok(()) = ready_rx.changed(), if unready => {
+                let Some(line) = ready_rx.borrow_and_update().clone() else {
+                    continue;
+                };
+                let message = format!(
+                    "The {} is ready; its output matched --ready-regex: '{}'.",
+                    supervised.describe(),
+                    line
+                );
+                info!("{} The timeout is now armed.", message);
+                audit::record(
+                    "ready",
+                    serde_json::json!({ "pid": supervised.pid(), "line": line }),
+                );
+                tokio::spawn(events::emit(events::Event::Ready, message));
+                #[cfg(target_os = "linux")]
+                sd_notify::ready("the child's output matched --ready-regex");
+                // The child gets a full timeout from when it became ready.
+                restarted_at = Some(Instant::now());
+            }
+
+            // Branch 2i: The watchdog received a signal it passes on to the child (--forward-signal)
+            Some(signal) = forward_rx.recv() => {
+                match supervised.signal(signal).await {
+                    Ok(()) => info!("Received {}; forwarded it to {}.", signal, supervised.describe()),
+                    Err(e) => error!(
+                        "Received {}; failed to forward it to {}: {}",
+                        signal,
+                        supervised.describe(),
+                        e
+                    ),
+                }
+                audit::record(
+                    "signal_forwarded",
+                    serde_json::json!({ "signal": signal.to_string(), "pid": supervised.pid() }),
+                );
             }
 
              // Branch 3: Check for timeout ONLY if the sleep duration completes
-            _ = sleep(time_to_next_check) => {
+            _ = sleep(time_to_next_check), if armed => {
                 // Re-verify timeout condition *after* sleep completes, using the latest signal time again.
                 // This guards against race conditions where a signal arrived *during* the sleep.
-                let current_elapsed = Instant::now().duration_since(*signal_rx.borrow());
-                if current_elapsed >= timeout_duration {
-                     eprintln!(
-                        "Timeout detected! No signal received for ~{:.2?} (limit: {:.2?}). Terminating child.",
-                        current_elapsed, // Display actual elapsed time
-                        timeout_duration
+                #[cfg(unix)]
+                if low_power {
+                    listener::drain_deferred();
+                    if signal_rx.has_changed().is_err() {
+                        sources_lost(&mut supervised, observe_only).await;
+                    }
+                }
+                // A suspension is dealt with first (--suspend-time).
+                if suspend_clock.changed() {
+                    continue;
+                }
+                let last_signal_time = restarted_at.map_or(*signal_rx.borrow(), |at| at.max(*signal_rx.borrow()));
+                let (limit, notify_only) = policy_for(*state_rx.borrow(), stats.starting());
+                let counted = if suspended.0 == last_signal_time { suspended.1 } else { Duration::ZERO };
+                let current_elapsed = Instant::now().duration_since(last_signal_time) + counted;
+                // How long ago the last heartbeat actually arrived, if there ever was one.
+                let last_heartbeat = (stats.heartbeats_accepted.load(Ordering::Relaxed) > 0)
+                    .then(|| signal_rx.borrow().elapsed());
+                if current_elapsed >= limit && notify_only {
+                    let missed = describe_miss(current_elapsed, limit);
+                    error!(
+                        "Timeout detected while child is degraded! No signal received {}. Notify-only policy: leaving child running.",
+                        missed
+                    );
+                    reported_for = Some(last_signal_time);
+                    tokio::spawn(events::emit(
+                        events::Event::Timeout,
+                        format!("No heartbeat {} while degraded; child left running.", missed),
+                    ));
+                    audit::record(
+                        "timeout",
+                        serde_json::json!({
+                            "elapsed_ms": current_elapsed.as_millis() as u64,
+                            "limit_ms": limit.as_millis() as u64,
+                            "action": "notify",
+                        }),
+                    );
+                    run_timeout_exec(
+                        &format!("No heartbeat {} while degraded", missed),
+                        current_elapsed,
+                        limit,
+                        last_heartbeat,
+                        "notify",
+                        supervised.pid(),
+                    );
+                } else if current_elapsed >= limit {
+                    let missed = describe_miss(current_elapsed, limit);
+                    // A child that keeps missing its deadline is not restarted forever
+                    // (--max-restarts).
+                    let gives_up = on_timeout == TimeoutAction::Restart
+                        && !crash_loop.as_mut().is_none_or(crash_loop::CrashLoop::allow);
+                    // What --on-timeout makes of the missed deadline, e.g. "Terminating child".
+                    let acting = match &on_timeout {
+                        TimeoutAction::Kill => supervised.acting().to_string(),
+                        TimeoutAction::Restart if gives_up => supervised.acting().to_string(),
+                        TimeoutAction::Restart => "Restarting child".to_string(),
+                        TimeoutAction::Signal(signal) => {
+                            format!("Sending {} and leaving child running", signal)
+                        }
+                        TimeoutAction::Exec(_) => {
+                            "Running the --on-timeout command and leaving child running".to_string()
+                        }
+                        TimeoutAction::None => "Leaving child running".to_string(),
+                    };
+                    // A child that was never heard from usually points at the setup (wrong
+                    // port, firewall, client not configured) rather than at the child hanging.
+                    let never_heard = stats.heartbeats_accepted.load(Ordering::Relaxed) == 0;
+                    if never_heard {
+                        error!(
+                            "No heartbeat ever received {}; check the client's configuration, the port and any firewall ({}). {}.",
+                            missed,
+                            stats.describe(),
+                            acting
+                        );
+                    } else if stats.any_problems() {
+                        // Rejected (e.g. replayed) or lost packets may be why the heartbeats stopped.
+                        error!(
+                            "Timeout detected! No signal received {} ({}). {}.",
+                            missed,
+                            stats.describe(),
+                            acting
+                        );
+                    } else {
+                        error!(
+                            "Timeout detected! No signal received {}. {}.",
+                            missed,
+                            acting
+                        );
+                    }
+                    let action = match &on_timeout {
+                        TimeoutAction::Kill => supervised.audit_action().to_string(),
+                        TimeoutAction::Restart if gives_up => supervised.audit_action().to_string(),
+                        action => action.to_string(),
+                    };
+                    audit::record(
+                        "timeout",
+                        serde_json::json!({
+                            "elapsed_ms": current_elapsed.as_millis() as u64,
+                            "limit_ms": limit.as_millis() as u64,
+                            "action": action,
+                            "never_heard": never_heard,
+                        }),
                     );
-                    // Terminate the child process tree
-                    // Since wait() hasn't completed, `child` should still be available here.
-                    kill_child_process_tree(child, child_pid).await; // kill_child_process_tree consumes child
+                    // Runs alongside the action, so site-specific cleanup can start right away.
+                    let timeout_exec = run_timeout_exec(
+                        &format!("No heartbeat {}", missed),
+                        current_elapsed,
+                        limit,
+                        last_heartbeat,
+                        &action,
+                        supervised.pid(),
+                    );
+                    // Evidence of why the child hung is gathered before it is gone
+                    // (--diagnose-exec).
+                    let terminates = gives_up
+                        || matches!(on_timeout, TimeoutAction::Kill | TimeoutAction::Restart);
+                    // So is a report on the incident, while the child is still there to look at
+                    // (--incident-report).
+                    if let Some(report) = incident_report.as_ref().filter(|_| terminates) {
+                        let reason = if never_heard { ExitReason::NoHeartbeat } else { ExitReason::Timeout };
+                        let incident = incident::Incident {
+                            reason: reason.name(),
+                            message: &format!("No heartbeat {}", missed),
+                            pid: supervised.pid(),
+                            elapsed_ms: current_elapsed.as_millis() as u64,
+                            limit_ms: limit.as_millis() as u64,
+                            last_heartbeat_ms: last_heartbeat.map(|ago| ago.as_millis() as u64),
+                            action: &action,
+                        };
+                        match report.write(&incident, &stats, output_rules.tail.as_ref()) {
+                            Ok(path) => {
+                                info!("Wrote incident report to {}.", path.display());
+                                audit::record("incident_report", serde_json::json!({ "path": path }));
+                            }
+                            Err(e) => error!(
+                                "Failed to write incident report to {}: {}",
+                                report.dir.display(),
+                                e
+                            ),
+                        }
+                    }
+                    if let Some(diagnose) = diagnose.as_ref().filter(|_| terminates) {
+                        diagnose.run(supervised.pid()).await;
+                    }
+                    if let Some(crash_loop) = crash_loop.as_ref().filter(|_| gives_up) {
+                        supervised.terminate().await;
+                        if let Some(timeout_exec) = timeout_exec {
+                            let _ = timeout_exec.await;
+                        }
+                        crash_loop_detected(crash_loop, supervised.pid(), "missed its deadline again")
+                            .await;
+                    }
+                    // Terminate the child process tree (or act on the container), restart it,
+                    // or leave it running, as --on-timeout says.
+                    let (after, outcome) = match &on_timeout {
+                        TimeoutAction::Kill => match supervised.act().await {
+                            (true, outcome) => (AfterTimeout::Restarted, outcome.to_string()),
+                            (false, outcome) => (AfterTimeout::Ended, outcome.to_string()),
+                        },
+                        TimeoutAction::Restart => match supervised.restart().await {
+                            Ok(()) => (AfterTimeout::Restarted, "child restarted".to_string()),
+                            Err(e) => {
+                                error!(
+                                    "Failed to restart {}: {}. Terminating it instead.",
+                                    supervised.describe(),
+                                    e
+                                );
+                                let (_, outcome) = supervised.terminate().await;
+                                (AfterTimeout::Ended, outcome.to_string())
+                            }
+                        },
+                        TimeoutAction::Signal(signal) => match supervised.signal(*signal).await {
+                            Ok(()) => (
+                                AfterTimeout::LeftRunning,
+                                format!("sent {}; child left running", signal),
+                            ),
+                            Err(e) => {
+                                error!("Failed to send {} to {}: {}", signal, supervised.describe(), e);
+                                (
+                                    AfterTimeout::LeftRunning,
+                                    format!("failed to send {}: {}; child left running", signal, e),
+                                )
+                            }
+                        },
+                        TimeoutAction::Exec(command) => {
+                            let env = [
+                                ("PING_GUARD_MESSAGE", format!("No heartbeat {}", missed)),
+                                ("PING_GUARD_ELAPSED_MS", current_elapsed.as_millis().to_string()),
+                                ("PING_GUARD_TIMEOUT_MS", limit.as_millis().to_string()),
+                                ("PING_GUARD_CHILD_PID", supervised.pid().to_string()),
+                            ];
+                            let command = command.clone();
+                            tokio::spawn(async move {
+                                events::run_hook("--on-timeout", &command, &env).await;
+                            });
+                            (AfterTimeout::LeftRunning, "child left running".to_string())
+                        }
+                        TimeoutAction::None => {
+                            (AfterTimeout::LeftRunning, "child left running".to_string())
+                        }
+                    };
+                    let (event, message, reason) = if never_heard {
+                        (
+                            events::Event::NoHeartbeat,
+                            format!(
+                                "No heartbeat ever received {} ({}); {}.",
+                                missed,
+                                stats.describe(),
+                                outcome
+                            ),
+                            ExitReason::NoHeartbeat,
+                        )
+                    } else {
+                        (
+                            events::Event::Timeout,
+                            format!("No heartbeat {}; {}.", missed, outcome),
+                            ExitReason::Timeout,
+                        )
+                    };
+                    match after {
+                        AfterTimeout::Restarted => {
+                            info!("Restarted after a timeout; the timeout starts over.");
+                            supervised.forward_output(&output_rules, &mut forwarders);
+                            stats.record_restart();
+                            tokio::spawn(events::emit(event, message));
+                            restarted_at = Some(Instant::now());
+                            if let Some(adaptive) = &adaptive {
+                                adaptive.restart();
+                            }
+                            continue;
+                        }
+                        AfterTimeout::LeftRunning => {
+                            // Reported once per missed deadline, like a notify-only timeout.
+                            reported_for = Some(last_signal_time);
+                            tokio::spawn(events::emit(event, message));
+                            continue;
+                        }
+                        AfterTimeout::Ended => {}
+                    }
+                    events::emit(event, message).await;
+                    if let Some(timeout_exec) = timeout_exec {
+                        let _ = timeout_exec.await;
+                    }
 
-                    println!("Exiting watchdog due to timeout.");
-                    std::process::exit(1); // Exit with non-zero for timeout
+                    info!("Exiting watchdog due to timeout.");
+                    exit::exit(reason);
                 } else {
                     // If we woke up from sleep but the condition is no longer met,
                     // it means a signal arrived very recently. Log this and continue.
-                    println!("Potential timeout check passed (signal received during sleep).");
+                    // In low-power mode this is the normal way to learn about heartbeats.
+                    if !low_power {
+                        info!("Potential timeout check passed (signal received during sleep).");
+                    }
                 }
             }
 
+            // Branch 4: Wake up regularly to notice a suspension that counts (--suspend-time count)
+            _ = sleep(SUSPEND_CHECK), if armed && suspend_time == suspend::SuspendTime::Count => {}
+
         }
     }
 }
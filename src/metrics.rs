@@ -0,0 +1,192 @@
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often `--metrics-file` is rewritten, if anything changed.
+const WRITE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Latest metrics reported by one sender.
+#[derive(Debug)]
+struct Reading {
+    values: BTreeMap<String, f64>,
+    arrived_ms: u64,
+}
+
+/// One metric, aggregated across the senders that currently report it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aggregate {
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    pub senders: usize,
+}
+
+impl Aggregate {
+    pub fn avg(&self) -> f64 {
+        self.sum / self.senders as f64
+    }
+}
+
+/// Metrics children report in structured payloads (`"metrics": {"queue_depth": 42}`), kept
+/// per sender and aggregated across senders, so that an autoscaler can read them from the
+/// watchdog (status queries, `--metrics-file`) rather than from each child. A sender that
+/// has not reported for `window` no longer counts, so a stopped sender's last values do
+/// not linger.
+#[derive(Debug)]
+pub struct Metrics {
+    window: Duration,
+    readings: Mutex<HashMap<String, Reading>>,
+    /// Set when a report has come in since `--metrics-file` was last written.
+    changed: AtomicBool,
+}
+
+impl Metrics {
+    pub fn new(window: Duration) -> Self {
+        Metrics {
+            window,
+            readings: Mutex::default(),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    /// Records the metrics `sender` reported in a heartbeat that arrived at `arrived_ms`
+    /// (Unix milliseconds). They replace whatever it reported before.
+    pub fn observe(&self, sender: &str, values: BTreeMap<String, f64>, arrived_ms: u64) {
+        let values = values
+            .into_iter()
+            .filter(|(_, value)| value.is_finite())
+            .collect();
+        self.readings
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(sender.to_string(), Reading { values, arrived_ms });
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Each metric some current sender reports, aggregated across those that do.
+    pub fn aggregates(&self) -> BTreeMap<String, Aggregate> {
+        let now_ms = crate::audit::now_ms();
+        let window_ms = self.window.as_millis() as u64;
+        let mut readings = self.readings.lock().unwrap_or_else(|e| e.into_inner());
+        readings.retain(|_, reading| now_ms.saturating_sub(reading.arrived_ms) <= window_ms);
+        let mut aggregates: BTreeMap<String, Aggregate> = BTreeMap::new();
+        for (name, &value) in readings.values().flat_map(|reading| &reading.values) {
+            aggregates
+                .entry(name.clone())
+                .and_modify(|aggregate| {
+                    aggregate.sum += value;
+                    aggregate.min = aggregate.min.min(value);
+                    aggregate.max = aggregate.max.max(value);
+                    aggregate.senders += 1;
+                })
+                .or_insert(Aggregate {
+                    sum: value,
+                    min: value,
+                    max: value,
+                    senders: 1,
+                });
+        }
+        aggregates
+    }
+
+    /// The aggregates as status queries show them, e.g.
+    /// `{"queue_depth": {"sum": 42, "min": 10, "max": 32, "avg": 21, "senders": 2}}`.
+    pub fn report(&self) -> Value {
+        self.aggregates()
+            .into_iter()
+            .map(|(name, aggregate)| {
+                let report = json!({
+                    "sum": aggregate.sum,
+                    "min": aggregate.min,
+                    "max": aggregate.max,
+                    "avg": aggregate.avg(),
+                    "senders": aggregate.senders,
+                });
+                (name, report)
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    /// The aggregates in the Prometheus text format, as node_exporter's textfile collector
+    /// and autoscalers that scrape it read them.
+    pub fn exposition(&self) -> String {
+        let name = crate::log::instance_name()
+            .map(|name| format!("name=\"{}\",", escape(name)))
+            .unwrap_or_default();
+        let mut text = String::from(
+            "# HELP ping_guard_child_metric Metrics reported in heartbeats, aggregated across senders.\n\
+             # TYPE ping_guard_child_metric gauge\n",
+        );
+        let aggregates = self.aggregates();
+        for (metric, aggregate) in &aggregates {
+            for (kind, value) in [
+                ("sum", aggregate.sum),
+                ("min", aggregate.min),
+                ("max", aggregate.max),
+                ("avg", aggregate.avg()),
+            ] {
+                let _ = writeln!(
+                    text,
+                    "ping_guard_child_metric{{{}metric=\"{}\",aggregate=\"{}\"}} {}",
+                    name,
+                    escape(metric),
+                    kind,
+                    value
+                );
+            }
+        }
+        text.push_str(
+            "# HELP ping_guard_child_metric_senders Senders currently reporting each metric.\n\
+             # TYPE ping_guard_child_metric_senders gauge\n",
+        );
+        for (metric, aggregate) in &aggregates {
+            let _ = writeln!(
+                text,
+                "ping_guard_child_metric_senders{{{}metric=\"{}\"}} {}",
+                name,
+                escape(metric),
+                aggregate.senders
+            );
+        }
+        text
+    }
+}
+
+/// Escapes a Prometheus label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Writes the metrics to `path` (`--metrics-file`) whenever they have changed, and at least
+/// once a `window` so that senders that went quiet drop out. The file is replaced in one
+/// step, so a reader never sees it half-written.
+pub async fn run_writer(metrics: Arc<Metrics>, path: PathBuf) {
+    let mut interval = tokio::time::interval(WRITE_INTERVAL);
+    let mut since_write = Duration::MAX;
+    loop {
+        interval.tick().await;
+        since_write = since_write.saturating_add(WRITE_INTERVAL);
+        if !metrics.changed.swap(false, Ordering::Relaxed) && since_write < metrics.window {
+            continue;
+        }
+        since_write = Duration::ZERO;
+        if let Err(e) = write(&path, &metrics.exposition()) {
+            error!("Failed to write metrics to {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn write(path: &Path, text: &str) -> std::io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    std::fs::write(&temporary, text)?;
+    std::fs::rename(&temporary, path)
+}
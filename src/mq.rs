@@ -0,0 +1,119 @@
+use crate::audit;
+use crate::error::Error;
+use crate::heartbeat::HeartbeatSink;
+use crate::payload::{self, Codec};
+use crate::stats::Stats;
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+
+/// Checks that a queue name has the `/name` form mq_open(3) requires.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    match name.strip_prefix('/') {
+        Some(rest) if !rest.is_empty() && !rest.contains('/') => Ok(()),
+        _ => Err(format!(
+            "--mq-name '{}' must be a single '/'-prefixed name, e.g. /ping-guard-app",
+            name
+        )),
+    }
+}
+
+/// A POSIX message queue opened for reading. On Linux the queue descriptor is a regular file
+/// descriptor, so it can be registered with the reactor like a socket.
+pub struct MessageQueue {
+    fd: AsyncFd<OwnedFd>,
+    msg_size: usize,
+}
+
+impl MessageQueue {
+    /// Opens (creating it if needed, owner-only) the named queue in non-blocking mode.
+    pub fn open(name: &str) -> io::Result<Self> {
+        let c_name = CString::new(name)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "name contains a NUL byte"))?;
+        // Safety: `c_name` is a valid NUL-terminated string; a null attr uses the system defaults.
+        let raw = unsafe {
+            libc::mq_open(
+                c_name.as_ptr(),
+                libc::O_RDONLY | libc::O_CREAT | libc::O_NONBLOCK | libc::O_CLOEXEC,
+                0o600 as libc::mode_t,
+                std::ptr::null_mut::<libc::mq_attr>(),
+            )
+        };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safety: on Linux mqd_t is a descriptor that nothing else owns; close(2) releases it.
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        // mq_receive fails unless the buffer can hold the queue's largest message.
+        // Safety: mq_attr is plain old data filled in by mq_getattr.
+        let mut attr: libc::mq_attr = unsafe { std::mem::zeroed() };
+        if unsafe { libc::mq_getattr(fd.as_raw_fd(), &mut attr) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(MessageQueue {
+            fd: AsyncFd::new(fd)?,
+            msg_size: attr.mq_msgsize as usize,
+        })
+    }
+
+    async fn receive(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let result = guard.try_io(|queue| {
+                // Safety: `buf` is valid for writes of `buf.len()` bytes.
+                let n = unsafe {
+                    libc::mq_receive(
+                        queue.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_char,
+                        buf.len(),
+                        std::ptr::null_mut(),
+                    )
+                };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            });
+            match result {
+                Ok(received) => return received,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// Treats every message on the queue as a heartbeat, applying any structured payload it
+/// carries.
+pub async fn run_mq_listener(queue: MessageQueue, name: String, codec: Codec, sink: HeartbeatSink) {
+    let mut buf = vec![0u8; queue.msg_size.max(1)];
+    loop {
+        match queue.receive(&mut buf).await {
+            Ok(len) => {
+                Stats::bump(&sink.stats.packets_received);
+                if !payload::apply(
+                    &buf[..len],
+                    codec,
+                    format_args!("message queue {}", name),
+                    audit::now_ms(),
+                    &sink,
+                ) {
+                    continue;
+                }
+                if !sink.beat() {
+                    break;
+                }
+            }
+            Err(e) => {
+                Error::Receive {
+                    what: format!("message queue {}", name),
+                    source: e,
+                }
+                .report();
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use crate::heartbeat::{HeartbeatSink, Trigger};
+use crate::stats::Stats;
+use regex::Regex;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// What the child's output lines mean to the watchdog, beyond being forwarded to its log.
+#[derive(Debug, Default)]
+pub struct OutputRules {
+    /// `--stdout-heartbeat`: stdout lines (optionally only matching ones) are heartbeats.
+    pub stdout_heartbeat: Option<(HeartbeatSink, Option<Regex>)>,
+    /// `--alive-regex`: matching lines on either stream are heartbeats.
+    pub alive: Option<(HeartbeatSink, Regex)>,
+    /// `--dead-regex`: a matching line on either stream means the child must be acted on now.
+    pub dead: Option<(Trigger, Regex)>,
+    /// `--ready-regex`: the first matching line on either stream since the child was last
+    /// (re)started, which arms the timeout.
+    pub ready: Option<(watch::Sender<Option<String>>, Regex)>,
+    /// `--incident-report`: the last lines on either stream, for the report.
+    pub tail: Option<OutputTail>,
+}
+
+/// The last lines the child wrote, oldest first, with the stream each came from.
+#[derive(Debug)]
+pub struct OutputTail {
+    limit: usize,
+    lines: Mutex<VecDeque<(&'static str, String)>>,
+}
+
+impl OutputTail {
+    pub fn new(limit: usize) -> Self {
+        OutputTail {
+            limit,
+            lines: Mutex::new(VecDeque::with_capacity(limit)),
+        }
+    }
+
+    fn push(&self, line: &str, from_stdout: bool) {
+        if self.limit == 0 {
+            return;
+        }
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        if lines.len() == self.limit {
+            lines.pop_front();
+        }
+        let stream = if from_stdout { "stdout" } else { "stderr" };
+        lines.push_back((stream, line.to_string()));
+    }
+
+    pub fn lines(&self) -> Vec<(&'static str, String)> {
+        let lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        lines.iter().cloned().collect()
+    }
+}
+
+impl OutputRules {
+    /// Applies the rules to one line the child wrote to stdout (`from_stdout`) or stderr.
+    pub fn on_line(&self, line: &str, from_stdout: bool) {
+        if let Some(tail) = &self.tail {
+            tail.push(line, from_stdout);
+        }
+        if let Some((trigger, regex)) = &self.dead {
+            if regex.is_match(line) {
+                trigger.declare_dead(format!("child output matched the dead pattern: {}", line));
+                return;
+            }
+        }
+        if let Some((ready, regex)) = &self.ready {
+            if regex.is_match(line) {
+                ready.send_if_modified(|ready| {
+                    let first = ready.is_none();
+                    if first {
+                        *ready = Some(line.to_string());
+                    }
+                    first
+                });
+            }
+        }
+        if let Some((sink, regex)) = &self.alive {
+            if regex.is_match(line) {
+                Stats::bump(&sink.stats.packets_received);
+                sink.beat();
+            }
+        }
+        if let Some((sink, filter)) = self.stdout_heartbeat.as_ref().filter(|_| from_stdout) {
+            if filter.as_ref().is_none_or(|regex| regex.is_match(line)) {
+                Stats::bump(&sink.stats.packets_received);
+                sink.beat();
+            }
+        }
+    }
+
+    /// Forgets that the child was ready: a child that was (re)started has to match
+    /// `--ready-regex` again.
+    pub fn restarted(&self) {
+        if let Some((ready, _)) = &self.ready {
+            ready.send_replace(None);
+        }
+    }
+}
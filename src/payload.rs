@@ -0,0 +1,384 @@
+use crate::audit;
+use crate::heartbeat::{ChildState, HeartbeatSink};
+use crate::sequence::Observation;
+use crate::stats::Stats;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Heartbeat sources that carry discrete messages, and so can carry structured payloads.
+pub const SOURCES: [&str; 2] = ["udp", "mq"];
+
+/// Structured heartbeat payload. Senders may send a JSON object (or, with the `protobuf`
+/// codec, a `ping_guard.v1.Heartbeat` message from `proto/heartbeat.proto`) instead of an
+/// arbitrary ping; every field is optional and unknown fields are ignored.
+#[derive(Debug, Default, Deserialize)]
+pub struct Payload {
+    /// Child-reported operating state, e.g. "degraded" or "ok".
+    #[serde(default)]
+    pub state: Option<String>,
+    /// Child generation the sender belongs to, as advertised in `PING_GUARD_GENERATION`.
+    #[serde(default)]
+    pub gen: Option<u64>,
+    /// Sender's clock when it sent the heartbeat, in milliseconds since the Unix epoch.
+    #[serde(default)]
+    pub ts: Option<u64>,
+    /// Sender's heartbeat counter, increased by one with every heartbeat.
+    #[serde(default)]
+    pub seq: Option<u64>,
+    /// Component of the child the heartbeat is for (`--require-component`).
+    #[serde(default)]
+    pub component: Option<String>,
+    /// Numbers the sender reports about itself, e.g. `{"queue_depth": 42}`, aggregated for
+    /// autoscalers (`--metrics-file`, status queries).
+    #[serde(default)]
+    pub metrics: Option<BTreeMap<String, f64>>,
+}
+
+/// How a heartbeat's bytes are interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Codec {
+    /// JSON objects are structured payloads; anything else is a plain ping.
+    Auto,
+    /// Every heartbeat must be a JSON object.
+    Json,
+    /// Every heartbeat must be a protobuf-encoded `ping_guard.v1.Heartbeat`.
+    Protobuf,
+    /// Every heartbeat is a plain ping; contents are never inspected.
+    Raw,
+}
+
+/// Token a UDP heartbeat must carry (`--expect-payload`), checked before any structured
+/// payload is decoded.
+#[derive(Debug, Clone)]
+pub struct Expected {
+    token: Vec<u8>,
+    /// Given with a trailing `*`: the payload only has to start with the token.
+    prefix: bool,
+}
+
+impl Expected {
+    /// Parses `TOKEN` (the whole payload, ignoring a trailing newline) or `TOKEN*` (any
+    /// payload starting with the token).
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (token, prefix) = match value.strip_suffix('*') {
+            Some(token) => (token, true),
+            None => (value, false),
+        };
+        if token.is_empty() {
+            return Err("the expected payload must not be empty".to_string());
+        }
+        Ok(Expected {
+            token: token.as_bytes().to_vec(),
+            prefix,
+        })
+    }
+
+    pub fn matches(&self, data: &[u8]) -> bool {
+        if self.prefix {
+            data.starts_with(&self.token)
+        } else {
+            let data = data.strip_suffix(b"\n").unwrap_or(data);
+            let data = data.strip_suffix(b"\r").unwrap_or(data);
+            data == self.token
+        }
+    }
+}
+
+/// Parses a `--payload-codec [SOURCE=]CODEC` entry. Without a source, the codec applies to
+/// every source that has no entry of its own.
+pub fn parse_codec_setting(value: &str) -> Result<(Option<String>, Codec), String> {
+    let (source, codec) = match value.split_once('=') {
+        Some((source, codec)) => {
+            let source = source.trim();
+            if !SOURCES.contains(&source) {
+                return Err(format!(
+                    "unknown source '{}' (expected one of: {})",
+                    source,
+                    SOURCES.join(", ")
+                ));
+            }
+            (Some(source.to_string()), codec)
+        }
+        None => (None, value),
+    };
+    let codec = Codec::from_str(codec.trim(), true).map_err(|_| {
+        format!(
+            "unknown codec '{}' (expected one of: auto, json, protobuf, raw)",
+            codec.trim()
+        )
+    })?;
+    Ok((source, codec))
+}
+
+/// The codec `settings` select for `source`; a source-specific entry beats a general one,
+/// and later entries beat earlier ones.
+pub fn codec_for(settings: &[(Option<String>, Codec)], source: &str) -> Codec {
+    let last = |specific: bool| {
+        settings
+            .iter()
+            .rev()
+            .find(|(s, _)| s.as_deref().map_or(!specific, |s| specific && s == source))
+            .map(|(_, codec)| *codec)
+    };
+    last(true).or_else(|| last(false)).unwrap_or(Codec::Auto)
+}
+
+/// Decodes a heartbeat. Returns `Ok(None)` for plain pings.
+pub fn decode(bytes: &[u8], codec: Codec) -> Result<Option<Payload>, String> {
+    match codec {
+        Codec::Raw => Ok(None),
+        Codec::Auto if !bytes.trim_ascii().starts_with(b"{") => Ok(None),
+        Codec::Auto | Codec::Json => serde_json::from_slice(bytes.trim_ascii())
+            .map(Some)
+            .map_err(|e| e.to_string()),
+        Codec::Protobuf => decode_protobuf(bytes).map(Some),
+    }
+}
+
+/// Decodes a `ping_guard.v1.Heartbeat` in the protobuf wire format. Unknown fields are
+/// skipped, and for repeated occurrences of a field the last one wins, as the format
+/// requires.
+fn decode_protobuf(mut bytes: &[u8]) -> Result<Payload, String> {
+    let mut payload = Payload::default();
+    while !bytes.is_empty() {
+        let key = read_varint(&mut bytes)?;
+        let (field, wire_type) = (key >> 3, key & 0x7);
+        match (field, wire_type) {
+            (0, _) => return Err("invalid field number 0".to_string()),
+            (1, 2) => {
+                let value = read_length_delimited(&mut bytes)?;
+                let state = std::str::from_utf8(value)
+                    .map_err(|_| "field 'state' is not valid UTF-8".to_string())?;
+                payload.state = Some(state.to_string());
+            }
+            (2, 0) => payload.gen = Some(read_varint(&mut bytes)?),
+            (3, 2) => {
+                let (name, value) = decode_metric(read_length_delimited(&mut bytes)?)?;
+                payload
+                    .metrics
+                    .get_or_insert_with(BTreeMap::new)
+                    .insert(name, value);
+            }
+            (4, 0) => payload.ts = Some(read_varint(&mut bytes)?),
+            (5, 0) => payload.seq = Some(read_varint(&mut bytes)?),
+            (6, 2) => {
+                let value = read_length_delimited(&mut bytes)?;
+                let component = std::str::from_utf8(value)
+                    .map_err(|_| "field 'component' is not valid UTF-8".to_string())?;
+                payload.component = Some(component.to_string());
+            }
+            (1..=6, _) => {
+                return Err(format!(
+                    "field {} has unexpected wire type {}",
+                    field, wire_type
+                ))
+            }
+            (_, 0) => {
+                read_varint(&mut bytes)?;
+            }
+            (_, 1) => skip(&mut bytes, 8)?,
+            (_, 2) => {
+                read_length_delimited(&mut bytes)?;
+            }
+            (_, 5) => skip(&mut bytes, 4)?,
+            (_, _) => return Err(format!("unsupported wire type {}", wire_type)),
+        }
+    }
+    Ok(payload)
+}
+
+/// Decodes one entry of the `metrics` map: a message with the name as field 1 and the value,
+/// a double, as field 2.
+fn decode_metric(mut bytes: &[u8]) -> Result<(String, f64), String> {
+    let (mut name, mut value) = (String::new(), 0.0);
+    while !bytes.is_empty() {
+        let key = read_varint(&mut bytes)?;
+        match (key >> 3, key & 0x7) {
+            (1, 2) => {
+                name = std::str::from_utf8(read_length_delimited(&mut bytes)?)
+                    .map_err(|_| "a metric name is not valid UTF-8".to_string())?
+                    .to_string();
+            }
+            (2, 1) => {
+                let (raw, rest) = bytes
+                    .split_first_chunk::<8>()
+                    .ok_or_else(|| "truncated fixed-width field".to_string())?;
+                value = f64::from_le_bytes(*raw);
+                bytes = rest;
+            }
+            (field, wire_type) => {
+                return Err(format!(
+                    "metrics entry field {} has unexpected wire type {}",
+                    field, wire_type
+                ))
+            }
+        }
+    }
+    Ok((name, value))
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes
+            .split_first()
+            .ok_or_else(|| "truncated varint".to_string())?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("varint longer than 10 bytes".to_string())
+}
+
+fn read_length_delimited<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8], String> {
+    let len = read_varint(bytes)?;
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|&len| len <= bytes.len())
+        .ok_or_else(|| "truncated length-delimited field".to_string())?;
+    let (value, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(value)
+}
+
+fn skip(bytes: &mut &[u8], len: usize) -> Result<(), String> {
+    if bytes.len() < len {
+        return Err("truncated fixed-width field".to_string());
+    }
+    *bytes = &bytes[len..];
+    Ok(())
+}
+
+/// Applies any structured payload in a heartbeat from `from` that arrived at `arrived_ms`
+/// (Unix milliseconds). Returns whether it counts as a heartbeat.
+pub fn apply(
+    data: &[u8],
+    codec: Codec,
+    from: impl Display,
+    arrived_ms: u64,
+    sink: &HeartbeatSink,
+) -> bool {
+    let stats = &sink.stats;
+    match decode(data, codec) {
+        Ok(Some(payload)) => {
+            if let Some(ts) = payload.ts {
+                sink.observe_clock(&from.to_string(), ts, arrived_ms);
+            }
+            // A replacement started ahead of the current child is up; its heartbeats count
+            // once it has taken over (--restart-strategy start-first).
+            if let Some(gen) = payload.gen.filter(|&gen| gen > sink.generation()) {
+                sink.hear_replacement(gen);
+                return false;
+            }
+            // Pings from an earlier generation must not vouch for the current child.
+            if let Some(gen) = payload.gen.filter(|&gen| gen != sink.generation()) {
+                let stale = Stats::bump(&stats.stale_generation);
+                if stale == 1 || stale.is_multiple_of(100) {
+                    error!(
+                        "Ignoring heartbeat from {} for stale generation {} (current: {}; {} ignored so far).",
+                        from,
+                        gen,
+                        sink.generation(),
+                        stale
+                    );
+                }
+                return false;
+            }
+            if let Some(seq) = payload.seq {
+                account_sequence(&from, payload.gen, seq, stats);
+            }
+            if let Some(state) = payload.state.as_deref() {
+                sink.report_state(ChildState::from_reported(state));
+            }
+            if let Some(component) = payload.component.as_deref() {
+                let age = Duration::from_millis(audit::now_ms().saturating_sub(arrived_ms));
+                let at = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+                sink.hear_component(component, at);
+            }
+            // Processes on one host tell their metrics apart by component.
+            if let Some(metrics) = payload.metrics {
+                let sender = match payload.component.as_deref() {
+                    Some(component) => format!("{} component {}", from, component),
+                    None => from.to_string(),
+                };
+                sink.metrics.observe(&sender, metrics, arrived_ms);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            let malformed = Stats::bump(&stats.malformed_payloads);
+            // Only `auto` guesses at the format; a heartbeat that breaks an explicit codec
+            // does not count.
+            let lenient = codec == Codec::Auto;
+            if malformed == 1 || malformed.is_multiple_of(100) {
+                error!(
+                    "Malformed structured payload from {}: {} ({} so far); {}.",
+                    from,
+                    e,
+                    malformed,
+                    if lenient {
+                        "counting it as a plain ping"
+                    } else {
+                        "ignoring it"
+                    }
+                );
+            }
+            return lenient;
+        }
+    }
+    true
+}
+
+/// Counts and logs lost, duplicated and reordered heartbeats from `from`. A sender's numbering
+/// is tracked per generation, since a new child starts counting afresh.
+fn account_sequence(from: &impl Display, gen: Option<u64>, seq: u64, stats: &Stats) {
+    let stream = match gen {
+        Some(gen) => format!("{} generation {}", from, gen),
+        None => from.to_string(),
+    };
+    match stats.sequences.observe(&stream, seq) {
+        Observation::InOrder => {}
+        Observation::Gap(skipped) => {
+            let gaps = Stats::bump(&stats.seq_gaps);
+            let missing = stats.seq_missing.fetch_add(skipped, Ordering::Relaxed) + skipped;
+            if gaps == 1 || gaps.is_multiple_of(100) {
+                error!(
+                    "Heartbeat sequence gap from {}: {} missing before #{} ({} missing so far).",
+                    from, skipped, seq, missing
+                );
+            }
+        }
+        Observation::Duplicate => {
+            let duplicates = Stats::bump(&stats.seq_duplicates);
+            if duplicates == 1 || duplicates.is_multiple_of(100) {
+                error!(
+                    "Duplicate heartbeat #{} from {} ({} so far).",
+                    seq, from, duplicates
+                );
+            }
+        }
+        Observation::Reordered => {
+            // It was counted as missing when the gap opened.
+            let _ = stats
+                .seq_missing
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+            let reordered = Stats::bump(&stats.seq_reordered);
+            if reordered == 1 || reordered.is_multiple_of(100) {
+                error!(
+                    "Heartbeat #{} from {} arrived out of order ({} so far).",
+                    seq, from, reordered
+                );
+            }
+        }
+        Observation::Restarted => {
+            info!("Heartbeat sequence from {} restarted at #{}.", from, seq);
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use crate::child;
+use crate::heartbeat::HeartbeatSink;
+use crate::stats::Stats;
+#[cfg(unix)]
+use std::net::IpAddr;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tokio::time::{interval, timeout, MissedTickBehavior};
+
+/// Upper bound on how much of an HTTP response is read looking for the status line.
+const MAX_STATUS_LINE: usize = 1024;
+
+/// Sequence number for echo probes, so a late reply to an abandoned check is not mistaken
+/// for the answer to the current one.
+static SEQUENCE: AtomicU16 = AtomicU16::new(0);
+
+/// A health check the watchdog runs itself, for children that never send heartbeats.
+#[derive(Debug, Clone)]
+pub enum Probe {
+    Http(HttpTarget),
+    /// A shell command; exit status 0 means healthy.
+    Exec(String),
+    /// A `host:port` that must accept TCP connections.
+    Tcp(String),
+    /// A remote host that must answer ICMP echo requests.
+    #[cfg(unix)]
+    Ping(String),
+    /// A remote `host:port` that must echo a UDP datagram back.
+    UdpEcho(String),
+}
+
+impl Probe {
+    /// The heartbeat source name of this kind of probe.
+    pub fn source(&self) -> &'static str {
+        match self {
+            Probe::Http(_) => "http",
+            Probe::Exec(_) => "exec",
+            Probe::Tcp(_) => "tcp",
+            #[cfg(unix)]
+            Probe::Ping(_) => "ping",
+            Probe::UdpEcho(_) => "udp-echo",
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Probe::Http(target) => format!("HTTP GET {}", target.url),
+            Probe::Exec(command) => format!("exec '{}'", command),
+            Probe::Tcp(addr) => format!("TCP connect {}", addr),
+            #[cfg(unix)]
+            Probe::Ping(host) => format!("ICMP ping {}", host),
+            Probe::UdpEcho(addr) => format!("UDP echo {}", addr),
+        }
+    }
+
+    /// Runs one check. `Err` carries a short reason suitable for the log.
+    async fn check(&self) -> Result<(), String> {
+        match self {
+            Probe::Http(target) => target.get().await,
+            Probe::Exec(command) => exec(command).await,
+            Probe::Tcp(addr) => TcpStream::connect(addr.as_str())
+                .await
+                .map(drop)
+                .map_err(|e| format!("connect failed: {}", e)),
+            #[cfg(unix)]
+            Probe::Ping(host) => ping(host).await,
+            Probe::UdpEcho(addr) => udp_echo(addr).await,
+        }
+    }
+}
+
+/// Runs a probe command through the platform shell. The command is killed if the check is
+/// abandoned (e.g. it outlived the interval).
+async fn exec(command: &str) -> Result<(), String> {
+    let status = child::shell(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .status()
+        .await
+        .map_err(|e| format!("failed to run: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(status.to_string())
+    }
+}
+
+/// Checks that a `--probe-tcp` or `--probe-udp-echo` target is `host:port`; the host is
+/// resolved on every attempt.
+pub fn validate_host_port(addr: &str) -> Result<(), String> {
+    match addr.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(()),
+        _ => Err(format!("'{}' must be HOST:PORT", addr)),
+    }
+}
+
+/// Resolves `host` (with `port`) to the first address it has.
+async fn resolve(host: &str, port: u16) -> Result<SocketAddr, String> {
+    lookup_host((host, port))
+        .await
+        .map_err(|e| format!("cannot resolve {}: {}", host, e))?
+        .next()
+        .ok_or_else(|| format!("{} has no addresses", host))
+}
+
+/// Sends a datagram carrying a sequence number to a UDP echo service and waits for the same
+/// bytes to come back. Datagrams that do not match (e.g. late answers) are skipped.
+async fn udp_echo(addr: &str) -> Result<(), String> {
+    let (host, port) = addr
+        .rsplit_once(':')
+        .ok_or_else(|| format!("'{}' must be HOST:PORT", addr))?;
+    let port = port
+        .parse::<u16>()
+        .map_err(|_| format!("'{}' must be HOST:PORT", addr))?;
+    let target = resolve(host.trim_start_matches('[').trim_end_matches(']'), port).await?;
+    let local: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local)
+        .await
+        .map_err(|e| format!("bind failed: {}", e))?;
+    socket
+        .connect(target)
+        .await
+        .map_err(|e| format!("connect failed: {}", e))?;
+    let request = format!("ping-guard {}", SEQUENCE.fetch_add(1, Ordering::Relaxed));
+    socket
+        .send(request.as_bytes())
+        .await
+        .map_err(|e| format!("send failed: {}", e))?;
+    let mut buf = [0u8; 64];
+    loop {
+        let n = socket
+            .recv(&mut buf)
+            .await
+            .map_err(|e| format!("no echo: {}", e))?;
+        if &buf[..n] == request.as_bytes() {
+            return Ok(());
+        }
+    }
+}
+
+/// ICMP echo types for IPv4 and IPv6 (request, reply).
+#[cfg(unix)]
+const ICMPV4_ECHO: (u8, u8) = (8, 0);
+#[cfg(unix)]
+const ICMPV6_ECHO: (u8, u8) = (128, 129);
+
+/// Opens an unprivileged ICMP socket ("ping socket") for `addr`'s family. On Linux the
+/// process's group must be within `net.ipv4.ping_group_range`.
+#[cfg(unix)]
+fn icmp_socket(addr: &IpAddr) -> std::io::Result<socket2::Socket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let (domain, protocol) = match addr {
+        IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+        IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+    };
+    let socket = Socket::new(domain, Type::DGRAM, Some(protocol))?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// Checks that an ICMP socket can be opened for `--probe-ping HOST`, so a missing permission
+/// is reported at startup rather than as probe failures. Host names are checked for IPv4;
+/// they are resolved on every attempt.
+#[cfg(unix)]
+pub fn check_ping_permitted(host: &str) -> Result<(), String> {
+    let family = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    icmp_socket(&family).map(drop).map_err(|e| {
+        format!(
+            "cannot open an ICMP socket: {} (on Linux, allow this group in the net.ipv4.ping_group_range sysctl)",
+            e
+        )
+    })
+}
+
+/// Sends one ICMP echo request to `host` and waits for the matching reply.
+#[cfg(unix)]
+async fn ping(host: &str) -> Result<(), String> {
+    let target = resolve(host.trim_start_matches('[').trim_end_matches(']'), 0).await?;
+    let socket = icmp_socket(&target.ip())
+        .and_then(|socket| UdpSocket::from_std(socket.into()))
+        .map_err(|e| format!("cannot open ICMP socket: {}", e))?;
+    let (request_type, reply_type) = match target {
+        SocketAddr::V4(_) => ICMPV4_ECHO,
+        SocketAddr::V6(_) => ICMPV6_ECHO,
+    };
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed).to_be_bytes();
+    let payload = b"ping-guard";
+    // Type, code, checksum, identifier (assigned by the kernel), sequence, payload.
+    let mut request = vec![request_type, 0, 0, 0, 0, 0, sequence[0], sequence[1]];
+    request.extend_from_slice(payload);
+    if target.is_ipv4() {
+        // The kernel fills in the ICMPv6 checksum, which covers a pseudo-header.
+        let checksum = internet_checksum(&request).to_be_bytes();
+        request[2..4].copy_from_slice(&checksum);
+    }
+    socket
+        .send_to(&request, target)
+        .await
+        .map_err(|e| format!("send failed: {}", e))?;
+
+    let mut buf = [0u8; 1500];
+    loop {
+        let (n, _) = socket
+            .recv_from(&mut buf)
+            .await
+            .map_err(|e| format!("no reply: {}", e))?;
+        let mut reply = &buf[..n];
+        // macOS delivers IPv4 replies with their IP header; Linux strips it.
+        if target.is_ipv4() && reply.first().is_some_and(|b| b >> 4 == 4) {
+            let header_len = usize::from(reply[0] & 0x0f) * 4;
+            reply = reply.get(header_len..).unwrap_or_default();
+        }
+        if reply.len() >= 8
+            && reply[0] == reply_type
+            && reply[6..8] == sequence
+            && &reply[8..] == payload
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// RFC 1071 one's-complement checksum.
+#[cfg(unix)]
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// A parsed `http://host[:port]/path` URL.
+#[derive(Debug, Clone)]
+pub struct HttpTarget {
+    url: String,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl HttpTarget {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err(format!(
+                "'{}' is not supported; only plain http:// URLs can be probed",
+                url
+            ));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        // Split off a port, leaving bracketed IPv6 literals intact.
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse::<u16>()
+                    .map_err(|_| format!("invalid port in '{}'", url))?,
+            ),
+            _ => (authority, 80),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(format!("missing host in '{}'", url));
+        }
+        Ok(HttpTarget {
+            url: url.to_string(),
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// Issues a GET and treats any 2xx or 3xx status as healthy, like Kubernetes does.
+    async fn get(&self) -> Result<(), String> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .map_err(|e| format!("connect failed: {}", e))?;
+        let host_header = if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        };
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: ping-guard\r\nConnection: close\r\n\r\n",
+            self.path, host_header
+        );
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .map_err(|e| format!("request failed: {}", e))?;
+
+        let mut response = Vec::new();
+        let mut buf = [0u8; 256];
+        while !response.windows(2).any(|w| w == b"\r\n") && response.len() < MAX_STATUS_LINE {
+            match stream.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => response.extend_from_slice(&buf[..n]),
+                Err(e) => return Err(format!("reading response failed: {}", e)),
+            }
+        }
+        let status_line = String::from_utf8_lossy(&response);
+        let status = status_line
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| "no HTTP status line in response".to_string())?;
+        if (200..400).contains(&status) {
+            Ok(())
+        } else {
+            Err(format!("HTTP status {}", status))
+        }
+    }
+}
+
+/// Runs `probe` every `every`. A success counts as a heartbeat; `failures` consecutive
+/// failures ask the monitor to act on the child immediately.
+pub async fn run_probe(probe: Probe, every: Duration, failures: u32, sink: HeartbeatSink) {
+    let mut ticker = interval(every);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut consecutive = 0u32;
+    loop {
+        ticker.tick().await;
+        // A check that outlives the interval counts as a failure.
+        let result = match timeout(every, probe.check()).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("no answer within {:?}", every)),
+        };
+        match result {
+            Ok(()) => {
+                if consecutive > 0 {
+                    info!(
+                        "Probe succeeded again after {} failure(s): {}",
+                        consecutive,
+                        probe.describe()
+                    );
+                }
+                consecutive = 0;
+                Stats::bump(&sink.stats.packets_received);
+                if !sink.beat() {
+                    break;
+                }
+            }
+            Err(reason) => {
+                consecutive += 1;
+                error!(
+                    "Probe failed ({}/{}): {}: {}",
+                    consecutive,
+                    failures,
+                    probe.describe(),
+                    reason
+                );
+                if consecutive >= failures {
+                    let reason = format!(
+                        "{} failed {} consecutive times (last: {})",
+                        probe.describe(),
+                        consecutive,
+                        reason
+                    );
+                    if !sink.trigger(reason) {
+                        break;
+                    }
+                    consecutive = 0;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,397 @@
+/// Identifies one specific process instance by PID plus its start time, so a PID that the
+/// kernel has recycled for an unrelated process is never mistaken for the child.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessIdentity {
+    pub pid: u32,
+    start_time: Option<u64>,
+}
+
+impl ProcessIdentity {
+    /// Records the identity of a live process. Call this right after spawning, while the PID
+    /// is still guaranteed to belong to the child.
+    pub fn capture(pid: u32) -> Self {
+        ProcessIdentity {
+            pid,
+            start_time: process_start_time(pid),
+        }
+    }
+
+    /// Whether this very process is still running: its PID has not been recycled, and it is
+    /// not a zombie waiting to be reaped (on Linux; elsewhere zombies count as running).
+    #[cfg(unix)]
+    pub fn is_running(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        {
+            let Some(fields) = stat_fields(self.pid) else {
+                return false;
+            };
+            stat_field::<String>(&fields, 3).as_deref() != Some("Z")
+                && self
+                    .start_time
+                    .is_none_or(|start| stat_field(&fields, 22) == Some(start))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            // Safety: signal 0 only checks that the process exists.
+            let exists = unsafe { libc::kill(self.pid as i32, 0) } == 0;
+            exists
+                && self
+                    .start_time
+                    .is_none_or(|start| process_start_time(self.pid) == Some(start))
+        }
+    }
+
+    /// Returns true if signalling the process group led by this PID can only reach our child's
+    /// group. The kernel never hands out a PID that is still in use as a process group ID, so:
+    /// - leader alive with the same start time: it's our leader;
+    /// - no process with this PID: only our (possibly orphaned) group can carry this PGID;
+    /// - a process with a different start time: the PID was recycled, so our group is gone.
+    #[cfg(unix)]
+    pub fn group_is_ours(&self) -> bool {
+        let Some(expected) = self.start_time else {
+            // Start time unavailable on this platform: nothing to verify against.
+            return true;
+        };
+        match process_start_time(self.pid) {
+            Some(current) => current == expected,
+            None => true,
+        }
+    }
+}
+
+/// Sends `signal` to the process itself after verifying that its PID has not been recycled.
+#[cfg(unix)]
+pub fn signal_process(identity: &ProcessIdentity, signal: libc::c_int) -> std::io::Result<()> {
+    if !identity.is_running() {
+        return Err(std::io::Error::from_raw_os_error(libc::ESRCH));
+    }
+    // Safety: kill has no memory-safety preconditions; the PID was verified above.
+    if unsafe { libc::kill(identity.pid as i32, signal) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Sends `signal` to the child's process group after verifying that the PGID has not been
+/// recycled. Every path that signals the child by raw PID should go through here.
+#[cfg(unix)]
+pub fn signal_process_group(
+    identity: &ProcessIdentity,
+    signal: libc::c_int,
+) -> std::io::Result<()> {
+    if !identity.group_is_ours() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "PID {} now belongs to a different process; refusing to signal its group",
+                identity.pid
+            ),
+        ));
+    }
+    // Safety: killpg has no memory-safety preconditions; the PGID was verified above.
+    if unsafe { libc::killpg(identity.pid as i32, signal) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Fields of /proc/<pid>/stat after the command name, the first being field 3 (state).
+#[cfg(target_os = "linux")]
+fn stat_fields(pid: u32) -> Option<Vec<String>> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name may contain spaces or parentheses, so parse after the last ')'.
+    let after_comm = &stat[stat.rfind(')')? + 1..];
+    Some(after_comm.split_whitespace().map(str::to_string).collect())
+}
+
+/// Field `n` of /proc/<pid>/stat, counting from 1 as proc(5) does, for `n` of 3 or more.
+#[cfg(target_os = "linux")]
+fn stat_field<T: std::str::FromStr>(fields: &[String], n: usize) -> Option<T> {
+    fields.get(n - 3)?.parse().ok()
+}
+
+/// Start time of `pid` in clock ticks since boot (field 22 of /proc/<pid>/stat).
+#[cfg(target_os = "linux")]
+fn process_start_time(pid: u32) -> Option<u64> {
+    stat_field(&stat_fields(pid)?, 22)
+}
+
+/// The PIDs in /proc with their stat fields.
+#[cfg(target_os = "linux")]
+fn all_processes() -> Vec<(u32, Vec<String>)> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .filter_map(|pid| Some((pid, stat_fields(pid)?)))
+        .collect()
+}
+
+/// Every live process descended from `pid`, including those that left its process group
+/// (e.g. daemons that called setsid), so that they can be killed along with it. Only Linux
+/// can list them; elsewhere there are none.
+#[cfg(target_os = "linux")]
+pub fn descendants(pid: u32) -> Vec<ProcessIdentity> {
+    let processes = all_processes();
+    let mut found = vec![pid];
+    let mut next = 0;
+    while next < found.len() {
+        let parent = found[next];
+        next += 1;
+        for (child, fields) in &processes {
+            if stat_field(fields, 4) == Some(parent) && !found.contains(child) {
+                found.push(*child);
+            }
+        }
+    }
+    found[1..]
+        .iter()
+        .map(|&pid| ProcessIdentity::capture(pid))
+        .collect()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn descendants(_pid: u32) -> Vec<ProcessIdentity> {
+    Vec::new()
+}
+
+/// Every live process descended from `pid`, from a Toolhelp snapshot of all processes,
+/// parents before their children. Windows keeps the PID of a parent that has exited, which may
+/// have been reused since; a process started before its supposed parent is not its child.
+#[cfg(windows)]
+pub fn descendants(pid: u32) -> Vec<ProcessIdentity> {
+    use windows_sys::Win32::Foundation::{CloseHandle, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+        TH32CS_SNAPPROCESS,
+    };
+
+    // Safety: the snapshot handle is closed below.
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) };
+    if snapshot == INVALID_HANDLE_VALUE {
+        return Vec::new();
+    }
+    let mut processes = Vec::new();
+    // Safety: all-zero is a valid PROCESSENTRY32W once its size is set.
+    let mut entry: PROCESSENTRY32W = unsafe { std::mem::zeroed() };
+    entry.dwSize = std::mem::size_of::<PROCESSENTRY32W>() as u32;
+    // Safety: the snapshot is valid and the entry's size is set.
+    let mut more = unsafe { Process32FirstW(snapshot, &mut entry) } != 0;
+    while more {
+        processes.push((entry.th32ProcessID, entry.th32ParentProcessID));
+        // Safety: as for Process32FirstW.
+        more = unsafe { Process32NextW(snapshot, &mut entry) } != 0;
+    }
+    // Safety: the snapshot was opened above.
+    unsafe { CloseHandle(snapshot) };
+
+    let mut found = vec![ProcessIdentity::capture(pid)];
+    let mut next = 0;
+    while next < found.len() {
+        let parent = found[next];
+        next += 1;
+        for &(child, parent_pid) in &processes {
+            if parent_pid != parent.pid || child == parent.pid {
+                continue;
+            }
+            let identity = ProcessIdentity::capture(child);
+            let started_after = match (parent.start_time, identity.start_time) {
+                (Some(parent), Some(child)) => child >= parent,
+                _ => true,
+            };
+            if started_after && !found.iter().any(|process| process.pid == child) {
+                found.push(identity);
+            }
+        }
+    }
+    found.split_off(1)
+}
+
+/// A child process of some process, as listed by [`children`].
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy)]
+pub struct ChildProcess {
+    pub identity: ProcessIdentity,
+    pub pgid: u32,
+    /// Whether it has exited and waits to be reaped.
+    pub zombie: bool,
+}
+
+/// The processes whose parent is `pid`, zombies included.
+#[cfg(target_os = "linux")]
+pub fn children(pid: u32) -> Vec<ChildProcess> {
+    all_processes()
+        .into_iter()
+        .filter(|(_, fields)| stat_field(fields, 4) == Some(pid))
+        .filter_map(|(child, fields)| {
+            Some(ChildProcess {
+                identity: ProcessIdentity {
+                    pid: child,
+                    start_time: stat_field(&fields, 22),
+                },
+                pgid: stat_field(&fields, 5)?,
+                zombie: stat_field::<String>(&fields, 3).as_deref() == Some("Z"),
+            })
+        })
+        .collect()
+}
+
+/// PIDs of the processes left in process group `pgid`, not counting zombies.
+#[cfg(target_os = "linux")]
+pub fn group_members(pgid: u32) -> Vec<u32> {
+    all_processes()
+        .into_iter()
+        .filter(|(_, fields)| {
+            stat_field(fields, 5) == Some(pgid)
+                && stat_field::<String>(fields, 3).as_deref() != Some("Z")
+        })
+        .map(|(pid, _)| pid)
+        .collect()
+}
+
+/// Without /proc the members cannot be listed, only whether there are any; the group's ID
+/// then stands in for them.
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn group_members(pgid: u32) -> Vec<u32> {
+    // Safety: signal 0 only checks that the group exists.
+    if unsafe { libc::killpg(pgid as i32, 0) } == 0 {
+        vec![pgid]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Start time of `pid` in microseconds since the epoch, from proc_pidinfo.
+#[cfg(target_os = "macos")]
+fn process_start_time(pid: u32) -> Option<u64> {
+    let mut info: libc::proc_bsdinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_bsdinfo>() as libc::c_int;
+    // Safety: `info` is a correctly sized, writable proc_bsdinfo buffer.
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid as libc::c_int,
+            libc::PROC_PIDTBSDINFO,
+            0,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    if written != size {
+        return None;
+    }
+    Some(info.pbi_start_tvsec * 1_000_000 + info.pbi_start_tvusec)
+}
+
+/// Start time of `pid` in 100-nanosecond intervals since 1601, from GetProcessTimes.
+#[cfg(windows)]
+fn process_start_time(pid: u32) -> Option<u64> {
+    use windows_sys::Win32::System::Threading::PROCESS_QUERY_LIMITED_INFORMATION;
+
+    let process = WindowsProcess::open(pid, PROCESS_QUERY_LIMITED_INFORMATION).ok()?;
+    process.start_time()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn process_start_time(_pid: u32) -> Option<u64> {
+    None
+}
+
+/// A handle to a process on Windows, closed when dropped.
+#[cfg(windows)]
+struct WindowsProcess(windows_sys::Win32::Foundation::HANDLE);
+
+#[cfg(windows)]
+impl WindowsProcess {
+    fn open(
+        pid: u32,
+        access: windows_sys::Win32::System::Threading::PROCESS_ACCESS_RIGHTS,
+    ) -> std::io::Result<Self> {
+        // Safety: OpenProcess has no memory-safety preconditions; the handle is owned below.
+        let handle = unsafe { windows_sys::Win32::System::Threading::OpenProcess(access, 0, pid) };
+        if handle.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(WindowsProcess(handle))
+    }
+
+    fn start_time(&self) -> Option<u64> {
+        use windows_sys::Win32::Foundation::FILETIME;
+
+        let mut times = [FILETIME {
+            dwLowDateTime: 0,
+            dwHighDateTime: 0,
+        }; 4];
+        let [created, exited, kernel, user] = &mut times;
+        // Safety: the handle is valid and each FILETIME is writable.
+        let ok = unsafe {
+            windows_sys::Win32::System::Threading::GetProcessTimes(
+                self.0, created, exited, kernel, user,
+            )
+        };
+        (ok != 0)
+            .then(|| (u64::from(created.dwHighDateTime) << 32) | u64::from(created.dwLowDateTime))
+    }
+}
+
+#[cfg(windows)]
+impl Drop for WindowsProcess {
+    fn drop(&mut self) {
+        // Safety: the handle is owned and closed only here.
+        unsafe { windows_sys::Win32::Foundation::CloseHandle(self.0) };
+    }
+}
+
+/// Terminates the processes in `tree`, as listed by [`descendants`], deepest first, so that
+/// none is left without its parent to start new ones. Returns how many were terminated and
+/// the errors for those that could not be; those already gone are neither.
+#[cfg(windows)]
+pub fn terminate_tree(tree: &[ProcessIdentity]) -> (usize, Vec<(u32, std::io::Error)>) {
+    let mut terminated = 0;
+    let mut errors = Vec::new();
+    for process in tree.iter().rev() {
+        match terminate_process(process, 1) {
+            Ok(()) => terminated += 1,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => errors.push((process.pid, e)),
+        }
+    }
+    (terminated, errors)
+}
+
+/// Terminates the process (`TerminateProcess`) without a handle from spawning it, after
+/// verifying that its PID has not been recycled. Its descendants are left running.
+#[cfg(windows)]
+pub fn terminate_process(identity: &ProcessIdentity, exit_code: u32) -> std::io::Result<()> {
+    use windows_sys::Win32::Foundation::{ERROR_INVALID_PARAMETER, WAIT_OBJECT_0};
+    use windows_sys::Win32::System::Threading::{
+        TerminateProcess, WaitForSingleObject, PROCESS_QUERY_LIMITED_INFORMATION,
+        PROCESS_SYNCHRONIZE, PROCESS_TERMINATE,
+    };
+
+    let gone = || std::io::Error::new(std::io::ErrorKind::NotFound, "no such process");
+    let process = match WindowsProcess::open(
+        identity.pid,
+        PROCESS_TERMINATE | PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_SYNCHRONIZE,
+    ) {
+        Ok(process) => process,
+        Err(e) if e.raw_os_error() == Some(ERROR_INVALID_PARAMETER as i32) => return Err(gone()),
+        Err(e) => return Err(e),
+    };
+    // Checked on the handle itself, so the PID cannot be recycled in between.
+    if identity
+        .start_time
+        .is_some_and(|start| process.start_time() != Some(start))
+    {
+        return Err(gone());
+    }
+    // Safety: the handle is valid; a zero timeout only polls.
+    if unsafe { WaitForSingleObject(process.0, 0) } == WAIT_OBJECT_0 {
+        return Err(gone());
+    }
+    // Safety: the handle was opened with PROCESS_TERMINATE.
+    if unsafe { TerminateProcess(process.0, exit_code) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
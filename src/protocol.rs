@@ -0,0 +1,23 @@
+/// Starts every framed packet, so heartbeats can be told apart from other traffic.
+pub const MAGIC: &[u8; 4] = b"PGRD";
+
+/// The protocol version this watchdog speaks, sent as one byte after [`MAGIC`].
+pub const VERSION: u8 = 1;
+
+/// Removes the `<magic><version>` frame from a packet. Returns what follows the frame, the
+/// whole packet if it is unframed (a legacy sender), or why the packet does not conform. With
+/// `strict` (`--strict-protocol`), unframed packets do not conform either.
+pub fn unframe(data: &[u8], strict: bool) -> Result<&[u8], String> {
+    let Some(framed) = data.strip_prefix(MAGIC) else {
+        return if strict {
+            Err("not framed".to_string())
+        } else {
+            Ok(data)
+        };
+    };
+    match framed.split_first() {
+        Some((&VERSION, contents)) => Ok(contents),
+        Some((version, _)) => Err(format!("unsupported protocol version {}", version)),
+        None => Err("no protocol version".to_string()),
+    }
+}
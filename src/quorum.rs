@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Heartbeats from several senders combined into one (`--require-senders`).
+///
+/// Like a liveness policy, every beat is translated into a heartbeat time for the monitor:
+/// when the `required`th most recently heard sender was last heard from. The timeout then runs
+/// out as soon as fewer than `required` senders have been heard from within it. Senders are
+/// told apart by address alone, so several senders on one host count once.
+#[derive(Debug)]
+pub struct Quorum {
+    required: usize,
+    /// The most recently heard senders, at most `required` of them: a sender that drops out
+    /// cannot count again until it sends another heartbeat, which is newer than all of these.
+    latest: Mutex<HashMap<IpAddr, Instant>>,
+}
+
+impl Quorum {
+    pub fn new(required: usize) -> Self {
+        Quorum {
+            required,
+            latest: Mutex::new(HashMap::with_capacity(required + 1)),
+        }
+    }
+
+    pub fn required(&self) -> usize {
+        self.required
+    }
+
+    /// Records a heartbeat from `sender` at `at`. Returns the quorum's heartbeat time, or
+    /// `None` while fewer than `required` senders have ever been heard from.
+    pub fn beat(&self, sender: IpAddr, at: Instant) -> Option<Instant> {
+        let mut latest = self.latest.lock().unwrap_or_else(|e| e.into_inner());
+        let last = latest.entry(sender.to_canonical()).or_insert(at);
+        *last = (*last).max(at);
+        if latest.len() > self.required {
+            let oldest = latest
+                .iter()
+                .min_by_key(|&(_, &last)| last)
+                .map(|(&sender, _)| sender);
+            if let Some(oldest) = oldest {
+                latest.remove(&oldest);
+            }
+        }
+        if latest.len() < self.required {
+            return None;
+        }
+        latest.values().min().copied()
+    }
+
+    /// How many senders were heard from within `window` (capped at `required`).
+    pub fn heard_within(&self, window: Duration) -> usize {
+        let now = Instant::now();
+        let latest = self.latest.lock().unwrap_or_else(|e| e.into_inner());
+        latest
+            .values()
+            .filter(|&&last| now.saturating_duration_since(last) < window)
+            .count()
+    }
+}
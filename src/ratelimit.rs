@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Most senders tracked for `--source-rate-limit`. A flood from more (e.g. spoofed) addresses
+/// than this is left to the global limit, and packets from new addresses are dropped until
+/// tracked senders go quiet.
+const MAX_SOURCES: usize = 4096;
+
+/// How often the tracked senders are pruned, at most, once [`MAX_SOURCES`] is reached.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often drops are logged, at most. A flood can be far bigger than other rejections, so
+/// logging every 100th drop would still drown out the log.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A token bucket refilled at `rate` tokens per second, holding at most one second's worth.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn full(rate: f64, now: Instant) -> Self {
+        Bucket {
+            tokens: rate,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, rate: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.last = now;
+    }
+
+    /// Takes a token if one is left.
+    fn take(&mut self, rate: f64, now: Instant) -> bool {
+        self.refill(rate, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Sources {
+    buckets: HashMap<IpAddr, Bucket>,
+    pruned: Instant,
+}
+
+/// Which limit a dropped packet exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exceeded {
+    Source,
+    Global,
+}
+
+/// Caps the packets the listener processes per second, overall (`--rate-limit`) and per
+/// sender address (`--source-rate-limit`), so a flood is dropped before any decryption,
+/// authentication or decoding. Short bursts of up to a second's worth are allowed.
+#[derive(Debug)]
+pub struct RateLimiter {
+    global: Option<(f64, Mutex<Bucket>)>,
+    per_source: Option<(f64, Mutex<Sources>)>,
+    reported: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(global: Option<u32>, per_source: Option<u32>) -> Self {
+        let now = Instant::now();
+        RateLimiter {
+            global: global.map(|rate| {
+                let rate = f64::from(rate);
+                (rate, Mutex::new(Bucket::full(rate, now)))
+            }),
+            per_source: per_source.map(|rate| {
+                let sources = Sources {
+                    buckets: HashMap::new(),
+                    pruned: now,
+                };
+                (f64::from(rate), Mutex::new(sources))
+            }),
+            reported: Mutex::new(None),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.global.is_none() && self.per_source.is_none()
+    }
+
+    /// Describes the limits, e.g. "100 packets/s overall, 10 packets/s per sender".
+    pub fn describe(&self) -> String {
+        let mut limits = Vec::new();
+        if let Some((rate, _)) = &self.global {
+            limits.push(format!("{} packets/s overall", rate));
+        }
+        if let Some((rate, _)) = &self.per_source {
+            limits.push(format!("{} packets/s per sender", rate));
+        }
+        limits.join(", ")
+    }
+
+    /// Whether a drop should be logged now: the first one, and then at most one every
+    /// [`REPORT_INTERVAL`].
+    pub fn should_report(&self) -> bool {
+        let now = Instant::now();
+        let mut reported = self.reported.lock().unwrap_or_else(|e| e.into_inner());
+        if reported.is_some_and(|at| now.saturating_duration_since(at) < REPORT_INTERVAL) {
+            return false;
+        }
+        *reported = Some(now);
+        true
+    }
+
+    /// Takes a packet from `source` out of its sender's allowance and then the global one.
+    /// A packet dropped by the sender's limit does not count against the global one, so one
+    /// noisy sender cannot starve the others.
+    pub fn admit(&self, source: IpAddr) -> Result<(), Exceeded> {
+        let now = Instant::now();
+        if let Some((rate, sources)) = &self.per_source {
+            let mut sources = sources.lock().unwrap_or_else(|e| e.into_inner());
+            let sources = &mut *sources;
+            let source = source.to_canonical();
+            if !sources.buckets.contains_key(&source) && sources.buckets.len() >= MAX_SOURCES {
+                if now.saturating_duration_since(sources.pruned) >= PRUNE_INTERVAL {
+                    // Senders whose buckets have refilled are indistinguishable from new ones.
+                    sources.buckets.retain(|_, bucket| {
+                        bucket.refill(*rate, now);
+                        bucket.tokens < *rate
+                    });
+                    sources.pruned = now;
+                }
+                if sources.buckets.len() >= MAX_SOURCES {
+                    return Err(Exceeded::Source);
+                }
+            }
+            let bucket = sources
+                .buckets
+                .entry(source)
+                .or_insert_with(|| Bucket::full(*rate, now));
+            if !bucket.take(*rate, now) {
+                return Err(Exceeded::Source);
+            }
+        }
+        if let Some((rate, bucket)) = &self.global {
+            let mut bucket = bucket.lock().unwrap_or_else(|e| e.into_inner());
+            if !bucket.take(*rate, now) {
+                return Err(Exceeded::Global);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct State {
+    skipped: u64,
+    last: Option<Instant>,
+}
+
+/// Picks which of a stream of events to log (`--log-heartbeats`): the first, and then one in
+/// every `every`, at most once per `interval`.
+#[derive(Debug)]
+pub struct Sampler {
+    every: u64,
+    interval: Option<Duration>,
+    state: Mutex<State>,
+}
+
+impl Sampler {
+    pub fn new(every: u64, interval: Option<Duration>) -> Self {
+        Sampler {
+            every,
+            interval,
+            state: Mutex::new(State {
+                skipped: 0,
+                last: None,
+            }),
+        }
+    }
+
+    /// Describes the sampling, e.g. "1 in 10, at most once every 5s".
+    pub fn describe(&self) -> String {
+        let mut sampling = if self.every > 1 {
+            format!("1 in {}", self.every)
+        } else {
+            "every one".to_string()
+        };
+        if let Some(interval) = self.interval {
+            sampling.push_str(&format!(", at most once every {:?}", interval));
+        }
+        sampling
+    }
+
+    /// Whether this event should be logged. If so, returns how many were skipped since the
+    /// last one that was.
+    pub fn sample(&self) -> Option<u64> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let due = match state.last {
+            None => true,
+            Some(last) => {
+                state.skipped + 1 >= self.every
+                    && self
+                        .interval
+                        .is_none_or(|interval| now.saturating_duration_since(last) >= interval)
+            }
+        };
+        if !due {
+            state.skipped += 1;
+            return None;
+        }
+        let skipped = state.skipped;
+        *state = State {
+            skipped: 0,
+            last: Some(now),
+        };
+        Some(skipped)
+    }
+}
@@ -0,0 +1,191 @@
+#[cfg(target_os = "linux")]
+use crate::child::Namespace;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicI32, Ordering};
+use tokio::process::Command;
+
+/// Registers a pre-exec hook that changes the child's root directory to `root` (`--chroot`)
+/// and its working directory to `dir` within it, or else to the new root. Needs
+/// `CAP_SYS_CHROOT`, so register it before the child's credentials are dropped.
+pub fn chroot(command: &mut Command, root: &Path, dir: Option<&Path>) -> Result<(), String> {
+    let root = CString::new(root.as_os_str().as_bytes())
+        .map_err(|_| format!("invalid --chroot '{}'", root.display()))?;
+    let dir = dir.unwrap_or(Path::new("/"));
+    let dir = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|_| format!("invalid --chdir '{}'", dir.display()))?;
+    // Safety: chroot and chdir are async-signal-safe and only affect the forked child.
+    unsafe {
+        command.pre_exec(move || {
+            // chroot leaves the working directory outside the new root, where a relative `dir`
+            // would be resolved, so the child first moves to the new root.
+            if libc::chroot(root.as_ptr()) == -1
+                || libc::chdir(c"/".as_ptr()) == -1
+                || libc::chdir(dir.as_ptr()) == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// Registers a pre-exec hook that moves the child into new `namespaces` (`--unshare`): mounts
+/// it makes no longer reach the host's, and it gets a network of its own with just a loopback
+/// interface, which is brought up. A new PID namespace only takes the child's own children,
+/// so the child must then also be started with [`enter_pid_namespace`]. Needs
+/// `CAP_SYS_ADMIN`, so register it before the child's root and credentials are changed.
+#[cfg(target_os = "linux")]
+pub fn unshare(command: &mut Command, namespaces: &[Namespace]) {
+    let flags = namespaces
+        .iter()
+        .fold(0, |flags, namespace| flags | namespace.as_raw());
+    let mount = namespaces.contains(&Namespace::Mount);
+    let net = namespaces.contains(&Namespace::Net);
+    // Safety: unshare, mount and the loopback ioctls are plain system calls that only affect
+    // the forked child.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::unshare(flags) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            // Mounts are usually shared with the host's namespace, and would still propagate.
+            if mount
+                && libc::mount(
+                    std::ptr::null(),
+                    c"/".as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    std::ptr::null(),
+                ) == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+            if net {
+                loopback_up()?;
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Brings up the loopback interface, which a new network namespace starts with down.
+#[cfg(target_os = "linux")]
+unsafe fn loopback_up() -> io::Result<()> {
+    let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+    if socket == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut request: libc::ifreq = std::mem::zeroed();
+    for (to, from) in request.ifr_name.iter_mut().zip(b"lo") {
+        *to = *from as libc::c_char;
+    }
+    let result = if libc::ioctl(socket, libc::SIOCGIFFLAGS as _, &mut request) == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+        if libc::ioctl(socket, libc::SIOCSIFFLAGS as _, &request) == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    };
+    libc::close(socket);
+    result
+}
+
+/// The child as PID 1 of its namespace, as the process it was forked from knows it.
+#[cfg(target_os = "linux")]
+static NAMESPACE_INIT: AtomicI32 = AtomicI32::new(0);
+
+/// Signals the process between the watchdog and a child in a PID namespace passes on: those
+/// the watchdog stops the child with, and those it may forward.
+#[cfg(target_os = "linux")]
+const RELAYED: [libc::c_int; 6] = [
+    libc::SIGTERM,
+    libc::SIGINT,
+    libc::SIGHUP,
+    libc::SIGQUIT,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+];
+
+#[cfg(target_os = "linux")]
+extern "C" fn relay(signal: libc::c_int) {
+    // Safety: kill is async-signal-safe.
+    unsafe { libc::kill(NAMESPACE_INIT.load(Ordering::Relaxed), signal) };
+}
+
+/// Registers a pre-exec hook that forks the child once more, into the PID namespace made by
+/// [`unshare`], where it runs as PID 1. The process forked from, which the watchdog sees as
+/// the child, passes signals on to it and exits as it does; whichever of the two dies, the
+/// other does too, and with PID 1 goes everything else in the namespace. Register it last,
+/// so that the other hooks also apply to the process in between.
+#[cfg(target_os = "linux")]
+pub fn enter_pid_namespace(command: &mut Command) {
+    // Safety: fork, sigprocmask and prctl are async-signal-safe, and the process in between
+    // never returns to the code that forked it.
+    unsafe {
+        command.pre_exec(|| {
+            // Until either side is set up, signals wait.
+            let mut all: libc::sigset_t = std::mem::zeroed();
+            let mut previous: libc::sigset_t = std::mem::zeroed();
+            libc::sigfillset(&mut all);
+            libc::sigprocmask(libc::SIG_SETMASK, &all, &mut previous);
+            match libc::fork() {
+                -1 => {
+                    let error = io::Error::last_os_error();
+                    libc::sigprocmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut());
+                    Err(error)
+                }
+                0 => {
+                    if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL as libc::c_ulong) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    libc::sigprocmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut());
+                    Ok(())
+                }
+                pid => wait_for_init(pid, &previous),
+            }
+        });
+    }
+}
+
+/// Runs in the process in between until the child in the PID namespace exits, then exits the
+/// same way.
+#[cfg(target_os = "linux")]
+unsafe fn wait_for_init(pid: libc::pid_t, mask: &libc::sigset_t) -> ! {
+    NAMESPACE_INIT.store(pid, Ordering::Relaxed);
+    for signal in RELAYED {
+        libc::signal(
+            signal,
+            relay as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+    // The child's exec, not this process, says whether it started: let go of every
+    // descriptor, among them the one a failed exec is reported through, and the child's
+    // output, which the watchdog reads until it is closed.
+    if libc::syscall(libc::SYS_close_range, 0, libc::c_uint::MAX, 0) == -1 {
+        for fd in 0..1024 {
+            libc::close(fd);
+        }
+    }
+    libc::sigprocmask(libc::SIG_SETMASK, mask, std::ptr::null_mut());
+    let mut status = 0;
+    while libc::waitpid(pid, &mut status, 0) == -1 {
+        if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            libc::_exit(1);
+        }
+    }
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status))
+}
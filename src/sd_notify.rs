@@ -0,0 +1,121 @@
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+/// Environment variable through which systemd hands a `Type=notify` service its notification
+/// socket, and through which the watchdog hands the child its own.
+pub const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+
+static SYSTEMD: OnceLock<Systemd> = OnceLock::new();
+
+/// systemd's notification socket, as passed to the watchdog.
+struct Systemd {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    /// Whether READY=1 was sent already; systemd only needs it once.
+    ready: AtomicBool,
+}
+
+/// Takes systemd's notification socket from the environment, if the watchdog runs as a
+/// `Type=notify` service. Returns the socket's address, as given.
+pub fn init() -> Option<String> {
+    let name = std::env::var(NOTIFY_SOCKET_ENV).ok()?;
+    let addr = match name.strip_prefix('@') {
+        Some(abstract_name) => SocketAddr::from_abstract_name(abstract_name),
+        None if name.starts_with('/') => SocketAddr::from_pathname(&name),
+        None => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "only path and abstract sockets are supported",
+        )),
+    };
+    let systemd = addr
+        .and_then(|addr| Ok((UnixDatagram::unbound()?, addr)))
+        .map(|(socket, addr)| Systemd {
+            socket,
+            addr,
+            ready: AtomicBool::new(false),
+        });
+    match systemd {
+        Ok(systemd) => {
+            let _ = SYSTEMD.set(systemd);
+            Some(name)
+        }
+        Err(e) => {
+            error!(
+                "Not notifying systemd through {}={}: {}",
+                NOTIFY_SOCKET_ENV, name, e
+            );
+            None
+        }
+    }
+}
+
+/// Tells systemd that the service is ready, the first time it is called; `why` says what
+/// showed it.
+pub fn ready(why: &str) {
+    let Some(systemd) = SYSTEMD.get() else {
+        return;
+    };
+    if systemd.ready.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    match systemd.socket.send_to_addr(b"READY=1", &systemd.addr) {
+        Ok(_) => info!("Told systemd the service is ready: {}.", why),
+        Err(e) => error!("Failed to tell systemd the service is ready: {}", e),
+    }
+}
+
+/// Binds the notification socket the child is given in place of systemd's, in the abstract
+/// namespace so that nothing is left behind on disk. Returns it with the name for the child's
+/// `NOTIFY_SOCKET`.
+pub fn bind_child_socket() -> io::Result<(UnixDatagram, String)> {
+    let name = format!("ping-guard-notify-{}", std::process::id());
+    let socket = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name)?)?;
+    socket.set_nonblocking(true)?;
+    Ok((socket, format!("@{}", name)))
+}
+
+/// Passes on what the child sends to its notification socket: READY=1 (once) and STATUS=
+/// lines. Anything else is dropped, e.g. MAINPID=, which would have systemd supervise the child
+/// instead of the watchdog.
+pub async fn run_child_socket(socket: UnixDatagram) {
+    let socket = match tokio::net::UnixDatagram::from_std(socket) {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("Failed to set up the child's notification socket: {}", e);
+            return;
+        }
+    };
+    let mut buf = vec![0; 4096];
+    loop {
+        let len = match socket.recv(&mut buf).await {
+            Ok(len) => len,
+            Err(e) => {
+                error!(
+                    "Failed to receive on the child's notification socket: {}",
+                    e
+                );
+                return;
+            }
+        };
+        let message = String::from_utf8_lossy(&buf[..len]);
+        for line in message.lines() {
+            if line == "READY=1" {
+                ready("the child sent READY=1");
+            } else if line.starts_with("STATUS=") {
+                status(line);
+            }
+        }
+    }
+}
+
+/// Passes a STATUS= line on to systemd.
+fn status(line: &str) {
+    if let Some(systemd) = SYSTEMD.get() {
+        if let Err(e) = systemd.socket.send_to_addr(line.as_bytes(), &systemd.addr) {
+            error!("Failed to pass the child's status on to systemd: {}", e);
+        }
+    }
+}
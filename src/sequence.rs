@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// How far behind the highest number a heartbeat can arrive and still be recognised as a
+/// duplicate or a late arrival. Anything further behind means the sender started counting
+/// again.
+const WINDOW: u64 = 64;
+
+/// What a heartbeat's sequence number says about the heartbeats before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Observation {
+    /// The next number, or the first one from this sender.
+    InOrder,
+    /// This many numbers were skipped; they may still arrive late.
+    Gap(u64),
+    /// This number already arrived.
+    Duplicate,
+    /// A skipped number arrived after all.
+    Reordered,
+    /// Far below the highest number: the sender started counting again.
+    Restarted,
+}
+
+#[derive(Debug)]
+struct Stream {
+    highest: u64,
+    /// Bit `n` is set if `highest - n` arrived.
+    seen: u64,
+}
+
+/// Sequence numbers seen per sender, for spotting lost, duplicated and reordered heartbeats
+/// (the `seq` payload field) before a lossy network turns into a spurious kill.
+#[derive(Debug, Default)]
+pub struct Sequences {
+    streams: Mutex<HashMap<String, Stream>>,
+}
+
+impl Sequences {
+    /// Records that `sender` sent heartbeat number `seq`.
+    pub fn observe(&self, sender: &str, seq: u64) -> Observation {
+        let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(stream) = streams.get_mut(sender) else {
+            streams.insert(
+                sender.to_string(),
+                Stream {
+                    highest: seq,
+                    seen: 1,
+                },
+            );
+            return Observation::InOrder;
+        };
+        if seq > stream.highest {
+            let skipped = seq - stream.highest - 1;
+            let shifted = u32::try_from(seq - stream.highest)
+                .ok()
+                .and_then(|shift| stream.seen.checked_shl(shift));
+            stream.seen = shifted.unwrap_or(0) | 1;
+            stream.highest = seq;
+            return match skipped {
+                0 => Observation::InOrder,
+                skipped => Observation::Gap(skipped),
+            };
+        }
+        let behind = stream.highest - seq;
+        if behind >= WINDOW {
+            *stream = Stream {
+                highest: seq,
+                seen: 1,
+            };
+            return Observation::Restarted;
+        }
+        let bit = 1 << behind;
+        if stream.seen & bit != 0 {
+            return Observation::Duplicate;
+        }
+        stream.seen |= bit;
+        Observation::Reordered
+    }
+}
@@ -0,0 +1,212 @@
+use crate::fdio;
+use crate::heartbeat::HeartbeatSink;
+use crate::stats::Stats;
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+use tokio::time::sleep;
+
+/// How long to wait before reopening a serial device that went away (e.g. USB unplug).
+const REOPEN_DELAY: Duration = Duration::from_secs(1);
+
+/// Parses a `--serial-magic` value: `0x`-prefixed hex digits, or the literal bytes otherwise.
+pub fn parse_magic(value: &str) -> Result<Vec<u8>, String> {
+    let Some(hex) = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    else {
+        if value.is_empty() {
+            return Err("magic sequence must not be empty".to_string());
+        }
+        return Ok(value.as_bytes().to_vec());
+    };
+    if hex.is_empty() || hex.len() % 2 != 0 {
+        return Err(format!(
+            "'{}' must contain an even, non-zero number of hex digits",
+            value
+        ));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("'{}' is not a valid hex sequence", value))
+        })
+        .collect()
+}
+
+/// Maps a numeric baud rate onto the termios speed constant.
+fn baud_constant(baud: u32) -> Option<libc::speed_t> {
+    let speed = match baud {
+        1200 => libc::B1200,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        #[cfg(target_os = "linux")]
+        460800 => libc::B460800,
+        #[cfg(target_os = "linux")]
+        921600 => libc::B921600,
+        #[cfg(target_os = "linux")]
+        1000000 => libc::B1000000,
+        #[cfg(target_os = "linux")]
+        2000000 => libc::B2000000,
+        _ => return None,
+    };
+    Some(speed)
+}
+
+/// Checks that a baud rate is supported before anything is spawned.
+pub fn validate_baud(baud: u32) -> Result<(), String> {
+    match baud_constant(baud) {
+        Some(_) => Ok(()),
+        None => Err(format!("unsupported baud rate {}", baud)),
+    }
+}
+
+/// Opens a serial device in raw, non-blocking mode at the requested baud rate.
+pub fn open(path: &Path, baud: u32) -> io::Result<AsyncFd<OwnedFd>> {
+    let speed = baud_constant(baud).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported baud rate {}", baud),
+        )
+    })?;
+    let c_path = CString::new(path.as_os_str().as_encoded_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+    // Safety: `c_path` is a valid NUL-terminated string.
+    let raw = unsafe {
+        libc::open(
+            c_path.as_ptr(),
+            libc::O_RDONLY | libc::O_NOCTTY | libc::O_NONBLOCK | libc::O_CLOEXEC,
+        )
+    };
+    if raw < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safety: `raw` is a freshly opened descriptor that nothing else owns.
+    let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+    // Safety: termios is plain old data filled in by tcgetattr before use.
+    let mut tty: libc::termios = unsafe { std::mem::zeroed() };
+    unsafe {
+        if libc::tcgetattr(fd.as_raw_fd(), &mut tty) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::cfmakeraw(&mut tty);
+        tty.c_cflag |= libc::CLOCAL | libc::CREAD;
+        if libc::cfsetispeed(&mut tty, speed) != 0 || libc::cfsetospeed(&mut tty, speed) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::tcsetattr(fd.as_raw_fd(), libc::TCSANOW, &tty) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    AsyncFd::new(fd)
+}
+
+/// Counts occurrences of `magic` in a byte stream, tolerating sequences split across reads.
+struct MagicMatcher {
+    magic: Vec<u8>,
+    window: Vec<u8>,
+}
+
+impl MagicMatcher {
+    fn new(magic: Vec<u8>) -> Self {
+        MagicMatcher {
+            magic,
+            window: Vec::new(),
+        }
+    }
+
+    fn feed(&mut self, data: &[u8]) -> usize {
+        self.window.extend_from_slice(data);
+        let mut found = 0;
+        let mut start = 0;
+        while let Some(pos) = self.window[start..]
+            .windows(self.magic.len())
+            .position(|w| w == self.magic.as_slice())
+        {
+            found += 1;
+            start += pos + self.magic.len();
+        }
+        // Keep just enough of the tail to complete a sequence split across reads.
+        let keep_from = self
+            .window
+            .len()
+            .saturating_sub(self.magic.len() - 1)
+            .max(start);
+        self.window.drain(..keep_from);
+        found
+    }
+}
+
+/// Reads the serial device and forwards heartbeats to the monitor: every read counts when
+/// `magic` is `None`, otherwise only complete magic sequences do. The device is reopened if
+/// it disappears.
+pub async fn run_serial_listener(
+    path: PathBuf,
+    baud: u32,
+    mut port: AsyncFd<OwnedFd>,
+    magic: Option<Vec<u8>>,
+    sink: HeartbeatSink,
+) {
+    let mut matcher = magic.map(MagicMatcher::new);
+    let mut buf = [0u8; 256];
+    loop {
+        match fdio::read(&port, &mut buf).await {
+            Ok(0) => error!("Serial device {} reported end of file.", path.display()),
+            Ok(n) => {
+                Stats::bump(&sink.stats.packets_received);
+                let beats = match matcher.as_mut() {
+                    Some(matcher) => matcher.feed(&buf[..n]),
+                    None => 1,
+                };
+                if beats == 0 {
+                    continue;
+                }
+                if !sink.beat() {
+                    return;
+                }
+                continue;
+            }
+            Err(e) => error!("Error reading serial device {}: {}", path.display(), e),
+        }
+
+        // The device went away; keep trying to reopen it until the monitor gives up on us.
+        let mut reported = false;
+        loop {
+            sleep(REOPEN_DELAY).await;
+            if sink.is_closed() {
+                return;
+            }
+            match open(&path, baud) {
+                Ok(reopened) => {
+                    info!("Reopened serial device {}.", path.display());
+                    port = reopened;
+                    if let Some(matcher) = matcher.as_mut() {
+                        matcher.window.clear();
+                    }
+                    break;
+                }
+                Err(e) if !reported => {
+                    error!(
+                        "Failed to reopen serial device {}: {}. Retrying every {:?}.",
+                        path.display(),
+                        e,
+                        REOPEN_DELAY
+                    );
+                    reported = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+}
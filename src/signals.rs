@@ -0,0 +1,166 @@
+use clap::ValueEnum;
+use std::fmt;
+
+/// A signal named on the command line, e.g. `TERM`, `SIGTERM` or `15`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signal {
+    pub number: i32,
+}
+
+/// Signals that can be named, with the names they are reported under.
+#[cfg(unix)]
+const NAMES: &[(&str, libc::c_int)] = &[
+    ("SIGHUP", libc::SIGHUP),
+    ("SIGINT", libc::SIGINT),
+    ("SIGQUIT", libc::SIGQUIT),
+    ("SIGABRT", libc::SIGABRT),
+    ("SIGKILL", libc::SIGKILL),
+    ("SIGUSR1", libc::SIGUSR1),
+    ("SIGUSR2", libc::SIGUSR2),
+    ("SIGTERM", libc::SIGTERM),
+    ("SIGCONT", libc::SIGCONT),
+    ("SIGSTOP", libc::SIGSTOP),
+    ("SIGTSTP", libc::SIGTSTP),
+    ("SIGWINCH", libc::SIGWINCH),
+];
+
+impl Signal {
+    /// Parses a signal name, with or without the `SIG` prefix and in any case, or number.
+    #[cfg(unix)]
+    pub fn parse(value: &str) -> Result<Signal, String> {
+        if let Ok(number) = value.parse::<i32>() {
+            // Safety: sigaction with null pointers only checks that the number is valid.
+            let valid = number > 0
+                && unsafe { libc::sigaction(number, std::ptr::null(), std::ptr::null_mut()) } == 0;
+            if !valid {
+                return Err(format!("invalid signal number {}", number));
+            }
+            return Ok(Signal { number });
+        }
+        let upper = value.to_ascii_uppercase();
+        let name = if upper.starts_with("SIG") {
+            upper
+        } else {
+            format!("SIG{}", upper)
+        };
+        NAMES
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|&(_, number)| Signal { number })
+            .ok_or_else(|| format!("unknown signal '{}'", value))
+    }
+
+    #[cfg(not(unix))]
+    pub fn parse(_value: &str) -> Result<Signal, String> {
+        Err("signals are only supported on Unix".to_string())
+    }
+
+    /// The signal's name, e.g. `SIGTERM`, if it has one.
+    pub fn name(self) -> Option<&'static str> {
+        #[cfg(unix)]
+        return NAMES
+            .iter()
+            .find(|&&(_, number)| number == self.number)
+            .map(|&(name, _)| name);
+        #[cfg(not(unix))]
+        None
+    }
+}
+
+/// Parses a signal the watchdog can catch, so that it can be told what to do with it
+/// (`--forward-signal`, `--signal-map`).
+pub fn parse_catchable(value: &str) -> Result<Signal, String> {
+    let signal = Signal::parse(value)?;
+    #[cfg(unix)]
+    if [
+        libc::SIGKILL,
+        libc::SIGSTOP,
+        libc::SIGILL,
+        libc::SIGFPE,
+        libc::SIGSEGV,
+    ]
+    .contains(&signal.number)
+    {
+        return Err(format!("{} cannot be caught", signal));
+    }
+    Ok(signal)
+}
+
+/// What the watchdog does when it receives a signal (`--signal-map`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Disposition {
+    /// Pass the signal on to the child.
+    Forward,
+    /// Log the signal and carry on.
+    Ignore,
+    /// Shut down, stopping the child with `--stop-signal` and `--kill-grace`, if given.
+    Graceful,
+    /// Shut down, killing the child right away.
+    Kill,
+    /// Pause the timeout, like the `PAUSE` command.
+    Pause,
+    /// Resume the timeout, like the `RESUME` command.
+    Resume,
+}
+
+impl Disposition {
+    #[cfg(unix)]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Disposition::Forward => "forward",
+            Disposition::Ignore => "ignore",
+            Disposition::Graceful => "graceful",
+            Disposition::Kill => "kill",
+            Disposition::Pause => "pause",
+            Disposition::Resume => "resume",
+        }
+    }
+}
+
+/// Parses a `--signal-map SIGNAL=DISPOSITION` entry.
+pub fn parse_disposition(value: &str) -> Result<(Signal, Disposition), String> {
+    let (signal, disposition) = value
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' must be SIGNAL=DISPOSITION", value))?;
+    let signal = parse_catchable(signal.trim())?;
+    let disposition = Disposition::from_str(disposition.trim(), true).map_err(|_| {
+        format!(
+            "unknown disposition '{}' (expected one of: forward, ignore, graceful, kill, pause, resume)",
+            disposition.trim()
+        )
+    })?;
+    Ok((signal, disposition))
+}
+
+/// What the watchdog does with each signal it handles: the defaults (`SIGTERM`, `SIGINT` and
+/// `SIGHUP` shut it down gracefully, `SIGUSR1` and `SIGUSR2` pause and resume the timeout)
+/// with `overrides` applied in order.
+#[cfg(unix)]
+pub fn dispositions(overrides: &[(Signal, Disposition)]) -> Vec<(Signal, Disposition)> {
+    let mut map: Vec<(Signal, Disposition)> = [
+        (libc::SIGTERM, Disposition::Graceful),
+        (libc::SIGINT, Disposition::Graceful),
+        (libc::SIGHUP, Disposition::Graceful),
+        (libc::SIGUSR1, Disposition::Pause),
+        (libc::SIGUSR2, Disposition::Resume),
+    ]
+    .into_iter()
+    .map(|(number, disposition)| (Signal { number }, disposition))
+    .collect();
+    for &(signal, disposition) in overrides {
+        match map.iter_mut().find(|(known, _)| *known == signal) {
+            Some(entry) => entry.1 = disposition,
+            None => map.push((signal, disposition)),
+        }
+    }
+    map
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "signal {}", self.number),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use crate::audit;
+use crate::events;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Senders that have not reported a timestamp for this long no longer count towards the
+/// skew between senders.
+const SENDER_WINDOW: Duration = Duration::from_secs(600);
+
+/// Latest clock reading from one sender.
+#[derive(Debug, Clone, Copy)]
+struct Reading {
+    /// Sender's clock minus the watchdog's at arrival, in milliseconds. Includes the transit
+    /// time, which is negligible next to any skew worth reporting.
+    offset_ms: i64,
+    arrived_ms: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    readings: HashMap<String, Reading>,
+    /// Senders currently reported as skewed against the watchdog's clock.
+    skewed: HashSet<String>,
+    /// Whether the spread between senders is currently reported as too wide.
+    spread: bool,
+}
+
+/// Compares the timestamps senders put in their heartbeats with the watchdog's clock and with
+/// each other (`--clock-skew-alarm`). Each condition is reported once when it starts and
+/// logged again when it clears; it only clears once the skew is back under half the limit, so
+/// a clock hovering around the limit does not raise an alarm on every heartbeat.
+#[derive(Debug)]
+pub struct ClockSkew {
+    limit: Duration,
+    state: Mutex<State>,
+}
+
+impl ClockSkew {
+    pub fn new(limit: Duration) -> Self {
+        ClockSkew {
+            limit,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Records that `sender` stamped a heartbeat with `sent_ms` that arrived at `arrived_ms`
+    /// (both Unix milliseconds).
+    pub fn observe(&self, sender: &str, sent_ms: u64, arrived_ms: u64) {
+        let limit_ms = self.limit.as_millis() as u64;
+        let offset_ms = sent_ms as i64 - arrived_ms as i64;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let state = &mut *state;
+        state.readings.insert(
+            sender.to_string(),
+            Reading {
+                offset_ms,
+                arrived_ms,
+            },
+        );
+        let window_ms = SENDER_WINDOW.as_millis() as u64;
+        state
+            .readings
+            .retain(|_, reading| arrived_ms.saturating_sub(reading.arrived_ms) <= window_ms);
+        state
+            .skewed
+            .retain(|sender| state.readings.contains_key(sender));
+
+        let skew = offset_ms.unsigned_abs();
+        if skew > limit_ms && state.skewed.insert(sender.to_string()) {
+            let message = format!(
+                "Clock skew: {}'s clock is {} {} the watchdog's (limit: {:?}).",
+                sender,
+                seconds(skew),
+                if offset_ms > 0 { "ahead of" } else { "behind" },
+                self.limit
+            );
+            alarm(
+                message,
+                serde_json::json!({ "sender": sender, "offset_ms": offset_ms }),
+            );
+        } else if skew <= limit_ms / 2 && state.skewed.remove(sender) {
+            info!(
+                "Clock of {} is back within {:?} of the watchdog's.",
+                sender, self.limit
+            );
+        }
+
+        let fastest = state.readings.iter().max_by_key(|(_, r)| r.offset_ms);
+        let slowest = state.readings.iter().min_by_key(|(_, r)| r.offset_ms);
+        let (Some((fastest, ahead)), Some((slowest, behind))) = (fastest, slowest) else {
+            return;
+        };
+        let spread = ahead.offset_ms.abs_diff(behind.offset_ms);
+        if spread > limit_ms && !state.spread {
+            state.spread = true;
+            let message = format!(
+                "Clock skew between senders: {}'s clock is {} ahead of {}'s (limit: {:?}).",
+                fastest,
+                seconds(spread),
+                slowest,
+                self.limit
+            );
+            alarm(
+                message,
+                serde_json::json!({
+                    "ahead": fastest,
+                    "behind": slowest,
+                    "spread_ms": spread,
+                }),
+            );
+        } else if spread <= limit_ms / 2 && state.spread {
+            state.spread = false;
+            info!(
+                "Sender clocks are back within {:?} of each other.",
+                self.limit
+            );
+        }
+    }
+}
+
+fn seconds(ms: u64) -> String {
+    format!("{:.1}s", ms as f64 / 1000.0)
+}
+
+fn alarm(message: String, fields: serde_json::Value) {
+    error!("{}", message);
+    audit::record("clock_skew", fields);
+    tokio::spawn(events::emit(events::Event::ClockSkew, message));
+}
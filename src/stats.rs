@@ -0,0 +1,172 @@
+use crate::sequence::Sequences;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Counters shared between the listener tasks, the monitor and whoever reports on them.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub packets_received: AtomicU64,
+    pub heartbeats_accepted: AtomicU64,
+    pub rejected_source: AtomicU64,
+    /// Packets that arrived with a lower TTL or hop limit than `--min-ttl`.
+    pub low_ttl: AtomicU64,
+    /// Packets dropped over `--rate-limit` or `--source-rate-limit`.
+    pub rate_limited: AtomicU64,
+    /// Packets larger than `--max-packet-size`.
+    pub oversized: AtomicU64,
+    /// Packets without a valid protocol frame (`--strict-protocol`), or with an unsupported
+    /// version.
+    pub nonconforming: AtomicU64,
+    pub unexpected_payload: AtomicU64,
+    pub malformed_payloads: AtomicU64,
+    pub stale_generation: AtomicU64,
+    pub undecryptable: AtomicU64,
+    pub unauthenticated: AtomicU64,
+    /// Correctly signed heartbeats rejected as replays (`--hmac-key-file`).
+    pub replayed: AtomicU64,
+    /// DTLS handshakes that failed or were refused (`--dtls`).
+    pub dtls_handshake_failures: AtomicU64,
+    /// Jumps in senders' `seq` numbers.
+    pub seq_gaps: AtomicU64,
+    /// Heartbeats skipped in senders' `seq` numbers that have not arrived late (yet).
+    pub seq_missing: AtomicU64,
+    pub seq_duplicates: AtomicU64,
+    pub seq_reordered: AtomicU64,
+    pub sequences: Sequences,
+    /// Times the child was restarted instead of terminated (e.g. `--docker-action restart`).
+    pub restarts: AtomicU64,
+    /// `heartbeats_accepted` when the child was last restarted.
+    heartbeats_at_restart: AtomicU64,
+    /// Addresses the UDP listener ended up bound to.
+    pub udp_addrs: Mutex<Vec<SocketAddr>>,
+}
+
+impl Stats {
+    /// Increments `counter` and returns the new value.
+    pub fn bump(counter: &AtomicU64) -> u64 {
+        counter.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Counts a restart of the child, which then has to send a first heartbeat again.
+    pub fn record_restart(&self) {
+        Self::bump(&self.restarts);
+        let accepted = self.heartbeats_accepted.load(Ordering::Relaxed);
+        self.heartbeats_at_restart
+            .store(accepted, Ordering::Relaxed);
+    }
+
+    /// Whether no heartbeat has been accepted since the child was started or last restarted,
+    /// so `--startup-timeout` applies.
+    pub fn starting(&self) -> bool {
+        self.heartbeats_accepted.load(Ordering::Relaxed)
+            == self.heartbeats_at_restart.load(Ordering::Relaxed)
+    }
+
+    /// Where heartbeats are expected and what has arrived so far, e.g. "listening on UDP
+    /// 0.0.0.0:12345; 3 packets received (3 from disallowed sources); sequence: 2 missing".
+    pub fn describe(&self) -> String {
+        let addrs = self.udp_addrs.lock().unwrap_or_else(|e| e.into_inner());
+        let listening = if addrs.is_empty() {
+            "not listening on UDP".to_string()
+        } else {
+            let addrs: Vec<String> = addrs.iter().map(SocketAddr::to_string).collect();
+            format!("listening on UDP {}", addrs.join(", "))
+        };
+        let rejected = self.rejections();
+        let received = self.packets_received.load(Ordering::Relaxed);
+        let mut description = if rejected.is_empty() {
+            format!("{}; {} packets received", listening, received)
+        } else {
+            format!(
+                "{}; {} packets received ({})",
+                listening,
+                received,
+                rejected.join(", ")
+            )
+        };
+        let irregular = self.sequence_problems();
+        if !irregular.is_empty() {
+            description.push_str(&format!("; sequence: {}", irregular.join(", ")));
+        }
+        let handshakes = self.dtls_handshake_failures.load(Ordering::Relaxed);
+        if handshakes > 0 {
+            description.push_str(&format!("; {} failed DTLS handshakes", handshakes));
+        }
+        description
+    }
+
+    /// The counters as JSON: restarts, packets received and rejected, irregular sequences and
+    /// failed DTLS handshakes, as status queries and incident reports show them.
+    pub fn report(&self) -> serde_json::Value {
+        let count = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        serde_json::json!({
+            "restarts": count(&self.restarts),
+            "packets": {
+                "received": count(&self.packets_received),
+                "heartbeats": count(&self.heartbeats_accepted),
+                "rate_limited": count(&self.rate_limited),
+                "rejected_source": count(&self.rejected_source),
+                "low_ttl": count(&self.low_ttl),
+                "oversized": count(&self.oversized),
+                "nonconforming": count(&self.nonconforming),
+                "undecryptable": count(&self.undecryptable),
+                "unauthenticated": count(&self.unauthenticated),
+                "replayed": count(&self.replayed),
+                "unexpected_payload": count(&self.unexpected_payload),
+                "malformed_payload": count(&self.malformed_payloads),
+                "stale_generation": count(&self.stale_generation),
+            },
+            "sequence": {
+                "missing": count(&self.seq_missing),
+                "duplicated": count(&self.seq_duplicates),
+                "reordered": count(&self.seq_reordered),
+            },
+            "dtls_handshake_failures": count(&self.dtls_handshake_failures),
+        })
+    }
+
+    /// Whether any packet was rejected (e.g. as a replay), heartbeats went missing or a DTLS
+    /// handshake failed, which may explain a timeout.
+    pub fn any_problems(&self) -> bool {
+        !self.rejections().is_empty()
+            || !self.sequence_problems().is_empty()
+            || self.dtls_handshake_failures.load(Ordering::Relaxed) > 0
+    }
+
+    /// Nonzero rejection counts, e.g. "3 from disallowed sources".
+    fn rejections(&self) -> Vec<String> {
+        [
+            (&self.rate_limited, "over the rate limit"),
+            (&self.rejected_source, "from disallowed sources"),
+            (&self.low_ttl, "below the minimum TTL"),
+            (&self.oversized, "oversized"),
+            (&self.nonconforming, "not conforming to the protocol"),
+            (&self.undecryptable, "undecryptable"),
+            (&self.unauthenticated, "unauthenticated"),
+            (&self.replayed, "replayed"),
+            (&self.unexpected_payload, "without the expected payload"),
+            (&self.malformed_payloads, "with malformed payloads"),
+            (&self.stale_generation, "for a stale generation"),
+        ]
+        .into_iter()
+        .map(|(counter, reason)| (counter.load(Ordering::Relaxed), reason))
+        .filter(|&(count, _)| count > 0)
+        .map(|(count, reason)| format!("{} {}", count, reason))
+        .collect()
+    }
+
+    /// Nonzero sequence counts, e.g. "2 missing".
+    fn sequence_problems(&self) -> Vec<String> {
+        [
+            (&self.seq_missing, "missing"),
+            (&self.seq_duplicates, "duplicated"),
+            (&self.seq_reordered, "out of order"),
+        ]
+        .into_iter()
+        .map(|(counter, what)| (counter.load(Ordering::Relaxed), what))
+        .filter(|&(count, _)| count > 0)
+        .map(|(count, what)| format!("{} {}", count, what))
+        .collect()
+    }
+}
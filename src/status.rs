@@ -0,0 +1,156 @@
+use crate::adaptive::Adaptive;
+use crate::backoff::Backoff;
+use crate::command::Control;
+use crate::heartbeat::{ChildState, HeartbeatSink};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Payload of a status query (`--status-query`); surrounding whitespace is ignored, so
+/// `echo 'STATUS?' | nc -u ...` works.
+const QUERY: &[u8] = b"STATUS?";
+
+/// Whether a packet's contents are a status query rather than a heartbeat.
+pub fn is_query(data: &[u8]) -> bool {
+    data.trim_ascii() == QUERY
+}
+
+/// What the watchdog tells senders about itself and the supervised child: pongs for accepted
+/// heartbeats (`--ack`) and answers to status queries (`--status-query`).
+#[derive(Debug)]
+pub struct Status {
+    timeout: Duration,
+    /// The timeout until the child's first heartbeat (`--startup-timeout`).
+    startup_timeout: Option<Duration>,
+    /// Whether the timeout only starts with the first heartbeat (`--arm-on-first-ping`).
+    arm_on_first_ping: bool,
+    /// The timeout while the child reports itself degraded.
+    degraded_timeout: Duration,
+    /// Learns the normal timeout from the heartbeats (`--adaptive-timeout`).
+    adaptive: Option<Arc<Adaptive>>,
+    /// Puts off restarts of a child that keeps exiting (`--restart`).
+    backoff: Option<Arc<Backoff>>,
+    pid: u32,
+    started: Instant,
+    control: Arc<Control>,
+}
+
+impl Status {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        timeout: Duration,
+        startup_timeout: Option<Duration>,
+        arm_on_first_ping: bool,
+        degraded_timeout: Duration,
+        adaptive: Option<Arc<Adaptive>>,
+        backoff: Option<Arc<Backoff>>,
+        pid: u32,
+        control: Arc<Control>,
+    ) -> Self {
+        Status {
+            timeout,
+            startup_timeout,
+            arm_on_first_ping,
+            degraded_timeout,
+            adaptive,
+            backoff,
+            pid,
+            started: Instant::now(),
+            control,
+        }
+    }
+
+    /// The timeout that applies in the state the child last reported, or until its first
+    /// heartbeat while `starting`.
+    fn limit(&self, state: ChildState, starting: bool) -> Duration {
+        match (state, self.startup_timeout) {
+            (ChildState::Normal, Some(startup)) if starting => startup,
+            (ChildState::Normal, _) => {
+                let timeout = self.control.timeout(self.timeout);
+                self.adaptive
+                    .as_ref()
+                    .map_or(timeout, |adaptive| adaptive.timeout(timeout))
+            }
+            (ChildState::Degraded, _) => self.degraded_timeout,
+        }
+    }
+
+    /// Milliseconds left until the deadline, counting any `EXTEND`; `None` while the timeout
+    /// is paused or not armed yet.
+    fn remaining_ms(&self, sink: &HeartbeatSink) -> Option<u64> {
+        if self.control.paused() || (self.arm_on_first_ping && sink.stats.starting()) {
+            return None;
+        }
+        let remaining = self
+            .limit(sink.state(), sink.stats.starting())
+            .saturating_sub(sink.last_heartbeat().elapsed())
+            .max(self.control.extension_left());
+        Some(remaining.as_millis() as u64)
+    }
+
+    /// The pong for a heartbeat `sink` has just accepted: a JSON object with the time left
+    /// until the deadline and the child's PID and reported state.
+    pub fn pong(&self, sink: &HeartbeatSink) -> Vec<u8> {
+        let state = sink.state();
+        let mut pong = serde_json::json!({
+            "pong": true,
+            "remaining_ms": self.remaining_ms(sink),
+            "child_pid": self.pid,
+            "child_state": state.as_str(),
+        });
+        if let Some(name) = crate::log::instance_name() {
+            pong["name"] = name.into();
+        }
+        pong.to_string().into_bytes()
+    }
+
+    /// The answer to a status query: a JSON object describing the child, the timeout and what
+    /// the listeners have received so far.
+    pub fn report(&self, sink: &HeartbeatSink) -> Vec<u8> {
+        let stats = &sink.stats;
+        let count = |counter: &std::sync::atomic::AtomicU64| counter.load(Ordering::Relaxed);
+        let state = sink.state();
+        // Until the first heartbeat, the monitor's heartbeat time is when it started waiting.
+        let last_heartbeat_ms = (count(&stats.heartbeats_accepted) > 0)
+            .then(|| sink.last_heartbeat().elapsed().as_millis() as u64);
+        let mut report = serde_json::json!({
+            "child_pid": self.pid,
+            "child_state": state.as_str(),
+            "generation": sink.generation(),
+            "uptime_ms": self.started.elapsed().as_millis() as u64,
+            "last_heartbeat_ms": last_heartbeat_ms,
+            "timeout_ms": self.limit(state, stats.starting()).as_millis() as u64,
+            "remaining_ms": self.remaining_ms(sink),
+            "paused": self.control.paused(),
+        });
+        // The counters, as incident reports show them too.
+        if let (Some(report), serde_json::Value::Object(counters)) =
+            (report.as_object_mut(), stats.report())
+        {
+            report.extend(counters);
+        }
+        if let Some(components) = sink.required_components() {
+            let ages: serde_json::Map<_, _> = components
+                .ages()
+                .into_iter()
+                .map(|(name, age)| (name, (age.as_millis() as u64).into()))
+                .collect();
+            report["components"] = ages.into();
+        }
+        if let Some(backoff) = &self.backoff {
+            report["restart_backoff"] = backoff.report();
+        }
+        let metrics = sink.metrics.report();
+        if metrics
+            .as_object()
+            .is_some_and(|metrics| !metrics.is_empty())
+        {
+            report["metrics"] = metrics;
+        }
+        if let Some(name) = crate::log::instance_name() {
+            report["name"] = name.into();
+        }
+        report.to_string().into_bytes()
+    }
+}
@@ -0,0 +1,56 @@
+use crate::signals::Signal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// How a child process is asked to stop before its process group is killed (`--stop-signal`
+/// and `--kill-grace`).
+#[derive(Debug, Clone, Copy)]
+pub struct GracefulStop {
+    pub signal: Signal,
+    /// How long the child gets to exit before the group is killed.
+    pub grace: Duration,
+}
+
+static GRACEFUL_STOP: OnceLock<GracefulStop> = OnceLock::new();
+
+/// Sets how the child is stopped from now on. Without it, the group is killed right away.
+pub fn configure(stop: GracefulStop) {
+    let _ = GRACEFUL_STOP.set(stop);
+}
+
+/// Set once the child is to be killed right away, whatever `--kill-grace` says.
+static IMMEDIATE: AtomicBool = AtomicBool::new(false);
+
+pub fn graceful() -> Option<&'static GracefulStop> {
+    if IMMEDIATE.load(Ordering::Relaxed) {
+        return None;
+    }
+    GRACEFUL_STOP.get()
+}
+
+/// Kills the child right away from now on, skipping the graceful stop, e.g. once a signal
+/// mapped to `kill` arrived (`--signal-map`).
+pub fn skip_grace() {
+    IMMEDIATE.store(true, Ordering::Relaxed);
+}
+
+/// Whether [`skip_grace`] was called.
+pub fn immediate() -> bool {
+    IMMEDIATE.load(Ordering::Relaxed)
+}
+
+/// How long a killed process tree is given to be confirmed gone, unless `--kill-timeout` says
+/// otherwise.
+pub const DEFAULT_KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+static KILL_TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+/// Sets how long a killed process tree is killed again until it is confirmed gone.
+pub fn set_kill_timeout(timeout: Duration) {
+    let _ = KILL_TIMEOUT.set(timeout);
+}
+
+pub fn kill_timeout() -> Duration {
+    KILL_TIMEOUT.get().copied().unwrap_or(DEFAULT_KILL_TIMEOUT)
+}
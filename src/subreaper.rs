@@ -0,0 +1,130 @@
+use crate::procinfo::{self, ProcessIdentity};
+use std::io;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::process::{Child, Command};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// How often adopted processes are looked for, besides whenever a child of the watchdog exits.
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The watchdog as a subreaper (`--subreaper`): descendants of the child that are orphaned,
+/// e.g. by daemonizing, are re-parented to it instead of to init.
+static SUBREAPER: OnceLock<Subreaper> = OnceLock::new();
+
+struct Subreaper {
+    /// The children the watchdog spawned in a process group of their own: the child (and,
+    /// during a restart, its predecessor or replacement). tokio reaps these; every other such
+    /// child of the watchdog was adopted.
+    spawned: Mutex<Vec<ProcessIdentity>>,
+}
+
+/// Makes the watchdog a subreaper.
+pub fn enable() -> io::Result<()> {
+    // Safety: prctl with PR_SET_CHILD_SUBREAPER only sets a flag on this process.
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let _ = SUBREAPER.set(Subreaper {
+        spawned: Mutex::new(Vec::new()),
+    });
+    Ok(())
+}
+
+/// Spawns the child, taking note of it so that it is never mistaken for an adopted process.
+pub fn spawn(command: &mut Command) -> io::Result<Child> {
+    let Some(subreaper) = SUBREAPER.get() else {
+        return command.spawn();
+    };
+    // Held across the spawn, so the child cannot be reaped before it is noted.
+    let mut spawned = subreaper.spawned.lock().unwrap_or_else(|e| e.into_inner());
+    let child = command.spawn()?;
+    if let Some(pid) = child.id() {
+        spawned.push(ProcessIdentity::capture(pid));
+    }
+    Ok(child)
+}
+
+/// Adopted processes: the watchdog's own children that it neither spawned as the child nor
+/// runs as hooks, which share its process group. `zombies` selects those that exited, or those
+/// still running.
+fn adopted(spawned: &mut Vec<ProcessIdentity>, zombies: bool) -> Vec<ProcessIdentity> {
+    let watchdog = std::process::id();
+    let children = procinfo::children(watchdog);
+    // Forget children that are gone, so that a recycled PID is not taken for one of them.
+    spawned.retain(|identity| children.iter().any(|child| child.identity == *identity));
+    // Safety: getpgrp has no preconditions.
+    let own_group = unsafe { libc::getpgrp() } as u32;
+    children
+        .into_iter()
+        .filter(|child| {
+            child.zombie == zombies && child.pgid != own_group && !spawned.contains(&child.identity)
+        })
+        .map(|child| child.identity)
+        .collect()
+}
+
+/// Adopted processes that are still running, and their descendants, which are killed along
+/// with the child.
+pub fn adopted_tree() -> Vec<ProcessIdentity> {
+    let Some(subreaper) = SUBREAPER.get() else {
+        return Vec::new();
+    };
+    let mut spawned = subreaper.spawned.lock().unwrap_or_else(|e| e.into_inner());
+    let mut tree = Vec::new();
+    for process in adopted(&mut spawned, false) {
+        tree.push(process);
+        tree.extend(procinfo::descendants(process.pid));
+    }
+    tree
+}
+
+/// Reaps adopted processes as they exit, which would otherwise be left as zombies.
+pub async fn run_reaper() {
+    let Some(subreaper) = SUBREAPER.get() else {
+        return;
+    };
+    let mut sigchld = match signal(SignalKind::child()) {
+        Ok(sigchld) => Some(sigchld),
+        Err(e) => {
+            error!(
+                "Failed to set up a SIGCHLD handler; adopted processes are reaped every {:?}: {}",
+                REAP_INTERVAL, e
+            );
+            None
+        }
+    };
+    loop {
+        match &mut sigchld {
+            Some(sigchld) => {
+                let _ = tokio::time::timeout(REAP_INTERVAL, sigchld.recv()).await;
+            }
+            None => tokio::time::sleep(REAP_INTERVAL).await,
+        }
+        let mut spawned = subreaper.spawned.lock().unwrap_or_else(|e| e.into_inner());
+        for process in adopted(&mut spawned, true) {
+            let mut status = 0;
+            // Safety: waits for one specific zombie that the watchdog adopted.
+            let reaped =
+                unsafe { libc::waitpid(process.pid as libc::pid_t, &mut status, libc::WNOHANG) };
+            if reaped == process.pid as libc::pid_t {
+                info!(
+                    "Reaped adopted process {} ({}).",
+                    process.pid,
+                    describe_status(status)
+                );
+            }
+        }
+    }
+}
+
+/// A wait status as a shell would describe it, e.g. "exit code 1" or "killed by signal 9".
+fn describe_status(status: libc::c_int) -> String {
+    if libc::WIFEXITED(status) {
+        format!("exit code {}", libc::WEXITSTATUS(status))
+    } else if libc::WIFSIGNALED(status) {
+        format!("killed by signal {}", libc::WTERMSIG(status))
+    } else {
+        format!("status {}", status)
+    }
+}
@@ -0,0 +1,94 @@
+use clap::ValueEnum;
+use std::time::Duration;
+
+/// Shortest suspension that is acted on; shorter differences between the clocks are noise
+/// from reading them one after the other.
+const MIN_SUSPEND: Duration = Duration::from_millis(500);
+
+/// How time the system spent suspended (a closed laptop lid, a paused VM) is treated
+/// (`--suspend-time`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SuspendTime {
+    /// The timeout starts over on wake-up, so the child gets a full timeout to resume.
+    Ignore,
+    /// Time spent suspended counts against the timeout, as if the system had stayed awake.
+    Count,
+}
+
+/// Notices when the system was suspended, by comparing a clock that keeps running during
+/// suspend with the monotonic clock the timeout is measured on, which stops.
+#[derive(Debug)]
+pub struct SuspendClock {
+    /// Total suspended time already reported.
+    seen: Option<Duration>,
+}
+
+impl SuspendClock {
+    pub fn new() -> Self {
+        SuspendClock {
+            seen: suspended_total(),
+        }
+    }
+
+    /// Whether suspension can be noticed on this platform.
+    pub fn supported() -> bool {
+        suspended_total().is_some()
+    }
+
+    /// Whether the system was suspended since the last [`SuspendClock::observe`], without
+    /// taking note of it.
+    pub fn changed(&self) -> bool {
+        self.pending() >= MIN_SUSPEND
+    }
+
+    /// How long the system was suspended since the last call, or zero.
+    pub fn observe(&mut self) -> Duration {
+        let pending = self.pending();
+        if pending < MIN_SUSPEND {
+            return Duration::ZERO;
+        }
+        self.seen = suspended_total();
+        pending
+    }
+
+    fn pending(&self) -> Duration {
+        match (self.seen, suspended_total()) {
+            (Some(seen), Some(total)) => total.saturating_sub(seen),
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn clock(id: libc::clockid_t) -> Option<Duration> {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // Safety: `now` is a valid, writable timespec.
+    if unsafe { libc::clock_gettime(id, &mut now) } != 0 {
+        return None;
+    }
+    Some(Duration::new(now.tv_sec as u64, now.tv_nsec as u32))
+}
+
+/// Total time suspended since boot: CLOCK_BOOTTIME keeps running while suspended, the
+/// CLOCK_MONOTONIC that timers use does not.
+#[cfg(target_os = "linux")]
+fn suspended_total() -> Option<Duration> {
+    let monotonic = clock(libc::CLOCK_MONOTONIC)?;
+    Some(clock(libc::CLOCK_BOOTTIME)?.saturating_sub(monotonic))
+}
+
+/// Total time asleep since boot: CLOCK_MONOTONIC keeps running while asleep on macOS, the
+/// CLOCK_UPTIME_RAW that timers use does not.
+#[cfg(target_os = "macos")]
+fn suspended_total() -> Option<Duration> {
+    let uptime = clock(libc::CLOCK_UPTIME_RAW)?;
+    Some(clock(libc::CLOCK_MONOTONIC)?.saturating_sub(uptime))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn suspended_total() -> Option<Duration> {
+    None
+}
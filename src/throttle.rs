@@ -0,0 +1,222 @@
+#[cfg(target_os = "linux")]
+use crate::procinfo::{self, ProcessIdentity};
+#[cfg(target_os = "linux")]
+use crate::{audit, events};
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+#[cfg(target_os = "linux")]
+use tokio::time::{interval, sleep, Instant, MissedTickBehavior};
+
+/// How often the child's process group is sampled against the limits.
+#[cfg(target_os = "linux")]
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Set while a throttling burst has the child's process group stopped, so that it is continued
+/// before it is stopped, restarted or left running.
+#[cfg(target_os = "linux")]
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// Soft resource limits for the child's process group. Breaching one throttles the child;
+/// heartbeats alone decide whether it is killed.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// CPU time as a percentage of one core (`200` = two full cores).
+    pub cpu_percent: Option<f64>,
+    /// Storage reads plus writes, in bytes per second.
+    pub io_bytes_per_sec: Option<u64>,
+}
+
+/// Parses a byte rate such as `512K`, `20M` or `1048576`. Suffixes are binary (`K` = 1024).
+pub fn parse_rate(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&value[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&value[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    match number.trim().parse::<u64>() {
+        Ok(n) if n > 0 => n
+            .checked_mul(multiplier)
+            .ok_or_else(|| format!("'{}' is too large", value)),
+        _ => Err(format!(
+            "'{}' must be a positive number of bytes, optionally with a K, M or G suffix",
+            value
+        )),
+    }
+}
+
+/// Resource usage of a process group, summed over its members.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    cpu_ticks: u64,
+    io_bytes: u64,
+}
+
+/// Sums CPU time and storage I/O over every process in group `pgid`. Processes that exit
+/// between samples take their usage with them, so a group's total can drop; callers treat
+/// that as no usage.
+#[cfg(target_os = "linux")]
+fn sample(pgid: u32) -> Usage {
+    let mut usage = Usage::default();
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return usage;
+    };
+    for pid in entries.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok()) {
+        let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) else {
+            continue;
+        };
+        // The command name may contain spaces or parentheses, so parse after the last ')'.
+        let Some(after_comm) = stat.rfind(')').map(|i| &stat[i + 1..]) else {
+            continue;
+        };
+        // Fields after comm start at field 3 (state): pgrp is 5, utime 14 and stime 15.
+        let fields: Vec<&str> = after_comm.split_whitespace().collect();
+        let field = |n: usize| fields.get(n - 3).and_then(|f| f.parse::<u64>().ok());
+        if field(5) != Some(u64::from(pgid)) {
+            continue;
+        }
+        usage.cpu_ticks += field(14).unwrap_or(0) + field(15).unwrap_or(0);
+        if let Ok(io) = std::fs::read_to_string(format!("/proc/{}/io", pid)) {
+            usage.io_bytes += io
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .filter(|(key, _)| *key == "read_bytes" || *key == "write_bytes")
+                .filter_map(|(_, value)| value.trim().parse::<u64>().ok())
+                .sum::<u64>();
+        }
+    }
+    usage
+}
+
+#[cfg(target_os = "linux")]
+fn clock_ticks_per_sec() -> f64 {
+    // Safety: sysconf has no preconditions.
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as f64,
+        _ => 100.0,
+    }
+}
+
+/// Describes which limits `cpu_percent` and `io_rate` breach, or `None` if neither.
+#[cfg(target_os = "linux")]
+fn breach(limits: &Limits, cpu_percent: f64, io_rate: u64) -> Option<String> {
+    let mut breaches = Vec::new();
+    if let Some(limit) = limits.cpu_percent.filter(|&limit| cpu_percent > limit) {
+        breaches.push(format!(
+            "CPU {:.0}% over the {:.0}% limit",
+            cpu_percent, limit
+        ));
+    }
+    if let Some(limit) = limits.io_bytes_per_sec.filter(|&limit| io_rate > limit) {
+        breaches.push(format!("I/O {} B/s over the {} B/s limit", io_rate, limit));
+    }
+    (!breaches.is_empty()).then(|| breaches.join(", "))
+}
+
+/// Samples the child's process group every [`SAMPLE_INTERVAL`]. While it is over a limit, the
+/// group is stopped (`SIGSTOP`) for `stop` out of every interval and then continued, which
+/// caps its share of the machine without restarting it. If the child cannot keep up its
+/// heartbeats while throttled, the timeout escalates as usual.
+#[cfg(target_os = "linux")]
+pub async fn run_throttle(identity: ProcessIdentity, limits: Limits, stop: Duration) {
+    let ticks_per_sec = clock_ticks_per_sec();
+    let mut ticker = interval(SAMPLE_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut previous = (Instant::now(), sample(identity.pid));
+    let mut bursts = 0u64;
+    // Time the group spent stopped since the previous sample. Usage is measured over the time
+    // it was allowed to run, so a child that is still busy stays throttled.
+    let mut stopped = Duration::ZERO;
+    loop {
+        ticker.tick().await;
+        // A restarted child gets a throttle of its own; this one ends with its group.
+        if !signal_group(&identity, 0, "check") {
+            break;
+        }
+        let now = Instant::now();
+        let current = sample(identity.pid);
+        let secs = now
+            .duration_since(previous.0)
+            .saturating_sub(stopped)
+            .as_secs_f64()
+            .max(f64::EPSILON);
+        let cpu_percent =
+            current.cpu_ticks.saturating_sub(previous.1.cpu_ticks) as f64 / ticks_per_sec / secs
+                * 100.0;
+        let io_rate = (current.io_bytes.saturating_sub(previous.1.io_bytes) as f64 / secs) as u64;
+        previous = (now, current);
+        stopped = Duration::ZERO;
+
+        let Some(reason) = breach(&limits, cpu_percent, io_rate) else {
+            if bursts > 0 {
+                info!(
+                    "Child back within its resource limits after {} throttling burst(s).",
+                    bursts
+                );
+                audit::record("throttle_end", serde_json::json!({ "bursts": bursts }));
+                bursts = 0;
+            }
+            continue;
+        };
+        bursts += 1;
+        if bursts == 1 {
+            let message = format!(
+                "Throttling child: {}; stopping it for {:?} of every {:?}.",
+                reason, stop, SAMPLE_INTERVAL
+            );
+            info!("{}", message);
+            audit::record(
+                "throttle",
+                serde_json::json!({
+                    "cpu_percent": cpu_percent.round() as u64,
+                    "io_bytes_per_sec": io_rate,
+                }),
+            );
+            tokio::spawn(events::emit(events::Event::Throttle, message));
+        }
+        STOPPED.store(true, Ordering::Relaxed);
+        if !signal_group(&identity, libc::SIGSTOP, "stop") {
+            STOPPED.store(false, Ordering::Relaxed);
+            break;
+        }
+        sleep(stop).await;
+        if STOPPED.swap(false, Ordering::Relaxed)
+            && !signal_group(&identity, libc::SIGCONT, "continue")
+        {
+            break;
+        }
+        stopped = stop;
+    }
+}
+
+/// Continues the child's process group if a throttling burst has it stopped.
+#[cfg(target_os = "linux")]
+pub fn release(identity: &ProcessIdentity) {
+    if STOPPED.swap(false, Ordering::Relaxed) && signal_group(identity, libc::SIGCONT, "continue") {
+        info!("Continued the child's process group, stopped by throttling.");
+    }
+}
+
+/// Sends `signal` to the child's process group. Returns false if throttling should stop.
+#[cfg(target_os = "linux")]
+fn signal_group(identity: &ProcessIdentity, signal: libc::c_int, action: &str) -> bool {
+    match procinfo::signal_process_group(identity, signal) {
+        Ok(()) => true,
+        // The group is gone, or its PID now leads another; the monitor reports the child's exit.
+        Err(e)
+            if e.raw_os_error() == Some(libc::ESRCH)
+                || e.kind() == std::io::ErrorKind::NotFound =>
+        {
+            false
+        }
+        Err(e) => {
+            error!("Failed to {} the child's process group: {}", action, e);
+            false
+        }
+    }
+}
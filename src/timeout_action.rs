@@ -0,0 +1,58 @@
+use crate::signals::Signal;
+use std::fmt;
+
+/// What is done with the child when it misses its deadline (`--on-timeout`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimeoutAction {
+    /// Terminate the child (or act on the container as `--docker-action` says) and exit.
+    Kill,
+    /// Restart the child and keep supervising it.
+    Restart,
+    /// Send the child a signal and leave it running.
+    Signal(Signal),
+    /// Run a shell command and leave the child running.
+    Exec(String),
+    /// Only report the missed deadline.
+    None,
+}
+
+impl TimeoutAction {
+    /// Parses `kill`, `restart`, `signal:<SIG>`, `exec:<COMMAND>` or `none`.
+    pub fn parse(value: &str) -> Result<TimeoutAction, String> {
+        match value.split_once(':') {
+            None if value == "kill" => Ok(TimeoutAction::Kill),
+            None if value == "restart" => Ok(TimeoutAction::Restart),
+            None if value == "none" => Ok(TimeoutAction::None),
+            Some(("signal", signal)) => Ok(TimeoutAction::Signal(Signal::parse(signal)?)),
+            Some(("exec", command)) if !command.trim().is_empty() => {
+                Ok(TimeoutAction::Exec(command.to_string()))
+            }
+            Some(("exec", _)) => Err("exec: needs a command".to_string()),
+            _ => Err(format!(
+                "unknown action '{}'; expected kill, restart, signal:<SIG>, exec:<COMMAND> or none",
+                value
+            )),
+        }
+    }
+
+    /// Whether the child is left running once the action has been taken.
+    pub fn leaves_running(&self) -> bool {
+        matches!(
+            self,
+            TimeoutAction::Signal(_) | TimeoutAction::Exec(_) | TimeoutAction::None
+        )
+    }
+}
+
+/// The action as recorded in the audit log, e.g. `signal:SIGTERM`; a command is not repeated.
+impl fmt::Display for TimeoutAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeoutAction::Kill => write!(f, "kill"),
+            TimeoutAction::Restart => write!(f, "restart"),
+            TimeoutAction::Signal(signal) => write!(f, "signal:{}", signal),
+            TimeoutAction::Exec(_) => write!(f, "exec"),
+            TimeoutAction::None => write!(f, "none"),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::error::Error;
+use crate::fdio;
+use crate::heartbeat::HeartbeatSink;
+use crate::stats::Stats;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+
+/// A listening AF_VSOCK stream socket. Any bytes received on an accepted connection count as
+/// a heartbeat, so a host-side agent can keep one connection open and write to it periodically.
+pub struct VsockListener {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl VsockListener {
+    /// Binds and listens on `cid:port`. Use `libc::VMADDR_CID_ANY` to accept from any context.
+    pub fn bind(cid: u32, port: u32) -> io::Result<Self> {
+        // Safety: plain socket(2) call; the returned descriptor is owned immediately below.
+        let raw = unsafe {
+            libc::socket(
+                libc::AF_VSOCK,
+                libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+            )
+        };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safety: `raw` is a freshly created descriptor that nothing else owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        // Safety: sockaddr_vm is plain old data; all-zero is a valid starting value.
+        let mut addr: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+        addr.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+        addr.svm_cid = cid;
+        addr.svm_port = port;
+        // Safety: `addr` is a valid sockaddr_vm and the length matches its size.
+        let bound = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_vm as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+            )
+        };
+        if bound < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safety: listen(2) on a socket we own.
+        if unsafe { libc::listen(fd.as_raw_fd(), 16) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(VsockListener {
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+
+    /// Accepts the next connection, returning it with the peer's context ID.
+    async fn accept(&self) -> io::Result<(AsyncFd<OwnedFd>, u32)> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let accepted = guard.try_io(|listener| {
+                // Safety: sockaddr_vm is plain old data and `len` describes its size.
+                let mut peer: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+                let mut len = std::mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+                let raw = unsafe {
+                    libc::accept4(
+                        listener.as_raw_fd(),
+                        &mut peer as *mut libc::sockaddr_vm as *mut libc::sockaddr,
+                        &mut len,
+                        libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                    )
+                };
+                if raw < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                // Safety: accept4 returned a new descriptor that we now own.
+                Ok((unsafe { OwnedFd::from_raw_fd(raw) }, peer.svm_cid))
+            });
+            match accepted {
+                Ok(result) => {
+                    let (fd, cid) = result?;
+                    return Ok((AsyncFd::new(fd)?, cid));
+                }
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// Accepts vsock connections and forwards received data to the monitor as heartbeats.
+pub async fn run_vsock_listener(listener: VsockListener, sink: HeartbeatSink) {
+    loop {
+        match listener.accept().await {
+            Ok((conn, peer_cid)) => {
+                info!("Accepted vsock heartbeat connection from CID {}.", peer_cid);
+                tokio::spawn(read_connection(conn, peer_cid, sink.clone()));
+            }
+            Err(e) => {
+                Error::Receive {
+                    what: "vsock listener".to_string(),
+                    source: e,
+                }
+                .report();
+                break;
+            }
+        }
+    }
+}
+
+async fn read_connection(conn: AsyncFd<OwnedFd>, peer_cid: u32, sink: HeartbeatSink) {
+    let mut buf = [0u8; 256];
+    loop {
+        match fdio::read(&conn, &mut buf).await {
+            Ok(0) => {
+                info!("vsock connection from CID {} closed.", peer_cid);
+                break;
+            }
+            Ok(_) => {
+                Stats::bump(&sink.stats.packets_received);
+                if !sink.beat() {
+                    break;
+                }
+            }
+            Err(e) => {
+                Error::Receive {
+                    what: format!("vsock connection from CID {}", peer_cid),
+                    source: e,
+                }
+                .report();
+                break;
+            }
+        }
+    }
+}
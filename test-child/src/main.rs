@@ -0,0 +1,177 @@
+//! A child process for ping-guard's integration tests. Each option adds one behaviour; they run
+//! in the order listed below, after which the process exits with `--exit` or hangs forever.
+//!
+//! - `--pid-file PATH`: append this process's PID (and its grandchildren's) to `PATH`.
+//! - `--ignore-sigterm`: ignore SIGTERM (Unix), so only a hard kill ends the process.
+//! - `--fork N`: start `N` grandchildren that hang, in the same process group.
+//! - `--detach-forks`: start the `--fork` grandchildren in process groups of their own instead
+//!   (Unix), as daemons do.
+//! - `--print TEXT`: write `TEXT` as a line to stdout (repeatable).
+//! - `--spew N`: write `N` numbered lines to each of stdout and stderr.
+//! - `--delay MS`: wait `MS` milliseconds, e.g. to boot slowly before heartbeating.
+//! - `--notify MESSAGE`: send `MESSAGE` (e.g. `READY=1`) to the socket in `NOTIFY_SOCKET`, as
+//!   a systemd service would (Linux).
+//! - `--heartbeat IP:PORT`: send a UDP heartbeat to `IP:PORT` every `--interval` milliseconds
+//!   (default 100).
+//! - `--gen`: send heartbeats as `{"gen":N}`, with the generation from `PING_GUARD_GENERATION`.
+//! - `--print-beats`: write `beat N` to stdout on every heartbeat, with or without `--heartbeat`.
+//! - `--beats N`: stop heartbeating after `N` heartbeats instead of continuing forever.
+//! - `--exit CODE`: exit with `CODE` once done instead of hanging.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::UdpSocket;
+use std::process::{self, Command};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Default)]
+struct Options {
+    pid_file: Option<String>,
+    ignore_sigterm: bool,
+    fork: u32,
+    detach_forks: bool,
+    print: Vec<String>,
+    spew: u32,
+    delay_ms: u64,
+    notify: Option<String>,
+    heartbeat: Option<String>,
+    interval_ms: u64,
+    gen: bool,
+    print_beats: bool,
+    beats: Option<u64>,
+    exit: Option<i32>,
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        interval_ms: 100,
+        ..Options::default()
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--pid-file" => options.pid_file = Some(value()?),
+            "--ignore-sigterm" => options.ignore_sigterm = true,
+            "--fork" => options.fork = number(&value()?)?,
+            "--detach-forks" => options.detach_forks = true,
+            "--print" => options.print.push(value()?),
+            "--spew" => options.spew = number(&value()?)?,
+            "--delay" => options.delay_ms = number(&value()?)?,
+            "--notify" => options.notify = Some(value()?),
+            "--heartbeat" => options.heartbeat = Some(value()?),
+            "--interval" => options.interval_ms = number(&value()?)?,
+            "--gen" => options.gen = true,
+            "--print-beats" => options.print_beats = true,
+            "--beats" => options.beats = Some(number(&value()?)?),
+            "--exit" => options.exit = Some(number(&value()?)?),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid number: {}", value))
+}
+
+fn main() {
+    let options = match parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("test-child: {}", e);
+            process::exit(2);
+        }
+    };
+
+    if let Some(path) = &options.pid_file {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .expect("open PID file");
+        writeln!(file, "{}", process::id()).expect("write PID file");
+    }
+
+    #[cfg(unix)]
+    if options.ignore_sigterm {
+        // SAFETY: installs a disposition, no handler code runs.
+        unsafe { libc::signal(libc::SIGTERM, libc::SIG_IGN) };
+    }
+
+    for _ in 0..options.fork {
+        let mut command = Command::new(std::env::current_exe().expect("current executable"));
+        if let Some(path) = &options.pid_file {
+            command.args(["--pid-file", path]);
+        }
+        if options.ignore_sigterm {
+            command.arg("--ignore-sigterm");
+        }
+        #[cfg(unix)]
+        if options.detach_forks {
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+        }
+        // Not waited for: the grandchild outlives this process unless its group is killed.
+        #[allow(clippy::zombie_processes)]
+        command.spawn().expect("spawn grandchild");
+    }
+
+    for text in &options.print {
+        println!("{}", text);
+    }
+    for i in 1..=options.spew {
+        println!("stdout line {}", i);
+        eprintln!("stderr line {}", i);
+    }
+    thread::sleep(Duration::from_millis(options.delay_ms));
+
+    #[cfg(target_os = "linux")]
+    if let Some(message) = &options.notify {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+        let name = std::env::var("NOTIFY_SOCKET").expect("NOTIFY_SOCKET is set");
+        let addr = match name.strip_prefix('@') {
+            Some(abstract_name) => SocketAddr::from_abstract_name(abstract_name),
+            None => SocketAddr::from_pathname(&name),
+        }
+        .expect("valid NOTIFY_SOCKET");
+        let socket = UnixDatagram::unbound().expect("create notify socket");
+        socket
+            .send_to_addr(message.as_bytes(), &addr)
+            .expect("send to NOTIFY_SOCKET");
+    }
+
+    if options.heartbeat.is_some() || options.print_beats {
+        let socket = options.heartbeat.as_ref().map(|addr| {
+            let socket = UdpSocket::bind("0.0.0.0:0").expect("bind heartbeat socket");
+            socket.connect(addr).expect("resolve heartbeat address");
+            socket
+        });
+        let payload = match std::env::var("PING_GUARD_GENERATION") {
+            Ok(gen) if options.gen => format!("{{\"gen\":{}}}", gen),
+            _ => "ping".to_string(),
+        };
+        let mut beat = 0;
+        while options.beats.is_none_or(|beats| beat < beats) {
+            beat += 1;
+            if let Some(socket) = &socket {
+                // Fails while nothing listens on the port yet; the next one may land.
+                let _ = socket.send(payload.as_bytes());
+            }
+            if options.print_beats {
+                println!("beat {}", beat);
+            }
+            thread::sleep(Duration::from_millis(options.interval_ms));
+        }
+    }
+
+    match options.exit {
+        Some(code) => process::exit(code),
+        None => loop {
+            thread::park();
+        },
+    }
+}
@@ -0,0 +1,2493 @@
+//! End-to-end tests: run the ping-guard binary against the `test-child` helper and check how it
+//! supervises it. The watchdog logs with `--log-format json`, so the exit record tells why it
+//! exited.
+
+use std::io::Read;
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::OnceLock;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long any single watchdog run may take before the test gives up on it.
+const RUN_LIMIT: Duration = Duration::from_secs(30);
+
+/// Builds the helper once per test run. Cargo only builds the package under test for its
+/// integration tests, so the helper is built here, into a target directory of its own so the
+/// build does not wait on the lock the outer `cargo test` holds.
+fn test_child() -> &'static Path {
+    static PATH: OnceLock<PathBuf> = OnceLock::new();
+    PATH.get_or_init(|| {
+        let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("test-child");
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--quiet", "-p", "test-child", "--target-dir"])
+            .arg(&target_dir)
+            .status()
+            .expect("run cargo build for test-child");
+        assert!(status.success(), "building test-child failed");
+        target_dir
+            .join("debug")
+            .join(format!("test-child{}", std::env::consts::EXE_SUFFIX))
+    })
+}
+
+/// A loopback port that was free a moment ago.
+fn free_port() -> u16 {
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("bind probe socket");
+    socket.local_addr().unwrap().port()
+}
+
+/// A fresh path for the helper's PID file.
+fn pid_file(test: &str) -> PathBuf {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.pids", test));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// PIDs the helper and its grandchildren wrote, waiting until there are `count` of them.
+fn read_pids(path: &Path, count: usize) -> Vec<u32> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let pids: Vec<u32> = std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.parse().ok())
+            .collect();
+        if pids.len() >= count {
+            return pids;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "only {} of {} PIDs were written",
+            pids.len(),
+            count
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    // SAFETY: signal 0 only checks that the process exists.
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+#[cfg(windows)]
+fn is_running(pid: u32) -> bool {
+    let output = Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .expect("run tasklist");
+    String::from_utf8_lossy(&output.stdout).contains(&pid.to_string())
+}
+
+/// Asserts that every process in `pids` is gone, allowing a moment for orphans to be reaped.
+fn assert_all_killed(pids: &[u32]) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while pids.iter().any(|&pid| is_running(pid)) {
+        let running: Vec<_> = pids.iter().filter(|&&pid| is_running(pid)).collect();
+        assert!(
+            Instant::now() < deadline,
+            "processes {:?} are still running",
+            running
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// A running watchdog with its output being collected.
+struct Watchdog {
+    process: Child,
+    stdout: JoinHandle<String>,
+    stderr: JoinHandle<String>,
+}
+
+/// What a watchdog run left behind.
+struct Run {
+    code: Option<i32>,
+    stdout: String,
+    stderr: String,
+}
+
+impl Watchdog {
+    /// Starts ping-guard listening on `port`, with `options`, supervising the helper run with
+    /// `child_args`.
+    fn start(port: u16, options: &[&str], child_args: &[&str]) -> Self {
+        Self::start_with_env(port, &[], options, child_args)
+    }
+
+    /// Like [`Watchdog::start`], with `env` added to ping-guard's environment.
+    fn start_with_env(
+        port: u16,
+        env: &[(&str, &str)],
+        options: &[&str],
+        child_args: &[&str],
+    ) -> Self {
+        let mut command = Self::command(port, options);
+        command
+            .envs(env.iter().copied())
+            .arg(test_child())
+            .arg("--")
+            .args(child_args);
+        Self::spawn(command)
+    }
+
+    /// Starts ping-guard listening on `port`, with `options`, supervising the running process
+    /// `pid` (`--attach-pid`).
+    #[cfg(unix)]
+    fn attach(port: u16, options: &[&str], pid: u32) -> Self {
+        let mut command = Self::command(port, options);
+        command.args(["--attach-pid", &pid.to_string()]);
+        Self::spawn(command)
+    }
+
+    /// Starts ping-guard with `socket` passed in by socket activation, as systemd would, with
+    /// `options`, supervising the helper run with `child_args`.
+    #[cfg(unix)]
+    fn activated(socket: &UdpSocket, options: &[&str], child_args: &[&str]) -> Self {
+        use std::os::fd::AsRawFd;
+        use std::os::unix::process::CommandExt;
+
+        let fd = socket.as_raw_fd();
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", "LISTEN_PID=$$ LISTEN_FDS=1 exec \"$0\" \"$@\""])
+            .arg(env!("CARGO_BIN_EXE_ping-guard"))
+            .args(["--log-format", "json"])
+            .args(options)
+            .arg(test_child())
+            .arg("--")
+            .args(child_args);
+        // Safety: only async-signal-safe calls between fork and exec.
+        unsafe {
+            command.pre_exec(move || {
+                if fd == 3 {
+                    // dup2 onto itself would keep close-on-exec.
+                    libc::fcntl(3, libc::F_SETFD, 0);
+                } else if libc::dup2(fd, 3) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Self::spawn(command)
+    }
+
+    fn command(port: u16, options: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_ping-guard"));
+        command
+            .args(["--log-format", "json", "-l", &format!("127.0.0.1:{}", port)])
+            .args(options);
+        command
+    }
+
+    fn spawn(mut command: Command) -> Self {
+        let mut process = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("spawn ping-guard");
+        let stdout = collect(process.stdout.take().unwrap());
+        let stderr = collect(process.stderr.take().unwrap());
+        Watchdog {
+            process,
+            stdout,
+            stderr,
+        }
+    }
+
+    /// Waits for the watchdog to exit on its own.
+    fn finish(mut self) -> Run {
+        let deadline = Instant::now() + RUN_LIMIT;
+        let status = loop {
+            if let Some(status) = self.process.try_wait().expect("wait for ping-guard") {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = self.process.kill();
+                let _ = self.process.wait();
+                panic!(
+                    "ping-guard did not exit within {:?}; output:\n{}{}",
+                    RUN_LIMIT,
+                    self.stdout.join().unwrap(),
+                    self.stderr.join().unwrap()
+                );
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+        Run {
+            code: status.code(),
+            stdout: self.stdout.join().unwrap(),
+            stderr: self.stderr.join().unwrap(),
+        }
+    }
+}
+
+fn collect(mut stream: impl Read + Send + 'static) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = String::new();
+        let _ = stream.read_to_string(&mut output);
+        output
+    })
+}
+
+impl Run {
+    /// The `exit_reason` of the watchdog's exit record.
+    fn exit_reason(&self) -> Option<String> {
+        self.records()
+            .find_map(|record| Some(record.get("exit_reason")?.as_str()?.to_string()))
+    }
+
+    /// Messages the watchdog logged, from both streams.
+    fn messages(&self) -> Vec<String> {
+        self.records()
+            .filter_map(|record| Some(record.get("message")?.as_str()?.to_string()))
+            .collect()
+    }
+
+    fn records(&self) -> impl Iterator<Item = serde_json::Value> + '_ {
+        self.stdout
+            .lines()
+            .chain(self.stderr.lines())
+            .filter_map(|line| serde_json::from_str(line).ok())
+    }
+
+    /// Asserts the watchdog exited with `code` for `reason`.
+    fn assert_exit(&self, code: i32, reason: &str) {
+        assert_eq!(
+            (self.code, self.exit_reason().as_deref()),
+            (Some(code), Some(reason)),
+            "unexpected exit; output:\n{}{}",
+            self.stdout,
+            self.stderr
+        );
+    }
+}
+
+#[test]
+fn timeout_kills_a_child_that_stops_heartbeating() {
+    let port = free_port();
+    let pids = pid_file("timeout");
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let run = Watchdog::start(
+        port,
+        &["-t", "1"],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--heartbeat",
+            &heartbeat,
+            "--beats",
+            "10",
+        ],
+    )
+    .finish();
+    run.assert_exit(1, "timeout");
+    assert_all_killed(&read_pids(&pids, 1));
+}
+
+#[test]
+fn a_child_that_never_heartbeats_is_reported_as_no_heartbeat() {
+    let run = Watchdog::start(free_port(), &["-t", "1"], &[]).finish();
+    run.assert_exit(1, "no_heartbeat");
+}
+
+#[test]
+fn heartbeats_keep_the_child_alive_past_the_timeout() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let started = Instant::now();
+    let run = Watchdog::start(
+        port,
+        &["-t", "1"],
+        &["--heartbeat", &heartbeat, "--beats", "25", "--exit", "0"],
+    )
+    .finish();
+    run.assert_exit(0, "child_exited");
+    assert!(started.elapsed() >= Duration::from_secs(2));
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[test]
+fn counting_suspend_time_leaves_an_awake_timeout_unchanged() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let started = Instant::now();
+    let run = Watchdog::start(
+        port,
+        &["-t", "1", "--suspend-time", "count"],
+        &["--heartbeat", &heartbeat, "--beats", "15"],
+    )
+    .finish();
+    run.assert_exit(1, "timeout");
+    assert!(started.elapsed() >= Duration::from_secs(2));
+    assert!(
+        !run.messages()
+            .iter()
+            .any(|message| message.starts_with("The system was suspended")),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[test]
+fn sub_second_timeouts_are_kept_by_frequent_heartbeats() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let run = Watchdog::start(
+        port,
+        &["-t", "500ms"],
+        &["--heartbeat", &heartbeat, "--beats", "15", "--exit", "0"],
+    )
+    .finish();
+    run.assert_exit(0, "child_exited");
+
+    let run = Watchdog::start(free_port(), &["-t", "500ms"], &[]).finish();
+    run.assert_exit(1, "no_heartbeat");
+}
+
+#[test]
+fn the_startup_timeout_applies_until_the_first_heartbeat() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let started = Instant::now();
+    let run = Watchdog::start(
+        port,
+        &["-t", "500ms", "--startup-timeout", "5s"],
+        &["--delay", "1500", "--heartbeat", &heartbeat, "--beats", "5"],
+    )
+    .finish();
+    run.assert_exit(1, "timeout");
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(1900), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+}
+
+#[test]
+fn arm_on_first_ping_waits_for_the_first_heartbeat() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let started = Instant::now();
+    let run = Watchdog::start(
+        port,
+        &["-t", "500ms", "--arm-on-first-ping"],
+        &["--delay", "1500", "--heartbeat", &heartbeat, "--beats", "5"],
+    )
+    .finish();
+    run.assert_exit(1, "timeout");
+    assert!(started.elapsed() >= Duration::from_millis(1900));
+
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "500ms", "--arm-on-first-ping"],
+        &["--delay", "1500", "--exit", "0"],
+    )
+    .finish();
+    run.assert_exit(0, "child_exited");
+}
+
+#[test]
+fn spawn_on_first_ping_starts_the_child_on_the_first_heartbeat() {
+    let port = free_port();
+    let pids = pid_file("spawn_on_first_ping");
+    let watchdog = Watchdog::start(
+        port,
+        &["-t", "2s", "--spawn-on-first-ping"],
+        &["--pid-file", pids.to_str().unwrap(), "--exit", "0"],
+    );
+    thread::sleep(Duration::from_millis(700));
+    assert!(
+        !pids.exists(),
+        "the child was started before the first heartbeat"
+    );
+
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.send_to(b"go", ("127.0.0.1", port)).unwrap();
+    let run = watchdog.finish();
+    run.assert_exit(0, "child_exited");
+    read_pids(&pids, 1);
+    assert!(run
+        .messages()
+        .iter()
+        .any(|message| message.starts_with("First heartbeat received from 127.0.0.1:")));
+}
+
+#[test]
+fn ready_regex_arms_the_timeout_once_the_child_is_ready() {
+    let started = Instant::now();
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "500ms", "--ready-regex", "^beat 1$"],
+        &["--delay", "1500", "--print-beats", "--beats", "1"],
+    )
+    .finish();
+    run.assert_exit(1, "no_heartbeat");
+    assert!(started.elapsed() >= Duration::from_millis(1900));
+    assert!(run.messages().iter().any(|message| message.ends_with(
+        "is ready; its output matched --ready-regex: 'beat 1'. The timeout is now armed."
+    )));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn child_readiness_is_passed_on_to_systemd() {
+    use std::os::unix::net::UnixDatagram;
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("notify.sock");
+    let _ = std::fs::remove_file(&path);
+    let systemd = UnixDatagram::bind(&path).unwrap();
+    let run = Watchdog::start_with_env(
+        free_port(),
+        &[("NOTIFY_SOCKET", path.to_str().unwrap())],
+        &["-t", "2s"],
+        &[
+            "--notify",
+            "STATUS=warming up\nMAINPID=1\nREADY=1",
+            "--print-beats",
+            "--beats",
+            "3",
+            "--exit",
+            "0",
+        ],
+    )
+    .finish();
+    run.assert_exit(0, "child_exited");
+
+    systemd.set_nonblocking(true).unwrap();
+    let mut buf = [0; 256];
+    let mut received = Vec::new();
+    while let Ok(len) = systemd.recv(&mut buf) {
+        received.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+    }
+    assert_eq!(received, ["STATUS=warming up", "READY=1"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn diagnose_exec_runs_against_the_child_before_it_is_killed() {
+    let pids = pid_file("diagnose_exec");
+    let run = Watchdog::start(
+        free_port(),
+        &[
+            "-t",
+            "500ms",
+            "--diagnose-exec",
+            "kill -0 {pid} && echo \"alive $PING_GUARD_CHILD_PID\"; echo done >&2",
+        ],
+        &["--pid-file", pids.to_str().unwrap()],
+    )
+    .finish();
+    run.assert_exit(1, "no_heartbeat");
+    let pid = read_pids(&pids, 1)[0];
+    let messages = run.messages();
+    let diagnosis: Vec<&String> = messages
+        .iter()
+        .filter(|message| message.starts_with("[diagnose] "))
+        .collect();
+    assert_eq!(
+        diagnosis,
+        [&format!("[diagnose] alive {}", pid), "[diagnose] done"],
+        "{:?}",
+        messages
+    );
+    assert_all_killed(&[pid]);
+}
+
+#[cfg(unix)]
+#[test]
+fn on_timeout_exec_describes_the_missed_deadline() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let pids = pid_file("on_timeout_exec");
+    let context = Path::new(env!("CARGO_TARGET_TMPDIR")).join("on_timeout_exec.env");
+    let _ = std::fs::remove_file(&context);
+    let run = Watchdog::start(
+        port,
+        &[
+            "-t",
+            "500ms",
+            "--on-timeout-exec",
+            &format!(
+                "sleep 0.2; echo \"$PING_GUARD_ACTION $PING_GUARD_CHILD_PID $PING_GUARD_TIMEOUT_MS $PING_GUARD_LAST_HEARTBEAT_MS\" > {}",
+                context.display()
+            ),
+        ],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--heartbeat",
+            &heartbeat,
+            "--beats",
+            "3",
+        ],
+    )
+    .finish();
+    run.assert_exit(1, "timeout");
+    let pid = read_pids(&pids, 1)[0];
+    // Written only after the watchdog has killed the child, so it was waited for.
+    let context = std::fs::read_to_string(&context).expect("--on-timeout-exec wrote its file");
+    let fields: Vec<&str> = context.split_whitespace().collect();
+    assert_eq!(
+        fields[..3],
+        ["kill", &pid.to_string(), "500"],
+        "{:?}",
+        fields
+    );
+    let last_heartbeat: u64 = fields[3].parse().unwrap();
+    assert!((500..1500).contains(&last_heartbeat), "{}", last_heartbeat);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn descendants_that_left_the_process_group_are_killed_too() {
+    let pids = pid_file("detached_descendants");
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "500ms"],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--fork",
+            "2",
+            "--detach-forks",
+        ],
+    )
+    .finish();
+    run.assert_exit(1, "no_heartbeat");
+    let pids = read_pids(&pids, 3);
+    assert_all_killed(&pids);
+    assert!(
+        run.messages()
+            .iter()
+            .any(|message| message.starts_with(&format!(
+                "Process group {} and its descendants are gone",
+                pids[0]
+            ))),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn attached_process_group_is_killed_on_timeout() {
+    use std::os::unix::process::CommandExt;
+
+    let pids = pid_file("attached");
+    let mut process = Command::new(test_child())
+        .args(["--pid-file", pids.to_str().unwrap(), "--fork", "1"])
+        .process_group(0)
+        .spawn()
+        .expect("spawn test-child");
+    let pids = read_pids(&pids, 2);
+    let run = Watchdog::attach(free_port(), &["-t", "500ms"], process.id()).finish();
+    run.assert_exit(1, "no_heartbeat");
+    // The attached process is this test's child, so it is only gone once reaped here.
+    let _ = process.wait();
+    assert_all_killed(&pids);
+    assert!(
+        run.messages()
+            .iter()
+            .any(|message| message == &format!("Sent SIGKILL to process group {}.", pids[0])),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn subreaper_kills_what_an_exited_child_left_behind() {
+    let pids = pid_file("subreaper");
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "5", "--subreaper"],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--fork",
+            "1",
+            "--detach-forks",
+            "--delay",
+            "300",
+            "--exit",
+            "0",
+        ],
+    )
+    .finish();
+    run.assert_exit(0, "child_exited");
+    let pids = read_pids(&pids, 2);
+    assert_all_killed(&pids);
+    assert!(
+        run.messages().iter().any(|message| message
+            == &format!(
+                "The child left adopted processes running: PIDs {}; killing them.",
+                pids[1]
+            )),
+        "{:?}",
+        run.messages()
+    );
+}
+
+// Needs a writable cgroup v2 hierarchy, i.e. root or a delegated cgroup.
+#[cfg(target_os = "linux")]
+#[test]
+fn cgroup_kills_descendants_and_is_removed() {
+    let pids = pid_file("cgroup");
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "500ms", "--cgroup"],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--fork",
+            "2",
+            "--detach-forks",
+        ],
+    )
+    .finish();
+    run.assert_exit(1, "no_heartbeat");
+    let pids = read_pids(&pids, 3);
+    assert_all_killed(&pids);
+    let messages = run.messages();
+    let created = messages
+        .iter()
+        .find_map(|message| message.strip_prefix("Running the child in cgroup "))
+        .expect("cgroup created")
+        .trim_end_matches('.');
+    assert!(
+        messages.contains(&format!("Killed cgroup {}.", created)),
+        "{:?}",
+        messages
+    );
+    assert!(
+        messages.contains(&format!("Removed cgroup {}.", created)),
+        "{:?}",
+        messages
+    );
+    assert!(!std::path::Path::new(created).exists());
+}
+
+#[test]
+fn chdir_runs_the_child_in_another_directory() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let pids = pid_file("chdir");
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "500ms", "--chdir", dir.to_str().unwrap()],
+        // A relative path, written where the child runs.
+        &["--pid-file", pids.file_name().unwrap().to_str().unwrap()],
+    )
+    .finish();
+    run.assert_exit(1, "no_heartbeat");
+    read_pids(&pids, 1);
+    let messages = run.messages();
+    assert!(
+        messages.contains(&format!("Running the child in {}.", dir.display())),
+        "{:?}",
+        messages
+    );
+}
+
+// Needs root, to change groups. The user stays root so the child can still be found under
+// the target directory.
+#[cfg(unix)]
+#[test]
+fn group_drops_the_childs_group_before_it_starts() {
+    use std::os::unix::fs::MetadataExt;
+    let pids = pid_file("group");
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "500ms", "--group", "65534"],
+        &["--pid-file", pids.to_str().unwrap()],
+    )
+    .finish();
+    run.assert_exit(1, "no_heartbeat");
+    read_pids(&pids, 1);
+    // The child wrote its PID file with its own group.
+    assert_eq!(std::fs::metadata(&pids).unwrap().gid(), 65534);
+    let messages = run.messages();
+    assert!(
+        messages.contains(&"Running the child as gid 65534.".to_string()),
+        "{:?}",
+        messages
+    );
+}
+
+#[test]
+fn restart_options_are_rejected_before_the_child_is_spawned() {
+    for (test, options) in [
+        (
+            "restart_stdin_pipe",
+            &["--restart", "always", "--stdin-pipe"][..],
+        ),
+        ("max_restarts_alone", &["--max-restarts", "3"][..]),
+    ] {
+        let pids = pid_file(test);
+        let mut options = options.to_vec();
+        options.extend(["-t", "5"]);
+        let run = Watchdog::start(
+            free_port(),
+            &options,
+            &["--pid-file", pids.to_str().unwrap()],
+        )
+        .finish();
+        run.assert_exit(1, "startup_error");
+        assert!(!pids.exists(), "{:?} started a child", options);
+    }
+}
+
+#[test]
+fn each_missed_interval_is_reported_before_the_timeout() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let run = Watchdog::start(
+        port,
+        &["--interval", "400ms", "--max-misses", "3"],
+        &["--heartbeat", &heartbeat, "--beats", "5"],
+    )
+    .finish();
+    run.assert_exit(1, "timeout");
+    let messages = run.messages();
+    let misses: Vec<&String> = messages
+        .iter()
+        .filter(|message| message.starts_with("Missed heartbeat "))
+        .collect();
+    assert_eq!(misses.len(), 2, "{:?}", misses);
+    assert!(
+        misses[0].starts_with("Missed heartbeat 1 of 3"),
+        "{:?}",
+        misses
+    );
+    assert!(
+        messages
+            .iter()
+            .any(|message| message.contains("(3 missed heartbeats at 400.00ms intervals)")),
+        "{:?}",
+        messages
+    );
+}
+
+#[test]
+fn max_runtime_terminates_a_heartbeating_child() {
+    let port = free_port();
+    let pids = pid_file("max-runtime");
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let started = Instant::now();
+    let run = Watchdog::start(
+        port,
+        &["-t", "1", "--max-runtime", "1500ms"],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--heartbeat",
+            &heartbeat,
+        ],
+    )
+    .finish();
+    run.assert_exit(5, "max_runtime");
+    assert!(started.elapsed() >= Duration::from_millis(1500));
+    assert_all_killed(&read_pids(&pids, 1));
+}
+
+#[test]
+fn on_timeout_restart_respawns_the_child() {
+    let port = free_port();
+    let pids = pid_file("on-timeout-restart");
+    let run = Watchdog::start(
+        port,
+        &[
+            "-t",
+            "700ms",
+            "--on-timeout",
+            "restart",
+            "--max-runtime",
+            "2s",
+        ],
+        &["--pid-file", pids.to_str().unwrap()],
+    )
+    .finish();
+    run.assert_exit(5, "max_runtime");
+    let pids = read_pids(&pids, 2);
+    assert!(pids.len() >= 2, "{:?}", pids);
+    assert_all_killed(&pids);
+    assert!(
+        run.messages()
+            .iter()
+            .any(|message| message.starts_with("Child process restarted (PID: ")),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[test]
+fn start_first_restart_overlaps_the_replacement_with_the_old_child() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let pids = pid_file("start-first");
+    let run = Watchdog::start(
+        port,
+        &[
+            "-t",
+            "700ms",
+            "--on-timeout",
+            "restart",
+            "--restart-strategy",
+            "start-first",
+            "--max-runtime",
+            "2s",
+        ],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--heartbeat",
+            &heartbeat,
+            "--gen",
+            "--interval",
+            "50",
+            "--beats",
+            "4",
+        ],
+    )
+    .finish();
+    run.assert_exit(5, "max_runtime");
+    let pids = read_pids(&pids, 2);
+    assert_all_killed(&pids);
+    let messages = run.messages();
+    let position = |matches: &dyn Fn(&str) -> bool| {
+        messages
+            .iter()
+            .position(|message| matches(message))
+            .unwrap_or_else(|| panic!("{:?}", messages))
+    };
+    let started = position(&|message| message.starts_with("Replacement child process started"));
+    let heard = position(&|message| message.ends_with("sent its first heartbeat."));
+    let terminated = position(&|message| {
+        message == format!("Terminating child process group (PID: {})...", pids[0])
+    });
+    assert!(started < heard && heard < terminated, "{:?}", messages);
+}
+
+#[test]
+fn restart_on_failure_respawns_a_failed_child_only() {
+    let pids = pid_file("restart-on-failure");
+    let run = Watchdog::start(
+        free_port(),
+        &[
+            "-t",
+            "5",
+            "--restart",
+            "on-failure",
+            "--restart-backoff",
+            "0",
+            "--max-runtime",
+            "1500ms",
+        ],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--delay",
+            "300",
+            "--exit",
+            "3",
+        ],
+    )
+    .finish();
+    run.assert_exit(5, "max_runtime");
+    assert_all_killed(&read_pids(&pids, 3));
+    assert!(
+        run.messages()
+            .iter()
+            .any(|message| message.ends_with("Restarting it (--restart on-failure).")),
+        "{:?}",
+        run.messages()
+    );
+
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "5", "--restart", "on-failure"],
+        &["--exit", "0"],
+    )
+    .finish();
+    run.assert_exit(0, "child_exited");
+}
+
+#[test]
+fn restart_respawns_a_child_that_exits_while_a_trigger_fires() {
+    // The dead line comes in just before the child exits, so the trigger sees the exit.
+    let pids = pid_file("restart-on-trigger");
+    let run = Watchdog::start(
+        free_port(),
+        &[
+            "-t",
+            "5",
+            "--dead-regex",
+            "FATAL",
+            "--restart",
+            "on-failure",
+            "--restart-backoff",
+            "0",
+            "--max-runtime",
+            "1500ms",
+        ],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--print",
+            "FATAL: out of disk",
+            "--delay",
+            "20",
+            "--exit",
+            "3",
+        ],
+    )
+    .finish();
+    run.assert_exit(5, "max_runtime");
+    assert_all_killed(&read_pids(&pids, 2));
+    assert!(
+        run.messages()
+            .iter()
+            .any(|message| message.ends_with("Restarting it (--restart on-failure).")),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn restart_still_notices_when_every_heartbeat_source_stops() {
+    // A socket-activated listener, connected to a port that is closed at first: the ICMP error
+    // its packet draws there fails the watchdog's next receive, which stops the listener.
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let peer = format!("127.0.0.1:{}", free_port());
+    socket.connect(&peer).unwrap();
+    let pids = pid_file("sources-lost-restart");
+    let watchdog = Watchdog::activated(
+        &socket,
+        &["-t", "5", "--restart", "always", "--dead-regex", "FATAL"],
+        &["--pid-file", pids.to_str().unwrap()],
+    );
+    let pids = read_pids(&pids, 1);
+    socket.send(b"ping").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    // Only a packet wakes the listener up to receive.
+    let peer = UdpSocket::bind(&peer).unwrap();
+    peer.send_to(b"ping", socket.local_addr().unwrap()).unwrap();
+    let run = watchdog.finish();
+    run.assert_exit(3, "heartbeat_sources_lost");
+    assert_all_killed(&pids);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn low_power_still_notices_when_every_heartbeat_source_stops() {
+    // As above, with the ICMP error already waiting when the deferred socket is first read.
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.connect(("127.0.0.1", free_port())).unwrap();
+    socket.send(b"ping").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let pids = pid_file("sources-lost-low-power");
+    let run = Watchdog::activated(
+        &socket,
+        &["-t", "1", "--low-power"],
+        &["--pid-file", pids.to_str().unwrap()],
+    )
+    .finish();
+    run.assert_exit(3, "heartbeat_sources_lost");
+    assert_all_killed(&read_pids(&pids, 1));
+}
+
+#[test]
+fn child_exit_status_is_passed_on() {
+    let run = Watchdog::start(free_port(), &["-t", "5"], &["--exit", "3"]).finish();
+    run.assert_exit(3, "child_exited");
+
+    #[cfg(unix)]
+    {
+        let pids = pid_file("exit-status");
+        let watchdog = Watchdog::start(
+            free_port(),
+            &["-t", "5"],
+            &["--pid-file", pids.to_str().unwrap(), "--delay", "5000"],
+        );
+        let pid = read_pids(&pids, 1)[0];
+        // SAFETY: plain kill(2) of the child the watchdog started.
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+        watchdog
+            .finish()
+            .assert_exit(128 + libc::SIGKILL, "child_exited");
+    }
+}
+
+#[test]
+fn exit_codes_are_classified_for_restarts_and_the_exit_status() {
+    let run = Watchdog::start(
+        free_port(),
+        &[
+            "-t",
+            "5",
+            "--restart",
+            "on-failure",
+            "--success-codes",
+            "0,3",
+        ],
+        &["--exit", "3"],
+    )
+    .finish();
+    run.assert_exit(0, "child_exited");
+
+    let pids = pid_file("no-restart-codes");
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "5", "--restart", "always", "--no-restart-codes", "78"],
+        &["--pid-file", pids.to_str().unwrap(), "--exit", "78"],
+    )
+    .finish();
+    run.assert_exit(78, "child_exited");
+    assert_eq!(read_pids(&pids, 1).len(), 1);
+}
+
+#[test]
+fn restart_backoff_doubles_up_to_its_maximum() {
+    let port = free_port();
+    let pids = pid_file("restart-backoff");
+    let watchdog = Watchdog::start(
+        port,
+        &[
+            "-t",
+            "5",
+            "--restart",
+            "always",
+            "--restart-backoff",
+            "200ms",
+            "--restart-backoff-max",
+            "500ms",
+            "--restart-jitter",
+            "0",
+            "--max-runtime",
+            "3s",
+            "--status-query",
+        ],
+        &["--pid-file", pids.to_str().unwrap(), "--exit", "0"],
+    );
+    thread::sleep(Duration::from_millis(700));
+    let status = request(port, b"STATUS?");
+    let run = watchdog.finish();
+    run.assert_exit(5, "max_runtime");
+    assert!(
+        status["restart_backoff"]["attempt"].as_u64() >= Some(2),
+        "{}",
+        status
+    );
+    let messages = run.messages();
+    let delays: Vec<&str> = messages
+        .iter()
+        .filter_map(|message| message.split(" Restarting it in ").nth(1))
+        .collect();
+    assert_eq!(
+        delays[..4],
+        [
+            "200.00ms (--restart always).",
+            "400.00ms (--restart always).",
+            "500.00ms (--restart always).",
+            "500.00ms (--restart always).",
+        ],
+        "{:?}",
+        messages
+    );
+    assert_all_killed(&read_pids(&pids, 5));
+}
+
+#[test]
+fn restart_backoff_starts_over_after_min_uptime() {
+    // Delays before each restart of a child that heartbeats for half a second, then fails.
+    let delays = |min_uptime: &str| {
+        let port = free_port();
+        let heartbeat = format!("127.0.0.1:{}", port);
+        let run = Watchdog::start(
+            port,
+            &[
+                "-t",
+                "5",
+                "--restart",
+                "on-failure",
+                "--restart-backoff",
+                "300ms",
+                "--restart-jitter",
+                "0",
+                "--min-uptime",
+                min_uptime,
+                "--max-runtime",
+                "2500ms",
+            ],
+            &[
+                "--heartbeat",
+                &heartbeat,
+                "--interval",
+                "50",
+                "--beats",
+                "10",
+                "--exit",
+                "3",
+            ],
+        )
+        .finish();
+        run.assert_exit(5, "max_runtime");
+        run.messages()
+            .iter()
+            .filter_map(|message| message.split(" Restarting it in ").nth(1))
+            .map(|delay| delay.split(' ').next().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    let settled = delays("200ms");
+    assert!(settled.len() >= 2, "{:?}", settled);
+    assert!(
+        settled.iter().all(|delay| delay == "300.00ms"),
+        "{:?}",
+        settled
+    );
+    let unsettled = delays("1h");
+    assert_eq!(unsettled[..2], ["300.00ms", "600.00ms"], "{:?}", unsettled);
+}
+
+#[test]
+fn max_restarts_gives_up_on_a_crash_loop() {
+    let pids = pid_file("crash-loop");
+    let run = Watchdog::start(
+        free_port(),
+        &[
+            "-t",
+            "5",
+            "--restart",
+            "on-failure",
+            "--restart-backoff",
+            "0",
+            "--max-restarts",
+            "3",
+            "--restart-window",
+            "10s",
+        ],
+        &["--pid-file", pids.to_str().unwrap(), "--exit", "1"],
+    )
+    .finish();
+    run.assert_exit(6, "crash_loop");
+    assert_eq!(read_pids(&pids, 4).len(), 4);
+    assert!(
+        run.messages().iter().any(|message| message
+            == "Crash loop: the child exited again after 3 restarts within 10s. Giving up on it (--max-restarts)."),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[test]
+fn observe_only_reports_but_never_kills() {
+    let port = free_port();
+    let run = Watchdog::start(
+        port,
+        &["-t", "300ms", "--max-runtime", "800ms", "--observe-only"],
+        &["--delay", "1500", "--exit", "0"],
+    )
+    .finish();
+    run.assert_exit(0, "child_exited");
+    let messages = run.messages();
+    let timeouts = messages
+        .iter()
+        .filter(|message| message.ends_with(". Leaving child running."))
+        .count();
+    assert_eq!(timeouts, 1, "{:?}", messages);
+    assert!(
+        messages
+            .iter()
+            .any(|message| message.ends_with("Observe-only: leaving it running.")),
+        "{:?}",
+        messages
+    );
+}
+
+#[test]
+fn a_deadline_warning_comes_once_before_the_timeout() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let run = Watchdog::start(
+        port,
+        &["-t", "1", "--warn-at", "50"],
+        &["--heartbeat", &heartbeat, "--beats", "5"],
+    )
+    .finish();
+    run.assert_exit(1, "timeout");
+    let warnings: Vec<String> = run
+        .messages()
+        .into_iter()
+        .filter(|message| message.starts_with("Deadline warning: "))
+        .collect();
+    assert_eq!(warnings.len(), 1, "{:?}", warnings);
+    assert!(warnings[0].contains("50% of the timeout"), "{:?}", warnings);
+}
+
+#[cfg(unix)]
+#[test]
+fn escalation_steps_are_taken_in_order_before_the_timeout() {
+    let port = free_port();
+    let run = Watchdog::start(
+        port,
+        &[
+            "-t",
+            "5",
+            "--escalate",
+            "1s=signal:TERM",
+            "--escalate",
+            "500ms=warn",
+        ],
+        &[],
+    )
+    .finish();
+    // The helper does not handle SIGTERM, so the second step ends it well before the timeout,
+    // and the watchdog passes on 128 + SIGTERM.
+    run.assert_exit(143, "child_exited");
+    let steps: Vec<String> = run
+        .messages()
+        .into_iter()
+        .filter(|message| message.starts_with("Escalation step "))
+        .collect();
+    assert_eq!(steps.len(), 2, "{:?}", steps);
+    assert!(
+        steps[0].starts_with("Escalation step 1 of 2"),
+        "{:?}",
+        steps
+    );
+    assert!(
+        steps[1].contains("sent SIGTERM to child process"),
+        "{:?}",
+        steps
+    );
+}
+
+#[test]
+fn an_adaptive_timeout_is_learned_from_the_heartbeat_cadence() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let started = Instant::now();
+    let run = Watchdog::start(
+        port,
+        &[
+            "-t",
+            "10",
+            "--adaptive-timeout",
+            "--adaptive-min",
+            "300ms",
+            "--adaptive-max",
+            "5s",
+        ],
+        &["--heartbeat", &heartbeat, "--beats", "30"],
+    )
+    .finish();
+    run.assert_exit(1, "timeout");
+    let elapsed = started.elapsed();
+    assert!(elapsed < Duration::from_secs(9), "{:?}", elapsed);
+    assert!(
+        run.messages()
+            .iter()
+            .any(|message| message.starts_with("Adaptive timeout is now ")),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[test]
+fn incident_report_is_written_before_a_timeout_kill() {
+    let dir = std::env::temp_dir().join(format!("ping-guard-incidents-{}", free_port()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let run = Watchdog::start(
+        free_port(),
+        &[
+            "-t",
+            "500ms",
+            "--incident-report",
+            dir.to_str().unwrap(),
+            "--incident-output-lines",
+            "2",
+        ],
+        &["--print", "first", "--print", "second", "--print", "third"],
+    )
+    .finish();
+    run.assert_exit(1, "no_heartbeat");
+    let reports: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(reports.len(), 1, "{:?}", reports);
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&reports[0]).unwrap()).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    assert_eq!(report["reason"], "no_heartbeat");
+    assert_eq!(report["action"], "kill");
+    assert_eq!(report["heartbeats"]["packets"]["heartbeats"], 0);
+    assert_eq!(
+        report["output"],
+        serde_json::json!([
+            { "stream": "stdout", "line": "second" },
+            { "stream": "stdout", "line": "third" },
+        ])
+    );
+    #[cfg(target_os = "linux")]
+    assert!(report["child"]["threads"]
+        .as_array()
+        .is_some_and(|threads| !threads.is_empty()));
+}
+
+#[test]
+fn timeout_kills_the_whole_process_tree() {
+    let pids = pid_file("tree");
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "1"],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--ignore-sigterm",
+            "--fork",
+            "2",
+        ],
+    )
+    .finish();
+    run.assert_exit(1, "no_heartbeat");
+    // On Windows, the grandchildren are killed with the child's Job Object.
+    assert_all_killed(&read_pids(&pids, 3));
+}
+
+#[cfg(windows)]
+#[test]
+fn timeout_kills_the_whole_process_tree_without_a_job_object() {
+    let pids = pid_file("tree_no_job");
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "1", "--no-job-object"],
+        &["--pid-file", pids.to_str().unwrap(), "--fork", "2"],
+    )
+    .finish();
+    run.assert_exit(1, "no_heartbeat");
+    assert_all_killed(&read_pids(&pids, 3));
+    assert!(
+        run.messages()
+            .iter()
+            .any(|message| message.starts_with("Terminated 2 descendant process(es)")),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn kill_grace_gives_the_child_time_before_the_group_is_killed() {
+    let pids = pid_file("kill-grace");
+    let started = Instant::now();
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "500ms", "--kill-grace", "1s", "--stop-signal", "TERM"],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--ignore-sigterm",
+            "--fork",
+            "1",
+        ],
+    )
+    .finish();
+    run.assert_exit(1, "no_heartbeat");
+    assert!(started.elapsed() >= Duration::from_millis(1500));
+    assert_all_killed(&read_pids(&pids, 2));
+    assert!(
+        run.messages()
+            .iter()
+            .any(|message| message == "Child process did not exit within the grace period."),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn shutdown_signal_kills_the_whole_process_tree() {
+    let port = free_port();
+    let pids = pid_file("shutdown");
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let watchdog = Watchdog::start(
+        port,
+        &["-t", "5"],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--ignore-sigterm",
+            "--fork",
+            "2",
+            "--heartbeat",
+            &heartbeat,
+        ],
+    );
+    let pids = read_pids(&pids, 3);
+    // SAFETY: plain kill(2) of the watchdog we started.
+    unsafe { libc::kill(watchdog.process.id() as libc::pid_t, libc::SIGTERM) };
+    let run = watchdog.finish();
+    run.assert_exit(0, "shutdown");
+    assert_all_killed(&pids);
+}
+
+#[cfg(unix)]
+#[test]
+fn no_kill_on_exit_leaves_the_child_running_on_shutdown() {
+    let port = free_port();
+    let pids = pid_file("no_kill_on_exit");
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let mut watchdog = Watchdog::start(
+        port,
+        &["-t", "5", "--no-kill-on-exit"],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--fork",
+            "1",
+            "--heartbeat",
+            &heartbeat,
+        ],
+    );
+    let pids = read_pids(&pids, 2);
+    // SAFETY: plain kill(2) of the watchdog we started.
+    unsafe { libc::kill(watchdog.process.id() as libc::pid_t, libc::SIGTERM) };
+    let deadline = Instant::now() + RUN_LIMIT;
+    while watchdog.process.try_wait().unwrap().is_none() {
+        assert!(Instant::now() < deadline, "ping-guard did not exit");
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert!(pids.iter().all(|&pid| is_running(pid)), "{:?}", pids);
+    // The child shares the watchdog's output, so the run is only over once it is gone.
+    for &pid in &pids {
+        // SAFETY: plain kill(2) of the helper processes.
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+    }
+    let run = watchdog.finish();
+    run.assert_exit(0, "shutdown");
+    assert_all_killed(&pids);
+}
+
+#[cfg(unix)]
+#[test]
+fn forwarded_signal_reaches_the_child_instead_of_shutting_down() {
+    let port = free_port();
+    let pids = pid_file("forward_signal");
+    let watchdog = Watchdog::start(
+        port,
+        &["-t", "5", "--forward-signal", "HUP"],
+        &["--pid-file", pids.to_str().unwrap()],
+    );
+    read_pids(&pids, 1);
+    thread::sleep(Duration::from_millis(200));
+    // SAFETY: plain kill(2) of the watchdog we started.
+    unsafe { libc::kill(watchdog.process.id() as libc::pid_t, libc::SIGHUP) };
+    let run = watchdog.finish();
+    // The helper does not handle SIGHUP, so it dies of it and the watchdog passes that on.
+    run.assert_exit(128 + libc::SIGHUP, "child_exited");
+    assert!(
+        run.messages()
+            .iter()
+            .any(|message| message.starts_with("Received SIGHUP; forwarded it to child process")),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn freeze_on_pause_stops_the_child_until_resumed() {
+    // The state letter in /proc/<pid>/stat: "T" while stopped.
+    fn stopped(pid: u32) -> bool {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap_or_default();
+        stat.rsplit_once(") ")
+            .is_some_and(|(_, rest)| rest.starts_with('T'))
+    }
+    fn wait_for(what: &str, condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(3);
+        while !condition() {
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting until {}",
+                what
+            );
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    let pids = pid_file("freeze_on_pause");
+    let watchdog = Watchdog::start(
+        free_port(),
+        &["-t", "5", "--freeze-on-pause"],
+        &["--pid-file", pids.to_str().unwrap()],
+    );
+    let child = read_pids(&pids, 1)[0];
+    thread::sleep(Duration::from_millis(200));
+    let watchdog_pid = watchdog.process.id() as libc::pid_t;
+    // SAFETY: plain kill(2) of the watchdog we started.
+    unsafe { libc::kill(watchdog_pid, libc::SIGUSR1) };
+    wait_for("the child is stopped", || stopped(child));
+    unsafe { libc::kill(watchdog_pid, libc::SIGUSR2) };
+    wait_for("the child runs again", || !stopped(child));
+    unsafe { libc::kill(watchdog_pid, libc::SIGTERM) };
+    let run = watchdog.finish();
+    run.assert_exit(0, "shutdown");
+    let messages = run.messages();
+    assert!(
+        messages.iter().any(|message| message.ends_with(&format!(
+            "timeout paused and process group {} frozen until resumed.",
+            child
+        ))),
+        "{:?}",
+        messages
+    );
+    assert!(
+        messages.contains(&format!("Thawed process group {}.", child)),
+        "{:?}",
+        messages
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn nice_ionice_and_cpu_affinity_apply_to_the_child() {
+    let pids = pid_file("nice");
+    let watchdog = Watchdog::start(
+        free_port(),
+        &[
+            "-t",
+            "500ms",
+            "--nice",
+            "7",
+            "--ionice",
+            "idle",
+            "--cpu-affinity",
+            "0",
+        ],
+        &["--pid-file", pids.to_str().unwrap()],
+    );
+    let child = read_pids(&pids, 1)[0];
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", child)).unwrap();
+    // The nice value is the 19th field, the 17th after the name.
+    let nice = stat.rsplit_once(") ").unwrap().1.split_whitespace().nth(16);
+    assert_eq!(nice, Some("7"), "{}", stat);
+    let status = std::fs::read_to_string(format!("/proc/{}/status", child)).unwrap();
+    assert!(status.contains("Cpus_allowed_list:\t0\n"), "{}", status);
+    // SAFETY: ioprio_get only reads the I/O priority of the given process.
+    let ioprio = unsafe { libc::syscall(libc::SYS_ioprio_get, 1, child) };
+    assert_eq!(ioprio, 3 << 13);
+    watchdog.finish().assert_exit(1, "no_heartbeat");
+}
+
+// Needs root, to change the root directory, and ldd, to find what the helper needs in it.
+#[cfg(target_os = "linux")]
+#[test]
+fn chroot_runs_the_child_in_its_new_root() {
+    // A root with the helper at its usual path and the libraries it is linked against.
+    let root = Path::new(env!("CARGO_TARGET_TMPDIR")).join("chroot");
+    let _ = std::fs::remove_dir_all(&root);
+    let within = |path: &Path| root.join(path.strip_prefix("/").unwrap());
+    let ldd = Command::new("ldd")
+        .arg(test_child())
+        .output()
+        .expect("run ldd");
+    let libraries: Vec<PathBuf> = String::from_utf8_lossy(&ldd.stdout)
+        .split_whitespace()
+        .filter(|word| word.starts_with('/'))
+        .map(PathBuf::from)
+        .collect();
+    for file in libraries.iter().map(PathBuf::as_path).chain([test_child()]) {
+        std::fs::create_dir_all(within(file.parent().unwrap())).unwrap();
+        std::fs::copy(file, within(file)).unwrap();
+    }
+    std::fs::create_dir(root.join("work")).unwrap();
+
+    // A relative --chdir is within the new root, whatever the watchdog's own directory.
+    let watchdog = Watchdog::start(
+        free_port(),
+        &[
+            "-t",
+            "500ms",
+            "--chroot",
+            root.to_str().unwrap(),
+            "--chdir",
+            "work",
+        ],
+        &["--pid-file", "child.pids"],
+    );
+    let child = read_pids(&root.join("work").join("child.pids"), 1)[0];
+    let cwd = std::fs::read_link(format!("/proc/{}/cwd", child)).unwrap();
+    let run = watchdog.finish();
+    run.assert_exit(1, "no_heartbeat");
+    assert_eq!(cwd, root.join("work"));
+    let messages = run.messages();
+    assert!(
+        messages.contains(&format!(
+            "Running the child with {} as its root (--chroot).",
+            root.display()
+        )),
+        "{:?}",
+        messages
+    );
+}
+
+// Needs root, to create the namespaces.
+#[cfg(target_os = "linux")]
+#[test]
+fn unshare_pid_runs_the_child_as_pid_1_and_kills_the_namespace() {
+    let pids = pid_file("unshare");
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "500ms", "--unshare", "pid,net,mount"],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--fork",
+            "2",
+            "--detach-forks",
+        ],
+    )
+    .finish();
+    run.assert_exit(1, "no_heartbeat");
+    // The PIDs as the namespace sees them: the child is its init.
+    let pids = read_pids(&pids, 3);
+    assert_eq!(pids[0], 1, "{:?}", pids);
+    let messages = run.messages();
+    assert!(
+        messages.contains(
+            &"Running the child in new pid, net, mount namespaces (--unshare).".to_string()
+        ),
+        "{:?}",
+        messages
+    );
+    assert!(
+        messages
+            .iter()
+            .any(|message| message.ends_with("and its descendants are gone.")),
+        "{:?}",
+        messages
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn signal_map_ignores_and_kills_as_configured() {
+    let port = free_port();
+    let pids = pid_file("signal_map");
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let watchdog = Watchdog::start(
+        port,
+        &[
+            "-t",
+            "5",
+            "--kill-grace",
+            "20s",
+            "--signal-map",
+            "TERM=ignore,SIGQUIT=kill",
+        ],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--ignore-sigterm",
+            "--heartbeat",
+            &heartbeat,
+        ],
+    );
+    let pids = read_pids(&pids, 1);
+    thread::sleep(Duration::from_millis(200));
+    let started = Instant::now();
+    // SAFETY: plain kill(2) of the watchdog we started.
+    unsafe { libc::kill(watchdog.process.id() as libc::pid_t, libc::SIGTERM) };
+    thread::sleep(Duration::from_millis(200));
+    // SAFETY: as above.
+    unsafe { libc::kill(watchdog.process.id() as libc::pid_t, libc::SIGQUIT) };
+    let run = watchdog.finish();
+    run.assert_exit(0, "shutdown");
+    // The child ignores SIGTERM, so only skipping --kill-grace ends it this soon.
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert_all_killed(&pids);
+    assert!(
+        run.messages()
+            .iter()
+            .any(|message| message == "Received SIGTERM signal; ignoring it (--signal-map)."),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn parent_death_signal_reaches_the_child_when_the_watchdog_is_killed() {
+    let pids = pid_file("parent_death_signal");
+    let watchdog = Watchdog::start(
+        free_port(),
+        &["-t", "5", "--parent-death-signal", "KILL"],
+        &["--pid-file", pids.to_str().unwrap(), "--ignore-sigterm"],
+    );
+    let pids = read_pids(&pids, 1);
+    // SAFETY: plain kill(2) of the watchdog we started; it gets no chance to clean up.
+    unsafe { libc::kill(watchdog.process.id() as libc::pid_t, libc::SIGKILL) };
+    let run = watchdog.finish();
+    assert_eq!(run.code, None);
+    assert_all_killed(&pids);
+}
+
+#[cfg(unix)]
+#[test]
+fn sigusr1_pauses_the_timeout_until_sigusr2() {
+    let port = free_port();
+    let pids = pid_file("pause_signal");
+    let mut watchdog = Watchdog::start(port, &["-t", "2"], &["--pid-file", pids.to_str().unwrap()]);
+    let child = read_pids(&pids, 1)[0];
+    thread::sleep(Duration::from_millis(200));
+    // SAFETY: plain kill(2) of the watchdog we started.
+    unsafe { libc::kill(watchdog.process.id() as libc::pid_t, libc::SIGUSR1) };
+    // Well past the timeout, without a heartbeat.
+    thread::sleep(Duration::from_secs(3));
+    assert!(watchdog.process.try_wait().unwrap().is_none());
+    assert!(is_running(child), "the paused watchdog killed the child");
+    let resumed = Instant::now();
+    // SAFETY: as above.
+    unsafe { libc::kill(watchdog.process.id() as libc::pid_t, libc::SIGUSR2) };
+    let run = watchdog.finish();
+    run.assert_exit(1, "no_heartbeat");
+    // Resuming gives the child a fresh timeout, which then runs out.
+    assert!(resumed.elapsed() >= Duration::from_secs(2));
+    assert_all_killed(&[child]);
+    assert!(
+        run.messages()
+            .iter()
+            .any(|message| message.starts_with("Command PAUSE from SIGUSR1")),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[test]
+fn child_output_is_forwarded() {
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "5"],
+        &[
+            "--print",
+            "hello from the child",
+            "--spew",
+            "3",
+            "--exit",
+            "0",
+        ],
+    )
+    .finish();
+    run.assert_exit(0, "child_exited");
+    let messages = run.messages();
+    for expected in [
+        "[child stdout] hello from the child",
+        "[child stdout] stdout line 3",
+        "[child stderr] stderr line 3",
+    ] {
+        assert!(
+            messages.iter().any(|message| message == expected),
+            "{:?} was not forwarded; output:\n{}{}",
+            expected,
+            run.stdout,
+            run.stderr
+        );
+    }
+}
+
+#[test]
+fn a_chatty_child_keeps_heartbeating() {
+    // Enough output to fill the pipes many times over if the watchdog stopped reading them.
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let run = Watchdog::start(
+        port,
+        &["-t", "1"],
+        &[
+            "--spew",
+            "20000",
+            "--heartbeat",
+            &heartbeat,
+            "--beats",
+            "20",
+            "--exit",
+            "0",
+        ],
+    )
+    .finish();
+    run.assert_exit(0, "child_exited");
+    assert!(run
+        .messages()
+        .iter()
+        .any(|message| message == "[child stdout] stdout line 20000"));
+}
+
+#[test]
+fn alive_regex_output_counts_as_a_heartbeat() {
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "1", "--alive-regex", "^beat [0-9]+$"],
+        &["--print-beats", "--beats", "25", "--exit", "0"],
+    )
+    .finish();
+    run.assert_exit(0, "child_exited");
+}
+
+#[test]
+fn dead_regex_output_kills_the_child() {
+    let pids = pid_file("dead_regex");
+    let started = Instant::now();
+    let run = Watchdog::start(
+        free_port(),
+        &["-t", "30", "--dead-regex", "FATAL"],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--print",
+            "FATAL: out of disk",
+        ],
+    )
+    .finish();
+    run.assert_exit(1, "probe_failed");
+    assert!(started.elapsed() < Duration::from_secs(30));
+    assert_all_killed(&read_pids(&pids, 1));
+}
+
+/// Sends `packet` to the watchdog on `port` until it replies, and returns the reply as JSON.
+fn request(port: u16, packet: &[u8]) -> serde_json::Value {
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("bind sender socket");
+    socket
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut buf = [0; 2048];
+    // Packets sent before the watchdog is listening go unanswered.
+    let len = loop {
+        socket
+            .send_to(packet, ("127.0.0.1", port))
+            .expect("send to watchdog");
+        if let Ok(len) = socket.recv(&mut buf) {
+            break len;
+        }
+        assert!(Instant::now() < deadline, "no reply received");
+    };
+    serde_json::from_slice(&buf[..len]).expect("reply is JSON")
+}
+
+#[test]
+fn a_port_in_use_kills_the_child_after_the_bind_retries() {
+    let taken = UdpSocket::bind("127.0.0.1:0").expect("bind test socket");
+    let port = taken.local_addr().unwrap().port();
+    let pids = pid_file("listen_failed");
+    let run = Watchdog::start(
+        port,
+        &["-t", "30", "--bind-retries", "2", "--bind-backoff", "50ms"],
+        &["--pid-file", pids.to_str().unwrap()],
+    )
+    .finish();
+    run.assert_exit(4, "listen_failed");
+    assert_all_killed(&read_pids(&pids, 1));
+}
+
+#[test]
+fn heartbeats_are_logged_one_in_n() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let run = Watchdog::start(
+        port,
+        &["-t", "2", "--log-heartbeats", "--log-heartbeats-every", "5"],
+        &["--heartbeat", &heartbeat, "--beats", "10", "--exit", "0"],
+    )
+    .finish();
+    run.assert_exit(0, "child_exited");
+    let logged: Vec<String> = run
+        .messages()
+        .into_iter()
+        .filter(|message| message.starts_with("Heartbeat from 127.0.0.1:"))
+        .collect();
+    assert!((1..=2).contains(&logged.len()), "{:?}", logged);
+    assert!(logged[0].ends_with("(4 bytes): ping"), "{:?}", logged);
+}
+
+#[test]
+fn ack_answers_heartbeats_with_a_pong() {
+    let port = free_port();
+    let pids = pid_file("ack");
+    let watchdog = Watchdog::start(
+        port,
+        &["-t", "5", "--ack"],
+        &[
+            "--pid-file",
+            pids.to_str().unwrap(),
+            "--print-beats",
+            "--beats",
+            "20",
+            "--exit",
+            "0",
+        ],
+    );
+    let pong = request(port, b"ping");
+    let run = watchdog.finish();
+    run.assert_exit(0, "child_exited");
+    let pid = read_pids(&pids, 1)[0];
+    assert_eq!(pong["pong"], true);
+    assert_eq!(pong["child_pid"], pid);
+    assert_eq!(pong["child_state"], "normal");
+    let remaining = pong["remaining_ms"].as_u64().expect("remaining_ms");
+    assert!((4000..=5000).contains(&remaining), "{}", pong);
+}
+
+#[test]
+fn status_queries_are_answered_but_are_not_heartbeats() {
+    let port = free_port();
+    let pids = pid_file("status");
+    let watchdog = Watchdog::start(
+        port,
+        &["-t", "2", "--status-query"],
+        &["--pid-file", pids.to_str().unwrap()],
+    );
+    let status = request(port, b"STATUS?\n");
+    let run = watchdog.finish();
+    run.assert_exit(1, "no_heartbeat");
+    assert_eq!(status["child_pid"], read_pids(&pids, 1)[0]);
+    assert_eq!(status["timeout_ms"], 2000);
+    assert_eq!(status["last_heartbeat_ms"], serde_json::Value::Null);
+    assert_eq!(status["restarts"], 0);
+    assert_eq!(status["packets"]["heartbeats"], 0);
+}
+
+#[test]
+fn malformed_payloads_are_not_heartbeats_with_an_explicit_codec() {
+    let port = free_port();
+    let watchdog = Watchdog::start(
+        port,
+        &["-t", "2", "--status-query", "--payload-codec", "json"],
+        &[],
+    );
+    request(port, b"STATUS?");
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for _ in 0..3 {
+        sender.send_to(b"ping", ("127.0.0.1", port)).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    let status = request(port, b"STATUS?");
+    let run = watchdog.finish();
+    run.assert_exit(1, "no_heartbeat");
+    assert_eq!(status["packets"]["malformed_payload"], 3);
+    assert_eq!(status["packets"]["heartbeats"], 0);
+}
+
+#[test]
+fn reported_metrics_are_aggregated_for_autoscalers() {
+    let port = free_port();
+    let metrics_file = Path::new(env!("CARGO_TARGET_TMPDIR")).join("metrics.prom");
+    let _ = std::fs::remove_file(&metrics_file);
+    let watchdog = Watchdog::start(
+        port,
+        &[
+            "-t",
+            "2",
+            "--status-query",
+            "--metrics-file",
+            metrics_file.to_str().unwrap(),
+        ],
+        &[],
+    );
+    // Once the watchdog answers, it hears the heartbeats too.
+    request(port, b"STATUS?");
+    // Two workers on one host, told apart by component.
+    for (worker, depth) in [("a", 10), ("b", 32)] {
+        let payload = format!(
+            "{{\"component\":\"{}\",\"metrics\":{{\"queue_depth\":{}}}}}",
+            worker, depth
+        );
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .send_to(payload.as_bytes(), ("127.0.0.1", port))
+            .unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    let status = request(port, b"STATUS?");
+    let depth = &status["metrics"]["queue_depth"];
+    assert_eq!(depth["sum"], 42.0, "{}", status);
+    assert_eq!(depth["max"], 32.0, "{}", status);
+    assert_eq!(depth["senders"], 2, "{}", status);
+    let deadline = Instant::now() + Duration::from_secs(3);
+    let text = loop {
+        let text = std::fs::read_to_string(&metrics_file).unwrap_or_default();
+        if text.contains("queue_depth") || Instant::now() > deadline {
+            break text;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    watchdog.finish().assert_exit(1, "timeout");
+    assert!(
+        text.contains("ping_guard_child_metric{metric=\"queue_depth\",aggregate=\"sum\"} 42\n"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("ping_guard_child_metric_senders{metric=\"queue_depth\"} 2\n"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn strict_protocol_ignores_unframed_heartbeats() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let watchdog = Watchdog::start(
+        port,
+        &["-t", "2", "--strict-protocol", "--status-query"],
+        &["--heartbeat", &heartbeat, "--beats", "20"],
+    );
+    std::thread::sleep(Duration::from_millis(1000));
+    let status = request(port, b"PGRD\x01STATUS?");
+    let run = watchdog.finish();
+    run.assert_exit(1, "no_heartbeat");
+    assert_eq!(status["packets"]["heartbeats"], 0);
+    assert!(
+        status["packets"]["nonconforming"].as_u64().unwrap() > 0,
+        "{}",
+        status
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn min_ttl_drops_heartbeats_sent_with_a_lower_ttl() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let run = Watchdog::start(
+        port,
+        &["-t", "1", "--min-ttl", "255"],
+        &["--heartbeat", &heartbeat, "--beats", "25", "--exit", "0"],
+    )
+    .finish();
+    run.assert_exit(1, "no_heartbeat");
+}
+
+#[test]
+fn a_single_sender_does_not_meet_a_quorum() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let run = Watchdog::start(
+        port,
+        &["-t", "1", "--require-senders", "2"],
+        &["--heartbeat", &heartbeat, "--beats", "25", "--exit", "0"],
+    )
+    .finish();
+    run.assert_exit(1, "timeout");
+    assert!(
+        run.messages()
+            .iter()
+            .any(|message| message.contains("from 2 senders, with only 1 heard from")),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[test]
+fn plain_pings_do_not_keep_a_required_component_alive() {
+    let port = free_port();
+    let heartbeat = format!("127.0.0.1:{}", port);
+    let started = Instant::now();
+    let run = Watchdog::start(
+        port,
+        &["-t", "10", "--require-component", "db=1s"],
+        &["--heartbeat", &heartbeat, "--beats", "100", "--exit", "0"],
+    )
+    .finish();
+    run.assert_exit(1, "timeout");
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(
+        run.messages()
+            .iter()
+            .any(|message| message.contains("from component db for")),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[test]
+fn a_flood_is_dropped_over_the_rate_limit() {
+    let port = free_port();
+    let watchdog = Watchdog::start(
+        port,
+        &["-t", "3", "--status-query", "--source-rate-limit", "5"],
+        &[],
+    );
+    request(port, b"STATUS?");
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("bind sender socket");
+    for _ in 0..200 {
+        socket.send_to(b"ping", ("127.0.0.1", port)).unwrap();
+    }
+    thread::sleep(Duration::from_secs(1));
+    let status = request(port, b"STATUS?");
+    let run = watchdog.finish();
+    run.assert_exit(1, "timeout");
+    assert!(
+        status["packets"]["heartbeats"].as_u64().unwrap() <= 10,
+        "{}",
+        status
+    );
+    assert!(
+        status["packets"]["rate_limited"].as_u64().unwrap() >= 150,
+        "{}",
+        status
+    );
+}
+
+#[test]
+fn packets_over_the_size_limit_are_counted_not_cut_short() {
+    let port = free_port();
+    let watchdog = Watchdog::start(
+        port,
+        &["-t", "2", "--status-query", "--max-packet-size", "16"],
+        &[],
+    );
+    request(port, b"STATUS?");
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("bind sender socket");
+    socket.send_to(&[b'x'; 100], ("127.0.0.1", port)).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    let status = request(port, b"STATUS?");
+    let run = watchdog.finish();
+    run.assert_exit(1, "no_heartbeat");
+    assert_eq!(status["packets"]["oversized"], 1, "{}", status);
+}
+
+/// Signs `payload` for `--hmac-key-file` (see "Authenticated heartbeats" in the README).
+fn signed(key: &[u8], payload: &[u8]) -> Vec<u8> {
+    use hmac::{Hmac, Mac};
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let nonce = format!("{}-{}", std::process::id(), now_ms);
+    let header = format!("{} {}", now_ms, nonce);
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key).unwrap();
+    mac.update(header.as_bytes());
+    mac.update(b"\n");
+    mac.update(payload);
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    [format!("{} {}\n", header, hex).as_bytes(), payload].concat()
+}
+
+#[test]
+fn unsigned_and_replayed_heartbeats_do_not_reset_the_timer() {
+    let port = free_port();
+    let key_file = Path::new(env!("CARGO_TARGET_TMPDIR")).join("hmac_rejections.key");
+    let key = b"hmac-rejections-test-key";
+    std::fs::write(&key_file, key).unwrap();
+    let watchdog = Watchdog::start(
+        port,
+        &[
+            "-t",
+            "2",
+            "--status-query",
+            "--hmac-key-file",
+            key_file.to_str().unwrap(),
+        ],
+        &[],
+    );
+    let before = request(port, &signed(key, b"STATUS?"));
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let heartbeat = signed(key, b"ping");
+    sender.send_to(&heartbeat, ("127.0.0.1", port)).unwrap();
+    let heard = Instant::now();
+    // Only the first copy counts; the rest, and packets without a valid signature, must not
+    // hold off the deadline.
+    let mut status = None;
+    while heard.elapsed() < Duration::from_secs(3) {
+        for packet in [
+            &heartbeat,
+            &b"ping".to_vec(),
+            &signed(b"wrong-key", b"ping"),
+        ] {
+            sender.send_to(packet, ("127.0.0.1", port)).unwrap();
+        }
+        if status.is_none() && heard.elapsed() >= Duration::from_secs(1) {
+            status = Some(request(port, &signed(key, b"STATUS?")));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let run = watchdog.finish();
+    run.assert_exit(1, "timeout");
+    assert!(heard.elapsed() < Duration::from_secs(5));
+    let (before, status) = (&before["packets"], &status.unwrap()["packets"]);
+    let count = |packets: &serde_json::Value, counter: &str| packets[counter].as_u64().unwrap();
+    assert_eq!(count(status, "heartbeats") - count(before, "heartbeats"), 1);
+    let replayed = count(status, "replayed") - count(before, "replayed");
+    let unauthenticated = count(status, "unauthenticated") - count(before, "unauthenticated");
+    // One replay and two unauthenticated packets per round, for about ten rounds.
+    assert!(replayed >= 5, "{}", status);
+    assert_eq!(unauthenticated, 2 * replayed, "{}", status);
+}
+
+#[test]
+fn kill_command_terminates_the_child() {
+    let port = free_port();
+    let pids = pid_file("kill_command");
+    let key_file = Path::new(env!("CARGO_TARGET_TMPDIR")).join("kill_command.key");
+    let key = b"kill-command-test-key";
+    std::fs::write(&key_file, key).unwrap();
+    let watchdog = Watchdog::start(
+        port,
+        &[
+            "-t",
+            "30",
+            "--allow-commands",
+            "--hmac-key-file",
+            key_file.to_str().unwrap(),
+        ],
+        &["--pid-file", pids.to_str().unwrap()],
+    );
+    let pids = read_pids(&pids, 1);
+    let reply = request(port, &signed(key, b"KILL"));
+    assert_eq!(reply["accepted"], true, "{}", reply);
+    let run = watchdog.finish();
+    run.assert_exit(0, "remote_kill");
+    assert_all_killed(&pids);
+}
+
+#[test]
+fn a_rotated_key_accepts_the_previous_one_for_the_overlap_only() {
+    let port = free_port();
+    let key_file = Path::new(env!("CARGO_TARGET_TMPDIR")).join("key_rotation.key");
+    let (old_key, new_key) = (&b"key-rotation-old-key"[..], &b"key-rotation-new-key-2"[..]);
+    std::fs::write(&key_file, old_key).unwrap();
+    let watchdog = Watchdog::start(
+        port,
+        &[
+            "-t",
+            "30",
+            "--status-query",
+            "--allow-commands",
+            "--hmac-key-file",
+            key_file.to_str().unwrap(),
+            "--key-rotation-overlap",
+            "2s",
+        ],
+        &[],
+    );
+    request(port, &signed(old_key, b"STATUS?"));
+    let replaced = key_file.with_extension("new");
+    std::fs::write(&replaced, new_key).unwrap();
+    std::fs::rename(&replaced, &key_file).unwrap();
+    // Answered once the watchdog has picked up the new key.
+    request(port, &signed(new_key, b"STATUS?"));
+    // Still answered, within the overlap.
+    request(port, &signed(old_key, b"STATUS?"));
+
+    thread::sleep(Duration::from_millis(2500));
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    sender
+        .send_to(&signed(old_key, b"STATUS?"), ("127.0.0.1", port))
+        .unwrap();
+    assert!(
+        sender.recv(&mut [0; 2048]).is_err(),
+        "the previous key was still accepted after the overlap"
+    );
+    let reply = request(port, &signed(new_key, b"KILL"));
+    assert_eq!(reply["accepted"], true, "{}", reply);
+    let run = watchdog.finish();
+    run.assert_exit(0, "remote_kill");
+    assert!(
+        run.messages().iter().any(|message| message.ends_with(
+            "changed; accepting the previous key for another 2s."
+        )),
+        "{:?}",
+        run.messages()
+    );
+}
+
+#[test]
+fn allow_extend_grants_bounded_extensions_only() {
+    let port = free_port();
+    let key_file = Path::new(env!("CARGO_TARGET_TMPDIR")).join("allow_extend.key");
+    let key = b"allow-extend-test-key";
+    std::fs::write(&key_file, key).unwrap();
+    let started = Instant::now();
+    let watchdog = Watchdog::start(
+        port,
+        &[
+            "-t",
+            "1",
+            "--allow-extend",
+            "--max-extension",
+            "2s",
+            "--hmac-key-file",
+            key_file.to_str().unwrap(),
+        ],
+        &[],
+    );
+    let reply = request(port, &signed(key, b"EXTEND 60"));
+    assert_eq!(reply["command"], "EXTEND 2", "{}", reply);
+    let reply = request(port, &signed(key, b"KILL"));
+    assert_eq!(reply["accepted"], false, "{}", reply);
+    let run = watchdog.finish();
+    run.assert_exit(1, "no_heartbeat");
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_secs(2), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(10), "{:?}", elapsed);
+}
+
+#[test]
+fn timeout_command_changes_the_timeout_at_runtime() {
+    let port = free_port();
+    let key_file = Path::new(env!("CARGO_TARGET_TMPDIR")).join("timeout_command.key");
+    let key = b"timeout-command-test-key";
+    std::fs::write(&key_file, key).unwrap();
+    let started = Instant::now();
+    let watchdog = Watchdog::start(
+        port,
+        &[
+            "-t",
+            "30",
+            "--allow-commands",
+            "--hmac-key-file",
+            key_file.to_str().unwrap(),
+        ],
+        &[],
+    );
+    let reply = request(port, &signed(key, b"TIMEOUT 1s"));
+    assert_eq!(reply["command"], "TIMEOUT 1s", "{}", reply);
+    let run = watchdog.finish();
+    run.assert_exit(1, "no_heartbeat");
+    assert!(started.elapsed() < Duration::from_secs(10));
+}
+
+/// Encrypts `payload` for `--encryption-key-file` (see "Encrypted heartbeats" in the README).
+fn encrypted(key: &[u8], payload: &[u8]) -> Vec<u8> {
+    use chacha20poly1305::aead::{Aead, KeyInit};
+    use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+    use sha2::Digest;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    // Unique per packet, which is all a nonce has to be.
+    static SENT: AtomicU32 = AtomicU32::new(0);
+    let now_ns = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64;
+    let nonce = [
+        &now_ns.to_le_bytes()[..],
+        &SENT.fetch_add(1, Ordering::Relaxed).to_le_bytes(),
+    ]
+    .concat();
+    let cipher = ChaCha20Poly1305::new(&sha2::Sha256::digest(key));
+    let sealed = cipher
+        .encrypt(Nonce::from_slice(&nonce), payload)
+        .expect("encrypt heartbeat");
+    [nonce, sealed].concat()
+}
+
+#[test]
+fn only_heartbeats_sealed_with_the_key_keep_the_child_alive() {
+    let port = free_port();
+    let pids = pid_file("encryption");
+    let key_file = Path::new(env!("CARGO_TARGET_TMPDIR")).join("encryption.key");
+    let key = b"encryption-test-key";
+    std::fs::write(&key_file, key).unwrap();
+    let watchdog = Watchdog::start(
+        port,
+        &[
+            "-t",
+            "1",
+            "--status-query",
+            "--encryption-key-file",
+            key_file.to_str().unwrap(),
+        ],
+        &["--pid-file", pids.to_str().unwrap()],
+    );
+    let pid = read_pids(&pids, 1)[0];
+    request(port, &encrypted(key, b"STATUS?"));
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    // Twice the timeout's worth of heartbeats.
+    for _ in 0..20 {
+        sender
+            .send_to(&encrypted(key, b"ping"), ("127.0.0.1", port))
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(is_running(pid), "the child was killed despite heartbeats");
+    let before = request(port, &encrypted(key, b"STATUS?"));
+
+    // A tampered heartbeat and one sealed with another key, for as long again.
+    let mut tampered = encrypted(key, b"ping");
+    *tampered.last_mut().unwrap() ^= 1;
+    let mut status = None;
+    for round in 0..20 {
+        for packet in [&tampered, &encrypted(b"wrong-key", b"ping")] {
+            sender.send_to(packet, ("127.0.0.1", port)).unwrap();
+        }
+        if round == 5 {
+            status = Some(request(port, &encrypted(key, b"STATUS?")));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let run = watchdog.finish();
+    run.assert_exit(1, "timeout");
+    assert_all_killed(&[pid]);
+    let (before, status) = (&before["packets"], &status.unwrap()["packets"]);
+    assert_eq!(status["heartbeats"], before["heartbeats"], "{}", status);
+    let undecryptable = status["undecryptable"].as_u64().unwrap();
+    assert_eq!(undecryptable, 12, "{}", status);
+}
+
+#[cfg(unix)]
+#[test]
+fn heartbeat_fd_defaults_to_3_and_leaves_the_standard_streams_alone() {
+    for fd in ["0", "2"] {
+        let pids = pid_file(&format!("heartbeat-fd-{}", fd));
+        let run = Watchdog::start(
+            free_port(),
+            &["-t", "5", "--heartbeat-fd", fd],
+            &["--pid-file", pids.to_str().unwrap()],
+        )
+        .finish();
+        run.assert_exit(1, "startup_error");
+        assert!(!pids.exists(), "--heartbeat-fd {} started a child", fd);
+    }
+
+    // Heartbeats on the default descriptor outlast the 1s timeout.
+    let mut command = Watchdog::command(free_port(), &["--heartbeat-fd", "-t", "1"]);
+    command.args([
+        "sh",
+        "--",
+        "-c",
+        r#"[ "$PING_GUARD_FD" = 3 ] || exit 9; for i in 1 2 3 4 5 6; do echo ping >&3; sleep 0.3; done"#,
+    ]);
+    Watchdog::spawn(command)
+        .finish()
+        .assert_exit(0, "child_exited");
+}
+
+#[test]
+fn a_timeout_too_short_for_the_probe_failures_is_rejected() {
+    let pids = pid_file("probe-timeout");
+    let run = Watchdog::start(
+        free_port(),
+        &[
+            "-t",
+            "3",
+            "--probe-exec",
+            "exit 0",
+            "--probe-interval",
+            "1s",
+        ],
+        &["--pid-file", pids.to_str().unwrap()],
+    )
+    .finish();
+    run.assert_exit(1, "startup_error");
+    assert!(!pids.exists(), "a child was started");
+}